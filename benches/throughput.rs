@@ -0,0 +1,76 @@
+//! Throughput of consensus generation on simulated nanopore-like and HiFi-like clusters, in template bases per
+//! second, so that performance changes across releases and backends can be compared.
+//!
+//! Run with `cargo bench`, or `cargo bench --features naive` to include the reference backend.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_spoa::{
+    poa, poa_batch, poa_batch_parallel, poa_with_backend, simulate_batch, simulate_cluster, AlignmentParams,
+    BatchOptions, ConsensusBackend, SimulationProfile, SpoaBackend,
+};
+
+// the template length and depth of each simulated dataset
+const ONT_CLUSTERS: [(usize, usize); 3] = [(500, 10), (1000, 20), (3000, 30)];
+const HIFI_CLUSTERS: [(usize, usize); 2] = [(5000, 5), (15000, 10)];
+
+fn bench_clusters(c: &mut Criterion, name: &str, clusters: &[(usize, usize)], profile: &SimulationProfile) {
+    let params = AlignmentParams::default();
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for &(len, depth) in clusters {
+        let cluster = simulate_cluster(len, depth, profile, 0);
+        group.throughput(Throughput::Elements((len * depth) as u64));
+        group.bench_function(BenchmarkId::from_parameter(format!("{}bp_x{}", len, depth)), |b| {
+            b.iter(|| poa(&cluster.seqs, &cluster.quals, &params))
+        });
+    }
+    group.finish();
+}
+
+fn bench_ont(c: &mut Criterion) {
+    bench_clusters(c, "ont", &ONT_CLUSTERS, &SimulationProfile::ont());
+}
+
+fn bench_hifi(c: &mut Criterion) {
+    bench_clusters(c, "hifi", &HIFI_CLUSTERS, &SimulationProfile::hifi());
+}
+
+// a batch of UMI-family-like amplicon clusters, sequentially and over the available parallelism
+fn bench_batch(c: &mut Criterion) {
+    let params = AlignmentParams::default();
+    let clusters = simulate_batch(200, 300, 10, &SimulationProfile::ont(), 0);
+    let mut group = c.benchmark_group("batch");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(200 * 300 * 10));
+    group.bench_function("sequential", |b| b.iter(|| poa_batch(&clusters, &params)));
+    group.bench_function("parallel", |b| {
+        b.iter(|| poa_batch_parallel(&clusters, &params, &BatchOptions::default()))
+    });
+    group.finish();
+}
+
+// the backends on a cluster small enough for the reference backend
+fn bench_backends(c: &mut Criterion) {
+    let params = AlignmentParams::default();
+    let cluster = simulate_cluster(300, 10, &SimulationProfile::ont(), 0);
+    // the backends take reads that are not null terminated
+    let seqs: Vec<&[u8]> = cluster.seqs.iter().map(|seq| &seq[..seq.len() - 1]).collect();
+    let quals: Vec<&[u8]> = cluster.quals.iter().map(|qual| &qual[..qual.len() - 1]).collect();
+    let backends: Vec<(&str, Box<dyn ConsensusBackend>)> = vec![
+        ("spoa", Box::new(SpoaBackend)),
+        #[cfg(feature = "naive")]
+        ("naive", Box::new(rust_spoa::NaiveBackend)),
+    ];
+
+    let mut group = c.benchmark_group("backend");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(300 * 10));
+    for (name, backend) in &backends {
+        group.bench_function(*name, |b| {
+            b.iter(|| poa_with_backend(backend.as_ref(), &seqs, &quals, &params))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ont, bench_hifi, bench_batch, bench_backends);
+criterion_main!(benches);
@@ -6,14 +6,69 @@ extern crate cmake;
 use cmake::Config;
 use std::env;
 
+// the SIMD instruction set of the SPOA kernels compiled for the building CPU, whose kernels use AVX2 or SSE4.1
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn native_level() -> &'static str {
+    if env::var("TARGET") != env::var("HOST") {
+        ""
+    } else if is_x86_feature_detected!("avx2") {
+        "AVX2"
+    } else if is_x86_feature_detected!("sse4.1") {
+        "SSE4.1"
+    } else {
+        ""
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn native_level() -> &'static str {
+    ""
+}
+
 fn main() {
 
-    let dst = Config::new("src/spoa")
-           .define("CMAKE_BUILD_TYPE","Release")
-           .build();
+    // the SIMD instruction set SPOA is compiled for: the host CPU by default, one of the sse41, avx2 and avx512
+    // features for a known fleet of CPUs, or all of them with the dispatch feature, chosen at runtime
+    let levels: Vec<&str> = ["SSE41", "AVX2", "AVX512", "DISPATCH"]
+        .iter()
+        .cloned()
+        .filter(|level| env::var(format!("CARGO_FEATURE_{}", level)).is_ok())
+        .collect();
+    if levels.len() > 1 {
+        panic!("At most one of the sse41, avx2, avx512 and dispatch features can be enabled");
+    }
+
+    let mut config = Config::new("src/spoa");
+    config.define("CMAKE_BUILD_TYPE","Release");
+    if !levels.is_empty() {
+        config.define("spoa_optimize_for_native", "OFF");
+    }
+    match levels.first().cloned() {
+        Some("SSE41") => config.define("spoa_optimize_for_portability", "ON"),
+        Some("AVX2") => config.cxxflag("-mavx2"),
+        // SPOA has no AVX-512 kernels: its AVX2 kernels are compiled with the AVX-512 instructions available
+        Some("AVX512") => config.cxxflag("-mavx512f").cxxflag("-mavx512bw"),
+        Some("DISPATCH") => config.define("spoa_generate_dispatch", "ON"),
+        _ => &mut config,
+    };
+    let dst = config.build();
+
+    // the instruction set the compiled SPOA requires of the CPU running it, checked by `check_cpu`
+    let needs = match levels.first().cloned() {
+        Some("SSE41") => "SSE4.1",
+        Some("AVX2") => "AVX2",
+        Some("AVX512") => "AVX-512",
+        Some(_) => "",
+        None => native_level(),
+    };
+    println!("cargo:rustc-env=SPOA_SIMD_LEVEL={}", needs);
 
     println!("cargo:rustc-link-search=native={}", dst.display());
     println!("cargo:rustc-link-lib=static=spoa");
+    if levels.first() == Some(&"DISPATCH") {
+        // the CPU detection of the dispatcher
+        println!("cargo:rustc-link-lib=static=cpu_features");
+    }
 
     let out_dir = env::var("OUT_DIR").unwrap();
     println!("cargo:rustc-flags=-L {}/lib64/ -L {}/lib/", &out_dir, &out_dir);
@@ -27,6 +82,7 @@ fn main() {
         .flag_if_supported("-Wall")
         .flag_if_supported("-std=c++11")
         .flag_if_supported("-Isrc/spoa/include")
+        .flag_if_supported("-Isrc/spoa/src")
         .flag_if_supported(&format!("-L{}/lib64 -L{}/lib", &out_dir, &out_dir))
         .flag_if_supported("-lspoa")
         .file("src/poa_func.cpp")
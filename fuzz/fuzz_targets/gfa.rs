@@ -0,0 +1,12 @@
+//! Arbitrary GFA imported with `PoaGraph::from_gfa`, which must either fail with an error or build a graph
+//! that can be queried.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust_spoa::{AlignmentParams, PoaGraph};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(graph) = PoaGraph::from_gfa(data, &AlignmentParams::default()) {
+        assert_eq!(graph.topology().paths.len(), graph.len());
+        graph.consensus();
+    }
+});
@@ -0,0 +1,35 @@
+//! Arbitrary reads aligned into a `PoaGraph` with `try_add`, then every read-only query of the graph.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust_spoa::PoaGraph;
+
+// the reads are added without their null terminators
+#[allow(dead_code)]
+mod input;
+
+fuzz_target!(|data: &[u8]| {
+    let input = match input::Input::decode(data) {
+        Some(input) => input,
+        None => return,
+    };
+    // the graph only takes scores in the range of the alignment engine
+    if input.params.validate().is_err() {
+        return;
+    }
+    let mut graph = PoaGraph::new(&input.params);
+    let added = input
+        .reads
+        .iter()
+        .filter(|(seq, qual)| graph.try_add(seq, qual).is_ok())
+        .count();
+    assert_eq!(graph.len(), added);
+
+    let topology = graph.topology();
+    assert_eq!(topology.paths.len(), graph.len());
+    assert_eq!(graph.msa().rows.len(), graph.len());
+    graph.consensus();
+    if let Some((query, _)) = input.reads.first() {
+        graph.score(query);
+        graph.align(query);
+    }
+});
@@ -0,0 +1,89 @@
+//! The decoding of fuzzer inputs into alignment parameters and reads, shared by the targets.
+use rust_spoa::{AlignmentParams, AlignmentType};
+
+/// The most reads decoded from an input.
+pub const MAX_READS: usize = 16;
+/// The largest total length of the reads of an input, so that each run stays fast.
+pub const MAX_LEN: usize = 4096;
+
+// the bits of the flags byte of the header
+const DETERMINISTIC: u8 = 1;
+const COLLAPSE_DUPLICATES: u8 = 2;
+const RAW_QUALS: u8 = 4;
+const UNTERMINATED: u8 = 8;
+const WILDCARD: u8 = 16;
+
+/// The parameters and reads of a fuzzer input.
+pub struct Input {
+    /// The alignment mode and scores, every score any `i8`, so that both valid and invalid scores are reached.
+    pub params: AlignmentParams,
+    /// The sequences and qualities of the reads (not null-terminated), possibly of different lengths.
+    pub reads: Vec<(Vec<u8>, Vec<u8>)>,
+    // whether the reads are passed without their null terminators
+    unterminated: bool,
+}
+
+impl Input {
+    /// Decodes an input: an 8-byte header of the alignment mode, the six scores and flags, followed by reads
+    /// separated by `0xff` bytes. Reads get `F` qualities, unless the raw qualities flag splits each of them
+    /// into a sequence and qualities at its middle. Inputs shorter than the header decode to nothing.
+    pub fn decode(data: &[u8]) -> Option<Input> {
+        if data.len() < 8 {
+            return None;
+        }
+        let score = |i: usize| data[i] as i8 as i32;
+        let flags = data[7];
+        let params = AlignmentParams {
+            alignment_type: match data[0] % 3 {
+                0 => AlignmentType::Local,
+                1 => AlignmentType::Global,
+                _ => AlignmentType::SemiGlobal,
+            },
+            match_score: score(1),
+            mismatch_score: score(2),
+            gap_open: score(3),
+            gap_extend: score(4),
+            gap2_open: score(5),
+            gap2_extend: score(6),
+            deterministic: flags & DETERMINISTIC != 0,
+            collapse_duplicates: flags & COLLAPSE_DUPLICATES != 0,
+            wildcard_score: if flags & WILDCARD != 0 { Some(0) } else { None },
+            ..AlignmentParams::default()
+        };
+        let body = &data[8..data.len().min(8 + MAX_LEN)];
+        let reads = body
+            .split(|&byte| byte == 0xff)
+            .take(MAX_READS)
+            .map(|read| match flags & RAW_QUALS {
+                0 => (read.to_vec(), vec![b'F'; read.len()]),
+                _ => {
+                    let (seq, qual) = read.split_at(read.len() / 2);
+                    (seq.to_vec(), qual.to_vec())
+                }
+            })
+            .collect();
+        Some(Input {
+            params,
+            reads,
+            unterminated: flags & UNTERMINATED != 0,
+        })
+    }
+
+    /// The sequences, null-terminated unless the input says otherwise.
+    pub fn seqs(&self) -> Vec<Vec<u8>> {
+        self.reads.iter().map(|(seq, _)| self.terminated(seq)).collect()
+    }
+
+    /// The qualities, null-terminated unless the input says otherwise.
+    pub fn quals(&self) -> Vec<Vec<u8>> {
+        self.reads.iter().map(|(_, qual)| self.terminated(qual)).collect()
+    }
+
+    fn terminated(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        if !self.unterminated {
+            bytes.push(0);
+        }
+        bytes
+    }
+}
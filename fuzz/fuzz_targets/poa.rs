@@ -0,0 +1,31 @@
+//! Consensus of arbitrary reads and scores through `try_poa_batch`, which must either fail with an error or
+//! return one alignment row per read, spelling the read once its gaps are removed.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust_spoa::{try_poa_batch, Cluster};
+
+mod input;
+
+fuzz_target!(|data: &[u8]| {
+    let input = match input::Input::decode(data) {
+        Some(input) => input,
+        None => return,
+    };
+    let cluster = Cluster {
+        id: "fuzz".to_string(),
+        seqs: input.seqs(),
+        quals: input.quals(),
+        metadata: Default::default(),
+    };
+    // a panic within the batch is caught as an error, but still aborts the fuzzer through its panic hook
+    if let Ok(result) = &try_poa_batch(&[cluster], &input.params, input::MAX_LEN)[0] {
+        let rows = &result.result.msa.rows;
+        assert_eq!(rows.len(), input.reads.len());
+        for (row, (seq, _)) in rows.iter().zip(input.reads.iter()) {
+            if !seq.contains(&b'-') {
+                let ungapped: Vec<u8> = row.iter().cloned().filter(|&base| base != b'-').collect();
+                assert_eq!(&ungapped, seq);
+            }
+        }
+    }
+});
@@ -0,0 +1,131 @@
+//! Placement of the worker threads of a pool on the CPUs and NUMA nodes of the host.
+use std::fs;
+use std::io;
+
+/// How the worker threads of a `ConsensusPool` or of `poa_batch_parallel_with_affinity` are placed on the CPUs
+/// of the host.
+///
+/// A pinned worker creates its graph, alignment engine and scratch buffers once pinned, so that on NUMA hosts,
+/// e.g. dual-socket nodes, the kernel places the memory it uses on its own node rather than across sockets.
+/// Pinning is only supported on Linux; elsewhere, or where the CPUs cannot be used (e.g. outside the CPU set of
+/// the process), workers are left unpinned with a warning on stderr.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Affinity {
+    /// Workers are placed by the scheduler of the operating system.
+    #[default]
+    Unpinned,
+    /// Worker `i` is pinned to the CPU `cpus[i % cpus.len()]`.
+    Cpus(Vec<usize>),
+    /// Workers are spread round-robin over the NUMA nodes, each pinned to the CPUs of its node. Without NUMA
+    /// information, workers are left unpinned.
+    NumaNodes,
+}
+
+impl Affinity {
+    // the CPUs each of `threads` workers is pinned to, or None for unpinned workers
+    pub(crate) fn worker_cpus(&self, threads: usize) -> Vec<Option<Vec<usize>>> {
+        let sets = match self {
+            Affinity::Unpinned => vec![],
+            Affinity::Cpus(cpus) => cpus.iter().map(|&cpu| vec![cpu]).collect(),
+            Affinity::NumaNodes => numa_nodes(),
+        };
+        (0..threads).map(|i| sets.get(i % sets.len().max(1)).cloned()).collect()
+    }
+}
+
+// the CPUs of a list such as `0-3,8,10-11`
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = vec![];
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut ends = range.splitn(2, '-').map(|end| end.trim().parse::<usize>());
+        match (ends.next(), ends.next()) {
+            (Some(Ok(first)), Some(Ok(last))) => cpus.extend(first..=last),
+            (Some(Ok(cpu)), None) => cpus.push(cpu),
+            _ => {}
+        }
+    }
+    cpus
+}
+
+/// Returns the CPUs of each NUMA node of the host, in node order, or no node if the host does not report
+/// them, as outside of Linux. Nodes without CPUs, such as memory-only nodes, are left out.
+pub fn numa_nodes() -> Vec<Vec<usize>> {
+    let entries = match fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut nodes: Vec<(usize, Vec<usize>)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let id = entry.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+            let cpus = parse_cpu_list(&fs::read_to_string(entry.path().join("cpulist")).ok()?);
+            Some((id, cpus))
+        })
+        .filter(|(_, cpus)| !cpus.is_empty())
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+// pins the calling thread to a set of CPUs
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    let pinned = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    };
+    if pinned {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "threads can only be pinned on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5"), vec![5]);
+        assert!(parse_cpu_list("\n").is_empty());
+    }
+
+    #[test]
+    fn test_worker_cpus() {
+        assert_eq!(Affinity::Unpinned.worker_cpus(2), vec![None, None]);
+        let cpus = Affinity::Cpus(vec![4, 6]).worker_cpus(3);
+        assert_eq!(cpus, vec![Some(vec![4]), Some(vec![6]), Some(vec![4])]);
+        // every worker of a NUMA host is pinned to the CPUs of one node
+        let nodes = numa_nodes();
+        if !nodes.is_empty() {
+            assert_eq!(Affinity::NumaNodes.worker_cpus(1), vec![Some(nodes[0].clone())]);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_pin_current_thread() {
+        // no CPU the kernel allows the thread on
+        assert!(pin_current_thread(&[]).is_err());
+        std::thread::spawn(|| {
+            let mut allowed: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut allowed) }, 0);
+            let cpu = (0..libc::CPU_SETSIZE as usize).find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &allowed) });
+            assert!(pin_current_thread(&[cpu.unwrap()]).is_ok());
+        })
+        .join()
+        .unwrap();
+    }
+}
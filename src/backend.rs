@@ -0,0 +1,234 @@
+//! Alignment engines behind the consensus API.
+use crate::batch::{check_reads, Cluster, ClusterResult};
+use crate::error::Error;
+use crate::msa::{Msa, GAP};
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// An engine aligning a set of sequences into a multiple sequence alignment with a consensus.
+///
+/// `SpoaBackend` runs the bundled SPOA library. Other engines, or test doubles, implement this trait to be
+/// used through `poa_with_backend` and `poa_batch_with_backend`, either as a type parameter or selected at
+/// runtime as a `&dyn ConsensusBackend`. `ColumnVoteBackend`, and `NaiveBackend` with the `naive` feature,
+/// run no C++ code, so that pipelines written against this trait can be unit-tested without SPOA.
+pub trait ConsensusBackend {
+    /// Aligns the sequences, returning one gapped row per sequence (in input order, named `seq_<index>`) and
+    /// the gapped consensus.
+    ///
+    /// # Arguments
+    ///
+    /// * `seqs` - the sequences (not null-terminated)
+    /// * `quals` - the Phred+33 qualities of the sequences (not null-terminated)
+    /// * `params` - the alignment mode and scores
+    fn msa(&self, seqs: &[&[u8]], quals: &[&[u8]], params: &AlignmentParams) -> Msa;
+
+    /// Returns the consensus of the sequences, with their alignment and its metrics.
+    fn consensus(&self, seqs: &[&[u8]], quals: &[&[u8]], params: &AlignmentParams) -> ConsensusResult {
+        ConsensusResult::from_msa(self.msa(seqs, quals, params))
+    }
+}
+
+/// The SPOA engine, run through the bundled C++ library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpoaBackend;
+
+impl ConsensusBackend for SpoaBackend {
+    fn msa(&self, seqs: &[&[u8]], quals: &[&[u8]], params: &AlignmentParams) -> Msa {
+        let seqs: Vec<Vec<u8>> = seqs.iter().map(|seq| null_terminated(seq)).collect();
+        let quals: Vec<Vec<u8>> = quals.iter().map(|qual| null_terminated(qual)).collect();
+        crate::msa_with_params(&seqs, &quals, None, params)
+    }
+}
+
+/// An engine that aligns nothing, as a trivially predictable stand-in for SPOA in tests: sequences are padded
+/// with gaps to the longest one and each consensus column takes its most frequent symbol, bases winning ties
+/// against gaps and smaller bytes against larger ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColumnVoteBackend;
+
+impl ConsensusBackend for ColumnVoteBackend {
+    fn msa(&self, seqs: &[&[u8]], _quals: &[&[u8]], _params: &AlignmentParams) -> Msa {
+        let len = seqs.iter().map(|seq| seq.len()).max().unwrap_or(0);
+        let rows: Vec<Vec<u8>> = seqs
+            .iter()
+            .map(|seq| seq.iter().cloned().chain(std::iter::repeat(GAP)).take(len).collect())
+            .collect();
+        let consensus = (0..len)
+            .map(|col| {
+                let mut counts = [0usize; 256];
+                for row in &rows {
+                    counts[row[col] as usize] += 1;
+                }
+                (0..=255u8)
+                    .filter(|&b| counts[b as usize] > 0)
+                    .max_by_key(|&b| (counts[b as usize], b != GAP, std::cmp::Reverse(b)))
+                    .unwrap()
+            })
+            .collect();
+        Msa {
+            names: (0..rows.len()).map(|i| format!("seq_{}", i)).collect(),
+            rows,
+            consensus,
+        }
+    }
+}
+
+/// Generates the consensus of a list of sequences with the given engine.
+/// # Arguments
+///
+/// * `backend` - the alignment engine, e.g. `SpoaBackend`
+/// * `seqs` - the sequences (not null-terminated)
+/// * `quals` - the Phred+33 qualities of the sequences (not null-terminated)
+/// * `params` - the alignment mode and scores
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_with_backend, AlignmentParams, ConsensusBackend, SpoaBackend};
+///
+///     let backend: Box<dyn ConsensusBackend> = Box::new(SpoaBackend);
+///     let seqs: Vec<&[u8]> = vec![b"ACGTT", b"ACTT"];
+///     let quals: Vec<&[u8]> = vec![b"FFFFF", b"FFFF"];
+///     let result = poa_with_backend(backend.as_ref(), &seqs, &quals, &AlignmentParams::default());
+/// ```
+pub fn poa_with_backend<B: ConsensusBackend + ?Sized>(
+    backend: &B,
+    seqs: &[&[u8]],
+    quals: &[&[u8]],
+    params: &AlignmentParams,
+) -> ConsensusResult {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    if seqs.is_empty() {
+        return ConsensusResult::default();
+    }
+    backend.consensus(seqs, quals, params)
+}
+
+/// Generates the consensus of every cluster of a batch with the given engine, like `poa_batch`, unless the
+/// parameters fail `AlignmentParams::validate` or the reads of a cluster are not the null-terminated sequences
+/// and Phred+33 qualities of equal lengths the consensus functions take (`Error::InvalidInput`).
+/// # Arguments
+///
+/// * `backend` - the alignment engine, e.g. `SpoaBackend`
+/// * `clusters` - the clusters to form consensuses from, with null-terminated reads
+/// * `params` - the alignment mode and scores, shared by all clusters
+///
+/// # Returns
+/// * returns one result per cluster, in input order, or the first error
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_batch_with_backend, AlignmentParams, Cluster, ColumnVoteBackend, ConsensusBackend};
+///
+///     let clusters = vec![Cluster {
+///         id: "umi_1".to_string(),
+///         seqs: vec![b"ACGT\0".to_vec(), b"ACGT\0".to_vec(), b"ACT\0".to_vec()],
+///         quals: vec![b"FFFF\0".to_vec(), b"FFFF\0".to_vec(), b"FFF\0".to_vec()],
+///         metadata: Default::default(),
+///     }];
+///     let backend: &dyn ConsensusBackend = &ColumnVoteBackend;
+///
+///     let results = poa_batch_with_backend(backend, &clusters, &AlignmentParams::default()).unwrap();
+///     assert_eq!(results[0].result.consensus, b"ACGT".to_vec());
+/// ```
+pub fn poa_batch_with_backend<B: ConsensusBackend + ?Sized>(
+    backend: &B,
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+) -> Result<Vec<ClusterResult>, Error> {
+    params.validate()?;
+    clusters
+        .iter()
+        .map(|cluster| {
+            check_reads(&cluster.seqs, &cluster.quals)?;
+            // the sequences are null terminated
+            let seqs: Vec<&[u8]> = cluster.seqs.iter().map(|seq| &seq[..seq.len() - 1]).collect();
+            let quals: Vec<&[u8]> = cluster.quals.iter().map(|qual| &qual[..qual.len() - 1]).collect();
+            Ok(ClusterResult::new(cluster, poa_with_backend(backend, &seqs, &quals, params)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // aligns nothing: every sequence is padded to the longest one, the consensus is the first sequence
+    struct PaddingBackend;
+
+    impl ConsensusBackend for PaddingBackend {
+        fn msa(&self, seqs: &[&[u8]], _quals: &[&[u8]], _params: &AlignmentParams) -> Msa {
+            let len = seqs.iter().map(|seq| seq.len()).max().unwrap_or(0);
+            let pad = |seq: &[u8]| {
+                let mut row = seq.to_vec();
+                row.resize(len, crate::GAP);
+                row
+            };
+            Msa {
+                names: (0..seqs.len()).map(|i| format!("seq_{}", i)).collect(),
+                rows: seqs.iter().map(|seq| pad(seq)).collect(),
+                consensus: pad(seqs[0]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let seqs: Vec<&[u8]> = vec![b"ACGT", b"ACG"];
+        let quals: Vec<&[u8]> = vec![b"FFFF", b"FFF"];
+        let backend: &dyn ConsensusBackend = &PaddingBackend;
+
+        let result = poa_with_backend(backend, &seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, b"ACGT".to_vec());
+        assert_eq!(result.msa.rows[1], b"ACG-".to_vec());
+        assert_eq!(result.metrics.num_reads, 2);
+        assert!(poa_with_backend(&PaddingBackend, &[], &[], &AlignmentParams::default()).consensus.is_empty());
+    }
+
+    #[test]
+    fn test_column_vote_backend() {
+        let seqs: Vec<&[u8]> = vec![b"ACGTT", b"ACCT", b"AGGTTA", b"ACG"];
+        let quals: Vec<&[u8]> = vec![b"FFFFF", b"FFFF", b"FFFFFF", b"FFF"];
+
+        let result = poa_with_backend(&ColumnVoteBackend, &seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.msa.rows[1], b"ACCT--".to_vec());
+        // T ties with gaps in the fifth column, gaps outnumber the base of the last one
+        assert_eq!(result.consensus, b"ACGTT".to_vec());
+    }
+
+    #[test]
+    fn test_poa_batch_with_backend() {
+        let clusters = vec![crate::test_cluster("umi_1", &["ACGT", "ACG"]), crate::test_cluster("empty", &[])];
+        let params = AlignmentParams::default();
+
+        let results = poa_batch_with_backend(&PaddingBackend, &clusters, &params).unwrap();
+
+        assert_eq!(results[0].id, "umi_1");
+        assert_eq!(results[0].result.msa.rows[1], b"ACG-".to_vec());
+        assert!(results[1].result.consensus.is_empty());
+        let mut unterminated = crate::test_cluster("umi_2", &["ACGT"]);
+        unterminated.seqs[0].pop();
+        let error = poa_batch_with_backend(&PaddingBackend, &[unterminated], &params).unwrap_err();
+        assert!(matches!(error, Error::InvalidInput { .. }));
+        let invalid = AlignmentParams { gap_open: 3, ..params };
+        assert!(poa_batch_with_backend(&PaddingBackend, &clusters, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_spoa_backend() {
+        let seqs: Vec<&[u8]> =
+            vec![b"ATTGCCCGTT", b"AATGCCGTT", b"AATGCCCGAT", b"AACGCCCGTC", b"AGTGCTCGTT", b"AATGCTCGTT"];
+        let quals: Vec<Vec<u8>> = seqs.iter().map(|seq| vec![b'F'; seq.len()]).collect();
+        let quals: Vec<&[u8]> = quals.iter().map(|qual| qual.as_slice()).collect();
+
+        let result = poa_with_backend(&SpoaBackend, &seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+    }
+}
@@ -0,0 +1,332 @@
+//! Consensus reads of the UMI families of a BAM file, in the manner of fgbio's CallMolecularConsensusReads, and
+//! polishing of draft sequences with the reads mapped to them in a BAM file.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use noodles::bam;
+use noodles::sam;
+use noodles::sam::alignment::io::Write as _;
+use noodles::sam::alignment::record::cigar::op::Kind;
+use noodles::sam::alignment::record::data::field::Tag;
+use noodles::sam::alignment::record::Flags;
+use noodles::sam::alignment::record_buf::data::field::Value;
+use noodles::sam::alignment::record_buf::{QualityScores, Sequence};
+use noodles::sam::alignment::RecordBuf;
+
+use crate::batch::consensus_quals;
+use crate::dna::reverse_complement;
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::polish::{DraftWindows, PolishOptions, PolishedDraft};
+
+/// Options of `poa_bam_consensus`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BamConsensusOptions {
+    /// The tags holding the family (molecular) identifier of a read, the first present one being used.
+    pub umi_tags: Vec<[u8; 2]>,
+    /// The minimum number of reads of a family, smaller families emitting no consensus read.
+    pub min_reads: usize,
+    /// If true, the reads of each family are adjacent in the input, as in the output of fgbio GroupReadsByUmi,
+    /// and families are called as soon as their last read is read; otherwise every family is gathered in memory
+    /// before any is called.
+    pub grouped: bool,
+    /// The Phred quality given to every base of the reads without qualities (`*` in SAM).
+    pub default_qual: u8,
+    /// The alignment mode and scores of the consensus.
+    pub params: AlignmentParams,
+}
+
+impl Default for BamConsensusOptions {
+    fn default() -> BamConsensusOptions {
+        BamConsensusOptions {
+            umi_tags: vec![*b"MI", *b"UB"],
+            min_reads: 1,
+            grouped: false,
+            default_qual: 20,
+            params: AlignmentParams::default(),
+        }
+    }
+}
+
+// the reads of a family: their sequences and Phred+33 qualities, null terminated
+type Family = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+// the family identifier of a record, from the first of the tags it holds as a string or an integer
+fn family_id(record: &RecordBuf, tags: &[[u8; 2]]) -> Option<String> {
+    tags.iter().find_map(|tag| match record.data().get(&Tag::new(tag[0], tag[1]))? {
+        Value::String(id) => Some(id.to_string()),
+        value => value.as_int().map(|id| id.to_string()),
+    })
+}
+
+// writes the consensus read of every family holding enough reads and clears the families, returning the number
+// of consensus reads written
+fn write_families<W: sam::alignment::io::Write>(
+    writer: &mut W,
+    header: &sam::Header,
+    families: &mut BTreeMap<(String, u8), Family>,
+    options: &BamConsensusOptions,
+) -> io::Result<usize> {
+    let mut written = 0;
+    for ((id, segment), (seqs, quals)) in std::mem::take(families) {
+        if seqs.len() < options.min_reads {
+            continue;
+        }
+        let result = crate::try_poa(&seqs, &quals, &options.params)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("family {}: {}", id, e)))?;
+        let row_quals: Vec<&[u8]> = quals.iter().map(|qual| &qual[..qual.len() - 1]).collect();
+        let consensus_qual: Vec<u8> = consensus_quals(&result.msa, &row_quals).iter().map(|q| q - 33).collect();
+        let flags = match segment {
+            0 => Flags::UNMAPPED,
+            1 => Flags::UNMAPPED | Flags::SEGMENTED | Flags::MATE_UNMAPPED | Flags::FIRST_SEGMENT,
+            _ => Flags::UNMAPPED | Flags::SEGMENTED | Flags::MATE_UNMAPPED | Flags::LAST_SEGMENT,
+        };
+        let min_depth = result.metrics.coverage.iter().cloned().min().unwrap_or(0);
+        let data = vec![
+            (Tag::new(b'M', b'I'), Value::String(id.as_str().into())),
+            (Tag::new(b'c', b'D'), Value::from(seqs.len() as i32)),
+            (Tag::new(b'c', b'M'), Value::from(min_depth as i32)),
+            (Tag::new(b'c', b'E'), Value::from(result.metrics.errors.error_rate() as f32)),
+        ]
+        .into_iter()
+        .collect();
+        let record = RecordBuf::builder()
+            .set_name(id.as_str())
+            .set_flags(flags)
+            .set_sequence(Sequence::from(result.consensus))
+            .set_quality_scores(QualityScores::from(consensus_qual))
+            .set_data(data)
+            .build();
+        writer.write_alignment_record(header, &record)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Reads the reads of a BAM file, groups them by UMI family and writes one unmapped consensus read per family
+/// (per segment for paired reads) to a new BAM file.
+///
+/// Secondary and supplementary records, records without a family tag and empty records are skipped; reads
+/// aligned to the reverse strand are reverse complemented back to their sequenced orientation and reads
+/// without qualities take `options.default_qual` for every base. By default, families are gathered in memory,
+/// as those of a coordinate-sorted file are spread over it, so that the whole input is held at once, and
+/// written sorted by identifier; with `options.grouped`, only the reads of the current family are held and
+/// families are written in input order. Each consensus read is named after its family and carries it as `MI`
+/// tag, its base qualities as in `poa_grouped`, and fgbio-style tags: the number of reads of its family (`cD`),
+/// the lowest number of reads covering one of its bases (`cM`) and the error rate of the reads against it
+/// (`cE`).
+/// # Arguments
+///
+/// * `input` - the BAM file of reads, e.g. the output of fgbio GroupReadsByUmi
+/// * `output` - the BAM file of consensus reads, created or truncated
+/// * `options` - the family tags, minimum family size and scores
+///
+/// # Returns
+/// * returns the number of consensus reads written, or the first I/O error, of kind `InvalidInput` for
+///   invalid parameters and `InvalidData` for a family whose consensus fails, see `try_poa`
+pub fn poa_bam_consensus<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    options: &BamConsensusOptions,
+) -> io::Result<usize> {
+    options.params.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let mut reader = bam::io::reader::Builder.build_from_path(input)?;
+    let header = reader.read_header()?;
+
+    let out_header = sam::Header::default();
+    let mut writer = bam::io::writer::Builder.build_from_path(output)?;
+    writer.write_header(&out_header)?;
+    let mut written = 0;
+    // keyed by family identifier and segment: 0 for unpaired reads, 1 and 2 for the first and last segments
+    let mut families: BTreeMap<(String, u8), Family> = BTreeMap::new();
+    for result in reader.record_bufs(&header) {
+        let record = result?;
+        let flags = record.flags();
+        if flags.is_secondary() || flags.is_supplementary() {
+            continue;
+        }
+        let id = match family_id(&record, &options.umi_tags) {
+            Some(id) => id,
+            None => continue,
+        };
+        let segment = match (flags.is_segmented(), flags.is_first_segment()) {
+            (false, _) => 0,
+            (true, true) => 1,
+            (true, false) => 2,
+        };
+        let mut seq = record.sequence().as_ref().to_vec();
+        let mut qual: Vec<u8> = record.quality_scores().as_ref().iter().map(|&q| q.saturating_add(33)).collect();
+        if qual.is_empty() {
+            qual = vec![options.default_qual.saturating_add(33); seq.len()];
+        }
+        if seq.is_empty() || qual.len() != seq.len() {
+            continue;
+        }
+        if flags.is_reverse_complemented() {
+            seq = reverse_complement(&seq);
+            qual.reverse();
+        }
+        // the reads of the current family, if grouped, are all read once another family starts
+        if options.grouped && families.keys().any(|(current, _)| *current != id) {
+            written += write_families(&mut writer, &out_header, &mut families, options)?;
+        }
+        let family = families.entry((id, segment)).or_default();
+        family.0.push(null_terminated(&seq));
+        family.1.push(null_terminated(&qual));
+    }
+    written += write_families(&mut writer, &out_header, &mut families, options)?;
+    writer.finish(&out_header)?;
+    Ok(written)
+}
+
+// the SAM code of a CIGAR operation
+fn cigar_code(kind: Kind) -> u8 {
+    match kind {
+        Kind::Match => b'M',
+        Kind::Insertion => b'I',
+        Kind::Deletion => b'D',
+        Kind::Skip => b'N',
+        Kind::SoftClip => b'S',
+        Kind::HardClip => b'H',
+        Kind::Pad => b'P',
+        Kind::SequenceMatch => b'=',
+        Kind::SequenceMismatch => b'X',
+    }
+}
+
+/// Polishes draft sequences with the reads mapped to them in a BAM file, as `poa_polish` does but placing the
+/// reads by their mappings instead of realigning them, so that whole assemblies can be polished.
+///
+/// Unmapped, secondary and supplementary records and records mapped to reference sequences other than the
+/// drafts are skipped. The reference sequences of the BAM header are matched to the drafts by name.
+/// # Arguments
+///
+/// * `input` - the BAM file of the reads mapped to the drafts, e.g. by minimap2 and samtools
+/// * `drafts` - the name and sequence of each draft, not null-terminated
+/// * `params` - the alignment mode and scores of the consensus of each window
+/// * `options` - the window length and minimum coverage
+///
+/// # Returns
+/// * returns the polished drafts, in input order
+pub fn poa_polish_bam<P: AsRef<Path>>(
+    input: P,
+    drafts: &[(&str, &[u8])],
+    params: &AlignmentParams,
+    options: &PolishOptions,
+) -> io::Result<Vec<PolishedDraft>> {
+    let mut reader = bam::io::reader::Builder.build_from_path(input)?;
+    let header = reader.read_header()?;
+    // the draft of each reference sequence of the header
+    let draft_of: Vec<Option<usize>> = header
+        .reference_sequences()
+        .keys()
+        .map(|name| drafts.iter().position(|(draft, _)| name.as_slice() == draft.as_bytes()))
+        .collect();
+
+    let mut windows: Vec<DraftWindows> = drafts
+        .iter()
+        .map(|(_, draft)| DraftWindows::new(draft.len(), options.window_len))
+        .collect();
+    for result in reader.record_bufs(&header) {
+        let record = result?;
+        let flags = record.flags();
+        if flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary() {
+            continue;
+        }
+        let (i, start) = match (record.reference_sequence_id(), record.alignment_start()) {
+            (Some(id), Some(start)) => match draft_of.get(id).cloned().flatten() {
+                Some(i) => (i, usize::from(start) - 1),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let seq = record.sequence().as_ref();
+        let qual: Vec<u8> = record.quality_scores().as_ref().iter().map(|&q| q.saturating_add(33)).collect();
+        if seq.is_empty() || qual.len() != seq.len() {
+            continue;
+        }
+        let ops = record.cigar().as_ref().iter().map(|op| (cigar_code(op.kind()), op.len()));
+        windows[i].add_read(start, ops, seq, &qual);
+    }
+    Ok(windows
+        .into_iter()
+        .zip(drafts.iter())
+        .map(|(windows, (_, draft))| windows.polish(draft, params, options))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_family_id() {
+        let tags = BamConsensusOptions::default().umi_tags;
+        let record = |tag: &[u8; 2], value: Value| {
+            RecordBuf::builder().set_data(vec![(Tag::new(tag[0], tag[1]), value)].into_iter().collect()).build()
+        };
+
+        assert_eq!(family_id(&record(b"MI", Value::from("12/A")), &tags), Some("12/A".to_string()));
+        assert_eq!(family_id(&record(b"UB", Value::from(7i32)), &tags), Some("7".to_string()));
+        assert_eq!(family_id(&record(b"RX", Value::from("ACGT")), &tags), None);
+    }
+
+    #[test]
+    fn test_poa_bam_consensus() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("rust_spoa_families_{}.bam", std::process::id()));
+        let output = dir.join(format!("rust_spoa_consensus_{}.bam", std::process::id()));
+        let header = sam::Header::default();
+        let mut writer = bam::io::writer::Builder.build_from_path(&input).unwrap();
+        writer.write_header(&header).unwrap();
+        let reads: [(&str, &[u8], &[u8]); 5] = [
+            ("1", b"ACGTTGCA", b"IIIIIIII"),
+            ("1", b"ACGTTGCA", b""),
+            ("1", b"ACGATGCA", b"IIII#III"),
+            ("2", b"GGCATTACCA", b"IIIIIIIIII"),
+            ("1", b"ACGTTGCA", b"IIIIIIII"),
+        ];
+        for (id, seq, qual) in reads.iter() {
+            let record = RecordBuf::builder()
+                .set_flags(Flags::UNMAPPED)
+                .set_sequence(Sequence::from(seq.to_vec()))
+                .set_quality_scores(QualityScores::from(qual.iter().map(|q| q - 33).collect::<Vec<u8>>()))
+                .set_data(vec![(Tag::new(b'M', b'I'), Value::from(*id))].into_iter().collect())
+                .build();
+            writer.write_alignment_record(&header, &record).unwrap();
+        }
+        writer.finish(&header).unwrap();
+        drop(writer);
+        let consensus_reads = |options: &BamConsensusOptions| {
+            let written = poa_bam_consensus(&input, &output, options).unwrap();
+            let mut reader = bam::io::reader::Builder.build_from_path(&output).unwrap();
+            let header = reader.read_header().unwrap();
+            let records: Vec<RecordBuf> = reader.record_bufs(&header).map(Result::unwrap).collect();
+            assert_eq!(records.len(), written);
+            records
+        };
+
+        let options = BamConsensusOptions { min_reads: 2, ..Default::default() };
+        let records = consensus_reads(&options);
+        // the read without qualities counts, and the single read of family 2 is too few
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name().map(|name| name.to_vec()), Some(b"1".to_vec()));
+        assert_eq!(records[0].sequence().as_ref(), &b"ACGTTGCA"[..]);
+        assert_eq!(records[0].data().get(&Tag::new(b'c', b'D')).and_then(Value::as_int), Some(4));
+
+        // grouped, the last read is a family of its own
+        let grouped = BamConsensusOptions { min_reads: 1, grouped: true, ..Default::default() };
+        let records = consensus_reads(&grouped);
+        let depth = |record: &RecordBuf| record.data().get(&Tag::new(b'c', b'D')).and_then(Value::as_int);
+        let depths: Vec<_> = records.iter().map(depth).collect();
+        assert_eq!(depths, vec![Some(3), Some(1), Some(1)]);
+
+        let invalid = BamConsensusOptions { params: AlignmentParams { gap_open: 3, ..options.params }, ..options };
+        assert_eq!(poa_bam_consensus(&input, &output, &invalid).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,808 @@
+//! Consensus generation over batches of independent clusters of reads.
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[cfg(feature = "affinity")]
+use crate::affinity::Affinity;
+use crate::duplex::column_quals;
+use crate::error::Error;
+use crate::graph::PoaGraph;
+use crate::msa::{Msa, GAP};
+use crate::paired::MAX_MERGED_QUAL;
+use crate::params::{AlignmentParams, ParamSchedule};
+use crate::result::ConsensusResult;
+#[cfg(feature = "affinity")]
+use crate::scheduler::worker_threads;
+use crate::scheduler::{cluster_cost, run_stealing, BatchOptions, BatchOrder};
+use crate::scratch::{poa_consensus_with_scratch, ConsensusScratch};
+
+/// A group of reads to form one consensus from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cluster {
+    /// The identifier of the cluster, carried through to its result.
+    pub id: String,
+    /// The sequences of the cluster (each as a null-terminated vector of u8).
+    pub seqs: Vec<Vec<u8>>,
+    /// The qualities of the sequences (each as a null-terminated vector of u8).
+    pub quals: Vec<Vec<u8>>,
+    /// Arbitrary key/value metadata of the cluster, such as its sample or gene, carried through to its result.
+    /// Keys should not hold `=` and neither keys nor values whitespace, for the records of the FASTA and
+    /// checkpoint writers to remain parsable.
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The consensus result of one cluster of a batch.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ClusterResult {
+    /// The identifier of the cluster.
+    pub id: String,
+    /// The consensus of the cluster.
+    pub result: ConsensusResult,
+    /// The metadata of the cluster.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl ClusterResult {
+    /// Builds the result of a cluster, carrying its identifier and metadata.
+    pub fn new(cluster: &Cluster, result: ConsensusResult) -> ClusterResult {
+        ClusterResult {
+            id: cluster.id.clone(),
+            result,
+            metadata: cluster.metadata.clone(),
+        }
+    }
+}
+
+/// Generates the consensus of every cluster of a batch.
+///
+/// Panics like `poa` on a cluster the alignment engine rejects, failing the whole batch; `try_poa_batch`
+/// fails such a cluster on its own.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+///
+/// # Returns
+/// * returns one result per cluster, in input order
+pub fn poa_batch(clusters: &[Cluster], params: &AlignmentParams) -> Vec<ClusterResult> {
+    clusters
+        .iter()
+        .map(|cluster| ClusterResult::new(cluster, crate::poa(&cluster.seqs, &cluster.quals, params)))
+        .collect()
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch`, each cluster with the parameters of
+/// its depth, its number of reads, in a schedule. Panics like `poa_batch`, see `try_poa_batch_scheduled`.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `schedule` - the alignment mode and scores of each depth
+///
+/// # Returns
+/// * returns one result per cluster, in input order
+pub fn poa_batch_scheduled(clusters: &[Cluster], schedule: &ParamSchedule) -> Vec<ClusterResult> {
+    clusters
+        .iter()
+        .map(|cluster| {
+            let params = schedule.params(cluster.seqs.len());
+            ClusterResult::new(cluster, crate::poa(&cluster.seqs, &cluster.quals, params))
+        })
+        .collect()
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch`, the clusters spread over worker
+/// threads.
+///
+/// Clusters are dealt to a queue per worker in the order of `options.order`, largest first by default, and a
+/// worker that runs out of clusters steals the next cluster of another worker, so that a few huge clusters do
+/// not hold up the end of the batch on a single thread. A panic of a worker, e.g. on a cluster the alignment
+/// engine rejects, is resumed once all workers stopped, failing the whole batch; `try_poa_batch_parallel`
+/// fails such a cluster on its own.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+/// * `options` - the number of worker threads and the order clusters are taken up in
+///
+/// # Returns
+/// * returns one result per cluster, in input order
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_batch_parallel, AlignmentParams, BatchOptions, BatchOrder, Cluster};
+///
+///     let clusters = vec![Cluster {
+///         id: "umi_1".to_string(),
+///         seqs: vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()],
+///         quals: vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()],
+///         metadata: Default::default(),
+///     }];
+///     let options = BatchOptions {
+///         threads: 4,
+///         order: BatchOrder::LargestFirst,
+///     };
+///     let results = poa_batch_parallel(&clusters, &AlignmentParams::default(), &options);
+/// ```
+pub fn poa_batch_parallel(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    options: &BatchOptions,
+) -> Vec<ClusterResult> {
+    batch_parallel(clusters, params, options, &[])
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch_parallel`, its workers placed on the
+/// CPUs of the host by `affinity`, e.g. spread over the NUMA nodes of the host, like the workers of
+/// `ConsensusPool::with_affinity`.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_batch_parallel_with_affinity, Affinity, AlignmentParams, BatchOptions};
+///
+///     let options = BatchOptions::default();
+///     let results = poa_batch_parallel_with_affinity(&[], &AlignmentParams::default(), &options, &Affinity::NumaNodes);
+///     assert!(results.is_empty());
+/// ```
+#[cfg(feature = "affinity")]
+pub fn poa_batch_parallel_with_affinity(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    options: &BatchOptions,
+    affinity: &Affinity,
+) -> Vec<ClusterResult> {
+    let worker_cpus = affinity.worker_cpus(worker_threads(options.threads));
+    batch_parallel(clusters, params, options, &worker_cpus)
+}
+
+// the consensus of every cluster of a batch over worker threads, each pinned to its entry of `worker_cpus`
+fn batch_parallel(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    options: &BatchOptions,
+    worker_cpus: &[Option<Vec<usize>>],
+) -> Vec<ClusterResult> {
+    let costs: Vec<usize> = clusters
+        .iter()
+        .map(|cluster| cluster_cost(cluster.seqs.iter().map(|seq| seq.len().saturating_sub(1))))
+        .collect();
+    run_stealing(
+        &costs,
+        options,
+        worker_cpus,
+        || (),
+        |_, i| {
+            let cluster = &clusters[i];
+            ClusterResult::new(cluster, crate::poa(&cluster.seqs, &cluster.quals, params))
+        },
+    )
+}
+
+/// The failure of one cluster of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterError {
+    /// The identifier of the cluster.
+    pub id: String,
+    /// Why the cluster failed.
+    pub error: Error,
+}
+
+impl std::fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "cluster {}: {}", self.id, self.error)
+    }
+}
+
+impl std::error::Error for ClusterError {}
+
+// the reads checked for the bytes the alignment engine expects
+pub(crate) fn check_reads(seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> Result<(), Error> {
+    let invalid = |reason: String| Err(Error::InvalidInput { reason });
+    if seqs.len() != quals.len() {
+        return invalid(format!("{} sequences but {} qualities", seqs.len(), quals.len()));
+    }
+    for (i, (seq, qual)) in seqs.iter().zip(quals.iter()).enumerate() {
+        if seq.last() != Some(&0) || qual.last() != Some(&0) {
+            return invalid(format!("read {} is not null terminated", i));
+        }
+        if seq.len() != qual.len() {
+            return invalid(format!("read {} has {} bases but {} qualities", i, seq.len() - 1, qual.len() - 1));
+        }
+        if seq[..seq.len() - 1].contains(&0) {
+            return invalid(format!("read {} holds a null byte", i));
+        }
+        if qual[..qual.len() - 1].iter().any(|&q| q < b'!') {
+            return invalid(format!("read {} holds a quality below the Phred+33 range", i));
+        }
+    }
+    Ok(())
+}
+
+// the reads of a cluster checked like `check_reads`, and against the largest total length
+fn check_cluster(cluster: &Cluster, max_len: usize) -> Result<(), Error> {
+    check_reads(&cluster.seqs, &cluster.quals)?;
+    // the sequences are null terminated
+    let len: usize = cluster.seqs.iter().map(|seq| seq.len() - 1).sum();
+    if max_len > 0 && len > max_len {
+        return Err(Error::GraphTooLarge { len, max_len });
+    }
+    Ok(())
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch`, a cluster failing on its own without
+/// failing the others.
+///
+/// A cluster fails with `Error::InvalidInput` when its reads are not null terminated, hold null bytes or
+/// qualities below `!`, or their sequences and qualities differ in number or length, with
+/// `Error::GraphTooLarge` when its reads are longer in total than `max_len`, bounding the memory its graph
+/// and alignment take, with the errors of `try_poa`, and with `Error::Panicked` when forming its consensus
+/// panics. The alignment engine cannot be interrupted, so that slow clusters are bounded by `max_len` only.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+/// * `max_len` - the largest total length of the reads of a cluster, or 0 for no limit
+///
+/// # Returns
+/// * returns the result or the failure of each cluster, in input order
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{try_poa_batch, AlignmentParams, Cluster, Error};
+///
+///     let clusters = vec![Cluster {
+///         id: "umi_1".to_string(),
+///         seqs: vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()],
+///         quals: vec![b"FFFFF\0".to_vec(), b"FFF\0".to_vec()],
+///         metadata: Default::default(),
+///     }];
+///
+///     let results = try_poa_batch(&clusters, &AlignmentParams::default(), 0);
+///     assert!(matches!(&results[0], Err(failure) if matches!(failure.error, Error::InvalidInput { .. })));
+/// ```
+pub fn try_poa_batch(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    max_len: usize,
+) -> Vec<Result<ClusterResult, ClusterError>> {
+    clusters.iter().map(|cluster| try_cluster(cluster, params, max_len)).collect()
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch_parallel`, the clusters spread over
+/// worker threads, a cluster failing on its own like in `try_poa_batch` without failing the others or
+/// stopping its worker.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+/// * `options` - the number of worker threads and the order clusters are taken up in
+/// * `max_len` - the largest total length of the reads of a cluster, or 0 for no limit
+///
+/// # Returns
+/// * returns the result or the failure of each cluster, in input order
+pub fn try_poa_batch_parallel(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    options: &BatchOptions,
+    max_len: usize,
+) -> Vec<Result<ClusterResult, ClusterError>> {
+    let costs: Vec<usize> = clusters
+        .iter()
+        .map(|cluster| cluster_cost(cluster.seqs.iter().map(|seq| seq.len().saturating_sub(1))))
+        .collect();
+    run_stealing(&costs, options, &[], || (), |_, i| try_cluster(&clusters[i], params, max_len))
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch_scheduled`, each cluster with the
+/// parameters of its depth, a cluster failing on its own like in `try_poa_batch`.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `schedule` - the alignment mode and scores of each depth
+/// * `max_len` - the largest total length of the reads of a cluster, or 0 for no limit
+///
+/// # Returns
+/// * returns the result or the failure of each cluster, in input order
+pub fn try_poa_batch_scheduled(
+    clusters: &[Cluster],
+    schedule: &ParamSchedule,
+    max_len: usize,
+) -> Vec<Result<ClusterResult, ClusterError>> {
+    clusters
+        .iter()
+        .map(|cluster| try_cluster(cluster, schedule.params(cluster.seqs.len()), max_len))
+        .collect()
+}
+
+// the consensus of a cluster by `try_poa`, failing on its own as described by `try_poa_batch`
+fn try_cluster(cluster: &Cluster, params: &AlignmentParams, max_len: usize) -> Result<ClusterResult, ClusterError> {
+    let result = check_cluster(cluster, max_len).and_then(|()| {
+        std::panic::catch_unwind(|| crate::try_poa(&cluster.seqs, &cluster.quals, params))
+            .unwrap_or_else(|payload| Err(panicked(payload)))
+    });
+    match result {
+        Ok(result) => Ok(ClusterResult::new(cluster, result)),
+        Err(error) => Err(ClusterError {
+            id: cluster.id.clone(),
+            error,
+        }),
+    }
+}
+
+// the error of a caught panic, with its message
+pub(crate) fn panicked(payload: Box<dyn std::any::Any + Send>) -> Error {
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    Error::Panicked { message }
+}
+
+/// The distribution of a quantity over the clusters of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Distribution {
+    /// The smallest value.
+    pub min: f64,
+    /// The median value, the mean of the two middle values for an even number of values.
+    pub median: f64,
+    /// The mean value.
+    pub mean: f64,
+    /// The largest value.
+    pub max: f64,
+}
+
+impl Distribution {
+    /// Computes the distribution of some values, all 0 without values.
+    pub fn new(mut values: Vec<f64>) -> Distribution {
+        if values.is_empty() {
+            return Distribution::default();
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let n = values.len();
+        Distribution {
+            min: values[0],
+            median: (values[(n - 1) / 2] + values[n / 2]) / 2.0,
+            mean: values.iter().sum::<f64>() / n as f64,
+            max: values[n - 1],
+        }
+    }
+}
+
+/// Aggregate statistics of a batch, e.g. for the QC dashboard of a run.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BatchSummary {
+    /// The number of clusters of the batch.
+    pub clusters: usize,
+    /// The number of clusters that failed.
+    pub failed: usize,
+    /// The number of clusters whose consensus is empty.
+    pub empty: usize,
+    /// The distribution of the number of reads of the clusters with a consensus.
+    pub depth: Distribution,
+    /// The distribution of the consensus lengths of the clusters with a consensus.
+    pub consensus_len: Distribution,
+    /// The mean identity to their consensus of all the reads of the clusters with a consensus.
+    pub mean_identity: f64,
+}
+
+impl BatchSummary {
+    /// Summarizes the results of a batch, as returned by `try_poa_batch`; the results of `poa_batch` are
+    /// summarized by `BatchSummary::from_results`.
+    pub fn new(results: &[Result<ClusterResult, ClusterError>]) -> BatchSummary {
+        let succeeded: Vec<&ClusterResult> = results.iter().filter_map(|result| result.as_ref().ok()).collect();
+        BatchSummary {
+            clusters: results.len(),
+            failed: results.len() - succeeded.len(),
+            ..BatchSummary::summarize(&succeeded)
+        }
+    }
+
+    /// Summarizes results of a batch none of which failed.
+    pub fn from_results(results: &[ClusterResult]) -> BatchSummary {
+        BatchSummary::summarize(&results.iter().collect::<Vec<_>>())
+    }
+
+    fn summarize(results: &[&ClusterResult]) -> BatchSummary {
+        let (empty, with_consensus): (Vec<&ClusterResult>, Vec<&ClusterResult>) =
+            results.iter().partition(|result| result.result.consensus.is_empty());
+        let metrics: Vec<_> = with_consensus.iter().map(|result| &result.result.metrics).collect();
+        let identities: Vec<f64> = metrics.iter().flat_map(|m| m.identity.iter().cloned()).collect();
+        BatchSummary {
+            clusters: results.len(),
+            failed: 0,
+            empty: empty.len(),
+            depth: Distribution::new(metrics.iter().map(|m| m.num_reads as f64).collect()),
+            consensus_len: Distribution::new(metrics.iter().map(|m| m.consensus_len as f64).collect()),
+            mean_identity: if identities.is_empty() {
+                0.0
+            } else {
+                identities.iter().sum::<f64>() / identities.len() as f64
+            },
+        }
+    }
+}
+
+/// Generates the consensus sequence of every cluster of a batch into caller-provided buffers, see
+/// `poa_consensus_into`. `consensuses` is resized to one buffer per cluster, in input order, reusing the
+/// buffers it already holds, and a single graph and alignment engine are reused for all clusters.
+pub fn poa_batch_consensus_into(consensuses: &mut Vec<Vec<u8>>, clusters: &[Cluster], params: &AlignmentParams) {
+    consensuses.resize_with(clusters.len(), Vec::new);
+    let mut scratch = ConsensusScratch::new();
+    for (consensus, cluster) in consensuses.iter_mut().zip(clusters) {
+        poa_consensus_with_scratch(&mut scratch, consensus, &cluster.seqs, &cluster.quals, params);
+    }
+}
+
+/// Writes the consensus of every cluster of a batch in FASTA format, one record per cluster named after its
+/// identifier, with its metadata as `key=value` fields of the header, e.g. `>umi_1 gene=IGHV3 sample=s1`.
+pub fn write_results_fasta<W: Write>(mut writer: W, results: &[ClusterResult]) -> io::Result<()> {
+    for result in results {
+        write!(writer, ">{}", result.id)?;
+        for (key, value) in &result.metadata {
+            write!(writer, " {}={}", key, value)?;
+        }
+        writer.write_all(b"\n")?;
+        writer.write_all(&result.result.consensus)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes the alignment of every cluster of a batch in MAF format, one block per cluster whose lines are
+/// prefixed with its identifier, e.g. `umi_1.Consensus` and `umi_1.seq_0`, see `Msa::write_maf_block`.
+/// Clusters without any aligned base, e.g. empty ones, have no block.
+pub fn write_results_maf<W: Write>(mut writer: W, results: &[ClusterResult]) -> io::Result<()> {
+    writeln!(writer, "##maf version=1 scoring=none")?;
+    writeln!(writer)?;
+    for result in results {
+        result.result.msa.write_maf_block(&mut writer, &result.id)?;
+    }
+    Ok(())
+}
+
+/// The consensus of one group of reads, e.g. the reads of a clonotype.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GroupConsensus {
+    /// The consensus sequence.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The Phred+33 quality of each consensus base.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub qual: Vec<u8>,
+    /// The number of reads of the group.
+    pub num_reads: usize,
+}
+
+// the sequence and qualities of a read
+type Read<'a> = (&'a [u8], &'a [u8]);
+
+/// Generates the consensus of every group of a set of reads tagged with a group identifier, such as the
+/// clonotype of immune receptor reads.
+///
+/// Groups are spread over `threads` worker threads like the clusters of `poa_batch_parallel`, largest first,
+/// each worker reusing a single graph and alignment engine for all the groups it processes. The quality of a
+/// consensus base sums the qualities of the reads agreeing with it and subtracts those of the reads
+/// disagreeing (gaps taking the lower quality of their flanking bases), within 2 and `MAX_MERGED_QUAL`; reads
+/// not reaching the base are ignored.
+///
+/// A panic of a worker, e.g. on reads the alignment engine rejects, fails all the groups; group the reads
+/// into `Cluster`s for `try_poa_batch_parallel` to fail them one by one.
+/// # Arguments
+///
+/// * `reads` - the group identifier, sequence and qualities (Phred+33) of each read, not null-terminated
+/// * `params` - the alignment mode and scores, shared by all groups
+/// * `threads` - the number of worker threads, or 0 to use the available parallelism
+///
+/// # Returns
+/// * returns the consensus of each group, keyed by group identifier
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_grouped, AlignmentParams};
+///
+///     let reads = vec![
+///         ("clone_1", &b"ACGTTGCA"[..], &b"FFFFFFFF"[..]),
+///         ("clone_1", &b"ACGTGCA"[..], &b"FFFFFFF"[..]),
+///         ("clone_2", &b"TTGACCA"[..], &b"FFFFFFF"[..]),
+///     ];
+///     let consensuses = poa_grouped(reads, &AlignmentParams::default(), 0);
+///     let clone_1 = &consensuses["clone_1"];
+/// ```
+pub fn poa_grouped<'a, I>(reads: I, params: &AlignmentParams, threads: usize) -> BTreeMap<String, GroupConsensus>
+where
+    I: IntoIterator<Item = (&'a str, &'a [u8], &'a [u8])>,
+{
+    let mut groups: BTreeMap<&str, Vec<Read>> = BTreeMap::new();
+    for (group, seq, qual) in reads {
+        if seq.len() != qual.len() {
+            panic!("Input sequence and qualities must be of same length");
+        }
+        groups.entry(group).or_default().push((seq, qual));
+    }
+    let groups: Vec<_> = groups.into_iter().collect();
+
+    // groups are taken up largest first, so that large groups do not hold up the end of the batch
+    let costs: Vec<usize> = groups
+        .iter()
+        .map(|(_, reads)| cluster_cost(reads.iter().map(|read| read.0.len())))
+        .collect();
+    let options = BatchOptions {
+        threads,
+        order: BatchOrder::LargestFirst,
+    };
+    let consensuses = run_stealing(
+        &costs,
+        &options,
+        &[],
+        || PoaGraph::new(params),
+        |graph, i| {
+            let reads = &groups[i].1;
+            graph.clear();
+            for (seq, qual) in reads {
+                graph.add(seq, qual);
+            }
+            let msa = graph.msa();
+            let quals: Vec<&[u8]> = reads.iter().map(|read| read.1).collect();
+            GroupConsensus {
+                consensus: msa.ungapped_consensus(),
+                qual: consensus_quals(&msa, &quals),
+                num_reads: reads.len(),
+            }
+        },
+    );
+    groups
+        .iter()
+        .map(|(group, _)| group.to_string())
+        .zip(consensuses)
+        .collect()
+}
+
+// the quality of every consensus base, given the qualities of the rows of the alignment
+pub(crate) fn consensus_quals(msa: &Msa, quals: &[&[u8]]) -> Vec<u8> {
+    let mut support = vec![0i64; msa.num_columns()];
+    for (row, qual) in msa.rows.iter().zip(quals.iter()) {
+        let (first, last) = match (row.iter().position(|&b| b != GAP), row.iter().rposition(|&b| b != GAP)) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let cols = column_quals(row, qual);
+        for col in first..=last {
+            let q = cols[col].saturating_sub(33) as i64;
+            if row[col] == msa.consensus[col] {
+                support[col] += q;
+            } else {
+                support[col] -= q;
+            }
+        }
+    }
+    (0..msa.num_columns())
+        .filter(|&col| msa.consensus[col] != GAP)
+        .map(|col| support[col].clamp(2, MAX_MERGED_QUAL as i64) as u8 + 33)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_cluster;
+
+    #[test]
+    fn test_poa_batch() {
+        let clusters = vec![
+            test_cluster("dna", &["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"]),
+            test_cluster("empty", &[]),
+        ];
+
+        let results = poa_batch(&clusters, &AlignmentParams::default());
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, "dna");
+        assert_eq!(results[0].result.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(results[1].id, "empty");
+        assert!(results[1].result.consensus.is_empty());
+    }
+
+    #[test]
+    fn test_poa_batch_parallel() {
+        let clusters = vec![
+            test_cluster("small", &["ACGT", "ACGT"]),
+            test_cluster("dna", &["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"]),
+            test_cluster("empty", &[]),
+        ];
+        let params = AlignmentParams::default();
+
+        for order in &[BatchOrder::Input, BatchOrder::LargestFirst] {
+            let options = BatchOptions {
+                threads: 2,
+                order: *order,
+            };
+            assert_eq!(poa_batch_parallel(&clusters, &params, &options), poa_batch(&clusters, &params));
+        }
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let result = |id: &str, rows: &[&str]| {
+            let msa = Msa {
+                names: (0..rows.len()).map(|i| i.to_string()).collect(),
+                rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+                consensus: rows.first().map_or(vec![], |row| row.as_bytes().to_vec()),
+            };
+            Ok(ClusterResult {
+                id: id.to_string(),
+                result: ConsensusResult::from_msa(msa),
+                metadata: BTreeMap::new(),
+            })
+        };
+        let results = vec![
+            result("a", &["ACGT", "ACGA"]),
+            result("b", &["ACG", "ACG", "ACG"]),
+            result("c", &[]),
+            Err(ClusterError {
+                id: "d".to_string(),
+                error: Error::Panicked {
+                    message: String::new(),
+                },
+            }),
+        ];
+
+        let summary = BatchSummary::new(&results);
+
+        assert_eq!((summary.clusters, summary.failed, summary.empty), (4, 1, 1));
+        let depth = Distribution {
+            min: 2.0,
+            median: 2.5,
+            mean: 2.5,
+            max: 3.0,
+        };
+        assert_eq!(summary.depth, depth);
+        assert_eq!((summary.consensus_len.min, summary.consensus_len.max), (3.0, 4.0));
+        assert_eq!(summary.mean_identity, (1.0 + 0.75 + 3.0) / 5.0);
+        assert_eq!(Distribution::new(vec![3.0, 1.0, 2.0]).median, 2.0);
+    }
+
+    #[test]
+    fn test_try_poa_batch_parallel() {
+        let mut unterminated = test_cluster("unterminated", &["ACGT", "ACG"]);
+        unterminated.seqs[1].pop();
+        let clusters = vec![
+            test_cluster("dna", &["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"]),
+            unterminated,
+            test_cluster("long", &["ACGTACGTAC"; 8]),
+        ];
+        let params = AlignmentParams::default();
+        let options = BatchOptions {
+            threads: 2,
+            order: BatchOrder::LargestFirst,
+        };
+
+        let results = try_poa_batch_parallel(&clusters, &params, &options, 60);
+
+        assert_eq!(results, try_poa_batch(&clusters, &params, 60));
+        assert_eq!(results, try_poa_batch_scheduled(&clusters, &ParamSchedule::new(params), 60));
+        assert!(matches!(&results[1], Err(failure) if matches!(failure.error, Error::InvalidInput { .. })));
+        assert!(matches!(&results[2], Err(failure) if matches!(failure.error, Error::GraphTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_check_cluster() {
+        let reason = |cluster: &Cluster| match check_cluster(cluster, 0) {
+            Err(Error::InvalidInput { reason }) => reason,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(check_cluster(&test_cluster("dna", &["ACGT", "ACG"]), 0), Ok(()));
+        assert_eq!(
+            check_cluster(&test_cluster("dna", &["ACGT", "ACG"]), 6),
+            Err(Error::GraphTooLarge { len: 7, max_len: 6 })
+        );
+
+        let mut bad = test_cluster("dna", &["ACGT", "ACG"]);
+        bad.quals.pop();
+        assert_eq!(reason(&bad), "2 sequences but 1 qualities");
+        let mut bad = test_cluster("dna", &["ACGT", "ACG"]);
+        bad.seqs[1].pop();
+        assert_eq!(reason(&bad), "read 1 is not null terminated");
+        let mut bad = test_cluster("dna", &["ACGT", "ACG"]);
+        bad.quals[0] = b"FF\0".to_vec();
+        assert_eq!(reason(&bad), "read 0 has 4 bases but 2 qualities");
+        assert_eq!(reason(&test_cluster("dna", &["AC\0T"])), "read 0 holds a null byte");
+        let mut bad = test_cluster("dna", &["ACGT"]);
+        bad.quals[0][2] = b' ';
+        assert_eq!(reason(&bad), "read 0 holds a quality below the Phred+33 range");
+    }
+
+    #[test]
+    fn test_poa_batch_consensus_into() {
+        let clusters = vec![
+            test_cluster("dna", &["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"]),
+            test_cluster("empty", &[]),
+        ];
+        let mut consensuses = vec![Vec::with_capacity(64), b"stale".to_vec(), b"dropped".to_vec()];
+
+        poa_batch_consensus_into(&mut consensuses, &clusters, &AlignmentParams::default());
+
+        assert_eq!(consensuses, vec![b"AATGCCCGTT".to_vec(), vec![]]);
+        assert!(consensuses[0].capacity() >= 64);
+    }
+
+    #[test]
+    fn test_write_results_maf() {
+        let mut result = ConsensusResult::default();
+        result.msa.consensus = b"ACGT".to_vec();
+        let results = vec![
+            ClusterResult::new(&test_cluster("umi_1", &[]), ConsensusResult::default()),
+            ClusterResult::new(&test_cluster("umi_2", &[]), result),
+        ];
+        let mut out = vec![];
+
+        write_results_maf(&mut out, &results).unwrap();
+
+        // the empty cluster has no block
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "##maf version=1 scoring=none\n\na\ns umi_2.Consensus 0 4 + 4 ACGT\n\n"
+        );
+    }
+
+    #[test]
+    fn test_write_results_fasta() {
+        let mut cluster = test_cluster("umi_1", &[]);
+        cluster.metadata.insert("sample".to_string(), "s1".to_string());
+        cluster.metadata.insert("gene".to_string(), "IGHV3".to_string());
+        let msa = Msa {
+            names: vec!["a".to_string()],
+            rows: vec![b"ACGT".to_vec()],
+            consensus: b"ACGT".to_vec(),
+        };
+        let results = vec![ClusterResult::new(&cluster, ConsensusResult::from_msa(msa))];
+
+        let mut fasta = vec![];
+        write_results_fasta(&mut fasta, &results).unwrap();
+
+        assert_eq!(String::from_utf8(fasta).unwrap(), ">umi_1 gene=IGHV3 sample=s1\nACGT\n");
+    }
+
+    #[test]
+    fn test_consensus_quals() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"ACGT".to_vec(), b"AC-T".to_vec(), b"-CTT".to_vec()],
+            consensus: b"ACGT".to_vec(),
+        };
+        let quals: Vec<&[u8]> = vec![b"+++5", b"+++", b"##5"];
+
+        // the read starting at the second column does not count against the first
+        assert_eq!(consensus_quals(&msa, &[quals[0], quals[1], quals[2]]), vec![b'5', b'7', b'#', b'S']);
+    }
+
+    #[test]
+    fn test_poa_grouped() {
+        let mut reads = vec![];
+        let mut quals = vec![];
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"].iter() {
+            quals.push("F".repeat(seq.len()));
+            reads.push(("b", seq.as_bytes()));
+        }
+        reads.push(("a", b"ACGT"));
+        quals.push("FFFF".to_string());
+
+        let consensuses = poa_grouped(
+            reads.iter().zip(quals.iter()).map(|(&(group, seq), qual)| (group, seq, qual.as_bytes())),
+            &AlignmentParams::default(),
+            2,
+        );
+
+        assert_eq!(consensuses.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(consensuses["a"].consensus, b"ACGT".to_vec());
+        assert_eq!(consensuses["a"].qual, b"FFFF".to_vec());
+        assert_eq!(consensuses["b"].consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(consensuses["b"].num_reads, 6);
+    }
+}
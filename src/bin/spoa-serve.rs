@@ -0,0 +1,262 @@
+//! A minimal HTTP service generating consensus sequences, for services not written in Rust.
+//!
+//! `POST /consensus` takes a JSON body `{"seqs": [...], "quals": [...], "params": {...}}`, where the
+//! qualities (Phred+33 strings) and each field of the parameters are optional, and answers with the
+//! consensus result as JSON: the consensus, its alignment and metrics. `GET /health` answers `ok`.
+//!
+//! Invalid scores or reads are answered with `400 Bad Request` and the reason. At most `MAX_CONNECTIONS`
+//! requests are served at once, further connections being answered with `503 Service Unavailable`, and a
+//! client sending nothing for `TIMEOUT` is disconnected.
+//!
+//! Usage: `spoa-serve [address]`, listening on `127.0.0.1:8080` by default.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_spoa::{check_cpu, try_poa, AlignmentParams};
+use serde::Deserialize;
+
+// the largest accepted request body
+const MAX_BODY_LEN: usize = 16 << 20;
+// the largest accepted request line and headers
+const MAX_HEADER_LEN: usize = 64 << 10;
+// the most requests served at once, each on its own thread
+const MAX_CONNECTIONS: usize = 64;
+// how long a read from or write to a client may block
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConsensusRequest {
+    seqs: Vec<String>,
+    quals: Option<Vec<String>>,
+    // the fields left out take their default, see `AlignmentParams::default`
+    #[serde(default)]
+    params: AlignmentParams,
+}
+
+// the JSON consensus result of a request body, or the message of a bad request
+fn consensus(body: &[u8]) -> Result<String, String> {
+    let request: ConsensusRequest = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    request_consensus(request)
+}
+
+fn request_consensus(request: ConsensusRequest) -> Result<String, String> {
+    let params = request.params;
+    let quals = match request.quals {
+        Some(quals) => quals,
+        // the unit weight SPOA gives to sequences without qualities
+        None => request.seqs.iter().map(|seq| "\"".repeat(seq.len())).collect(),
+    };
+    let null_terminated = |s: &String| {
+        let mut bytes = s.clone().into_bytes();
+        bytes.push(0);
+        bytes
+    };
+    let seqs: Vec<Vec<u8>> = request.seqs.iter().map(null_terminated).collect();
+    let quals: Vec<Vec<u8>> = quals.iter().map(null_terminated).collect();
+    // scores out of the range of the engine, and reads that are not null terminated or not as long as their
+    // qualities, are reported rather than truncated or aborting the thread
+    let result = try_poa(&seqs, &quals, &params).map_err(|e| e.to_string())?;
+    serde_json::to_string(&result).map_err(|e| e.to_string())
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// the method, path and body length of a request, None if its request line and headers exceed
+// `MAX_HEADER_LEN`
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, String, usize)>> {
+    let mut head = reader.take(MAX_HEADER_LEN as u64);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or("").to_string(), parts.next().unwrap_or("").to_string());
+
+    let mut content_len = 0;
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 {
+            // the headers end with an empty line, unless cut at the limit
+            return Ok(None);
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_len = value.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+    Ok(Some((method, path, content_len)))
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (method, path, content_len) = match read_head(&mut reader)? {
+        Some(head) => head,
+        None => {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "text/plain", "headers too large")
+        }
+    };
+
+    match (method.as_str(), path.as_str()) {
+        ("GET", "/health") => respond(&mut stream, "200 OK", "text/plain", "ok"),
+        ("POST", "/consensus") if content_len > MAX_BODY_LEN => {
+            respond(&mut stream, "413 Payload Too Large", "text/plain", "request body too large")
+        }
+        ("POST", "/consensus") => {
+            let mut body = vec![0u8; content_len];
+            reader.read_exact(&mut body)?;
+            match consensus(&body) {
+                Ok(json) => respond(&mut stream, "200 OK", "application/json", &json),
+                Err(message) => respond(&mut stream, "400 Bad Request", "text/plain", &message),
+            }
+        }
+        (_, "/health") | (_, "/consensus") => {
+            respond(&mut stream, "405 Method Not Allowed", "text/plain", "method not allowed")
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found"),
+    }
+}
+
+// a request being served, counted in the open connections until dropped, also when its thread panics
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn open(connections: &Arc<AtomicUsize>) -> Connection {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Connection(Arc::clone(connections))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn main() -> io::Result<()> {
+    if let Err(e) = check_cpu() {
+        eprintln!("spoa-serve: {}", e);
+        std::process::exit(1);
+    }
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let listener = TcpListener::bind(&address)?;
+    eprintln!("spoa-serve listening on {}", address);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS => {
+                let _ = stream.set_write_timeout(Some(TIMEOUT));
+                let _ = respond(&mut stream, "503 Service Unavailable", "text/plain", "too many connections");
+            }
+            Ok(stream) => {
+                let connection = Connection::open(&connections);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream) {
+                        eprintln!("spoa-serve: {}", e);
+                    }
+                    drop(connection);
+                });
+            }
+            Err(e) => eprintln!("spoa-serve: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the response of the service to a raw request
+    fn request(raw: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let server = std::thread::spawn(move || handle(stream));
+        client.write_all(raw).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap().unwrap();
+        response
+    }
+
+    fn rejected(seqs: &[&str], quals: Option<&[&str]>, params: AlignmentParams) -> String {
+        let strings = |strs: &[&str]| strs.iter().map(|s| s.to_string()).collect();
+        let request = ConsensusRequest {
+            seqs: strings(seqs),
+            quals: quals.map(strings),
+            params,
+        };
+        request_consensus(request).unwrap_err()
+    }
+
+    #[test]
+    fn test_read_head() {
+        let mut head = &b"POST /consensus HTTP/1.1\r\nHost: x\r\ncontent-length: 12\r\n\r\n{}"[..];
+        let parsed = read_head(&mut head).unwrap();
+        assert_eq!(parsed, Some(("POST".to_string(), "/consensus".to_string(), 12)));
+        // the body is left to read
+        assert_eq!(head, b"{}");
+
+        let mut long = b"GET /health HTTP/1.1\r\nX-Padding: ".to_vec();
+        long.resize(MAX_HEADER_LEN + 1, b'a');
+        long.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(read_head(&mut long.as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_handle() {
+        assert!(request(b"GET /health HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200 OK"));
+        assert!(request(b"GET /consensus HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(request(b"GET /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        let too_large = format!("POST /consensus HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN + 1);
+        assert!(request(too_large.as_bytes()).starts_with("HTTP/1.1 413"));
+        let response = request(b"POST /consensus HTTP/1.1\r\nContent-Length: 8\r\n\r\nnot json");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn test_consensus_errors() {
+        // a truncated body
+        assert!(consensus(b"{\"seqs\": [\"ACGT\"]").is_err());
+        let banded = consensus(br#"{"seqs": ["ACGT"], "params": {"alignment_type": "banded"}}"#).unwrap_err();
+        assert!(banded.contains("unknown variant `banded`"), "{}", banded);
+        let unknown = consensus(br#"{"seqs": ["ACGT"], "params": {"band_width": 10}}"#).unwrap_err();
+        assert!(unknown.contains("unknown field `band_width`"), "{}", unknown);
+        // scores the alignment engine rejects or would truncate
+        let gap = AlignmentParams {
+            gap_open: 3,
+            ..AlignmentParams::default()
+        };
+        assert!(rejected(&["ACGT", "ACGT"], None, gap).contains("gap_open 3 must not be positive"));
+        let score = AlignmentParams {
+            match_score: 200,
+            ..AlignmentParams::default()
+        };
+        assert!(rejected(&["ACGT", "ACGT"], None, score).contains("match_score 200 is out of the range"));
+        // reads that are not sequences and qualities of equal lengths
+        let count = rejected(&["ACGT", "ACGT"], Some(&["FFFF"]), AlignmentParams::default());
+        assert!(count.contains("2 sequences but 1 qualities"), "{}", count);
+        let len = rejected(&["ACGT", "ACGT"], Some(&["FFFF", "FFF"]), AlignmentParams::default());
+        assert!(len.contains("read 1 has 4 bases but 3 qualities"), "{}", len);
+        let null = rejected(&["AC\0GT"], None, AlignmentParams::default());
+        assert!(null.contains("null byte"), "{}", null);
+    }
+}
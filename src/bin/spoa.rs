@@ -0,0 +1,735 @@
+//! Command-line consensus of sequencing reads.
+//!
+//! Usage:
+//!
+//! * `spoa consensus [options] <reads>`: the consensus of all reads of a FASTA or FASTQ file (`-` for the
+//!   standard input), written as FASTA to the standard output
+//! * `spoa cluster [options] <reads>`: the reads of a FASTA or FASTQ file (`-` for the standard input) split
+//!   into clusters, e.g. the amplicons or isoforms of a mixed sample, see `poa_haplotypes`, and the consensus
+//!   of each written as FASTA to the standard output, largest cluster first
+//! * `spoa batch [options] <cluster>...`: the consensus of each cluster of reads, one FASTA or FASTQ file per
+//!   cluster named after the file, written as FASTA to the standard output, with a progress bar (requires the
+//!   `progress` feature) and an end-of-run summary on the standard error
+//! * `spoa polish [options] <draft.fa> <reads>`: the sequences of a draft FASTA file polished with the reads of
+//!   a FASTA or FASTQ file, see `poa_polish`, written as FASTA to the standard output; with `--bam <mappings>`,
+//!   the reads mapped to the drafts in a BAM file are polished with instead, see `poa_polish_bam` (requires the
+//!   `bam` feature)
+//! * `spoa bam-consensus [options] <in.bam> <out.bam>`: one consensus read per UMI family of a BAM file, see
+//!   `poa_bam_consensus` (requires the `bam` feature)
+//!
+//! Scoring options: `-p/--preset ont|hifi|illumina|protein` for the scores of a platform or `--config <file>`
+//! for parameters read from a JSON or TOML file (requires the `config` feature), which the other options
+//! override wherever they appear, `-l/--alignment-type local|global|semi-global`, `-m/--match`,
+//! `-n/--mismatch`, `-g/--gap-open`, `-e/--gap-extend`, `-q/--gap2-open`, `-c/--gap2-extend` and
+//! `--deterministic`.
+//! `consensus`, `cluster` and `batch` also take read filters, see `ReadFilter`: `--min-read-len`,
+//! `--max-read-len`, `--min-mean-qual` and `--max-n-fraction`.
+//! `cluster` also takes `--min-abundance`, the smallest fraction of the reads a cluster holds, and
+//! `--max-clusters`.
+//! `batch` also takes `--log-clusters`, logging the number of reads and the time of each cluster, and
+//! `--manifest <file>`, recording each completed cluster so that a run started again with the same manifest
+//! writes the consensus of the clusters completed before from the manifest instead of computing it again; a
+//! manifest recorded with other scoring options is discarded and every cluster computed again.
+//! A cluster that cannot be read or aligned, or whose reads are longer in total than `--max-len`, fails on
+//! its own, see `try_poa_batch`, as does a cluster whose consensus is shorter than `--min-consensus-len` or
+//! longer than `--max-consensus-len`; the failures are listed at the end of the run.
+//! `polish` also takes `--window-len` and `--min-coverage`.
+//! `bam-consensus` also takes `--min-reads`, `--umi-tag`, repeated to look up several tags in order, and
+//! `--grouped`, for inputs whose families are adjacent, which are then called without holding the whole input.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+use rust_spoa::{
+    params_key, poa_polish, try_poa, try_poa_batch, try_poa_haplotypes, write_results_fasta, AlignmentParams,
+    AlignmentType, Cluster, Error, FastqReader, FilterCounts, HaplotypeOptions, PolishOptions, PolishedDraft, Preset,
+    ReadFilter,
+};
+
+const USAGE: &str = "usage: spoa consensus [options] <reads.fa|reads.fq|->
+       spoa cluster [options] <reads.fa|reads.fq|->
+       spoa batch [options] <cluster.fa|cluster.fq>...
+       spoa polish [options] <draft.fa> <reads.fa|reads.fq|->
+       spoa polish [options] --bam <mappings.bam> <draft.fa>
+       spoa bam-consensus [options] <in.bam> <out.bam>";
+
+struct Args {
+    command: String,
+    params: AlignmentParams,
+    min_reads: usize,
+    grouped: bool,
+    filter: ReadFilter,
+    umi_tags: Vec<[u8; 2]>,
+    log_clusters: bool,
+    max_len: usize,
+    min_consensus_len: usize,
+    max_consensus_len: usize,
+    manifest: Option<String>,
+    clusters: HaplotypeOptions,
+    polish: PolishOptions,
+    bam: Option<String>,
+    paths: Vec<String>,
+}
+
+fn value<T: FromStr, I: Iterator<Item = String>>(name: &str, args: &mut I) -> Result<T, String> {
+    let value = args.next().ok_or_else(|| format!("missing value of {}", name))?;
+    value.parse().map_err(|_| format!("invalid value of {}: {}", name, value))
+}
+
+#[cfg(feature = "config")]
+fn read_config(path: &str) -> Result<AlignmentParams, String> {
+    File::open(path)
+        .and_then(AlignmentParams::from_reader)
+        .map_err(|e| format!("invalid configuration {}: {}", path, e))
+}
+
+#[cfg(not(feature = "config"))]
+fn read_config(_path: &str) -> Result<AlignmentParams, String> {
+    Err("spoa was built without the config feature".to_string())
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+    let args: Vec<String> = args.collect();
+    // the preset or configuration file applies first, so that explicit scores override it wherever they appear
+    let preset = args.iter().position(|arg| arg == "-p" || arg == "--preset");
+    let config = args.iter().position(|arg| arg == "--config");
+    let params = match (preset, config) {
+        (Some(_), Some(_)) => return Err("--preset and --config are mutually exclusive".to_string()),
+        (Some(i), None) => AlignmentParams::preset(value::<Preset, _>(&args[i], &mut args[i + 1..].iter().cloned())?),
+        (None, Some(i)) => read_config(&value::<String, _>(&args[i], &mut args[i + 1..].iter().cloned())?)?,
+        (None, None) => AlignmentParams::default(),
+    };
+    let mut args = args.into_iter();
+    let command = args.next().ok_or_else(|| USAGE.to_string())?;
+    let mut parsed = Args {
+        command,
+        params,
+        min_reads: 1,
+        grouped: false,
+        filter: ReadFilter::default(),
+        umi_tags: vec![],
+        log_clusters: false,
+        max_len: 0,
+        min_consensus_len: 0,
+        max_consensus_len: usize::MAX,
+        manifest: None,
+        clusters: HaplotypeOptions::default(),
+        polish: PolishOptions::default(),
+        bam: None,
+        paths: vec![],
+    };
+    while let Some(arg) = args.next() {
+        let params = &mut parsed.params;
+        match arg.as_str() {
+            "-l" | "--alignment-type" => {
+                params.alignment_type = match value::<String, _>(&arg, &mut args)?.as_str() {
+                    "local" => AlignmentType::Local,
+                    "global" => AlignmentType::Global,
+                    "semi-global" => AlignmentType::SemiGlobal,
+                    other => return Err(format!("unknown alignment type {}", other)),
+                }
+            }
+            "-p" | "--preset" => {
+                value::<Preset, _>(&arg, &mut args)?;
+            }
+            "--config" => {
+                value::<String, _>(&arg, &mut args)?;
+            }
+            "-m" | "--match" => params.match_score = value(&arg, &mut args)?,
+            "-n" | "--mismatch" => params.mismatch_score = value(&arg, &mut args)?,
+            "-g" | "--gap-open" => params.gap_open = value(&arg, &mut args)?,
+            "-e" | "--gap-extend" => params.gap_extend = value(&arg, &mut args)?,
+            "-q" | "--gap2-open" => params.gap2_open = value(&arg, &mut args)?,
+            "-c" | "--gap2-extend" => params.gap2_extend = value(&arg, &mut args)?,
+            "--deterministic" => params.deterministic = true,
+            "--min-abundance" => parsed.clusters.min_abundance = value(&arg, &mut args)?,
+            "--max-clusters" => parsed.clusters.max_haplotypes = value(&arg, &mut args)?,
+            "--window-len" => parsed.polish.window_len = value(&arg, &mut args)?,
+            "--min-coverage" => parsed.polish.min_coverage = value(&arg, &mut args)?,
+            "--bam" => parsed.bam = Some(value(&arg, &mut args)?),
+            "--log-clusters" => parsed.log_clusters = true,
+            "--max-len" => parsed.max_len = value(&arg, &mut args)?,
+            "--min-consensus-len" => parsed.min_consensus_len = value(&arg, &mut args)?,
+            "--max-consensus-len" => parsed.max_consensus_len = value(&arg, &mut args)?,
+            "--manifest" => parsed.manifest = Some(value(&arg, &mut args)?),
+            "--min-read-len" => parsed.filter.min_len = value(&arg, &mut args)?,
+            "--max-read-len" => parsed.filter.max_len = value(&arg, &mut args)?,
+            "--min-mean-qual" => parsed.filter.min_mean_qual = value(&arg, &mut args)?,
+            "--max-n-fraction" => parsed.filter.max_n_fraction = value(&arg, &mut args)?,
+            "--min-reads" => parsed.min_reads = value(&arg, &mut args)?,
+            "--grouped" => parsed.grouped = true,
+            "--umi-tag" => {
+                let tag: String = value(&arg, &mut args)?;
+                match tag.as_bytes() {
+                    &[a, b] => parsed.umi_tags.push([a, b]),
+                    _ => return Err(format!("invalid tag {}", tag)),
+                }
+            }
+            "-" => parsed.paths.push(arg),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => parsed.paths.push(arg),
+        }
+    }
+    parsed.params.validate().map_err(|e| e.to_string())?;
+    Ok(parsed)
+}
+
+// null-terminated sequences and their qualities
+type Reads = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+// the sequences and qualities of the records of a FASTA or FASTQ file, told apart by their first character; the
+// sequences of a FASTA file get the unit weight SPOA gives to sequences without qualities
+fn read_reads<R: BufRead>(mut reader: R) -> io::Result<Reads> {
+    let first = loop {
+        match reader.fill_buf()?.first().copied() {
+            Some(b) if b.is_ascii_whitespace() => reader.consume(1),
+            first => break first,
+        }
+    };
+    let (seqs, quals): (Vec<Vec<u8>>, Vec<Vec<u8>>) = match first {
+        Some(b'@') => FastqReader::new(reader)
+            .map(|record| record.map(|record| (record.seq, record.qual)))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .unzip(),
+        Some(b'>') | None => read_fasta(reader)?
+            .into_iter()
+            .map(|(_, seq)| {
+                let qual = vec![b'"'; seq.len()];
+                (seq, qual)
+            })
+            .unzip(),
+        Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "neither FASTA nor FASTQ data")),
+    };
+    let null_terminated = |mut s: Vec<u8>| {
+        s.push(0);
+        s
+    };
+    Ok((
+        seqs.into_iter().map(null_terminated).collect(),
+        quals.into_iter().map(null_terminated).collect(),
+    ))
+}
+
+// the reads of a file, or of the standard input for `-`
+fn read_path(path: &str) -> io::Result<Reads> {
+    match path {
+        "-" => read_reads(io::stdin().lock()),
+        _ => read_reads(BufReader::new(File::open(path)?)),
+    }
+}
+
+// the reads of the single input file of a command passing the read filters
+fn read_input(args: &Args) -> io::Result<Reads> {
+    let (seqs, quals) = match args.paths.as_slice() {
+        [path] => read_path(path)?,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE)),
+    };
+    let (seqs, quals, counts) = args.filter.apply(&seqs, &quals);
+    if counts.rejected() > 0 {
+        eprintln!("{}", filter_report(&counts));
+    }
+    Ok((seqs, quals))
+}
+
+fn filter_report(counts: &FilterCounts) -> String {
+    format!(
+        "spoa: kept {} reads, rejected {} too short, {} too long, {} with too many N and {} of low quality",
+        counts.passed, counts.too_short, counts.too_long, counts.too_many_n, counts.low_quality
+    )
+}
+
+// the name (the first word of the header) and sequence of each record of a FASTA file
+fn read_fasta<R: BufRead>(reader: R) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut records: Vec<(String, Vec<u8>)> = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('>') {
+            records.push((header.split_whitespace().next().unwrap_or("").to_string(), vec![]));
+        } else if let Some((_, seq)) = records.last_mut() {
+            seq.extend_from_slice(line.trim_end().as_bytes());
+        } else if !line.trim().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not FASTA data"));
+        }
+    }
+    Ok(records)
+}
+
+// the error of a consensus function, as an error of the command
+fn consensus_error(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+}
+
+fn consensus(args: &Args) -> io::Result<()> {
+    let (seqs, quals) = read_input(args)?;
+    let result = try_poa(&seqs, &quals, &args.params).map_err(consensus_error)?;
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, ">consensus reads={}", seqs.len())?;
+    stdout.write_all(&result.consensus)?;
+    writeln!(stdout)
+}
+
+fn cluster(args: &Args) -> io::Result<()> {
+    let (seqs, quals) = read_input(args)?;
+    let haplotypes = try_poa_haplotypes(&seqs, &quals, &args.params, &args.clusters).map_err(consensus_error)?;
+    let mut stdout = io::stdout().lock();
+    for (i, haplotype) in haplotypes.iter().enumerate() {
+        writeln!(
+            stdout,
+            ">cluster_{} size={} abundance={:.4}",
+            i + 1,
+            haplotype.reads.len(),
+            haplotype.abundance
+        )?;
+        stdout.write_all(&haplotype.result.consensus)?;
+        writeln!(stdout)?;
+    }
+    eprintln!("spoa: {} reads in {} clusters", seqs.len(), haplotypes.len());
+    Ok(())
+}
+
+#[cfg(feature = "bam")]
+fn polish_bam(path: &str, drafts: &[(String, Vec<u8>)], args: &Args) -> io::Result<Vec<PolishedDraft>> {
+    let drafts: Vec<(&str, &[u8])> = drafts.iter().map(|(name, seq)| (name.as_str(), &seq[..])).collect();
+    rust_spoa::poa_polish_bam(path, &drafts, &args.params, &args.polish)
+}
+
+#[cfg(not(feature = "bam"))]
+fn polish_bam(_path: &str, _drafts: &[(String, Vec<u8>)], _args: &Args) -> io::Result<Vec<PolishedDraft>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "spoa was built without the bam feature"))
+}
+
+fn polish(args: &Args) -> io::Result<()> {
+    match (args.paths.len(), &args.bam) {
+        (1, Some(_)) | (2, None) => {}
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE)),
+    }
+    let drafts = read_fasta(BufReader::new(File::open(&args.paths[0])?))?;
+    let polished = match &args.bam {
+        Some(bam) => polish_bam(bam, &drafts, args)?,
+        None => {
+            let (seqs, quals) = read_path(&args.paths[1])?;
+            let draft_seqs: Vec<&[u8]> = drafts.iter().map(|(_, seq)| &seq[..]).collect();
+            poa_polish(&draft_seqs, &seqs, &quals, &args.params, &args.polish)
+        }
+    };
+    let mut stdout = io::stdout().lock();
+    for ((name, _), draft) in drafts.iter().zip(polished.iter()) {
+        eprintln!("spoa: {}: polished {} of {} windows", name, draft.polished_windows, draft.windows);
+        writeln!(stdout, ">{}", name)?;
+        stdout.write_all(&draft.sequence)?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+// the progress bar of a batch, drawn on the standard error when it is a terminal
+#[cfg(feature = "progress")]
+struct Progress(indicatif::ProgressBar);
+
+#[cfg(feature = "progress")]
+impl Progress {
+    fn new(len: usize) -> Progress {
+        let bar = indicatif::ProgressBar::new(len as u64);
+        let template = "{elapsed_precise} [{bar:40}] {pos}/{len} clusters, {eta} left";
+        if let Ok(style) = indicatif::ProgressStyle::with_template(template) {
+            bar.set_style(style);
+        }
+        Progress(bar)
+    }
+
+    fn inc(&self) {
+        self.0.inc(1)
+    }
+
+    // a line on the standard error, above the bar
+    fn log(&self, message: &str) {
+        self.0.suspend(|| eprintln!("{}", message))
+    }
+
+    fn finish(&self) {
+        self.0.finish_and_clear()
+    }
+}
+
+#[cfg(not(feature = "progress"))]
+struct Progress;
+
+#[cfg(not(feature = "progress"))]
+impl Progress {
+    fn new(_len: usize) -> Progress {
+        Progress
+    }
+
+    fn inc(&self) {}
+
+    fn log(&self, message: &str) {
+        eprintln!("{}", message)
+    }
+
+    fn finish(&self) {}
+}
+
+// the totals of a batch, reported at the end of the run
+#[derive(Default)]
+struct Summary {
+    clusters: usize,
+    resumed: usize,
+    // the path of each failed cluster, with the reason of its failure
+    failures: Vec<String>,
+    reads: FilterCounts,
+    coverage: f64,
+    consensus_len: usize,
+}
+
+impl Summary {
+    // records a failed cluster, logged as it fails and again at the end of the run
+    fn fail(&mut self, progress: &Progress, path: &str, reason: &str) {
+        let failure = format!("{}: {}", path, reason);
+        progress.log(&format!("spoa: {}", failure));
+        self.failures.push(failure);
+    }
+
+    fn report(&self, seconds: f64) -> String {
+        let succeeded = (self.clusters - self.failures.len()).max(1) as f64;
+        let processed = format!(
+            "spoa: processed {} clusters in {:.1}s, {} resumed, {} failed",
+            self.clusters,
+            seconds,
+            self.resumed,
+            self.failures.len()
+        );
+        format!(
+            "{}, mean depth {:.1}, mean consensus length {:.1}",
+            processed,
+            self.coverage / succeeded,
+            self.consensus_len as f64 / succeeded
+        )
+    }
+}
+
+// the cluster of the reads of a file, identified by the file name without its extension
+fn read_cluster(path: &str) -> io::Result<Cluster> {
+    let (seqs, quals) = read_reads(BufReader::new(File::open(path)?))?;
+    if seqs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no reads"));
+    }
+    let id = match Path::new(path).file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => path.to_string(),
+    };
+    let metadata = vec![("reads".to_string(), seqs.len().to_string())].into_iter().collect();
+    Ok(Cluster {
+        id,
+        seqs,
+        quals,
+        metadata,
+    })
+}
+
+// a cluster completed by an earlier run, as recorded in the manifest of a batch
+struct Completed {
+    id: String,
+    num_reads: usize,
+    mean_coverage: f64,
+    consensus: String,
+}
+
+// the first line of the manifest of a batch, identifying the scoring parameters its clusters were computed with
+fn manifest_header(params: &AlignmentParams) -> String {
+    format!("#params\t{:016x}\n", params_key(params))
+}
+
+// the clusters recorded in the manifest of a batch, one tab-separated line per cluster with the path of its
+// file, its id, number of reads, mean depth and consensus, keyed by path, after the header of the parameters;
+// a missing manifest, or one recorded with other parameters, records none
+fn read_manifest(path: &str, params: &AlignmentParams) -> io::Result<HashMap<String, Completed>> {
+    let manifest = match fs::read_to_string(path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let manifest = match manifest.strip_prefix(&manifest_header(params)) {
+        Some(manifest) => manifest,
+        None => {
+            if !manifest.is_empty() {
+                eprintln!("spoa: {} was recorded with other parameters, computing every cluster again", path);
+            }
+            return Ok(HashMap::new());
+        }
+    };
+    let mut completed = HashMap::new();
+    // a last line without its line break was cut by the interruption of a run
+    for line in manifest.split_inclusive('\n').filter(|line| line.ends_with('\n')) {
+        let fields: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+        if let [path, id, num_reads, mean_coverage, consensus] = fields[..] {
+            if let (Ok(num_reads), Ok(mean_coverage)) = (num_reads.parse(), mean_coverage.parse()) {
+                let id = id.to_string();
+                let consensus = consensus.to_string();
+                completed.insert(path.to_string(), Completed { id, num_reads, mean_coverage, consensus });
+            }
+        }
+    }
+    Ok(completed)
+}
+
+fn write_manifest_line<W: Write>(writer: &mut W, path: &str, completed: &Completed) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}",
+        path, completed.id, completed.num_reads, completed.mean_coverage, completed.consensus
+    )?;
+    writer.flush()
+}
+
+// the manifest of a batch opened for appending, rewritten first without a line cut by an interrupted run
+fn open_manifest(path: &str, params: &AlignmentParams, completed: &HashMap<String, Completed>) -> io::Result<File> {
+    let tmp = format!("{}.tmp", path);
+    let mut writer = io::BufWriter::new(File::create(&tmp)?);
+    writer.write_all(manifest_header(params).as_bytes())?;
+    for (cluster_path, cluster) in completed {
+        write_manifest_line(&mut writer, cluster_path, cluster)?;
+    }
+    drop(writer);
+    fs::rename(&tmp, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+fn batch(args: &Args) -> io::Result<()> {
+    if args.paths.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE));
+    }
+    let start = Instant::now();
+    let progress = Progress::new(args.paths.len());
+    let mut summary = Summary::default();
+    let mut completed = match &args.manifest {
+        Some(path) => read_manifest(path, &args.params)?,
+        None => HashMap::new(),
+    };
+    let mut manifest = match &args.manifest {
+        Some(path) => Some(open_manifest(path, &args.params, &completed)?),
+        None => None,
+    };
+    let mut stdout = io::stdout().lock();
+    for path in &args.paths {
+        let cluster_start = Instant::now();
+        summary.clusters += 1;
+        if let Some(cluster) = completed.remove(path) {
+            summary.resumed += 1;
+            summary.coverage += cluster.mean_coverage;
+            summary.consensus_len += cluster.consensus.len();
+            writeln!(stdout, ">{} reads={}\n{}", cluster.id, cluster.num_reads, cluster.consensus)?;
+            progress.inc();
+            continue;
+        }
+        let result = read_cluster(path).map_err(|e| e.to_string()).and_then(|mut cluster| {
+            let (seqs, quals, counts) = args.filter.apply(&cluster.seqs, &cluster.quals);
+            summary.reads += counts;
+            cluster.metadata.insert("reads".to_string(), seqs.len().to_string());
+            cluster.seqs = seqs;
+            cluster.quals = quals;
+            let mut results = try_poa_batch(std::slice::from_ref(&cluster), &args.params, args.max_len);
+            let result = results.pop().unwrap().map_err(|failure| failure.error.to_string())?;
+            let expected_len = args.min_consensus_len..=args.max_consensus_len;
+            result.result.check_len(expected_len).map_err(|e| e.to_string())?;
+            Ok(result)
+        });
+        match result {
+            Ok(result) if !result.result.consensus.is_empty() => {
+                let metrics = &result.result.metrics;
+                summary.coverage += metrics.mean_coverage;
+                summary.consensus_len += metrics.consensus_len;
+                if args.log_clusters {
+                    progress.log(&format!(
+                        "spoa: {}: {} reads, consensus of {} bases in {:.3}s",
+                        result.id,
+                        metrics.num_reads,
+                        metrics.consensus_len,
+                        cluster_start.elapsed().as_secs_f64()
+                    ));
+                }
+                write_results_fasta(&mut stdout, std::slice::from_ref(&result))?;
+                if let Some(manifest) = manifest.as_mut() {
+                    let cluster = Completed {
+                        id: result.id.clone(),
+                        num_reads: metrics.num_reads,
+                        mean_coverage: metrics.mean_coverage,
+                        consensus: String::from_utf8_lossy(&result.result.consensus).into_owned(),
+                    };
+                    // the consensus is written out before the cluster is recorded as completed
+                    stdout.flush()?;
+                    write_manifest_line(manifest, path, &cluster)?;
+                }
+            }
+            Ok(_) => summary.fail(&progress, path, "empty consensus"),
+            Err(e) => summary.fail(&progress, path, &e),
+        }
+        progress.inc();
+    }
+    progress.finish();
+    eprintln!("{}", summary.report(start.elapsed().as_secs_f64()));
+    if summary.reads.rejected() > 0 {
+        eprintln!("{}", filter_report(&summary.reads));
+    }
+    for failure in &summary.failures {
+        eprintln!("spoa: failed {}", failure);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bam")]
+fn bam_consensus(args: &Args) -> io::Result<()> {
+    let (input, output) = match args.paths.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE)),
+    };
+    let mut options = rust_spoa::BamConsensusOptions {
+        min_reads: args.min_reads,
+        grouped: args.grouped,
+        params: args.params,
+        ..Default::default()
+    };
+    if !args.umi_tags.is_empty() {
+        options.umi_tags = args.umi_tags.clone();
+    }
+    let written = rust_spoa::poa_bam_consensus(input, output, &options)?;
+    eprintln!("spoa: wrote {} consensus reads", written);
+    Ok(())
+}
+
+#[cfg(not(feature = "bam"))]
+fn bam_consensus(_args: &Args) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "spoa was built without the bam feature"))
+}
+
+fn main() {
+    if let Err(e) = rust_spoa::check_cpu() {
+        eprintln!("spoa: {}", e);
+        std::process::exit(1);
+    }
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("spoa: {}", message);
+            std::process::exit(2);
+        }
+    };
+    let result = match args.command.as_str() {
+        "consensus" => consensus(&args),
+        "cluster" => cluster(&args),
+        "batch" => batch(&args),
+        "polish" => polish(&args),
+        "bam-consensus" => bam_consensus(&args),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("spoa: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn rejected(args: &[&str]) -> String {
+        parse(args).err().expect("the arguments are rejected")
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["consensus", "-p", "ont", "-m", "6", "--min-read-len", "100", "reads.fq"]).unwrap();
+        assert_eq!(args.command, "consensus");
+        // explicit scores override the preset wherever they appear
+        assert_eq!(
+            args.params,
+            AlignmentParams {
+                match_score: 6,
+                ..AlignmentParams::preset(Preset::Ont)
+            }
+        );
+        assert_eq!(args.filter.min_len, 100);
+        assert_eq!(args.paths, vec!["reads.fq".to_string()]);
+
+        let args = parse(&["bam-consensus", "--umi-tag", "RX", "--umi-tag", "MI", "--grouped", "-", "out.bam"]).unwrap();
+        assert_eq!(args.umi_tags, vec![*b"RX", *b"MI"]);
+        assert!(args.grouped);
+        assert_eq!(args.paths, vec!["-".to_string(), "out.bam".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert_eq!(rejected(&[]), USAGE);
+        assert_eq!(rejected(&["consensus", "--frobnicate"]), "unknown option --frobnicate");
+        assert_eq!(rejected(&["consensus", "-m"]), "missing value of -m");
+        assert_eq!(rejected(&["consensus", "-m", "five"]), "invalid value of -m: five");
+        assert_eq!(rejected(&["consensus", "-l", "banded"]), "unknown alignment type banded");
+        assert_eq!(rejected(&["consensus", "--umi-tag", "UMI"]), "invalid tag UMI");
+        assert!(rejected(&["consensus", "-p", "sanger"]).contains("sanger"));
+        assert_eq!(
+            rejected(&["consensus", "-p", "ont", "--config", "params.toml"]),
+            "--preset and --config are mutually exclusive"
+        );
+        // scores the alignment engine would truncate or reject
+        assert!(rejected(&["consensus", "-m", "200"]).contains("match_score 200"));
+        assert!(rejected(&["consensus", "-g", "3"]).contains("gap_open 3 must not be positive"));
+    }
+
+    #[test]
+    fn test_read_reads() {
+        let (seqs, quals) = read_reads(&b">a\nACG\nT\n\n>b\nAC\n"[..]).unwrap();
+        assert_eq!(seqs, vec![b"ACGT\0".to_vec(), b"AC\0".to_vec()]);
+        assert_eq!(quals, vec![b"\"\"\"\"\0".to_vec(), b"\"\"\0".to_vec()]);
+
+        let (seqs, quals) = read_reads(&b"@a\nACGT\n+\nFF#F\n"[..]).unwrap();
+        assert_eq!((seqs, quals), (vec![b"ACGT\0".to_vec()], vec![b"FF#F\0".to_vec()]));
+    }
+
+    #[test]
+    fn test_read_reads_errors() {
+        let kind = |data: &[u8]| read_reads(data).unwrap_err().kind();
+        assert_eq!(kind(b"@a\nACGT\n+\n"), io::ErrorKind::InvalidData);
+        assert_eq!(kind(b"@a\nACGT\n+\nFF\n"), io::ErrorKind::InvalidData);
+        assert_eq!(kind(b"ACGT\n"), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        let path = std::env::temp_dir().join(format!("spoa-manifest-{}.tsv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let params = AlignmentParams::default();
+        assert!(read_manifest(path, &params).unwrap().is_empty());
+
+        let completed = Completed {
+            id: "umi_1".to_string(),
+            num_reads: 3,
+            mean_coverage: 2.5,
+            consensus: "ACGT".to_string(),
+        };
+        let mut manifest = manifest_header(&params).into_bytes();
+        write_manifest_line(&mut manifest, "umi_1.fq", &completed).unwrap();
+        // a line cut by an interrupted run, and a malformed one, are skipped
+        manifest.extend_from_slice(b"umi_2.fq\tumi_2\tthree\t1\tAC\numi_3.fq\tumi_3\t2");
+        fs::write(path, &manifest).unwrap();
+
+        let read = read_manifest(path, &params).unwrap();
+        // the clusters were computed with other scores
+        let rescored = read_manifest(path, &AlignmentParams { match_score: 3, ..params }).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(rescored.is_empty());
+        assert_eq!(read.len(), 1);
+        let cluster = &read["umi_1.fq"];
+        assert_eq!((cluster.id.as_str(), cluster.num_reads, cluster.consensus.as_str()), ("umi_1", 3, "ACGT"));
+        assert_eq!(cluster.mean_coverage, 2.5);
+    }
+}
@@ -0,0 +1,250 @@
+//! Bubbles of a partial order graph: sites where reads leave the consensus path along alternative branches.
+use std::collections::{BTreeMap, HashMap};
+
+use crate::graph::PoaGraph;
+
+/// One branch of a bubble, spelling the bases between its two consensus anchors.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BubbleBranch {
+    /// The bases of the branch, empty for a branch skipping the consensus bases (a deletion).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub seq: Vec<u8>,
+    /// The indices (in graph order) of the reads taking the branch.
+    pub reads: Vec<usize>,
+}
+
+impl BubbleBranch {
+    /// Returns the number of reads taking the branch.
+    pub fn support(&self) -> usize {
+        self.reads.len()
+    }
+}
+
+/// A site where reads leave the consensus path after one consensus base and rejoin it at a later one.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Bubble {
+    /// The 0-based consensus position of the base before the bubble.
+    pub start: usize,
+    /// The 0-based consensus position of the base after the bubble.
+    pub end: usize,
+    /// The branches of the bubble: the consensus branch first, then the alternative branches, best supported
+    /// first (ties by sequence).
+    pub branches: Vec<BubbleBranch>,
+}
+
+impl Bubble {
+    /// Returns the number of reads spanning the bubble along any of its branches.
+    pub fn depth(&self) -> usize {
+        self.branches.iter().map(|branch| branch.support()).sum()
+    }
+}
+
+/// The support below which a bubble branch is collapsed before the consensus is extracted, see
+/// `PoaGraph::popped_consensus`. A branch is kept if it is taken by at least `min_reads` reads and by at
+/// least `min_fraction` of the reads spanning its bubble.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BubblePopping {
+    /// The minimum number of reads taking a branch.
+    pub min_reads: usize,
+    /// The minimum fraction of the reads spanning the bubble that take a branch.
+    pub min_fraction: f64,
+}
+
+impl Default for BubblePopping {
+    fn default() -> BubblePopping {
+        BubblePopping {
+            min_reads: 2,
+            min_fraction: 0.2,
+        }
+    }
+}
+
+impl BubblePopping {
+    fn keeps(&self, branch: &BubbleBranch, depth: usize) -> bool {
+        branch.support() >= self.min_reads && branch.support() as f64 >= self.min_fraction * depth as f64
+    }
+}
+
+impl PoaGraph {
+    /// Returns the consensus after collapsing the bubble branches below the `popping` thresholds. The
+    /// consensus keeps its branch through a bubble unless that branch is collapsed, in which case it takes
+    /// the best supported branch left, so that a consensus following a few heavily weighted reads through a
+    /// noisy site falls back to the branch most reads support.
+    pub fn popped_consensus(&self, popping: &BubblePopping) -> Vec<u8> {
+        let topology = self.topology();
+        let consensus = self.consensus_path();
+        let bubbles = find_bubbles(&topology.labels, &consensus, &topology.paths, 1);
+        pop_bubbles(&topology.labels, &consensus, &bubbles, popping)
+    }
+
+    /// Returns the bubbles of the graph having at least one alternative branch taken by `min_support` reads or
+    /// more, ordered by consensus position. Alternative branches below `min_support` are left out; the
+    /// consensus branch is always reported, with the reads following the consensus across the bubble.
+    pub fn bubbles(&self, min_support: usize) -> Vec<Bubble> {
+        let topology = self.topology();
+        let consensus = self.consensus_path();
+        find_bubbles(&topology.labels, &consensus, &topology.paths, min_support)
+    }
+}
+
+/// Returns the bubbles of a graph given the base of each node, the consensus path and the read paths, see
+/// `PoaGraph::bubbles`.
+pub(crate) fn find_bubbles(
+    labels: &[u8],
+    consensus: &[u32],
+    paths: &[Vec<u32>],
+    min_support: usize,
+) -> Vec<Bubble> {
+    let positions: HashMap<u32, usize> = consensus.iter().enumerate().map(|(pos, &id)| (id, pos)).collect();
+
+    // the alternative branches by anchors and sequence, and the runs of consecutive consensus bases per read
+    let mut alternatives: BTreeMap<(usize, usize), BTreeMap<Vec<u8>, Vec<usize>>> = BTreeMap::new();
+    let mut runs: Vec<Vec<(usize, usize)>> = vec![vec![]; paths.len()];
+    for (read, path) in paths.iter().enumerate() {
+        let mut previous: Option<usize> = None;
+        let mut detour = vec![];
+        for id in path {
+            let pos = match positions.get(id) {
+                Some(&pos) => pos,
+                None => {
+                    detour.push(labels[*id as usize]);
+                    continue;
+                }
+            };
+            match previous {
+                Some(prev) if detour.is_empty() && pos == prev + 1 => {
+                    runs[read].last_mut().unwrap().1 = pos;
+                }
+                Some(prev) => {
+                    alternatives.entry((prev, pos)).or_default().entry(detour.clone()).or_default().push(read);
+                    runs[read].push((pos, pos));
+                }
+                None => runs[read].push((pos, pos)),
+            }
+            previous = Some(pos);
+            detour.clear();
+        }
+    }
+
+    let mut bubbles = vec![];
+    for ((start, end), branches) in alternatives {
+        let mut branches: Vec<BubbleBranch> = branches
+            .into_iter()
+            .filter(|(_, reads)| reads.len() >= min_support)
+            .map(|(seq, reads)| BubbleBranch { seq, reads })
+            .collect();
+        if branches.is_empty() {
+            continue;
+        }
+        branches.sort_by(|a, b| b.support().cmp(&a.support()).then(a.seq.cmp(&b.seq)));
+        let reference = BubbleBranch {
+            seq: consensus[start + 1..end].iter().map(|&id| labels[id as usize]).collect(),
+            reads: (0..paths.len())
+                .filter(|&read| runs[read].iter().any(|&(first, last)| first <= start && last >= end))
+                .collect(),
+        };
+        branches.insert(0, reference);
+        bubbles.push(Bubble { start, end, branches });
+    }
+    bubbles
+}
+
+/// Spells the consensus path, taking the best supported branch kept by `popping` through each bubble whose
+/// consensus branch is collapsed; bubbles overlapping a replaced one are left as they are.
+pub(crate) fn pop_bubbles(
+    labels: &[u8],
+    consensus: &[u32],
+    bubbles: &[Bubble],
+    popping: &BubblePopping,
+) -> Vec<u8> {
+    let mut out = vec![];
+    let mut next = 0;
+    for bubble in bubbles {
+        if bubble.start < next {
+            continue;
+        }
+        let depth = bubble.depth();
+        if popping.keeps(&bubble.branches[0], depth) {
+            continue;
+        }
+        let best = bubble.branches[1..]
+            .iter()
+            .filter(|branch| popping.keeps(branch, depth))
+            .max_by(|a, b| a.support().cmp(&b.support()).then(b.seq.cmp(&a.seq)));
+        if let Some(best) = best {
+            out.extend(consensus[next..=bubble.start].iter().map(|&id| labels[id as usize]));
+            out.extend_from_slice(&best.seq);
+            next = bubble.end;
+        }
+    }
+    out.extend(consensus[next..].iter().map(|&id| labels[id as usize]));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_bubbles() {
+        // consensus ACGTA on nodes 0-4, a C/T substitution on node 5 and a bypass of G (a deletion)
+        let labels = b"ACGTAT".to_vec();
+        let consensus = vec![0, 1, 2, 3, 4];
+        let paths = vec![
+            vec![0, 1, 2, 3, 4],
+            vec![0, 1, 2, 3, 4],
+            vec![0, 5, 2, 3, 4],
+            vec![0, 5, 2, 3, 4],
+            vec![0, 1, 3, 4],
+            vec![1, 2, 3],
+        ];
+
+        let bubbles = find_bubbles(&labels, &consensus, &paths, 1);
+
+        assert_eq!(bubbles.len(), 2);
+        assert_eq!((bubbles[0].start, bubbles[0].end), (0, 2));
+        assert_eq!(bubbles[0].branches[0], BubbleBranch { seq: b"C".to_vec(), reads: vec![0, 1] });
+        assert_eq!(bubbles[0].branches[1], BubbleBranch { seq: b"T".to_vec(), reads: vec![2, 3] });
+        assert_eq!(bubbles[0].depth(), 4);
+        assert_eq!((bubbles[1].start, bubbles[1].end), (1, 3));
+        assert_eq!(bubbles[1].branches[0].reads, vec![0, 1, 5]);
+        assert_eq!(bubbles[1].branches[1], BubbleBranch { seq: vec![], reads: vec![4] });
+
+        let supported = find_bubbles(&labels, &consensus, &paths, 2);
+
+        assert_eq!(supported.len(), 1);
+        assert_eq!(supported[0].branches.len(), 2);
+    }
+
+    #[test]
+    fn test_pop_bubbles() {
+        // consensus ACGTA, its C taken by 1 heavily weighted read and the alternative T by 3 reads
+        let labels = b"ACGTAT".to_vec();
+        let consensus = vec![0, 1, 2, 3, 4];
+        let paths = vec![vec![0, 1, 2, 3, 4], vec![0, 5, 2, 3, 4], vec![0, 5, 2, 3, 4], vec![0, 5, 2, 3, 4]];
+        let bubbles = find_bubbles(&labels, &consensus, &paths, 1);
+
+        let popped = pop_bubbles(&labels, &consensus, &bubbles, &BubblePopping::default());
+        let kept = pop_bubbles(&labels, &consensus, &bubbles, &BubblePopping { min_reads: 1, min_fraction: 0.0 });
+
+        assert_eq!(popped, b"ATGTA".to_vec());
+        assert_eq!(kept, b"ACGTA".to_vec());
+    }
+
+    #[test]
+    fn test_bubbles() {
+        let mut graph = PoaGraph::new(&crate::AlignmentParams::default());
+        for seq in ["ACGTTGCA", "ACGTTGCA", "ACGTTGCA", "ACGATGCA", "ACGATGCA"].iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+
+        let bubbles = graph.bubbles(2);
+
+        assert_eq!(bubbles.len(), 1);
+        assert_eq!((bubbles[0].start, bubbles[0].end), (2, 4));
+        assert_eq!(bubbles[0].branches[0].seq, b"T".to_vec());
+        assert_eq!(bubbles[0].branches[1], BubbleBranch { seq: b"A".to_vec(), reads: vec![3, 4] });
+    }
+}
@@ -0,0 +1,265 @@
+//! Caching of consensus results keyed by a hash of their inputs, so that re-runs skip computed clusters.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::batch::{Cluster, ClusterResult};
+use crate::msa::Msa;
+use crate::params::{AlignmentParams, SoftMask};
+use crate::result::ConsensusResult;
+
+// bumped whenever the key derivation or the result computation changes, invalidating cached results
+const CACHE_VERSION: u64 = 1;
+
+// the 64-bit FNV-1a hash, stable across platforms and Rust versions
+pub(crate) struct Fnv64(pub(crate) u64);
+
+impl Fnv64 {
+    pub(crate) fn new() -> Fnv64 {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+/// Returns the cache key of a consensus: a hash of the sequences, qualities and alignment parameters that is
+/// stable across platforms, so that keys computed by different runs and hosts match.
+pub fn cache_key(seqs: &[Vec<u8>], quals: &[Vec<u8>], params: &AlignmentParams) -> u64 {
+    let mut hash = Fnv64::new();
+    hash.write_u64(CACHE_VERSION);
+    for seqs in [seqs, quals].iter() {
+        hash.write_u64(seqs.len() as u64);
+        for seq in seqs.iter() {
+            hash.write_u64(seq.len() as u64);
+            hash.write(seq);
+        }
+    }
+    write_params(&mut hash, params);
+    hash.0
+}
+
+// hashes the alignment parameters that change a consensus
+fn write_params(hash: &mut Fnv64, params: &AlignmentParams) {
+    let scores = [
+        params.alignment_type as i32,
+        params.match_score,
+        params.mismatch_score,
+        params.gap_open,
+        params.gap_extend,
+        params.gap2_open,
+        params.gap2_extend,
+        params.deterministic as i32,
+        params.collapse_duplicates as i32,
+    ];
+    for score in scores.iter() {
+        hash.write(&score.to_le_bytes());
+    }
+    // the later options are hashed only when set, keeping the keys of results cached without them
+    if let Some(score) = params.wildcard_score {
+        hash.write(b"N");
+        hash.write(&score.to_le_bytes());
+    }
+    match params.soft_mask {
+        SoftMask::Preserve => {}
+        SoftMask::Fold => hash.write(b"fold"),
+        SoftMask::DownWeight { max_qual } => {
+            hash.write(b"down-weight");
+            hash.write(&[max_qual]);
+        }
+    }
+}
+
+/// Returns a hash of the alignment parameters alone, stable across platforms like `cache_key`, e.g. to tell
+/// whether the results recorded by an earlier run were computed with the same parameters.
+pub fn params_key(params: &AlignmentParams) -> u64 {
+    let mut hash = Fnv64::new();
+    hash.write_u64(CACHE_VERSION);
+    write_params(&mut hash, params);
+    hash.0
+}
+
+/// A store of consensus results keyed by `cache_key`.
+pub trait ResultCache {
+    /// Returns the result stored under `key`, if any.
+    fn get(&mut self, key: u64) -> io::Result<Option<ConsensusResult>>;
+
+    /// Stores a result under `key`, replacing any previous one.
+    fn put(&mut self, key: u64, result: &ConsensusResult) -> io::Result<()>;
+}
+
+/// A cache holding results in memory, e.g. across the batches of one run.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCache {
+    results: HashMap<u64, ConsensusResult>,
+}
+
+impl MemoryCache {
+    /// Creates an empty cache.
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+
+    /// Returns the number of cached results.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Returns true if the cache holds no result.
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+impl ResultCache for MemoryCache {
+    fn get(&mut self, key: u64) -> io::Result<Option<ConsensusResult>> {
+        Ok(self.results.get(&key).cloned())
+    }
+
+    fn put(&mut self, key: u64, result: &ConsensusResult) -> io::Result<()> {
+        self.results.insert(key, result.clone());
+        Ok(())
+    }
+}
+
+/// A cache holding results on disk, one file per result, so that they survive pipeline restarts.
+///
+/// Each result is stored as its alignment, in the FASTA format of `Msa::write_fasta` with the consensus, in
+/// a file named after its key; the consensus and metrics are derived from the alignment when read back.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// Opens a cache in `dir`, creating the directory if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<DiskCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(DiskCache { dir })
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.fa", key))
+    }
+}
+
+impl ResultCache for DiskCache {
+    fn get(&mut self, key: u64) -> io::Result<Option<ConsensusResult>> {
+        let file = match File::open(self.path(key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let msa = Msa::read_fasta(BufReader::new(file))?;
+        Ok(Some(ConsensusResult::from_msa(msa)))
+    }
+
+    fn put(&mut self, key: u64, result: &ConsensusResult) -> io::Result<()> {
+        // written aside and renamed, so that an interrupted write never leaves a truncated result
+        let path = self.path(key);
+        let tmp = path.with_extension("fa.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        result.msa.write_fasta(&mut writer, true)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, &path)
+    }
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch`, taking the results of clusters
+/// already computed with the same inputs and parameters from `cache` and storing the others into it.
+///
+/// Panics like `poa_batch` on a cluster the alignment engine rejects, failing the whole batch; the results
+/// already stored stay in the cache, so that a rerun without the cluster takes them up.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+/// * `cache` - the store of results, e.g. a `MemoryCache` or a `DiskCache`
+///
+/// # Returns
+/// * returns one result per cluster, in input order, or the first error of the cache
+pub fn poa_batch_cached<C: ResultCache + ?Sized>(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    cache: &mut C,
+) -> io::Result<Vec<ClusterResult>> {
+    let mut results = Vec::with_capacity(clusters.len());
+    for cluster in clusters {
+        let key = cache_key(&cluster.seqs, &cluster.quals, params);
+        let result = match cache.get(key)? {
+            Some(result) => result,
+            None => {
+                let result = crate::poa(&cluster.seqs, &cluster.quals, params);
+                cache.put(key, &result)?;
+                result
+            }
+        };
+        results.push(ClusterResult::new(cluster, result));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_cluster;
+
+    #[test]
+    fn test_cache_key() {
+        let params = AlignmentParams::default();
+        let a = test_cluster("a", &["ACGT", "ACG"]);
+        let b = test_cluster("b", &["ACG", "TACG"]);
+
+        let key = cache_key(&a.seqs, &a.quals, &params);
+
+        assert_eq!(key, cache_key(&a.seqs, &a.quals, &params));
+        assert_ne!(key, cache_key(&b.seqs, &b.quals, &params));
+        let rescored = AlignmentParams { match_score: 4, ..params };
+        assert_ne!(key, cache_key(&a.seqs, &a.quals, &rescored));
+        assert_eq!(params_key(&params), params_key(&AlignmentParams::default()));
+        assert_ne!(params_key(&params), params_key(&rescored));
+    }
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = std::env::temp_dir().join(format!("rust_spoa_cache_{}", std::process::id()));
+        let mut cache = DiskCache::new(&dir).unwrap();
+        let msa = Msa {
+            names: vec!["seq_0".to_string(), "seq_1".to_string()],
+            rows: vec![b"AC-GT".to_vec(), b"ACTGT".to_vec()],
+            consensus: b"AC-GT".to_vec(),
+        };
+        let result = ConsensusResult::from_msa(msa);
+
+        assert_eq!(cache.get(7).unwrap(), None);
+        cache.put(7, &result).unwrap();
+        assert_eq!(cache.get(7).unwrap(), Some(result));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_poa_batch_cached() {
+        let clusters = vec![test_cluster("a", &["AATGCCCGTT", "AATGCCGTT"]), test_cluster("b", &["GGCATTACCA"])];
+        let params = AlignmentParams::default();
+        let mut cache = MemoryCache::new();
+
+        let first = poa_batch_cached(&clusters, &params, &mut cache).unwrap();
+        let second = poa_batch_cached(&clusters, &params, &mut cache).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(first, second);
+        assert_eq!(second[1].id, "b");
+        assert_eq!(second[1].result.consensus, b"GGCATTACCA".to_vec());
+    }
+}
@@ -0,0 +1,260 @@
+//! Checkpointing of batch jobs, so that an interrupted run resumes with the clusters it had not completed.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::batch::{Cluster, ClusterResult};
+use crate::cache::params_key;
+use crate::msa::Msa;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+// the line opening the record of a completed cluster, with its id and tab-separated `key=value` metadata,
+// followed by its alignment in FASTA
+const RECORD_PREFIX: &str = "#cluster\t";
+// the first line of a checkpoint, with the `params_key` of the parameters its clusters were computed with
+const PARAMS_PREFIX: &str = "#params\t";
+
+// the id and metadata of a record
+type RecordHeader = (String, BTreeMap<String, String>);
+
+fn parse_record_line(line: &str) -> RecordHeader {
+    let mut fields = line.split('\t');
+    let id = fields.next().unwrap_or("").to_string();
+    let metadata = fields
+        .filter_map(|field| field.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    (id, metadata)
+}
+
+/// Reads the completed clusters of a checkpoint file written by `poa_batch_checkpointed`. A truncated last
+/// record, left by a run interrupted while writing it, is ignored.
+/// # Arguments
+///
+/// * `path` - the checkpoint file; a missing file holds no cluster
+///
+/// # Returns
+/// * returns the results of the completed clusters, in completion order
+pub fn read_checkpoint<P: AsRef<Path>>(path: P) -> io::Result<Vec<ClusterResult>> {
+    read_records(path.as_ref()).map(|(_, results)| results)
+}
+
+// the parameters key and the completed clusters of a checkpoint
+fn read_records(path: &Path) -> io::Result<(Option<u64>, Vec<ClusterResult>)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((None, vec![])),
+        Err(e) => return Err(e),
+    };
+    let mut key = None;
+    let mut results = vec![];
+    let mut record: Option<(RecordHeader, Vec<u8>)> = None;
+    let mut lines = BufReader::new(file).lines().peekable();
+    if let Some(Ok(line)) = lines.peek() {
+        if let Some(hex) = line.strip_prefix(PARAMS_PREFIX) {
+            let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "invalid parameters key");
+            key = Some(u64::from_str_radix(hex, 16).map_err(invalid)?);
+            lines.next();
+        }
+    }
+    loop {
+        let line = lines.next().transpose()?;
+        let next_id = match &line {
+            Some(line) => line.strip_prefix(RECORD_PREFIX).map(parse_record_line),
+            None => None,
+        };
+        if line.is_none() || next_id.is_some() {
+            if let Some(((id, metadata), fasta)) = record.take() {
+                match Msa::read_fasta(&fasta[..]) {
+                    Ok(msa) => results.push(ClusterResult {
+                        id,
+                        result: ConsensusResult::from_msa(msa),
+                        metadata,
+                    }),
+                    // only the last record can be incomplete
+                    Err(_) if line.is_none() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        match (line, next_id) {
+            (None, _) => break,
+            (Some(_), Some(id)) => record = Some((id, vec![])),
+            (Some(line), None) => match record.as_mut() {
+                Some((_, fasta)) => {
+                    fasta.extend_from_slice(line.as_bytes());
+                    fasta.push(b'\n');
+                }
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "data before the first cluster")),
+            },
+        }
+    }
+    Ok((key, results))
+}
+
+fn write_record<W: Write>(writer: &mut W, result: &ClusterResult) -> io::Result<()> {
+    write!(writer, "{}{}", RECORD_PREFIX, result.id)?;
+    for (key, value) in &result.metadata {
+        write!(writer, "\t{}={}", key, value)?;
+    }
+    writeln!(writer)?;
+    result.result.msa.write_fasta(writer, true)
+}
+
+/// Generates the consensus of every cluster of a batch like `poa_batch`, recording completed clusters in a
+/// checkpoint file so that an interrupted run started again with the same file only computes the clusters
+/// it had not completed.
+///
+/// Clusters are identified by their `id`, which must be unique within the batch and hold no tab or line break.
+/// Resumed clusters take their metadata from `clusters`. The checkpoint records the parameters, see
+/// `params_key`, and resuming from one recorded with other parameters fails rather than mixing results.
+/// The checkpoint is flushed to disk every `every` completed clusters; on resume, a record truncated by the
+/// interruption is dropped and its cluster computed again.
+/// # Arguments
+///
+/// * `clusters` - the clusters to form consensuses from
+/// * `params` - the alignment mode and scores, shared by all clusters
+/// * `path` - the checkpoint file, created if missing
+/// * `every` - the number of completed clusters between two flushes of the checkpoint (at least 1)
+///
+/// # Returns
+/// * returns one result per cluster, in input order, or the first I/O error of the checkpoint, of kind
+///   `InvalidInput` if it holds clusters computed with other parameters
+pub fn poa_batch_checkpointed<P: AsRef<Path>>(
+    clusters: &[Cluster],
+    params: &AlignmentParams,
+    path: P,
+    every: usize,
+) -> io::Result<Vec<ClusterResult>> {
+    let path = path.as_ref();
+    let key = params_key(params);
+    let (recorded, completed) = read_records(path)?;
+    if !completed.is_empty() && recorded != Some(key) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("checkpoint {} was recorded with other parameters", path.display()),
+        ));
+    }
+
+    // rewrite the completed records, dropping a truncated one, before appending to them
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writeln!(writer, "{}{:016x}", PARAMS_PREFIX, key)?;
+    for result in &completed {
+        write_record(&mut writer, result)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp, path)?;
+
+    let mut done: HashMap<String, ConsensusResult> =
+        completed.into_iter().map(|result| (result.id, result.result)).collect();
+    let mut writer = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+    let mut pending = 0;
+    let mut results = Vec::with_capacity(clusters.len());
+    for cluster in clusters {
+        let result = match done.remove(&cluster.id) {
+            Some(result) => ClusterResult::new(cluster, result),
+            None => {
+                let result = ClusterResult::new(cluster, crate::poa(&cluster.seqs, &cluster.quals, params));
+                write_record(&mut writer, &result)?;
+                pending += 1;
+                if pending >= every.max(1) {
+                    writer.flush()?;
+                    writer.get_ref().sync_data()?;
+                    pending = 0;
+                }
+                result
+            }
+        };
+        results.push(result);
+    }
+    writer.flush()?;
+    writer.get_ref().sync_data()?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(id: &str, rows: &[&str]) -> ClusterResult {
+        let msa = Msa {
+            names: (0..rows.len()).map(|i| format!("seq_{}", i)).collect(),
+            rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+            consensus: rows[0].as_bytes().to_vec(),
+        };
+        ClusterResult {
+            id: id.to_string(),
+            result: ConsensusResult::from_msa(msa),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_read_checkpoint() {
+        let path = std::env::temp_dir().join(format!("rust_spoa_checkpoint_{}", std::process::id()));
+        let mut umi_1 = result("umi_1", &["AC-GT", "ACTGT"]);
+        umi_1.metadata.insert("sample".to_string(), "s1".to_string());
+        let mut out = vec![];
+        write_record(&mut out, &umi_1).unwrap();
+        write_record(&mut out, &result("umi_2", &["GGCA"])).unwrap();
+        // a record interrupted before its consensus
+        out.extend_from_slice(b"#cluster\tumi_3\n>seq_0\nTTG");
+        fs::write(&path, &out).unwrap();
+
+        let results = read_checkpoint(&path).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], umi_1);
+        assert_eq!(results[1].result.consensus, b"GGCA".to_vec());
+        fs::remove_file(&path).unwrap();
+        assert!(read_checkpoint(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_poa_batch_checkpointed() {
+        let path = std::env::temp_dir().join(format!("rust_spoa_resume_{}", std::process::id()));
+        // a previous run completed the first cluster, with a result no new run would compute
+        let params = AlignmentParams::default();
+        let mut out = format!("{}{:016x}\n", PARAMS_PREFIX, params_key(&params)).into_bytes();
+        write_record(&mut out, &result("a", &["TTTT"])).unwrap();
+        fs::write(&path, &out).unwrap();
+        let clusters = vec![crate::test_cluster("a", &["GGCATTACCA"]), crate::test_cluster("b", &["GGCATTACCA"])];
+
+        let results = poa_batch_checkpointed(&clusters, &params, &path, 1).unwrap();
+
+        assert_eq!(results[0].result.consensus, b"TTTT".to_vec());
+        assert_eq!(results[1].result.consensus, b"GGCATTACCA".to_vec());
+        assert_eq!(read_checkpoint(&path).unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_poa_batch_checkpointed_params() {
+        let path = std::env::temp_dir().join(format!("rust_spoa_rescored_{}", std::process::id()));
+        let params = AlignmentParams::default();
+        let mut out = format!("{}{:016x}\n", PARAMS_PREFIX, params_key(&params)).into_bytes();
+        write_record(&mut out, &result("a", &["TTTT"])).unwrap();
+        fs::write(&path, &out).unwrap();
+        let clusters = vec![crate::test_cluster("a", &["GGCA"])];
+
+        let rescored = AlignmentParams { match_score: 3, ..params };
+        let error = poa_batch_checkpointed(&clusters, &rescored, &path, 1).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // the checkpoint is left as it was
+        assert_eq!(read_records(&path).unwrap().0, Some(params_key(&params)));
+        assert_eq!(read_checkpoint(&path).unwrap().len(), 1);
+        // a checkpoint without the parameters, e.g. written by hand, is resumed from only if empty
+        fs::write(&path, b"").unwrap();
+        assert!(poa_batch_checkpointed(&[], &rescored, &path, 1).unwrap().is_empty());
+        let mut out = vec![];
+        write_record(&mut out, &result("a", &["TTTT"])).unwrap();
+        fs::write(&path, &out).unwrap();
+        assert!(poa_batch_checkpointed(&clusters, &params, &path, 1).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
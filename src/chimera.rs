@@ -0,0 +1,260 @@
+//! Detection of chimeric reads before consensus: reads with an adapter in their middle, and fold-back reads
+//! followed by their own reverse complement.
+use std::collections::HashMap;
+
+use crate::amplicon::best_match;
+use crate::dna::reverse_complement;
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// What is done with a chimeric read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChimeraAction {
+    /// The read is split into its pieces: at internal adapters, and at the fold of a fold-back read, of which
+    /// only the longer arm is kept.
+    Split,
+    /// The read is excluded.
+    Exclude,
+}
+
+/// How chimeric reads are detected and handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChimeraOptions {
+    /// The adapter searched for in the middle of reads, in both orientations; empty to skip adapter search.
+    /// IUPAC codes are allowed.
+    pub adapter: Vec<u8>,
+    /// The maximum number of mismatches of an adapter match.
+    pub max_mismatches: usize,
+    /// The number of bases at each end of a read where adapters are expected and not searched for.
+    pub end_margin: usize,
+    /// The length of the k-mers shared by the two arms of a fold-back read.
+    pub kmer_len: usize,
+    /// The minimum number of k-mers whose reverse complement is found further along the read for the read to
+    /// be a fold-back.
+    pub min_shared_kmers: usize,
+    /// The minimum length of the pieces kept when splitting reads.
+    pub min_piece_len: usize,
+    /// What is done with chimeric reads.
+    pub action: ChimeraAction,
+}
+
+impl Default for ChimeraOptions {
+    fn default() -> ChimeraOptions {
+        ChimeraOptions {
+            adapter: vec![],
+            max_mismatches: 2,
+            end_margin: 50,
+            kmer_len: 15,
+            min_shared_kmers: 20,
+            min_piece_len: 100,
+            action: ChimeraAction::Split,
+        }
+    }
+}
+
+/// The number of chimeric reads found and how they were handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChimeraReport {
+    /// The number of input reads.
+    pub reads: usize,
+    /// The number of reads with at least one internal adapter.
+    pub adapter_chimeras: usize,
+    /// The number of reads with at least one fold-back piece.
+    pub fold_back_chimeras: usize,
+    /// The number of reads of which no piece was kept.
+    pub excluded: usize,
+    /// The number of sequences passed on to the consensus.
+    pub pieces: usize,
+}
+
+// the occurrences of the adapters within `range` of the read, searched around the best match first
+fn find_adapters(
+    read: &[u8],
+    range: std::ops::Range<usize>,
+    adapters: &[Vec<u8>],
+    max_mismatches: usize,
+    found: &mut Vec<(usize, usize)>,
+) {
+    let window = &read[range.clone()];
+    let best = adapters
+        .iter()
+        .filter_map(|adapter| {
+            best_match(window, adapter, max_mismatches, false).map(|(pos, mismatches)| (mismatches, pos, adapter.len()))
+        })
+        .min();
+    if let Some((_, pos, len)) = best {
+        let (start, end) = (range.start + pos, range.start + pos + len);
+        find_adapters(read, range.start..start, adapters, max_mismatches, found);
+        found.push((start, end));
+        find_adapters(read, end..range.end, adapters, max_mismatches, found);
+    }
+}
+
+// the position of the fold of a read followed by its reverse complement, as the median midpoint of the k-mers
+// whose reverse complement is found further along the read
+fn find_fold(read: &[u8], k: usize, min_shared_kmers: usize) -> Option<usize> {
+    if k == 0 || read.len() < 2 * k {
+        return None;
+    }
+    let mut first: HashMap<&[u8], usize> = HashMap::new();
+    for pos in 0..=read.len() - k {
+        first.entry(&read[pos..pos + k]).or_insert(pos);
+    }
+    let mut folds = vec![];
+    for pos in 0..=read.len() - k {
+        let rc = reverse_complement(&read[pos..pos + k]);
+        if let Some(&i) = first.get(rc.as_slice()) {
+            if i + k <= pos {
+                folds.push((i + pos + k) / 2);
+            }
+        }
+    }
+    if folds.len() < min_shared_kmers.max(1) {
+        return None;
+    }
+    folds.sort_unstable();
+    Some(folds[folds.len() / 2])
+}
+
+/// Detects chimeric reads and splits or excludes them.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8)
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `options` - how chimeric reads are detected and handled
+///
+/// # Returns
+/// * returns the sequences and qualities (null-terminated) of the kept reads and pieces, in input order, and
+///   the number of chimeric reads found
+pub fn split_chimeras(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    options: &ChimeraOptions,
+) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, ChimeraReport) {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let adapters = if options.adapter.is_empty() {
+        vec![]
+    } else {
+        vec![options.adapter.clone(), reverse_complement(&options.adapter)]
+    };
+
+    let mut report = ChimeraReport {
+        reads: seqs.len(),
+        ..ChimeraReport::default()
+    };
+    let mut kept_seqs = vec![];
+    let mut kept_quals = vec![];
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        let seq = &seq[..seq.len().saturating_sub(1)];
+        let qual = &qual[..qual.len().saturating_sub(1)];
+
+        let mut cuts = vec![];
+        if !adapters.is_empty() && seq.len() > 2 * options.end_margin {
+            let range = options.end_margin..seq.len() - options.end_margin;
+            find_adapters(seq, range, &adapters, options.max_mismatches, &mut cuts);
+        }
+        let mut pieces = vec![];
+        let mut start = 0;
+        for &(cut_start, cut_end) in cuts.iter().chain(std::iter::once(&(seq.len(), seq.len()))) {
+            pieces.push(start..cut_start);
+            start = cut_end;
+        }
+
+        let mut fold_back = false;
+        for piece in pieces.iter_mut() {
+            if let Some(fold) = find_fold(&seq[piece.clone()], options.kmer_len, options.min_shared_kmers) {
+                fold_back = true;
+                let fold = piece.start + fold;
+                *piece = if fold - piece.start >= piece.end - fold { piece.start..fold } else { fold..piece.end };
+            }
+        }
+
+        report.adapter_chimeras += !cuts.is_empty() as usize;
+        report.fold_back_chimeras += fold_back as usize;
+        if options.action == ChimeraAction::Exclude && (!cuts.is_empty() || fold_back) {
+            report.excluded += 1;
+            continue;
+        }
+        let mut kept = 0;
+        for piece in pieces.into_iter().filter(|piece| piece.len() >= options.min_piece_len) {
+            kept_seqs.push(null_terminated(&seq[piece.clone()]));
+            kept_quals.push(null_terminated(&qual[piece]));
+            kept += 1;
+        }
+        if kept == 0 {
+            report.excluded += 1;
+        }
+        report.pieces += kept;
+    }
+    (kept_seqs, kept_quals, report)
+}
+
+/// Generates the consensus of reads after splitting or excluding chimeric reads, see `split_chimeras`.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - how chimeric reads are detected and handled
+pub fn poa_without_chimeras(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &ChimeraOptions,
+) -> (ConsensusResult, ChimeraReport) {
+    let (seqs, quals, report) = split_chimeras(seqs, quals, options);
+    (crate::poa(&seqs, &quals, params), report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSERT: &[u8] = b"TTGCAGGCATGCAAGCTTGACTGATCGGATCCGTAGTCAATGC";
+    const ADAPTER: &[u8] = b"AATGTACTTCGTTCAGTTACG";
+
+    #[test]
+    fn test_find_fold() {
+        let fold_back = [INSERT, &reverse_complement(INSERT)].concat();
+        assert_eq!(find_fold(&fold_back, 11, 10), Some(INSERT.len()));
+        // the second arm stops early, reading back from the fold
+        let fold_back = [INSERT, &reverse_complement(&INSERT[20..])].concat();
+        assert_eq!(find_fold(&fold_back, 11, 10), Some(INSERT.len()));
+        assert_eq!(find_fold(INSERT, 11, 10), None);
+    }
+
+    #[test]
+    fn test_split_chimeras() {
+        let seqs = vec![
+            null_terminated(INSERT),
+            null_terminated(&[INSERT, ADAPTER, INSERT].concat()),
+            null_terminated(&[INSERT, &reverse_complement(ADAPTER), INSERT].concat()),
+            null_terminated(&[INSERT, &reverse_complement(&INSERT[13..])].concat()),
+        ];
+        let options = ChimeraOptions {
+            adapter: ADAPTER.to_vec(),
+            end_margin: 10,
+            kmer_len: 11,
+            min_shared_kmers: 10,
+            min_piece_len: 20,
+            ..ChimeraOptions::default()
+        };
+
+        let (kept, _, report) = split_chimeras(&seqs, &crate::quals_for(&seqs), &options);
+        assert_eq!(kept, vec![null_terminated(INSERT); 6]);
+        assert_eq!((report.adapter_chimeras, report.fold_back_chimeras, report.excluded), (2, 1, 0));
+        assert_eq!(report.pieces, 6);
+
+        let options = ChimeraOptions {
+            action: ChimeraAction::Exclude,
+            ..options
+        };
+        let (kept, _, report) = split_chimeras(&seqs, &crate::quals_for(&seqs), &options);
+        assert_eq!(kept, vec![null_terminated(INSERT)]);
+        assert_eq!((report.reads, report.excluded, report.pieces), (4, 3, 1));
+    }
+}
@@ -0,0 +1,150 @@
+//! Consensus of circular sequences, such as plasmids or mitochondrial genomes.
+use std::collections::HashMap;
+
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// The default length of the anchor k-mer shared by rotated reads.
+pub const DEFAULT_ANCHOR_LEN: usize = 15;
+
+/// The consensus of circular reads rotated to a common start.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CircularConsensus {
+    /// The consensus of the rotated reads. The consensus is circular: it starts with the anchor, and its last
+    /// base precedes its first one.
+    pub result: ConsensusResult,
+    /// The anchor k-mer every rotated read starts with (empty if no anchor was found).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub anchor: Vec<u8>,
+    /// The input indices of the reads in which the anchor was not found once, excluded from the consensus.
+    pub unanchored: Vec<usize>,
+}
+
+// the k-mers found exactly once in the circular sequence, with their start position
+fn unique_kmers(seq: &[u8], k: usize) -> HashMap<Vec<u8>, Option<usize>> {
+    let mut kmers = HashMap::new();
+    if seq.len() < k {
+        return kmers;
+    }
+    // k-mers may span the junction of the circle, as the start of a read is arbitrary
+    let circle = [seq, &seq[..k - 1]].concat();
+    for pos in 0..seq.len() {
+        kmers
+            .entry(circle[pos..pos + k].to_vec())
+            .and_modify(|found: &mut Option<usize>| *found = None)
+            .or_insert(Some(pos));
+    }
+    kmers.retain(|_, found| found.is_some());
+    kmers
+}
+
+// the k-mer unique in the first read that is unique in the most reads, with its position in each read
+fn find_anchor(seqs: &[&[u8]], k: usize) -> Option<(Vec<u8>, Vec<Option<usize>>)> {
+    let kmers: Vec<HashMap<Vec<u8>, Option<usize>>> = seqs.iter().map(|seq| unique_kmers(seq, k)).collect();
+    let mut candidates: Vec<(&Vec<u8>, usize)> =
+        kmers.first()?.iter().map(|(kmer, pos)| (kmer, pos.unwrap())).collect();
+    // candidates are scanned in the order of the first read, so that ties keep the leftmost k-mer
+    candidates.sort_by_key(|&(_, pos)| pos);
+
+    let mut best: Option<(&Vec<u8>, usize)> = None;
+    for (kmer, _) in candidates {
+        let count = kmers.iter().filter(|read_kmers| read_kmers.contains_key(kmer)).count();
+        match best {
+            Some((_, best_count)) if best_count >= count => {}
+            _ => best = Some((kmer, count)),
+        }
+    }
+    let (anchor, _) = best?;
+    let positions = kmers.iter().map(|read_kmers| read_kmers.get(anchor).cloned().flatten()).collect();
+    Some((anchor.clone(), positions))
+}
+
+/// Generates the consensus of reads of a circular sequence, each covering the circle once from an arbitrary
+/// start.
+///
+/// The anchor is the k-mer occurring once in the first read that occurs once in the most reads. Every read
+/// containing the anchor is rotated to start with it before alignment, so that the circular permutation of
+/// the reads does not break the global alignment; the other reads are excluded.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `anchor_len` - the length of the anchor k-mer, e.g. `DEFAULT_ANCHOR_LEN`
+/// * `params` - the alignment mode and scores
+pub fn poa_circular(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    anchor_len: usize,
+    params: &AlignmentParams,
+) -> CircularConsensus {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    if anchor_len == 0 {
+        panic!("Anchor length must be positive");
+    }
+    let reads: Vec<&[u8]> = seqs.iter().map(|seq| &seq[..seq.len().saturating_sub(1)]).collect();
+
+    let (anchor, positions) = match find_anchor(&reads, anchor_len) {
+        Some(found) => found,
+        None => (vec![], vec![None; reads.len()]),
+    };
+
+    let mut rotated_seqs = vec![];
+    let mut rotated_quals = vec![];
+    let mut unanchored = vec![];
+    for (i, pos) in positions.into_iter().enumerate() {
+        match pos {
+            Some(pos) => {
+                let qual = &quals[i][..reads[i].len()];
+                rotated_seqs.push(null_terminated(&[&reads[i][pos..], &reads[i][..pos]].concat()));
+                rotated_quals.push(null_terminated(&[&qual[pos..], &qual[..pos]].concat()));
+            }
+            None => unanchored.push(i),
+        }
+    }
+
+    CircularConsensus {
+        result: crate::poa(&rotated_seqs, &rotated_quals, params),
+        anchor,
+        unanchored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_anchor() {
+        let reads: Vec<&[u8]> = vec![b"ACGTTGCAT", b"GTTGCATAC", b"TTTTTT"];
+
+        // all k-mers of the first read are unique in the first two reads, the leftmost is kept even though it
+        // spans the junction of the second read
+        let (anchor, positions) = find_anchor(&reads, 4).unwrap();
+        assert_eq!(anchor, b"ACGT".to_vec());
+        assert_eq!(positions, vec![Some(0), Some(7), None]);
+
+        assert_eq!(unique_kmers(b"AAAA", 2).len(), 0);
+        assert!(find_anchor(&reads, 10).is_none());
+    }
+
+    #[test]
+    fn test_poa_circular() {
+        let circle = "ATTGCCCGTTAGGCATCGACTTGA";
+        let mut seqs = vec![];
+        for start in [0, 5, 11, 17, 23].iter() {
+            seqs.push(format!("{}{}\0", &circle[*start..], &circle[..*start]).into_bytes());
+        }
+        seqs.push(b"GGGGGGGG\0".to_vec());
+        let quals = crate::quals_for(&seqs);
+
+        let circular = poa_circular(&seqs, &quals, 8, &AlignmentParams::default());
+
+        assert_eq!(circular.anchor, b"ATTGCCCG".to_vec());
+        assert_eq!(circular.unanchored, vec![5]);
+        assert_eq!(circular.result.consensus, circle.as_bytes().to_vec());
+    }
+}
@@ -0,0 +1,282 @@
+//! Arrow record batch input of reads, and export of batch results as Arrow record batches and Parquet files.
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, BinaryArray, Float64Array, LargeBinaryArray, LargeStringArray, MapBuilder, StringArray,
+    StringBuilder, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Fields, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+
+use crate::batch::{Cluster, ClusterResult};
+use crate::graph::PoaGraph;
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+
+/// Returns the schema of the read batches accepted by `clusters_from_record_batch` and `poa_record_batch`:
+/// one row per read, with its cluster id, sequence and Phred+33 qualities. The columns may also be large
+/// strings or (large) binaries, as exported by Polars or DataFusion, and the batch may hold other columns.
+pub fn reads_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("cluster_id", DataType::Utf8, false),
+        Field::new("sequence", DataType::Utf8, false),
+        Field::new("quality", DataType::Utf8, false),
+    ])
+}
+
+// the values of a string or binary column, borrowed from the batch
+fn byte_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<Vec<&'a [u8]>, ArrowError> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| ArrowError::SchemaError(format!("missing column {}", name)))?;
+    if column.null_count() > 0 {
+        return Err(ArrowError::InvalidArgumentError(format!("column {} holds nulls", name)));
+    }
+    let any = column.as_any();
+    let values: Option<Vec<&[u8]>> = match column.data_type() {
+        DataType::Utf8 => {
+            any.downcast_ref::<StringArray>().map(|a| a.iter().flatten().map(str::as_bytes).collect())
+        }
+        DataType::LargeUtf8 => {
+            any.downcast_ref::<LargeStringArray>().map(|a| a.iter().flatten().map(str::as_bytes).collect())
+        }
+        DataType::Binary => any.downcast_ref::<BinaryArray>().map(|a| a.iter().flatten().collect()),
+        DataType::LargeBinary => any.downcast_ref::<LargeBinaryArray>().map(|a| a.iter().flatten().collect()),
+        _ => None,
+    };
+    values.ok_or_else(|| ArrowError::SchemaError(format!("column {} is not a string or binary column", name)))
+}
+
+// the rows of each cluster of a read batch, clusters in order of first appearance
+fn cluster_rows(batch: &RecordBatch) -> Result<Vec<(String, Vec<usize>)>, ArrowError> {
+    let ids = byte_column(batch, "cluster_id")?;
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    let mut clusters: Vec<(String, Vec<usize>)> = vec![];
+    for (row, &id) in ids.iter().enumerate() {
+        let i = *index.entry(id).or_insert_with(|| {
+            clusters.push((String::from_utf8_lossy(id).into_owned(), vec![]));
+            clusters.len() - 1
+        });
+        clusters[i].1.push(row);
+    }
+    Ok(clusters)
+}
+
+// the sequence and quality columns of a read batch
+type ReadColumns<'a> = (Vec<&'a [u8]>, Vec<&'a [u8]>);
+
+// the sequences and qualities of a read batch, checked to be of same length
+fn reads(batch: &RecordBatch) -> Result<ReadColumns<'_>, ArrowError> {
+    let seqs = byte_column(batch, "sequence")?;
+    let quals = byte_column(batch, "quality")?;
+    if let Some(row) = (0..seqs.len()).find(|&row| seqs[row].len() != quals[row].len()) {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "the sequence and qualities of row {} differ in length",
+            row
+        )));
+    }
+    Ok((seqs, quals))
+}
+
+/// Groups the reads of a record batch with the columns of `reads_schema` into clusters, in order of first
+/// appearance of their cluster id, for the batch functions taking clusters.
+pub fn clusters_from_record_batch(batch: &RecordBatch) -> Result<Vec<Cluster>, ArrowError> {
+    let (seqs, quals) = reads(batch)?;
+    Ok(cluster_rows(batch)?
+        .into_iter()
+        .map(|(id, rows)| Cluster {
+            id,
+            seqs: rows.iter().map(|&row| null_terminated(seqs[row])).collect(),
+            quals: rows.iter().map(|&row| null_terminated(quals[row])).collect(),
+            metadata: Default::default(),
+        })
+        .collect())
+}
+
+/// Generates the consensus of every cluster of a record batch of reads with the columns of `reads_schema`,
+/// aligning the reads straight from the columns of the batch into a single reused graph.
+/// # Arguments
+///
+/// * `batch` - the reads, one per row
+/// * `params` - the alignment mode and scores, shared by all clusters
+///
+/// # Returns
+/// * returns one result per cluster, in order of first appearance of their cluster id, or
+///   `ArrowError::ComputeError` for parameters or reads the alignment engine rejects, see `PoaGraph::try_add`
+pub fn poa_record_batch(batch: &RecordBatch, params: &AlignmentParams) -> Result<Vec<ClusterResult>, ArrowError> {
+    let (seqs, quals) = reads(batch)?;
+    params.validate().map_err(|e| ArrowError::ComputeError(e.to_string()))?;
+    let mut graph = PoaGraph::new(params);
+    cluster_rows(batch)?
+        .into_iter()
+        .map(|(id, rows)| {
+            graph.clear();
+            for row in rows {
+                graph
+                    .try_add(seqs[row], quals[row])
+                    .map_err(|e| ArrowError::ComputeError(format!("cluster {}: {}", id, e)))?;
+            }
+            Ok(ClusterResult {
+                id,
+                result: graph.result(),
+                metadata: Default::default(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the schema of the record batches built by `results_to_record_batch`.
+pub fn results_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("cluster_id", DataType::Utf8, false),
+        Field::new("consensus", DataType::Utf8, false),
+        Field::new("consensus_len", DataType::UInt64, false),
+        Field::new("num_reads", DataType::UInt64, false),
+        Field::new("mean_coverage", DataType::Float64, false),
+        Field::new("min_coverage", DataType::UInt64, false),
+        Field::new("max_coverage", DataType::UInt64, false),
+        Field::new("mean_identity", DataType::Float64, false),
+        Field::new("min_identity", DataType::Float64, false),
+        Field::new("min_support", DataType::Float64, false),
+        Field::new("metadata", metadata_type(), false),
+    ])
+}
+
+// the map of the metadata column, as built by `MapBuilder`
+fn metadata_type() -> DataType {
+    let entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, true),
+    ]);
+    DataType::Map(Arc::new(Field::new("entries", DataType::Struct(entries), false)), false)
+}
+
+/// Converts batch results to an Arrow record batch with one row per cluster, its metadata as a map column.
+pub fn results_to_record_batch(results: &[ClusterResult]) -> Result<RecordBatch, ArrowError> {
+    let metrics = || results.iter().map(|r| &r.result.metrics);
+    let mut metadata = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for result in results {
+        for (key, value) in &result.metadata {
+            metadata.keys().append_value(key);
+            metadata.values().append_value(value);
+        }
+        metadata.append(true)?;
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(results.iter().map(|r| r.id.as_str()))),
+        Arc::new(StringArray::from_iter_values(
+            results.iter().map(|r| String::from_utf8_lossy(&r.result.consensus)),
+        )),
+        Arc::new(UInt64Array::from_iter_values(metrics().map(|m| m.consensus_len as u64))),
+        Arc::new(UInt64Array::from_iter_values(metrics().map(|m| m.num_reads as u64))),
+        Arc::new(Float64Array::from_iter_values(metrics().map(|m| m.mean_coverage))),
+        Arc::new(UInt64Array::from_iter_values(
+            metrics().map(|m| m.coverage.iter().min().cloned().unwrap_or(0) as u64),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            metrics().map(|m| m.coverage.iter().max().cloned().unwrap_or(0) as u64),
+        )),
+        Arc::new(Float64Array::from_iter_values(metrics().map(|m| m.mean_identity))),
+        Arc::new(Float64Array::from_iter_values(
+            metrics().map(|m| m.identity.iter().cloned().fold(None, min_f64).unwrap_or(0.0)),
+        )),
+        Arc::new(Float64Array::from_iter_values(metrics().map(|m| m.min_support))),
+        Arc::new(metadata.finish()),
+    ];
+
+    RecordBatch::try_new(Arc::new(results_schema()), columns)
+}
+
+fn min_f64(acc: Option<f64>, x: f64) -> Option<f64> {
+    Some(acc.map_or(x, |acc| acc.min(x)))
+}
+
+/// Writes batch results as a Parquet file with the schema of `results_schema`.
+pub fn write_parquet<W: Write + Send>(results: &[ClusterResult], writer: W) -> Result<(), ParquetError> {
+    let batch = results_to_record_batch(results)?;
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msa::Msa;
+    use crate::result::ConsensusResult;
+
+    #[test]
+    fn test_record_batch() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string()],
+            rows: vec![b"ACGT".to_vec(), b"AC-T".to_vec()],
+            consensus: b"ACGT".to_vec(),
+        };
+        let results = vec![ClusterResult {
+            id: "umi_1".to_string(),
+            result: ConsensusResult::from_msa(msa),
+            metadata: Default::default(),
+        }];
+
+        let batch = results_to_record_batch(&results).unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(batch.num_columns(), 11);
+
+        let min_coverage = batch.column(5).as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(min_coverage.value(0), 1);
+        let ids = batch.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(ids.value(0), "umi_1");
+        assert!(!ids.is_null(0));
+
+        let mut parquet = vec![];
+        write_parquet(&results, &mut parquet).unwrap();
+        assert!(parquet.starts_with(b"PAR1"));
+    }
+
+    #[test]
+    fn test_clusters_from_record_batch() {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["umi_2", "umi_1", "umi_2"])),
+            Arc::new(LargeStringArray::from(vec!["ACGT", "GGA", "ACT"])),
+            Arc::new(BinaryArray::from(vec![&b"FFFF"[..], &b"FF#"[..], &b"FFF"[..]])),
+        ];
+        let batch = RecordBatch::try_from_iter(vec!["cluster_id", "sequence", "quality"].into_iter().zip(columns))
+            .unwrap();
+
+        let clusters = clusters_from_record_batch(&batch).unwrap();
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].id, "umi_2");
+        assert_eq!(clusters[0].seqs, vec![b"ACGT\0".to_vec(), b"ACT\0".to_vec()]);
+        assert_eq!(clusters[1].quals, vec![b"FF#\0".to_vec()]);
+
+        let truncated = batch.project(&[0, 1]).unwrap();
+        assert!(clusters_from_record_batch(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_poa_record_batch_errors() {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["umi_1", "umi_1"])),
+            Arc::new(StringArray::from(vec!["ACGT", "AC\0T"])),
+            Arc::new(StringArray::from(vec!["FFFF", "FFFF"])),
+        ];
+        let batch = RecordBatch::try_from_iter(vec!["cluster_id", "sequence", "quality"].into_iter().zip(columns))
+            .unwrap();
+        let params = AlignmentParams {
+            gap_open: 3,
+            ..AlignmentParams::default()
+        };
+
+        // the read holding a null byte and the positive gap score fail rather than panic
+        assert!(matches!(poa_record_batch(&batch, &AlignmentParams::default()), Err(ArrowError::ComputeError(_))));
+        assert!(matches!(poa_record_batch(&batch, &params), Err(ArrowError::ComputeError(_))));
+    }
+}
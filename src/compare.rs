@@ -0,0 +1,158 @@
+//! Comparison of two consensus sequences, e.g. from two replicates or two parameter sets.
+use crate::msa::GAP;
+use crate::pairwise::{align_pair, left_align_pair};
+use crate::params::AlignmentParams;
+use crate::variant::VariantKind;
+
+/// A difference between two consensus sequences `a` and `b`, expressed as the change from `a` to `b`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConsensusDifference {
+    /// The kind of the difference, insertions holding bases of `b` only.
+    pub kind: VariantKind,
+    /// The 0-based position in `a` of the first affected base. Insertions are placed before this base.
+    pub pos_a: usize,
+    /// The 0-based position in `b` of the first affected base. Deletions are placed before this base.
+    pub pos_b: usize,
+    /// The bases of `a` (empty for insertions).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub allele_a: Vec<u8>,
+    /// The bases of `b` (empty for deletions).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub allele_b: Vec<u8>,
+}
+
+/// The global alignment of two consensus sequences and their differences.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConsensusComparison {
+    /// The gapped row of `a`.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub row_a: Vec<u8>,
+    /// The gapped row of `b`.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub row_b: Vec<u8>,
+    /// The differences, ordered by position.
+    pub differences: Vec<ConsensusDifference>,
+    /// The fraction of alignment columns holding the same base in both sequences (1 for two empty sequences).
+    pub identity: f64,
+}
+
+impl ConsensusComparison {
+    /// Returns true if the two sequences are identical.
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// Globally aligns two consensus sequences and reports their substitutions and indels.
+/// # Arguments
+///
+/// * `a` - the first consensus (not null-terminated)
+/// * `b` - the second consensus (not null-terminated)
+/// * `params` - the scores used to globally align the two sequences
+///
+/// # Returns
+/// * returns the alignment of the two sequences, with left-aligned indels, their differences from `a` to `b`
+///   and their identity
+pub fn compare_consensus(a: &[u8], b: &[u8], params: &AlignmentParams) -> ConsensusComparison {
+    let (mut row_a, mut row_b) = align_pair(a, b, params);
+    left_align_pair(&mut row_a, &mut row_b);
+    let differences = row_differences(&row_a, &row_b);
+    let matches = row_a.iter().zip(row_b.iter()).filter(|(x, y)| x == y && **x != GAP).count();
+    let identity = if row_a.is_empty() { 1.0 } else { matches as f64 / row_a.len() as f64 };
+    ConsensusComparison {
+        row_a,
+        row_b,
+        differences,
+        identity,
+    }
+}
+
+// the differences between two gapped rows, consecutive gap columns of one row forming a single indel
+fn row_differences(row_a: &[u8], row_b: &[u8]) -> Vec<ConsensusDifference> {
+    let mut differences = vec![];
+    let (mut pos_a, mut pos_b, mut col) = (0, 0, 0);
+    while col < row_a.len() {
+        let (x, y) = (row_a[col], row_b[col]);
+        if x != GAP && y != GAP {
+            if x != y {
+                differences.push(ConsensusDifference {
+                    kind: VariantKind::Snv,
+                    pos_a,
+                    pos_b,
+                    allele_a: vec![x],
+                    allele_b: vec![y],
+                });
+            }
+            pos_a += 1;
+            pos_b += 1;
+            col += 1;
+        } else if x == GAP && y != GAP {
+            let start = col;
+            while col < row_a.len() && row_a[col] == GAP && row_b[col] != GAP {
+                col += 1;
+            }
+            differences.push(ConsensusDifference {
+                kind: VariantKind::Insertion,
+                pos_a,
+                pos_b,
+                allele_a: vec![],
+                allele_b: row_b[start..col].to_vec(),
+            });
+            pos_b += col - start;
+        } else if x != GAP {
+            let start = col;
+            while col < row_a.len() && row_b[col] == GAP && row_a[col] != GAP {
+                col += 1;
+            }
+            differences.push(ConsensusDifference {
+                kind: VariantKind::Deletion,
+                pos_a,
+                pos_b,
+                allele_a: row_a[start..col].to_vec(),
+                allele_b: vec![],
+            });
+            pos_a += col - start;
+        } else {
+            col += 1;
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_differences() {
+        let differences = row_differences(b"ACGT--GACC", b"ACTTAAGA-C");
+
+        assert_eq!(differences.len(), 3);
+        assert_eq!(differences[0].kind, VariantKind::Snv);
+        assert_eq!((differences[0].pos_a, differences[0].pos_b), (2, 2));
+        assert_eq!((differences[0].allele_a[0], differences[0].allele_b[0]), (b'G', b'T'));
+        assert_eq!(differences[1].kind, VariantKind::Insertion);
+        assert_eq!((differences[1].pos_a, differences[1].pos_b), (4, 4));
+        assert_eq!(differences[1].allele_b, b"AA".to_vec());
+        assert_eq!(differences[2].kind, VariantKind::Deletion);
+        assert_eq!((differences[2].pos_a, differences[2].pos_b), (6, 8));
+        assert_eq!(differences[2].allele_a, b"C".to_vec());
+    }
+
+    #[test]
+    fn test_compare_consensus() {
+        let params = AlignmentParams::default();
+
+        let same = compare_consensus(b"ACGTTGCA", b"ACGTTGCA", &params);
+        let different = compare_consensus(b"ACGTTGCA", b"ACGATGCA", &params);
+
+        assert!(same.is_identical());
+        assert_eq!(same.identity, 1.0);
+        assert_eq!(different.differences.len(), 1);
+        assert_eq!(different.differences[0].pos_a, 3);
+        assert_eq!(different.identity, 7.0 / 8.0);
+        assert!(compare_consensus(b"", b"", &params).is_identical());
+    }
+}
@@ -0,0 +1,90 @@
+//! Detection of the SIMD instruction set the bundled SPOA was compiled for on the CPU running it.
+use crate::error::Error;
+
+/// Returns the SIMD instruction set the bundled SPOA was compiled for, `SSE4.1`, `AVX2` or `AVX-512`, or None
+/// if it runs on any CPU of its target, e.g. with the `dispatch` feature.
+pub fn compiled_simd_level() -> Option<&'static str> {
+    // set by the build script
+    match option_env!("SPOA_SIMD_LEVEL") {
+        Some("") | None => None,
+        level => level,
+    }
+}
+
+// whether the CPU running the process supports a SIMD instruction set
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn supported(level: &str) -> bool {
+    match level {
+        "SSE4.1" => is_x86_feature_detected!("sse4.1"),
+        "AVX2" => is_x86_feature_detected!("avx2"),
+        "AVX-512" => is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw"),
+        _ => true,
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn supported(_level: &str) -> bool {
+    true
+}
+
+/// Checks that the CPU running the process supports the SIMD instruction set SPOA was compiled for, so that
+/// a binary built for a newer CPU fails with an error rather than crashing with an illegal instruction, e.g.
+/// on older cluster nodes. The fallible consensus functions and `PoaGraph::try_add` return this error before
+/// aligning, and every other function calling into SPOA, such as `poa`, `poa_consensus` or `poa_batch`,
+/// panics with it.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{check_cpu, Error};
+///
+///     if let Err(Error::UnsupportedCpu { needs }) = check_cpu() {
+///         eprintln!("this CPU does not support {}", needs);
+///     }
+/// ```
+pub fn check_cpu() -> Result<(), Error> {
+    check_level(compiled_simd_level())
+}
+
+// checks that the CPU running the process supports an instruction set, if any
+fn check_level(level: Option<&'static str>) -> Result<(), Error> {
+    match level {
+        Some(needs) if !supported(needs) => Err(Error::UnsupportedCpu { needs }),
+        _ => Ok(()),
+    }
+}
+
+// panics with the error of `check_cpu`, before any call into SPOA that may run its SIMD instructions
+pub(crate) fn assert_cpu() {
+    if let Err(e) = check_cpu() {
+        panic!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_cpu() {
+        // the tests run on a CPU the crate was built for
+        assert_eq!(check_cpu(), Ok(()));
+        assert_eq!(check_level(None), Ok(()));
+        assert_eq!(check_level(Some("unknown")), Ok(()));
+        assert_ne!(compiled_simd_level(), Some(""));
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_check_level() {
+        let detected = [
+            ("SSE4.1", is_x86_feature_detected!("sse4.1")),
+            ("AVX2", is_x86_feature_detected!("avx2")),
+            ("AVX-512", is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw")),
+        ];
+        for &(level, detected) in detected.iter() {
+            let expected = if detected { Ok(()) } else { Err(Error::UnsupportedCpu { needs: level }) };
+            assert_eq!(check_level(Some(level)), expected);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Indel spectrum of edited reads around a CRISPR cut site.
+use std::collections::BTreeMap;
+
+use crate::msa::GAP;
+use crate::pairwise::align_pair;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+use crate::variant::VariantKind;
+
+/// An indel found in reads around the cut site, with the number of reads carrying it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IndelAllele {
+    /// Either `VariantKind::Insertion` or `VariantKind::Deletion`.
+    pub kind: VariantKind,
+    /// The 0-based reference position of the first deleted base, or of the base the insertion precedes.
+    pub ref_pos: usize,
+    /// The inserted or deleted bases.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub bases: Vec<u8>,
+    /// The number of reads spanning the window that carry the indel.
+    pub reads: usize,
+    /// The fraction of the reads spanning the window that carry the indel.
+    pub frequency: f64,
+}
+
+impl IndelAllele {
+    /// The signed size of the indel: positive for insertions, negative for deletions.
+    pub fn size(&self) -> i64 {
+        match self.kind {
+            VariantKind::Deletion => -(self.bases.len() as i64),
+            _ => self.bases.len() as i64,
+        }
+    }
+}
+
+/// The consensus of reads of an edited locus and the indels they carry around the cut site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditAnalysis {
+    /// The consensus of all reads.
+    pub result: ConsensusResult,
+    /// The number of reads whose alignment to the reference spans the whole window.
+    pub spanning_reads: usize,
+    /// The number of spanning reads carrying at least one indel in the window.
+    pub edited_reads: usize,
+    /// The indels found in spanning reads, most frequent first and then by reference position.
+    pub spectrum: Vec<IndelAllele>,
+}
+
+impl EditAnalysis {
+    /// The fraction of spanning reads carrying at least one indel in the window.
+    pub fn edit_frequency(&self) -> f64 {
+        if self.spanning_reads == 0 {
+            return 0.0;
+        }
+        self.edited_reads as f64 / self.spanning_reads as f64
+    }
+
+    /// The number of observed indels of each signed size (positive for insertions, negative for deletions).
+    pub fn size_distribution(&self) -> BTreeMap<i64, usize> {
+        let mut sizes = BTreeMap::new();
+        for allele in &self.spectrum {
+            *sizes.entry(allele.size()).or_insert(0) += allele.reads;
+        }
+        sizes
+    }
+}
+
+// an indel of a read: its kind, reference position and bases
+type ReadIndel = (VariantKind, usize, Vec<u8>);
+
+// the reference range covered by a read (inclusive, None if the read is empty) and its indels, ignoring the
+// terminal gaps of a read shorter than the reference
+fn row_indels(ref_row: &[u8], read_row: &[u8]) -> (Option<(usize, usize)>, Vec<ReadIndel>) {
+    let (first, last) = match (
+        read_row.iter().position(|&b| b != GAP),
+        read_row.iter().rposition(|&b| b != GAP),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return (None, vec![]),
+    };
+
+    let mut ref_pos = ref_row[..first].iter().filter(|&&b| b != GAP).count();
+    let start = ref_pos;
+    let mut indels = vec![];
+    let mut col = first;
+    while col <= last {
+        let (r, c) = (ref_row[col], read_row[col]);
+        if r == GAP {
+            let from = col;
+            while col <= last && ref_row[col] == GAP {
+                col += 1;
+            }
+            indels.push((VariantKind::Insertion, ref_pos, read_row[from..col].to_vec()));
+        } else if c == GAP {
+            let from = col;
+            while col <= last && read_row[col] == GAP && ref_row[col] != GAP {
+                col += 1;
+            }
+            indels.push((VariantKind::Deletion, ref_pos, ref_row[from..col].to_vec()));
+            ref_pos += col - from;
+        } else {
+            ref_pos += 1;
+            col += 1;
+        }
+    }
+    // the last covered base is the one before `ref_pos`, unless the read ends with an insertion
+    (Some((start, ref_pos.saturating_sub(1).max(start))), indels)
+}
+
+/// Generates the consensus of reads of an edited locus, and the spectrum of indels found in the reads within
+/// a window around the cut site.
+///
+/// Each read is globally aligned against the reference. Only reads spanning the whole window are counted,
+/// and terminal gaps of reads shorter than the reference are not reported as deletions.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `reference` - the unedited reference sequence of the locus (not null-terminated)
+/// * `cut_site` - the 0-based reference position of the base following the cut
+/// * `window` - the number of reference bases on each side of the cut site in which indels are reported
+/// * `params` - the alignment mode and scores
+pub fn analyze_edits(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    reference: &[u8],
+    cut_site: usize,
+    window: usize,
+    params: &AlignmentParams,
+) -> EditAnalysis {
+    if cut_site > reference.len() {
+        panic!("Cut site must lie within the reference");
+    }
+    let result = crate::poa(seqs, quals, params);
+
+    let lo = cut_site.saturating_sub(window);
+    let hi = (cut_site + window).min(reference.len());
+    let mut spanning_reads = 0;
+    let mut edited_reads = 0;
+    let mut counts: BTreeMap<ReadIndel, usize> = BTreeMap::new();
+    for seq in seqs {
+        let seq = &seq[..seq.len().saturating_sub(1)];
+        let (ref_row, read_row) = align_pair(reference, seq, params);
+        let (range, indels) = row_indels(&ref_row, &read_row);
+        match range {
+            Some((start, end)) if start <= lo && end + 1 >= hi => {}
+            _ => continue,
+        }
+        spanning_reads += 1;
+
+        // an indel touches the window if it starts before its end and ends after its start
+        let mut edited = false;
+        for indel in indels {
+            let len = if indel.0 == VariantKind::Deletion { indel.2.len() } else { 0 };
+            if indel.1 <= hi && indel.1 + len >= lo {
+                edited = true;
+                *counts.entry(indel).or_insert(0) += 1;
+            }
+        }
+        if edited {
+            edited_reads += 1;
+        }
+    }
+
+    let mut spectrum: Vec<IndelAllele> = counts
+        .into_iter()
+        .map(|((kind, ref_pos, bases), reads)| IndelAllele {
+            kind,
+            ref_pos,
+            bases,
+            reads,
+            frequency: reads as f64 / spanning_reads as f64,
+        })
+        .collect();
+    spectrum.sort_by(|a, b| b.reads.cmp(&a.reads).then(a.ref_pos.cmp(&b.ref_pos)));
+
+    EditAnalysis {
+        result,
+        spanning_reads,
+        edited_reads,
+        spectrum,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_indels() {
+        let (range, indels) = row_indels(b"ACGTAC-GTACGT", b"--GTA-TGTAC--");
+        assert_eq!(range, Some((2, 9)));
+        assert_eq!(
+            indels,
+            vec![(VariantKind::Deletion, 5, b"C".to_vec()), (VariantKind::Insertion, 6, b"T".to_vec())]
+        );
+        assert_eq!(row_indels(b"ACGT", b"----"), (None, vec![]));
+    }
+
+    #[test]
+    fn test_analyze_edits() {
+        let reference = b"ACGTTGCAGGCATGCAAGCTTGACTGATCGGATCCGTAGT";
+        let wild_type = reference.to_vec();
+        let mut deletion = reference.to_vec();
+        deletion.drain(18..21);
+        let mut insertion = reference.to_vec();
+        insertion.insert(20, b'A');
+
+        let mut seqs = vec![];
+        for seq in [&wild_type, &deletion, &deletion, &insertion].iter() {
+            seqs.push(crate::pairwise::null_terminated(seq));
+        }
+        let quals = crate::quals_for(&seqs);
+
+        let analysis = analyze_edits(&seqs, &quals, reference, 20, 5, &AlignmentParams::default());
+
+        assert_eq!((analysis.spanning_reads, analysis.edited_reads), (4, 3));
+        assert_eq!(analysis.edit_frequency(), 0.75);
+        assert_eq!(analysis.spectrum[0].size(), -3);
+        assert_eq!(analysis.spectrum[0].frequency, 0.5);
+        assert_eq!(analysis.spectrum[1].size(), 1);
+        assert_eq!(analysis.size_distribution().into_iter().collect::<Vec<_>>(), vec![(-3, 2), (1, 1)]);
+    }
+}
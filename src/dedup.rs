@@ -0,0 +1,129 @@
+//! Alignment of byte-identical sequences as a single weighted sequence.
+use libc::c_char;
+use std::collections::HashMap;
+use std::ffi::CStr;
+
+use crate::msa::{with_empty_rows, Msa};
+
+extern "C" {
+    fn poa_msa_weighted_func(
+        seqs: *const *const u8,
+        weights: *const *const u32,
+        num_seqs: i32,
+        alignment_type: i32,
+        match_score: i32,
+        mismatch_score: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        deterministic: i32,
+        num_rows: *mut i32,
+    ) -> *mut *mut c_char;
+    fn poa_free_rows(rows: *mut *mut c_char, num_rows: i32);
+}
+
+// the distinct sequences (as the index of their first copy), the summed base weights of their copies, and the
+// distinct sequence of every input sequence
+fn collapse(seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> (Vec<usize>, Vec<Vec<u32>>, Vec<usize>) {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    let mut firsts = vec![];
+    let mut weights: Vec<Vec<u32>> = vec![];
+    let mut members = Vec::with_capacity(seqs.len());
+    for (i, (seq, qual)) in seqs.iter().zip(quals.iter()).enumerate() {
+        let unique = *index.entry(seq.as_slice()).or_insert_with(|| {
+            firsts.push(i);
+            weights.push(vec![0; seq.len() - 1]);
+            firsts.len() - 1
+        });
+        for (weight, &q) in weights[unique].iter_mut().zip(qual.iter()) {
+            *weight += q.saturating_sub(33) as u32;
+        }
+        members.push(unique);
+    }
+    (firsts, weights, members)
+}
+
+// aligns every distinct sequence once, then gives each input sequence the row of its distinct sequence
+pub(crate) fn collapsed_msa(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    names: Vec<String>,
+    alignment_type: i32,
+    scores: [i32; 6],
+    deterministic: bool,
+) -> Msa {
+    let (firsts, weights, members) = collapse(seqs, quals);
+    let seq_ptrs: Vec<*const u8> = firsts.iter().map(|&i| seqs[i].as_ptr()).collect();
+    let weight_ptrs: Vec<*const u32> = weights.iter().map(|w| w.as_ptr()).collect();
+
+    let mut num_rows: i32 = 0;
+    let c_rows = unsafe {
+        poa_msa_weighted_func(
+            seq_ptrs.as_ptr(),
+            weight_ptrs.as_ptr(),
+            seq_ptrs.len() as i32,
+            alignment_type,
+            scores[0],
+            scores[1],
+            scores[2],
+            scores[3],
+            scores[4],
+            scores[5],
+            deterministic as i32,
+            &mut num_rows,
+        )
+    };
+    let mut rows: Vec<Vec<u8>> = (0..num_rows as usize)
+        .map(|i| unsafe { CStr::from_ptr(*c_rows.add(i)) }.to_bytes().to_vec())
+        .collect();
+    unsafe { poa_free_rows(c_rows, num_rows) };
+
+    let consensus = rows.pop().unwrap_or_default();
+    let empty: Vec<bool> = firsts.iter().map(|&i| seqs[i][0] == b'\0').collect();
+    let rows = with_empty_rows(rows, &empty, consensus.len());
+    Msa {
+        names,
+        rows: members.iter().map(|&unique| rows[unique].clone()).collect(),
+        consensus,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::AlignmentParams;
+
+    #[test]
+    fn test_collapse() {
+        let seqs = vec![b"ACGT\0".to_vec(), b"ACT\0".to_vec(), b"ACGT\0".to_vec()];
+        let quals = vec![b"++5+\0".to_vec(), b"+++\0".to_vec(), b"5+5+\0".to_vec()];
+
+        let (firsts, weights, members) = collapse(&seqs, &quals);
+
+        assert_eq!(firsts, vec![0, 1]);
+        assert_eq!(weights, vec![vec![30, 20, 40, 20], vec![10, 10, 10]]);
+        assert_eq!(members, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn test_collapsed_poa() {
+        let mut seqs = vec![];
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGTT", "AATGCCCGTT", "AGTGCTCGTT", "AATGCCCGTT"].iter() {
+            seqs.push(format!("{}\0", seq).into_bytes());
+        }
+        let quals = crate::quals_for(&seqs);
+        let params = AlignmentParams {
+            collapse_duplicates: true,
+            ..AlignmentParams::default()
+        };
+
+        let collapsed = crate::poa(&seqs, &quals, &params);
+
+        assert_eq!(collapsed.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(collapsed.msa.rows.len(), 6);
+        assert_eq!(collapsed.msa.rows[2], collapsed.msa.rows[5]);
+        assert_eq!(collapsed.metrics.num_reads, 6);
+        assert_eq!(collapsed.metrics, crate::poa(&seqs, &quals, &AlignmentParams::default()).metrics);
+    }
+}
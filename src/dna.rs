@@ -0,0 +1,74 @@
+//! Nucleotide sequence helpers.
+
+/// Returns the complement of a nucleotide, preserving case. IUPAC ambiguity codes are complemented, other
+/// symbols (e.g. `N` or gaps) are returned unchanged.
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' | b'u' => b'a',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        other => other,
+    }
+}
+
+/// Returns the reverse complement of a nucleotide sequence.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&base| complement(base)).collect()
+}
+
+/// Returns true if `base` is one of the nucleotides represented by the (IUPAC) code `code`, ignoring case.
+pub fn iupac_matches(code: u8, base: u8) -> bool {
+    let base = base.to_ascii_uppercase();
+    let bases: &[u8] = match code.to_ascii_uppercase() {
+        b'A' => b"A",
+        b'C' => b"C",
+        b'G' => b"G",
+        b'T' | b'U' => b"TU",
+        b'R' => b"AG",
+        b'Y' => b"CT",
+        b'S' => b"CG",
+        b'W' => b"AT",
+        b'K' => b"GT",
+        b'M' => b"AC",
+        b'B' => b"CGT",
+        b'D' => b"AGT",
+        b'H' => b"ACT",
+        b'V' => b"ACG",
+        b'N' => b"ACGTN",
+        _ => return false,
+    };
+    bases.contains(&base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_complement() {
+        assert_eq!(reverse_complement(b"AACGTN"), b"NACGTT".to_vec());
+        assert_eq!(reverse_complement(b"acgR"), b"Ycgt".to_vec());
+        assert!(reverse_complement(b"").is_empty());
+    }
+
+    #[test]
+    fn test_iupac_matches() {
+        assert!(iupac_matches(b'R', b'g'));
+        assert!(iupac_matches(b'N', b'T'));
+        assert!(!iupac_matches(b'Y', b'A'));
+        assert!(!iupac_matches(b'-', b'-'));
+    }
+}
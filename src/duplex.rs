@@ -0,0 +1,117 @@
+//! Consensus of nanopore duplex read pairs.
+use crate::dna::reverse_complement;
+use crate::msa::GAP;
+use crate::paired::combine_bases;
+use crate::pairwise::align_pair;
+use crate::params::AlignmentParams;
+
+/// Generates the consensus of a duplex pair: the template read and the complement read, sequenced from the
+/// opposite strand of the same molecule.
+///
+/// The reverse complemented complement read is aligned once against the template. Aligned bases are combined
+/// by quality (agreeing bases sum their qualities, disagreeing bases keep the better supported base). A base
+/// present in only one read is treated as disagreeing with a gap whose quality is the lower quality of the
+/// bases flanking the gap in the other read.
+/// # Arguments
+///
+/// * `template` - the template read (not null-terminated)
+/// * `template_qual` - the Phred+33 qualities of the template read
+/// * `complement` - the complement read, in sequencing orientation (not null-terminated)
+/// * `complement_qual` - the Phred+33 qualities of the complement read
+/// * `params` - the scores used to align the reads
+///
+/// # Returns
+/// * returns the duplex consensus, in the orientation of the template, and its qualities
+pub fn poa_duplex(
+    template: &[u8],
+    template_qual: &[u8],
+    complement: &[u8],
+    complement_qual: &[u8],
+    params: &AlignmentParams,
+) -> (Vec<u8>, Vec<u8>) {
+    if template.len() != template_qual.len() || complement.len() != complement_qual.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let rc = reverse_complement(complement);
+    let rc_qual: Vec<u8> = complement_qual.iter().rev().cloned().collect();
+    let (template_row, rc_row) = align_pair(template, &rc, params);
+    combine_rows(&template_row, template_qual, &rc_row, &rc_qual)
+}
+
+// combine two gapped rows, given the qualities of their ungapped bases
+pub(crate) fn combine_rows(row1: &[u8], qual1: &[u8], row2: &[u8], qual2: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let cols1 = column_quals(row1, qual1);
+    let cols2 = column_quals(row2, qual2);
+
+    let mut seq = vec![];
+    let mut qual = vec![];
+    for col in 0..row1.len() {
+        let (base1, q1) = (row1[col], cols1[col]);
+        let (base2, q2) = (row2[col], cols2[col]);
+        if base1 == GAP && base2 == GAP {
+            continue;
+        }
+        let (base, q) = combine_bases(base1, q1, base2, q2);
+        if base != GAP {
+            seq.push(base);
+            qual.push(q);
+        }
+    }
+    (seq, qual)
+}
+
+// the quality of every column of a row; gaps get the lower quality of their flanking bases
+pub(crate) fn column_quals(row: &[u8], qual: &[u8]) -> Vec<u8> {
+    let mut bases = 0;
+    let quals: Vec<Option<u8>> = row
+        .iter()
+        .map(|&b| {
+            if b == GAP {
+                None
+            } else {
+                bases += 1;
+                Some(qual[bases - 1])
+            }
+        })
+        .collect();
+
+    let mut prev = vec![None; row.len()];
+    let mut next = vec![None; row.len()];
+    for col in 1..row.len() {
+        prev[col] = quals[col - 1].or(prev[col - 1]);
+    }
+    for col in (0..row.len().saturating_sub(1)).rev() {
+        next[col] = quals[col + 1].or(next[col + 1]);
+    }
+
+    (0..row.len())
+        .map(|col| {
+            quals[col].unwrap_or_else(|| match (prev[col], next[col]) {
+                (Some(p), Some(n)) => p.min(n),
+                (Some(q), None) | (None, Some(q)) => q,
+                (None, None) => b'!',
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_rows() {
+        let (seq, qual) = combine_rows(b"ACG-TA", b"+++++", b"ACCATA", b"++5#++");
+
+        // the mismatch keeps the better C, the low quality insertion is dropped
+        assert_eq!(seq, b"ACCTA".to_vec());
+        assert_eq!(qual, b"55+55".to_vec());
+    }
+
+    #[test]
+    fn test_confident_insertion_is_kept() {
+        let (seq, qual) = combine_rows(b"AC-T", b"###", b"ACGT", b"++I+");
+        assert_eq!(seq, b"ACGT".to_vec());
+        assert_eq!(qual[2], 38 + 33);
+    }
+}
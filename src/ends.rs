@@ -0,0 +1,135 @@
+//! Consensus with free end gaps on the reads, for reads containing the whole template between ragged adapters.
+use crate::graph::PoaGraph;
+use crate::pairwise::null_terminated;
+use crate::params::{AlignmentParams, AlignmentType};
+use crate::result::ConsensusResult;
+
+/// The number of bases trimmed from each end of a read, as overhanging the template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EndTrim {
+    /// The number of bases trimmed from the start.
+    pub start: usize,
+    /// The number of bases trimmed from the end.
+    pub end: usize,
+}
+
+// the first and last query positions aligned to a node of the path, or None if no base is aligned
+fn aligned_span(path: &[(Option<u32>, Option<u32>)]) -> Option<(u32, u32)> {
+    let mut aligned = path.iter().filter_map(|&(node, pos)| node.and(pos));
+    let first = aligned.next()?;
+    Some((first, aligned.next_back().unwrap_or(first)))
+}
+
+/// Generates the consensus of reads with free leading and trailing gaps in the reads but not in the template.
+///
+/// Unlike semi-global alignment, which also leaves the ends of the consensus free, reads are expected to
+/// cover the whole template: a provisional consensus is built in semi-global mode and trimmed to the bases
+/// covered by most reads, the bases of each read overhanging it (such as the ragged remains of adapters) are
+/// trimmed, and the trimmed reads are aligned globally into the final consensus. Reads not overlapping the
+/// provisional consensus are trimmed whole and get a row of gaps, so that the rows of the alignment stay in
+/// input order.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the scores; the alignment mode is ignored
+///
+/// # Returns
+/// * returns the consensus of the trimmed reads (one row per read) and the bases trimmed from each read, in order
+pub fn poa_free_end_gaps(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+) -> (ConsensusResult, Vec<EndTrim>) {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let semi_global = AlignmentParams {
+        alignment_type: AlignmentType::SemiGlobal,
+        ..*params
+    };
+    let provisional = crate::poa(seqs, quals, &semi_global);
+    // the template, without the ends of the provisional consensus spelled by the overhangs of a few reads
+    let covered = |&(_, &coverage): &(usize, &usize)| 2 * coverage > provisional.metrics.num_reads;
+    let mut covered_bases = provisional.metrics.coverage.iter().enumerate().filter(covered);
+    let template = match covered_bases.next() {
+        Some((first, _)) => {
+            let last = covered_bases.next_back().map_or(first, |(last, _)| last);
+            &provisional.consensus[first..last + 1]
+        }
+        None => &[][..],
+    };
+    let mut graph = PoaGraph::new(&semi_global);
+    if !template.is_empty() {
+        graph.add(template, &vec![b'F'; template.len()]);
+    }
+
+    let mut trimmed_seqs = vec![];
+    let mut trimmed_quals = vec![];
+    let mut trims = vec![];
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        let seq = &seq[..seq.len().saturating_sub(1)];
+        let qual = &qual[..qual.len().saturating_sub(1)];
+        match aligned_span(&graph.align(seq).path) {
+            Some((first, last)) => {
+                let range = first as usize..last as usize + 1;
+                trimmed_seqs.push(null_terminated(&seq[range.clone()]));
+                trimmed_quals.push(null_terminated(&qual[range]));
+                trims.push(EndTrim {
+                    start: first as usize,
+                    end: seq.len() - last as usize - 1,
+                });
+            }
+            None => {
+                trimmed_seqs.push(null_terminated(b""));
+                trimmed_quals.push(null_terminated(b""));
+                trims.push(EndTrim {
+                    start: seq.len(),
+                    end: 0,
+                });
+            }
+        }
+    }
+
+    let global = AlignmentParams {
+        alignment_type: AlignmentType::Global,
+        ..*params
+    };
+    (crate::poa(&trimmed_seqs, &trimmed_quals, &global), trims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_span() {
+        let path = vec![(None, Some(0)), (Some(0), Some(1)), (Some(1), None), (Some(2), Some(2)), (None, Some(3))];
+
+        assert_eq!(aligned_span(&path), Some((1, 2)));
+        assert_eq!(aligned_span(&[(None, Some(0))]), None);
+    }
+
+    #[test]
+    fn test_poa_free_end_gaps() {
+        let template = "AATGCCCGTTAGCATCGGAT";
+        let mut seqs: Vec<Vec<u8>> = ["GT", "CAGGT", "", "TCAG"]
+            .iter()
+            .zip(["TCAA", "", "GT", "C"].iter())
+            .map(|(head, tail)| null_terminated(format!("{}{}{}", head, template, tail).as_bytes()))
+            .collect();
+        // a read without any base aligned to the template keeps its row
+        seqs.insert(1, null_terminated(b""));
+        let quals = crate::quals_for(&seqs);
+
+        let (result, trims) = poa_free_end_gaps(&seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, template.as_bytes().to_vec());
+        assert_eq!(trims[3], EndTrim { start: 0, end: 2 });
+        assert_eq!(trims[1], EndTrim::default());
+        assert_eq!(result.msa.rows.len(), seqs.len());
+        assert!(result.msa.rows[1].iter().all(|&b| b == b'-'));
+        assert_eq!(result.msa.rows[3].iter().filter(|&&b| b != b'-').count(), template.len());
+    }
+}
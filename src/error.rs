@@ -0,0 +1,120 @@
+//! Errors of the fallible consensus functions and of the parameter builder.
+use std::fmt;
+
+/// An error detected before alignment, where SPOA would otherwise compute a wrong consensus or abort, or a
+/// consensus failing the checks of its caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A score outside the range of the 8-bit scores of the SPOA alignment engine, which would be truncated.
+    ScoreOutOfRange {
+        /// The name of the score, e.g. `match_score`.
+        name: &'static str,
+        /// The score.
+        value: i32,
+    },
+    /// Alignment scores that may overflow the 32-bit lanes of the SPOA alignment engine, for a sequence
+    /// aligned against a graph of the given number of nodes.
+    ScoreOverflow {
+        /// The length of the sequence.
+        seq_len: usize,
+        /// The number of nodes of the graph.
+        graph_len: usize,
+    },
+    /// An alignment by the matrix engine of `PoaGraph::with_matrix`, which keeps its whole dynamic programming
+    /// matrices for the traceback, taking more than the given number of 32-bit cells for a sequence aligned
+    /// against a graph of the given number of nodes.
+    MatrixTooLarge {
+        /// The length of the sequence.
+        seq_len: usize,
+        /// The number of nodes of the graph.
+        graph_len: usize,
+        /// The largest number of cells allowed.
+        max_cells: usize,
+    },
+    /// Alignment parameters the SPOA alignment engine would not use as configured, e.g. affine gap scores it
+    /// would score as linear gaps.
+    InvalidParams {
+        /// Why the parameters are rejected.
+        reason: String,
+    },
+    /// Reads that are not the null-terminated sequences and Phred+33 qualities the consensus functions take.
+    InvalidInput {
+        /// Why the reads are rejected.
+        reason: String,
+    },
+    /// Reads too long in total for their graph and alignment matrices to be allocated within the given limit.
+    GraphTooLarge {
+        /// The total length of the reads.
+        len: usize,
+        /// The largest total length allowed.
+        max_len: usize,
+    },
+    /// A consensus shorter than expected, e.g. of a cluster of primer dimers.
+    ConsensusTooShort {
+        /// The length of the consensus.
+        got: usize,
+        /// The shortest expected length.
+        expected_min: usize,
+    },
+    /// A consensus longer than expected, e.g. of a cluster of chimeric reads.
+    ConsensusTooLong {
+        /// The length of the consensus.
+        got: usize,
+        /// The longest expected length.
+        expected_max: usize,
+    },
+    /// A CPU lacking the SIMD instruction set SPOA was compiled for, which would crash with an illegal
+    /// instruction.
+    UnsupportedCpu {
+        /// The instruction set SPOA was compiled for, e.g. `AVX2`.
+        needs: &'static str,
+    },
+    /// A panic while forming the consensus, caught so that it does not abort other work.
+    Panicked {
+        /// The message of the panic.
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ScoreOutOfRange { name, value } => {
+                write!(f, "{} {} is out of the range -128..=127 of the alignment engine", name, value)
+            }
+            Error::ScoreOverflow { seq_len, graph_len } => write!(
+                f,
+                "aligning a sequence of length {} to a graph of {} nodes may overflow 32-bit scores",
+                seq_len, graph_len
+            ),
+            Error::MatrixTooLarge {
+                seq_len,
+                graph_len,
+                max_cells,
+            } => write!(
+                f,
+                "aligning a sequence of length {} to a graph of {} nodes exceeds the {} cells of the matrix engine",
+                seq_len, graph_len, max_cells
+            ),
+            Error::InvalidParams { reason } => write!(f, "invalid alignment parameters: {}", reason),
+            Error::InvalidInput { reason } => write!(f, "invalid reads: {}", reason),
+            Error::GraphTooLarge { len, max_len } => {
+                write!(f, "reads of total length {} exceed the limit of {} bases", len, max_len)
+            }
+            Error::ConsensusTooShort { got, expected_min } => {
+                write!(f, "consensus of {} bases is shorter than the expected {}", got, expected_min)
+            }
+            Error::ConsensusTooLong { got, expected_max } => {
+                write!(f, "consensus of {} bases is longer than the expected {}", got, expected_max)
+            }
+            Error::UnsupportedCpu { needs } => write!(
+                f,
+                "this CPU does not support {}, which SPOA was compiled for; rebuild with the dispatch feature",
+                needs
+            ),
+            Error::Panicked { message } => write!(f, "consensus panicked: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
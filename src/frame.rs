@@ -0,0 +1,170 @@
+//! Polars adapters: consensus of the reads of a DataFrame grouped by a key column, and results as a DataFrame.
+use std::collections::{BTreeSet, HashMap};
+
+use polars::prelude::*;
+
+use crate::batch::ClusterResult;
+use crate::graph::PoaGraph;
+use crate::params::AlignmentParams;
+
+// the values of a string column, which must hold no null
+fn str_column<'a>(df: &'a DataFrame, name: &str) -> PolarsResult<Vec<&'a str>> {
+    let column = df.column(name)?.str()?;
+    column
+        .into_iter()
+        .map(|value| value.ok_or_else(|| polars_err!(ComputeError: "column {} holds nulls", name)))
+        .collect()
+}
+
+/// Generates the consensus of the reads of a DataFrame grouped by a key column.
+/// # Arguments
+///
+/// * `df` - the reads, one per row
+/// * `key` - the string column grouping the reads into clusters, e.g. the UMI or the clonotype
+/// * `seq` - the string column of the sequences
+/// * `qual` - the string column of the Phred+33 qualities of the sequences
+/// * `params` - the alignment mode and scores, shared by all clusters
+///
+/// # Returns
+/// * returns one result per cluster, in order of first appearance of their key, or a `ComputeError` for
+///   parameters or reads the alignment engine rejects, see `PoaGraph::try_add`
+pub fn poa_dataframe(
+    df: &DataFrame,
+    key: &str,
+    seq: &str,
+    qual: &str,
+    params: &AlignmentParams,
+) -> PolarsResult<Vec<ClusterResult>> {
+    let keys = str_column(df, key)?;
+    let seqs = str_column(df, seq)?;
+    let quals = str_column(df, qual)?;
+    if let Some(row) = (0..seqs.len()).find(|&row| seqs[row].len() != quals[row].len()) {
+        polars_bail!(ComputeError: "the sequence and qualities of row {} differ in length", row);
+    }
+
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut groups: Vec<(&str, Vec<usize>)> = vec![];
+    for (row, &key) in keys.iter().enumerate() {
+        let i = *index.entry(key).or_insert_with(|| {
+            groups.push((key, vec![]));
+            groups.len() - 1
+        });
+        groups[i].1.push(row);
+    }
+
+    params.validate().map_err(|e| polars_err!(ComputeError: "{}", e))?;
+    let mut graph = PoaGraph::new(params);
+    groups
+        .into_iter()
+        .map(|(key, rows)| {
+            graph.clear();
+            for row in rows {
+                graph
+                    .try_add(seqs[row].as_bytes(), quals[row].as_bytes())
+                    .map_err(|e| polars_err!(ComputeError: "cluster {}: {}", key, e))?;
+            }
+            Ok(ClusterResult {
+                id: key.to_string(),
+                result: graph.result(),
+                metadata: Default::default(),
+            })
+        })
+        .collect()
+}
+
+/// Converts batch results to a DataFrame with one row per cluster, with the columns `cluster_id`, `consensus`,
+/// `consensus_len`, `num_reads`, `mean_coverage`, `min_coverage`, `max_coverage`, `mean_identity`,
+/// `min_identity` and `min_support` of `results_schema`, then one string column per metadata key, in
+/// key order, null for the clusters without the key.
+pub fn results_to_dataframe(results: &[ClusterResult]) -> PolarsResult<DataFrame> {
+    let metrics = || results.iter().map(|r| &r.result.metrics);
+    let keys: BTreeSet<&str> = results.iter().flat_map(|r| r.metadata.keys().map(|key| key.as_str())).collect();
+    let mut columns = vec![
+        Series::new("cluster_id", results.iter().map(|r| r.id.as_str()).collect::<Vec<_>>()),
+        Series::new(
+            "consensus",
+            results.iter().map(|r| String::from_utf8_lossy(&r.result.consensus).into_owned()).collect::<Vec<_>>(),
+        ),
+        Series::new("consensus_len", metrics().map(|m| m.consensus_len as u64).collect::<Vec<_>>()),
+        Series::new("num_reads", metrics().map(|m| m.num_reads as u64).collect::<Vec<_>>()),
+        Series::new("mean_coverage", metrics().map(|m| m.mean_coverage).collect::<Vec<_>>()),
+        Series::new(
+            "min_coverage",
+            metrics().map(|m| m.coverage.iter().min().cloned().unwrap_or(0) as u64).collect::<Vec<_>>(),
+        ),
+        Series::new(
+            "max_coverage",
+            metrics().map(|m| m.coverage.iter().max().cloned().unwrap_or(0) as u64).collect::<Vec<_>>(),
+        ),
+        Series::new("mean_identity", metrics().map(|m| m.mean_identity).collect::<Vec<_>>()),
+        Series::new(
+            "min_identity",
+            metrics().map(|m| m.identity.iter().cloned().reduce(f64::min).unwrap_or(0.0)).collect::<Vec<_>>(),
+        ),
+        Series::new("min_support", metrics().map(|m| m.min_support).collect::<Vec<_>>()),
+    ];
+    for key in keys {
+        let values: Vec<Option<&str>> = results.iter().map(|r| r.metadata.get(key).map(|v| v.as_str())).collect();
+        columns.push(Series::new(key, values));
+    }
+    DataFrame::new(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msa::Msa;
+    use crate::result::ConsensusResult;
+
+    #[test]
+    fn test_results_to_dataframe() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string()],
+            rows: vec![b"ACGT".to_vec(), b"AC-T".to_vec()],
+            consensus: b"ACGT".to_vec(),
+        };
+        let results = vec![ClusterResult {
+            id: "umi_1".to_string(),
+            result: ConsensusResult::from_msa(msa),
+            metadata: vec![("sample".to_string(), "s1".to_string())].into_iter().collect(),
+        }];
+
+        let df = results_to_dataframe(&results).unwrap();
+
+        assert_eq!(df.shape(), (1, 11));
+        assert_eq!(df.column("sample").unwrap().str().unwrap().get(0), Some("s1"));
+        assert_eq!(df.column("cluster_id").unwrap().str().unwrap().get(0), Some("umi_1"));
+        assert_eq!(df.column("min_coverage").unwrap().u64().unwrap().get(0), Some(1));
+    }
+
+    #[test]
+    fn test_poa_dataframe_checks_lengths() {
+        let df = df!(
+            "umi" => &["a", "a"],
+            "seq" => &["ACGT", "ACG"],
+            "qual" => &["FFFF", "FF"],
+        )
+        .unwrap();
+
+        assert!(poa_dataframe(&df, "umi", "seq", "qual", &AlignmentParams::default()).is_err());
+        assert!(poa_dataframe(&df, "cluster", "seq", "qual", &AlignmentParams::default()).is_err());
+    }
+
+    #[test]
+    fn test_poa_dataframe_errors() {
+        let df = df!(
+            "umi" => &["a", "a"],
+            "seq" => &["ACGT", "AC\0T"],
+            "qual" => &["FFFF", "FFFF"],
+        )
+        .unwrap();
+        let params = AlignmentParams {
+            gap_open: 3,
+            ..AlignmentParams::default()
+        };
+
+        // the read holding a null byte and the positive gap score fail rather than panic
+        assert!(poa_dataframe(&df, "umi", "seq", "qual", &AlignmentParams::default()).is_err());
+        assert!(poa_dataframe(&df, "umi", "seq", "qual", &params).is_err());
+    }
+}
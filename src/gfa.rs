@@ -0,0 +1,488 @@
+//! GFA export of partial order graphs, and import of GFA graphs to continue aligning into them.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::graph::{GraphTopology, PoaGraph};
+use crate::params::AlignmentParams;
+
+/// The quality of the bases of imported paths without a `ql` tag, i.e. the unit weight SPOA gives to
+/// sequences added without qualities.
+pub const GFA_DEFAULT_QUAL: u8 = b'"';
+
+/// Options of `PoaGraph::write_gfa_with` and `PoaGraph::dot_with`. The default options write every node and edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GfaOptions {
+    /// If true, unbranched runs of nodes are compacted into single segments, see `PoaGraph::write_compact_gfa`.
+    pub compact: bool,
+    /// The minimum weight of the edges written as links.
+    pub min_edge_weight: i64,
+    /// If true, the edges bypassing a longer path between their nodes, through the edges heavy enough to be
+    /// written, are left out: the transitive reduction of the graph.
+    pub remove_transitive: bool,
+}
+
+impl PoaGraph {
+    /// Writes the graph in GFA 1.0: one segment per node, one link per edge (with its weight as a `wt:i` tag)
+    /// and one path per sequence, holding the qualities of the sequence as a `ql:Z` tag. Segments aligned to
+    /// other segments (mismatching bases of the same alignment column) name the first segment of their column
+    /// in a `cl:Z` tag.
+    pub fn write_gfa<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_gfa_with(writer, &GfaOptions::default())
+    }
+
+    /// Writes the graph in GFA 1.0 like `write_gfa`, with every unbranched run of nodes compacted into one
+    /// segment spelling the bases of its nodes, so that the graphs of real clusters remain viewable. A segment
+    /// holds the summed weight of the edges within it as a `wt:i` tag. Runs are broken at the nodes aligned to
+    /// other nodes, which keep their own segment and `cl:Z` tag, and wherever a path starts or ends, so that
+    /// every path still spells its whole sequence and the file can be imported by `from_gfa`.
+    pub fn write_compact_gfa<W: Write>(&self, writer: W) -> io::Result<()> {
+        let options = GfaOptions {
+            compact: true,
+            ..GfaOptions::default()
+        };
+        self.write_gfa_with(writer, &options)
+    }
+
+    /// Writes the graph in GFA 1.0 like `write_gfa`, compacted like `write_compact_gfa` with `options.compact`
+    /// and without the links of the edges lighter than `options.min_edge_weight` or, with
+    /// `options.remove_transitive`, bypassing a longer path through the other links, so that visualizations
+    /// and graph analyses focus on the structure supported by the reads. Paths are written whole, also
+    /// through the links left out, so that the file still imports to the whole graph with `from_gfa`.
+    pub fn write_gfa_with<W: Write>(&self, mut writer: W, options: &GfaOptions) -> io::Result<()> {
+        let topology = self.topology();
+        let chains = if options.compact {
+            compact_chains(&topology)
+        } else {
+            (0..topology.labels.len() as u32).map(|id| vec![id]).collect()
+        };
+        let mut segment_of = vec![0; topology.labels.len()];
+        for (segment, chain) in chains.iter().enumerate() {
+            for &id in chain {
+                segment_of[id as usize] = segment;
+            }
+        }
+        let weight_of: HashMap<(u32, u32), i64> =
+            topology.edges.iter().map(|edge| ((edge.tail, edge.head), edge.weight)).collect();
+
+        writeln!(writer, "H\tVN:Z:1.0")?;
+        for (segment, chain) in chains.iter().enumerate() {
+            let label: String = chain.iter().map(|&id| topology.labels[id as usize] as char).collect();
+            write!(writer, "S\t{}\t{}", segment + 1, label)?;
+            if options.compact {
+                let weight: i64 = chain.windows(2).map(|pair| weight_of[&(pair[0], pair[1])]).sum();
+                write!(writer, "\twt:i:{}", weight)?;
+            }
+            let column = topology.columns[chain[0] as usize];
+            if column != chain[0] {
+                write!(writer, "\tcl:Z:{}", segment_of[column as usize] + 1)?;
+            }
+            writeln!(writer)?;
+        }
+        // runs are entered at their first node and left at their last one only
+        for (edge, kept) in topology.edges.iter().zip(kept_edges(&topology, options)) {
+            let (tail, head) = (segment_of[edge.tail as usize], segment_of[edge.head as usize]);
+            if kept && tail != head {
+                writeln!(writer, "L\t{}\t+\t{}\t+\t0M\twt:i:{}", tail + 1, head + 1, edge.weight)?;
+            }
+        }
+        for (i, path) in topology.paths.iter().enumerate() {
+            let mut segments: Vec<usize> = path.iter().map(|&id| segment_of[id as usize]).collect();
+            segments.dedup();
+            let segments: Vec<String> = segments.iter().map(|segment| format!("{}+", segment + 1)).collect();
+            writeln!(
+                writer,
+                "P\t{}\t{}\t*\tql:Z:{}",
+                self.names()[i],
+                segments.join(","),
+                String::from_utf8_lossy(self.qual(i))
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Builds a graph from a GFA file, e.g. one written by `write_gfa` or by another tool, so that more
+    /// sequences can be aligned into it.
+    ///
+    /// The paths of the file are added in order, each base of a segment becoming one node shared by all paths
+    /// through the segment, which reproduces the graph the paths were exported from (including its alignment
+    /// columns, given by `cl:Z` tags). Paths get the qualities
+    /// of their `ql:Z` tag, or `GFA_DEFAULT_QUAL` without one. Links and segments not covered by any path are
+    /// dropped, so that a file with segments but no path, which gives no sequence to weight the graph with, is
+    /// rejected as `InvalidData`; reverse oriented segments are not supported.
+    pub fn from_gfa<R: BufRead>(reader: R, params: &AlignmentParams) -> io::Result<PoaGraph> {
+        let (segments, columns, paths) = parse_gfa(reader)?;
+        if paths.is_empty() && !segments.is_empty() {
+            return Err(invalid_data("GFA without paths (P lines) to rebuild the graph from".to_string()));
+        }
+
+        let mut graph = PoaGraph::new(params);
+        let mut node_of: HashMap<(usize, usize), i32> = HashMap::new();
+        let mut num_nodes = 0;
+        for path in paths {
+            let mut seq = vec![];
+            let mut node_ids = vec![];
+            let mut new_bases = vec![];
+            for &segment in &path.segments {
+                for (offset, &base) in segments[segment].iter().enumerate() {
+                    match node_of.get(&(segment, offset)) {
+                        Some(&id) => node_ids.push(id),
+                        None => {
+                            // a base starting its own node, aligned to the first node of its column
+                            let column = columns[segment].and_then(|column| node_of.get(&(column, 0)));
+                            node_ids.push(column.cloned().unwrap_or(-1));
+                            new_bases.push((segment, offset));
+                        }
+                    }
+                    seq.push(base);
+                }
+            }
+            if seq.is_empty() {
+                continue;
+            }
+            let qual = path.qual.unwrap_or_else(|| vec![GFA_DEFAULT_QUAL; seq.len()]);
+            if qual.len() != seq.len() {
+                return Err(invalid_data(format!("qualities of path {} do not match its length", path.name)));
+            }
+            if seq.contains(&0) || qual.contains(&0) {
+                return Err(invalid_data(format!("path {} holds a null byte", path.name)));
+            }
+
+            graph.add_on_nodes(path.name, &seq, &qual, &node_ids);
+            // new nodes are numbered in the order of their bases
+            for key in new_bases {
+                node_of.insert(key, num_nodes);
+                num_nodes += 1;
+            }
+        }
+        Ok(graph)
+    }
+}
+
+// the unbranched runs of nodes of a graph, ordered by their first node: a node continues the run of its
+// predecessor when it is the only successor of the predecessor and the predecessor its only predecessor,
+// neither is aligned to other nodes, and no path ends at the predecessor or starts at the node
+pub(crate) fn compact_chains(topology: &GraphTopology) -> Vec<Vec<u32>> {
+    let num_nodes = topology.labels.len();
+    let mut aligned = vec![false; num_nodes];
+    for (id, &column) in topology.columns.iter().enumerate() {
+        if column as usize != id {
+            aligned[id] = true;
+            aligned[column as usize] = true;
+        }
+    }
+    let (mut starts, mut ends) = (vec![false; num_nodes], vec![false; num_nodes]);
+    for path in &topology.paths {
+        if let (Some(&first), Some(&last)) = (path.first(), path.last()) {
+            starts[first as usize] = true;
+            ends[last as usize] = true;
+        }
+    }
+    let (mut in_degree, mut out_degree) = (vec![0; num_nodes], vec![0; num_nodes]);
+    let mut successor = vec![0; num_nodes];
+    for edge in &topology.edges {
+        out_degree[edge.tail as usize] += 1;
+        in_degree[edge.head as usize] += 1;
+        successor[edge.tail as usize] = edge.head as usize;
+    }
+
+    let mut next: Vec<Option<usize>> = vec![None; num_nodes];
+    let mut continued = vec![false; num_nodes];
+    for id in 0..num_nodes {
+        let head = successor[id];
+        let unbranched = out_degree[id] == 1 && in_degree[head] == 1;
+        if unbranched && !aligned[id] && !aligned[head] && !ends[id] && !starts[head] {
+            next[id] = Some(head);
+            continued[head] = true;
+        }
+    }
+    (0..num_nodes)
+        .filter(|&id| !continued[id])
+        .map(|first| {
+            let mut chain = vec![first as u32];
+            let mut id = first;
+            while let Some(head) = next[id] {
+                chain.push(head as u32);
+                id = head;
+            }
+            chain
+        })
+        .collect()
+}
+
+// whether each edge of a graph is written as a link: heavy enough and, when transitive edges are removed, the
+// only path between its nodes through the edges heavy enough
+pub(crate) fn kept_edges(topology: &GraphTopology, options: &GfaOptions) -> Vec<bool> {
+    let mut kept: Vec<bool> = topology.edges.iter().map(|edge| edge.weight >= options.min_edge_weight).collect();
+    if !options.remove_transitive {
+        return kept;
+    }
+    let num_nodes = topology.labels.len();
+    let mut successors = vec![vec![]; num_nodes];
+    let mut in_degree = vec![0; num_nodes];
+    for (edge, _) in topology.edges.iter().zip(kept.iter()).filter(|(_, &kept)| kept) {
+        successors[edge.tail as usize].push(edge.head as usize);
+        in_degree[edge.head as usize] += 1;
+    }
+    // the rank of each node in a topological order, as a node only reaches nodes of a higher rank
+    let mut rank = vec![0; num_nodes];
+    let mut queue: Vec<usize> = (0..num_nodes).filter(|&id| in_degree[id] == 0).collect();
+    let mut next_rank = 0;
+    while let Some(id) = queue.pop() {
+        rank[id] = next_rank;
+        next_rank += 1;
+        for &head in &successors[id] {
+            in_degree[head] -= 1;
+            if in_degree[head] == 0 {
+                queue.push(head);
+            }
+        }
+    }
+
+    // the last edge whose search reached each node
+    let mut seen = vec![usize::MAX; num_nodes];
+    for (i, (edge, kept)) in topology.edges.iter().zip(kept.iter_mut()).enumerate().filter(|(_, (_, kept))| **kept) {
+        let (tail, head) = (edge.tail as usize, edge.head as usize);
+        let mut stack: Vec<usize> = successors[tail].iter().cloned().filter(|&id| id != head).collect();
+        while let Some(id) = stack.pop() {
+            if id == head {
+                *kept = false;
+                break;
+            }
+            if seen[id] == i || rank[id] > rank[head] {
+                continue;
+            }
+            seen[id] = i;
+            stack.extend(successors[id].iter().cloned());
+        }
+    }
+    kept
+}
+
+#[derive(Debug, PartialEq)]
+struct GfaPath {
+    name: String,
+    segments: Vec<usize>,
+    qual: Option<Vec<u8>>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+type ParsedGfa = (Vec<Vec<u8>>, Vec<Option<usize>>, Vec<GfaPath>);
+
+// returns the labels of the segments, the segment each segment is aligned to, and the paths through them,
+// all as indices into the segments
+fn parse_gfa<R: BufRead>(reader: R) -> io::Result<ParsedGfa> {
+    let mut segment_index: HashMap<String, usize> = HashMap::new();
+    let mut segments = vec![];
+    let mut raw_columns = vec![];
+    let mut raw_paths = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.trim_end().split('\t').collect();
+        match fields[0] {
+            "S" if fields.len() >= 3 => {
+                if segment_index.insert(fields[1].to_string(), segments.len()).is_some() {
+                    return Err(invalid_data(format!("duplicate segment {}", fields[1])));
+                }
+                segments.push(fields[2].as_bytes().to_vec());
+                raw_columns.push(fields[3..].iter().find_map(|field| field.strip_prefix("cl:Z:")).map(String::from));
+            }
+            "P" if fields.len() >= 3 => {
+                let qual = fields[3..]
+                    .iter()
+                    .find_map(|field| field.strip_prefix("ql:Z:"))
+                    .map(|qual| qual.as_bytes().to_vec());
+                raw_paths.push((fields[1].to_string(), fields[2].to_string(), qual));
+            }
+            "S" | "P" => return Err(invalid_data(format!("truncated GFA line: {}", line))),
+            _ => {}
+        }
+    }
+
+    // a column tag aligns the base of a segment to that of the first segment of its column
+    let mut columns = vec![];
+    for (segment, column) in raw_columns.iter().enumerate() {
+        columns.push(match column {
+            Some(id) => match segment_index.get(id) {
+                Some(&index)
+                    if segments[segment].len() == 1 && segments[index].len() == 1 && raw_columns[index].is_none() =>
+                {
+                    Some(index)
+                }
+                Some(_) => return Err(invalid_data(format!("column tag {} not aligning two single bases", id))),
+                None => return Err(invalid_data(format!("unknown segment {} in column tag", id))),
+            },
+            None => None,
+        });
+    }
+
+    let mut paths = vec![];
+    for (name, segment_list, qual) in raw_paths {
+        let mut path_segments = vec![];
+        for segment in segment_list.split(',') {
+            let (id, orientation) = segment.split_at(segment.len().saturating_sub(1));
+            if orientation != "+" {
+                return Err(invalid_data(format!("unsupported orientation of segment {} in path {}", segment, name)));
+            }
+            match segment_index.get(id) {
+                Some(&index) => path_segments.push(index),
+                None => return Err(invalid_data(format!("unknown segment {} in path {}", id, name))),
+            }
+        }
+        paths.push(GfaPath {
+            name,
+            segments: path_segments,
+            qual,
+        });
+    }
+    check_acyclic(&columns, &paths)?;
+    Ok((segments, columns, paths))
+}
+
+// checks that no path enters a column, a segment and those aligned to it, that it or another path left before,
+// as the graph would then hold a cycle, on which the topological sort of SPOA does not terminate
+fn check_acyclic(columns: &[Option<usize>], paths: &[GfaPath]) -> io::Result<()> {
+    let column = |segment: usize| columns[segment].unwrap_or(segment);
+    let mut successors = vec![vec![]; columns.len()];
+    let mut in_degree = vec![0; columns.len()];
+    for path in paths {
+        for pair in path.segments.windows(2) {
+            successors[column(pair[0])].push(column(pair[1]));
+            in_degree[column(pair[1])] += 1;
+        }
+    }
+    let mut queue: Vec<usize> = (0..columns.len()).filter(|&id| in_degree[id] == 0).collect();
+    let mut num_sorted = 0;
+    while let Some(id) = queue.pop() {
+        num_sorted += 1;
+        for &head in &successors[id] {
+            in_degree[head] -= 1;
+            if in_degree[head] == 0 {
+                queue.push(head);
+            }
+        }
+    }
+    if num_sorted < columns.len() {
+        return Err(invalid_data("paths through the segments form a cycle".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::GraphEdge;
+
+    #[test]
+    fn test_parse_gfa() {
+        let gfa = "H\tVN:Z:1.0\nS\ts1\tAC\nS\ts2\tG\nS\ts3\tT\tcl:Z:s2\nL\ts1\t+\ts2\t+\t0M\n\
+                   P\tread_1\ts1+,s2+\t*\tql:Z:FFF\nP\tread_2\ts1+,s3+\t*\n";
+        let (segments, columns, paths) = parse_gfa(gfa.as_bytes()).unwrap();
+
+        assert_eq!(segments, vec![b"AC".to_vec(), b"G".to_vec(), b"T".to_vec()]);
+        assert_eq!(columns, vec![None, None, Some(1)]);
+        assert_eq!(
+            paths[0],
+            GfaPath {
+                name: "read_1".to_string(),
+                segments: vec![0, 1],
+                qual: Some(b"FFF".to_vec()),
+            }
+        );
+        assert_eq!(paths[1].segments, vec![0, 2]);
+        assert_eq!(paths[1].qual, None);
+
+        assert!(parse_gfa("S\t1\tA\nP\tr\t1-\t*\n".as_bytes()).is_err());
+        assert!(parse_gfa("S\t1\tA\nP\tr\t2+\t*\n".as_bytes()).is_err());
+        assert!(parse_gfa("S\t1\tA\nS\t1\tC\n".as_bytes()).is_err());
+        // column tags align single bases to the first of their column
+        assert!(parse_gfa("S\t1\tA\nS\t2\tCG\tcl:Z:1\n".as_bytes()).is_err());
+        assert!(parse_gfa("S\t1\tA\nS\t2\tC\tcl:Z:1\nS\t3\tG\tcl:Z:2\n".as_bytes()).is_err());
+        // paths returning to a column would make the graph cyclic
+        assert!(parse_gfa("S\t1\tA\nS\t2\tC\nP\tr1\t1+,2+\t*\nP\tr2\t2+,1+\t*\n".as_bytes()).is_err());
+        assert!(parse_gfa("S\t1\tA\nS\t2\tC\tcl:Z:1\nP\tr\t1+,2+\t*\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_compact_chains() {
+        let edge = |tail, head| GraphEdge { tail, head, weight: 1 };
+        // A-C, then G or T aligned in the same column, then A-C
+        let mut topology = GraphTopology {
+            labels: b"ACGTAC".to_vec(),
+            columns: vec![0, 1, 2, 2, 4, 5],
+            edges: vec![edge(0, 1), edge(1, 2), edge(1, 3), edge(2, 4), edge(3, 4), edge(4, 5)],
+            paths: vec![vec![0, 1, 2, 4, 5], vec![0, 1, 3, 4, 5]],
+        };
+
+        assert_eq!(compact_chains(&topology), vec![vec![0, 1], vec![2], vec![3], vec![4, 5]]);
+
+        // a path starting within a run breaks it
+        topology.paths.push(vec![5]);
+        assert_eq!(compact_chains(&topology), vec![vec![0, 1], vec![2], vec![3], vec![4], vec![5]]);
+    }
+
+    #[test]
+    fn test_kept_edges() {
+        let edge = |tail, head, weight| GraphEdge { tail, head, weight };
+        // A-C-G, with the edge A-G of a deletion of C, and a light edge A-T
+        let topology = GraphTopology {
+            labels: b"ACGT".to_vec(),
+            columns: vec![0, 1, 2, 2],
+            edges: vec![edge(0, 1, 5), edge(1, 2, 5), edge(0, 2, 2), edge(0, 3, 1)],
+            paths: vec![],
+        };
+        let options = |min_edge_weight, remove_transitive| GfaOptions {
+            compact: false,
+            min_edge_weight,
+            remove_transitive,
+        };
+
+        assert_eq!(kept_edges(&topology, &options(0, false)), vec![true, true, true, true]);
+        assert_eq!(kept_edges(&topology, &options(2, false)), vec![true, true, true, false]);
+        assert_eq!(kept_edges(&topology, &options(0, true)), vec![true, true, false, true]);
+        // an edge is only bypassed through the edges heavy enough
+        assert_eq!(kept_edges(&topology, &options(6, true)), vec![false, false, false, false]);
+        assert_eq!(kept_edges(&topology, &options(2, true)), vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_gfa_round_trip() {
+        let params = AlignmentParams::default();
+        let mut graph = PoaGraph::new(&params);
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC"].iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+        let mut gfa = vec![];
+        graph.write_gfa(&mut gfa).unwrap();
+
+        let mut imported = PoaGraph::from_gfa(&gfa[..], &params).unwrap();
+        assert_eq!(imported.topology(), graph.topology());
+        assert_eq!(imported.names(), graph.names());
+
+        let mut compact = vec![];
+        graph.write_compact_gfa(&mut compact).unwrap();
+        assert!(compact.len() < gfa.len());
+        assert_eq!(PoaGraph::from_gfa(&compact[..], &params).unwrap().topology(), graph.topology());
+
+        // continue aligning into the imported graph
+        for seq in ["AGTGCTCGTT", "AATGCTCGTT"].iter() {
+            let qual = "F".repeat(seq.len());
+            graph.add(seq.as_bytes(), qual.as_bytes());
+            imported.add(seq.as_bytes(), qual.as_bytes());
+        }
+        assert_eq!(imported.consensus(), b"AATGCCCGTT".to_vec());
+        assert_eq!(imported.msa().rows, graph.msa().rows);
+    }
+
+    #[test]
+    fn test_from_gfa_without_paths() {
+        let gfa = "H\tVN:Z:1.0\nS\t1\tAC\nS\t2\tG\nL\t1\t+\t2\t+\t0M\n";
+
+        let error = PoaGraph::from_gfa(gfa.as_bytes(), &AlignmentParams::default()).err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
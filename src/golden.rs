@@ -0,0 +1,209 @@
+//! A small bundled corpus of clusters with their expected consensus, to check that a build, e.g. against a
+//! system libspoa or with another SIMD level, or a new backend reproduces the reference results.
+use crate::backend::{poa_with_backend, ConsensusBackend};
+use crate::matrix::{try_poa_with_matrix, SubstitutionMatrix};
+use crate::pairwise::null_terminated;
+use crate::params::{AlignmentParams, Preset};
+use crate::stream::FastqReader;
+
+// the name, scores, reads (FASTQ) and expected consensus (FASTA) of each cluster of the corpus
+const CORPUS: [(&str, Preset, &str, &str); 3] = [
+    (
+        "ont_amplicon",
+        Preset::Ont,
+        include_str!("../golden/ont_amplicon.fastq"),
+        include_str!("../golden/ont_amplicon.consensus.fa"),
+    ),
+    (
+        "hifi",
+        Preset::Hifi,
+        include_str!("../golden/hifi.fastq"),
+        include_str!("../golden/hifi.consensus.fa"),
+    ),
+    (
+        "protein",
+        Preset::Protein,
+        include_str!("../golden/protein.fastq"),
+        include_str!("../golden/protein.consensus.fa"),
+    ),
+];
+
+/// A cluster of the golden corpus, see `golden_corpus`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCluster {
+    /// The name of the cluster.
+    pub name: String,
+    /// The alignment mode and scores the cluster is aligned with.
+    pub params: AlignmentParams,
+    /// The matrix scoring the substitutions of the cluster instead of the match and mismatch scores, if any.
+    pub matrix: Option<SubstitutionMatrix>,
+    /// The reads (not null-terminated).
+    pub seqs: Vec<Vec<u8>>,
+    /// The Phred+33 qualities of the reads (not null-terminated).
+    pub quals: Vec<Vec<u8>>,
+    /// The expected consensus.
+    pub expected: Vec<u8>,
+}
+
+/// The consensus of a cluster of the golden corpus generated by a backend, see `check_golden`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenOutcome {
+    /// The name of the cluster.
+    pub name: String,
+    /// The consensus generated by the backend.
+    pub consensus: Vec<u8>,
+    /// The expected consensus.
+    pub expected: Vec<u8>,
+}
+
+impl GoldenOutcome {
+    /// Returns whether the backend reproduced the expected consensus.
+    pub fn passed(&self) -> bool {
+        self.consensus == self.expected
+    }
+}
+
+// the sequence of a FASTA file of a single record, which may span several lines
+fn fasta_sequence(fasta: &str) -> Vec<u8> {
+    fasta
+        .lines()
+        .filter(|line| !line.starts_with('>'))
+        .flat_map(|line| line.trim().bytes())
+        .collect()
+}
+
+/// Returns the clusters of the golden corpus: an amplicon of nanopore-like reads, about 5% of their bases
+/// in error, PacBio HiFi-like reads of a longer template, and variants of the human ubiquitin protein. The
+/// nucleotide clusters are aligned with the scores of the preset of their platform and the protein one with
+/// BLOSUM62. The reads are simulated, drawn from fixed templates with the errors of their platform.
+///
+/// The expected consensus of each cluster is the output recorded by the reference engine of `NaiveBackend`,
+/// which follows SPOA's algorithms, the protein cluster scored by BLOSUM62 with the dynamic programming the
+/// matrix engine of the C++ shim runs: not the template the reads were drawn from, which a consensus need not
+/// recover. `record_golden` records them again, e.g. with a reference SPOA build.
+pub fn golden_corpus() -> Vec<GoldenCluster> {
+    CORPUS
+        .iter()
+        .map(|&(name, preset, fastq, consensus)| {
+            let (seqs, quals) = FastqReader::new(fastq.as_bytes())
+                .map(|record| {
+                    let record = record.expect("malformed FASTQ record in the golden corpus");
+                    (record.seq, record.qual)
+                })
+                .unzip();
+            GoldenCluster {
+                name: name.to_string(),
+                params: AlignmentParams::preset(preset),
+                matrix: if preset == Preset::Protein {
+                    Some(SubstitutionMatrix::blosum62())
+                } else {
+                    None
+                },
+                seqs,
+                quals,
+                expected: fasta_sequence(consensus),
+            }
+        })
+        .collect()
+}
+
+// the consensus of a cluster: the clusters scored by a matrix through `try_poa_with_matrix`, empty if it fails,
+// and the others with the backend
+fn cluster_consensus<B: ConsensusBackend + ?Sized>(backend: &B, cluster: &GoldenCluster) -> Vec<u8> {
+    match &cluster.matrix {
+        Some(matrix) => {
+            let seqs: Vec<Vec<u8>> = cluster.seqs.iter().map(|seq| null_terminated(seq)).collect();
+            let quals: Vec<Vec<u8>> = cluster.quals.iter().map(|qual| null_terminated(qual)).collect();
+            try_poa_with_matrix(&seqs, &quals, &cluster.params, matrix)
+                .map(|result| result.consensus)
+                .unwrap_or_default()
+        }
+        None => {
+            let seqs: Vec<&[u8]> = cluster.seqs.iter().map(|seq| seq.as_slice()).collect();
+            let quals: Vec<&[u8]> = cluster.quals.iter().map(|qual| qual.as_slice()).collect();
+            poa_with_backend(backend, &seqs, &quals, &cluster.params).consensus
+        }
+    }
+}
+
+/// Generates the consensus of every cluster of the golden corpus with a backend, to validate a build or a
+/// new backend against the reference results. The clusters scored by a matrix are aligned by
+/// `try_poa_with_matrix` whatever the backend.
+/// # Arguments
+///
+/// * `backend` - the alignment engine, e.g. `SpoaBackend`
+///
+/// # Returns
+/// * returns the consensus and expected consensus of each cluster, in corpus order
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{check_golden, SpoaBackend};
+///
+///     let outcomes = check_golden(&SpoaBackend);
+///     assert_eq!(outcomes.len(), 3);
+///     assert!(outcomes.iter().all(|outcome| outcome.passed()));
+/// ```
+pub fn check_golden<B: ConsensusBackend + ?Sized>(backend: &B) -> Vec<GoldenOutcome> {
+    golden_corpus()
+        .into_iter()
+        .map(|cluster| GoldenOutcome {
+            consensus: cluster_consensus(backend, &cluster),
+            name: cluster.name,
+            expected: cluster.expected,
+        })
+        .collect()
+}
+
+/// Writes the consensus of every cluster of the golden corpus generated by a backend as its expected
+/// consensus, `<name>.consensus.fa` in `dir`, to record the corpus again with a reference build.
+/// # Arguments
+///
+/// * `backend` - the alignment engine of the reference build, e.g. `SpoaBackend`
+/// * `dir` - the directory of the corpus, `golden` in the crate
+pub fn record_golden<B: ConsensusBackend + ?Sized>(backend: &B, dir: &std::path::Path) -> std::io::Result<()> {
+    for outcome in check_golden(backend) {
+        let mut fasta = format!(">{}\n", outcome.name);
+        for line in outcome.consensus.chunks(80) {
+            fasta.push_str(&String::from_utf8_lossy(line));
+            fasta.push('\n');
+        }
+        std::fs::write(dir.join(format!("{}.consensus.fa", outcome.name)), fasta)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SpoaBackend;
+
+    #[test]
+    fn test_golden_corpus() {
+        let corpus = golden_corpus();
+        let names: Vec<&str> = corpus.iter().map(|cluster| cluster.name.as_str()).collect();
+        assert_eq!(names, vec!["ont_amplicon", "hifi", "protein"]);
+        for cluster in &corpus {
+            assert!(cluster.seqs.len() >= 5);
+            assert_eq!(cluster.seqs.len(), cluster.quals.len());
+        }
+        assert_eq!(corpus[2].matrix, Some(SubstitutionMatrix::blosum62()));
+        assert!(corpus[..2].iter().all(|cluster| cluster.matrix.is_none()));
+    }
+
+    #[test]
+    fn test_check_golden() {
+        for outcome in check_golden(&SpoaBackend) {
+            assert!(outcome.passed(), "{}: {:?}", outcome.name, String::from_utf8_lossy(&outcome.consensus));
+        }
+    }
+
+    #[cfg(feature = "naive")]
+    #[test]
+    fn test_check_golden_naive() {
+        for outcome in check_golden(&crate::naive::NaiveBackend) {
+            assert!(outcome.passed(), "{}: {:?}", outcome.name, String::from_utf8_lossy(&outcome.consensus));
+        }
+    }
+}
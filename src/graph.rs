@@ -0,0 +1,1241 @@
+//! Incremental construction of a partial order graph, one sequence at a time.
+use libc::{c_char, c_void};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ops::Range;
+
+use crate::error::Error;
+use crate::gfa::{compact_chains, kept_edges, GfaOptions};
+use crate::matrix::{check_matrix_size, SubstitutionMatrix};
+use crate::msa::{with_empty_rows, Msa};
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+extern "C" {
+    fn poa_graph_new(
+        alignment_type: i32,
+        match_score: i32,
+        mismatch_score: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        deterministic: i32,
+    ) -> *mut c_void;
+    fn poa_graph_new_matrix(
+        alignment_type: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        scores: *const i32,
+    ) -> *mut c_void;
+    fn poa_graph_free(graph: *mut c_void);
+    fn poa_graph_clear(graph: *mut c_void);
+    fn poa_graph_add(graph: *mut c_void, seq: *const u8, qual: *const u8);
+    fn poa_graph_score(graph: *mut c_void, seq: *const u8) -> i32;
+    fn poa_graph_align(
+        graph: *mut c_void,
+        seq: *const u8,
+        node_ids: *mut i32,
+        seq_ids: *mut i32,
+        score: *mut i32,
+    ) -> i32;
+    fn poa_graph_consensus(graph: *mut c_void) -> *mut c_char;
+    fn poa_graph_consensus_path(graph: *mut c_void, node_ids: *mut i32) -> i32;
+    fn poa_graph_msa(graph: *mut c_void, num_rows: *mut i32) -> *mut *mut c_char;
+    fn poa_graph_add_alignment(graph: *mut c_void, seq: *const u8, qual: *const u8, node_ids: *const i32);
+    fn poa_graph_num_nodes(graph: *mut c_void) -> i32;
+    fn poa_graph_num_edges(graph: *mut c_void) -> i32;
+    fn poa_graph_topology(
+        graph: *mut c_void,
+        labels: *mut u8,
+        columns: *mut i32,
+        tails: *mut i32,
+        heads: *mut i32,
+        weights: *mut i64,
+    );
+    fn poa_graph_path(graph: *mut c_void, seq_index: i32, node_ids: *mut i32);
+    fn poa_graph_subgraph(graph: *mut c_void, subgraph: *mut c_void, begin: i32, end: i32, node_ids: *mut i32) -> i32;
+    fn poa_free_string(string: *mut c_char);
+    fn poa_free_rows(rows: *mut *mut c_char, num_rows: i32);
+}
+
+// checks that a read is what the C++ side takes: as many qualities as bases, and no null byte, which would end
+// the sequence or its qualities early, SPOA then throwing across the FFI boundary on their different lengths
+fn check_read(seq: &[u8], qual: &[u8]) -> Result<(), Error> {
+    let invalid = |reason: String| Err(Error::InvalidInput { reason });
+    if seq.len() != qual.len() {
+        return invalid(format!("{} bases but {} qualities", seq.len(), qual.len()));
+    }
+    if seq.contains(&0) || qual.contains(&0) {
+        return invalid("a null byte in the sequence or its qualities".to_string());
+    }
+    Ok(())
+}
+
+// the rank of the alignment column of each node in a topological order of the columns, the nodes of a column
+// being contracted into one, or None for the nodes of columns on a cycle
+fn column_ranks(topology: &GraphTopology) -> Vec<Option<usize>> {
+    let num_nodes = topology.labels.len();
+    let mut successors: Vec<Vec<usize>> = vec![vec![]; num_nodes];
+    let mut in_degrees = vec![0; num_nodes];
+    for edge in &topology.edges {
+        let tail = topology.columns[edge.tail as usize] as usize;
+        let head = topology.columns[edge.head as usize] as usize;
+        successors[tail].push(head);
+        in_degrees[head] += 1;
+    }
+    // the columns are numbered by their smallest node id
+    let mut stack: Vec<usize> =
+        (0..num_nodes).filter(|&id| topology.columns[id] as usize == id && in_degrees[id] == 0).collect();
+    let mut column_rank = vec![None; num_nodes];
+    let mut rank = 0;
+    while let Some(column) = stack.pop() {
+        column_rank[column] = Some(rank);
+        rank += 1;
+        for &next in &successors[column] {
+            in_degrees[next] -= 1;
+            if in_degrees[next] == 0 {
+                stack.push(next);
+            }
+        }
+    }
+    topology.columns.iter().map(|&column| column_rank[column as usize]).collect()
+}
+
+/// An edge of a partial order graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphEdge {
+    /// The id of the node the edge leaves.
+    pub tail: u32,
+    /// The id of the node the edge enters.
+    pub head: u32,
+    /// The summed weight of the sequences traversing the edge.
+    pub weight: i64,
+}
+
+/// The nodes, edges and sequence paths of a `PoaGraph`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GraphTopology {
+    /// The base of each node, indexed by node id.
+    pub labels: Vec<u8>,
+    /// The alignment column of each node, as the smallest id among the node and the nodes aligned to it.
+    pub columns: Vec<u32>,
+    /// The edges of the graph.
+    pub edges: Vec<GraphEdge>,
+    /// The nodes spelling each sequence of the graph, in input order.
+    pub paths: Vec<Vec<u32>>,
+}
+
+/// The alignment of a query against a `PoaGraph`, see `PoaGraph::align`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GraphAlignment {
+    /// The alignment score.
+    pub score: i32,
+    /// The aligned node id and query position of each alignment step, in graph order; the node is `None` for
+    /// an insertion in the query and the position is `None` for a deletion.
+    pub path: Vec<(Option<u32>, Option<u32>)>,
+    /// The CIGAR string of the query against the path, with `M`, `I` and `D` operations and soft clips (`S`)
+    /// for the query ends left out of a local or semi-global alignment.
+    pub cigar: String,
+}
+
+// appends `len` operations `op` to a CIGAR string
+fn push_cigar(cigar: &mut String, len: usize, op: char) {
+    if len > 0 {
+        cigar.push_str(&len.to_string());
+        cigar.push(op);
+    }
+}
+
+/// Returns the CIGAR string of an alignment path of a query of length `query_len`, see `GraphAlignment`.
+fn path_cigar(path: &[(Option<u32>, Option<u32>)], query_len: usize) -> String {
+    let first = path.iter().find_map(|&(_, pos)| pos).map_or(query_len, |pos| pos as usize);
+    let last = path.iter().rev().find_map(|&(_, pos)| pos).map_or(query_len, |pos| pos as usize + 1);
+
+    let mut cigar = String::new();
+    push_cigar(&mut cigar, first, 'S');
+    let mut run: Option<(char, usize)> = None;
+    for step in path {
+        let op = match step {
+            (Some(_), Some(_)) => 'M',
+            (None, _) => 'I',
+            (Some(_), None) => 'D',
+        };
+        run = match run {
+            Some((prev, len)) if prev == op => Some((op, len + 1)),
+            Some((prev, len)) => {
+                push_cigar(&mut cigar, len, prev);
+                Some((op, 1))
+            }
+            None => Some((op, 1)),
+        };
+    }
+    if let Some((op, len)) = run {
+        push_cigar(&mut cigar, len, op);
+    }
+    push_cigar(&mut cigar, query_len - last.max(first), 'S');
+    cigar
+}
+
+// the DOT format of a graph topology with the nodes of its consensus and the edges flagged `kept`, each run of
+// nodes of `chains` drawn as a single node named after its first node and spelling the bases of the run
+fn topology_dot(topology: &GraphTopology, consensus: &[u32], chains: &[Vec<u32>], kept: &[bool]) -> String {
+    let mut in_consensus = vec![false; topology.labels.len()];
+    for &id in consensus {
+        in_consensus[id as usize] = true;
+    }
+    let mut chain_of = vec![0; topology.labels.len()];
+    for chain in chains {
+        for &id in chain {
+            chain_of[id as usize] = chain[0];
+        }
+    }
+    let mut dot = String::from("digraph poa {\n    rankdir = LR\n");
+    for chain in chains {
+        let label: String = chain.iter().map(|&id| topology.labels[id as usize] as char).collect();
+        let style = if in_consensus[chain[0] as usize] { ", style = filled, fillcolor = goldenrod1" } else { "" };
+        dot.push_str(&format!("    {} [label = \"{}|{}\"{}]\n", chain[0], chain[0], label, style));
+    }
+    for (edge, &kept) in topology.edges.iter().zip(kept.iter()) {
+        let (tail, head) = (chain_of[edge.tail as usize], chain_of[edge.head as usize]);
+        if kept && tail != head {
+            dot.push_str(&format!("    {} -> {} [label = \"{}\"]\n", tail, head, edge.weight));
+        }
+    }
+    // each node of an alignment column after the first is joined to the previous one
+    let mut previous: HashMap<u32, u32> = HashMap::new();
+    for (id, &col) in topology.columns.iter().enumerate() {
+        if let Some(prev) = previous.insert(col, chain_of[id]) {
+            dot.push_str(&format!("    {} -> {} [style = dotted, arrowhead = none]\n", prev, chain_of[id]));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The part of a graph spanning a range of consensus positions, see `PoaGraph::subgraph`.
+pub struct Subgraph {
+    /// The nodes and edges of the part of the graph, with new node ids.
+    pub graph: PoaGraph,
+    /// The id in the whole graph of each node of the subgraph, indexed by subgraph node id.
+    pub node_ids: Vec<u32>,
+}
+
+/// The consensus of a graph after new sequences were appended to it, see `PoaGraph::append`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConsensusUpdate {
+    /// The consensus of all the sequences of the graph.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// True if the consensus differs from the one before the sequences were appended.
+    pub changed: bool,
+    /// The number of appended sequences.
+    pub added: usize,
+}
+
+/// The state of a graph after a sequence was added to it, see `PoaGraph::add_traced`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GraphStep {
+    /// The index of the sequence added, among the sequences of the graph.
+    pub seq_index: usize,
+    /// The number of nodes of the graph.
+    pub num_nodes: usize,
+    /// The number of edges of the graph.
+    pub num_edges: usize,
+    /// The number of nodes created by the sequence, for its bases aligned to no node of the same base.
+    pub new_nodes: usize,
+    /// The consensus of the sequences added so far.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The graph in Graphviz DOT format, see `PoaGraph::dot`, for graphs small enough to be drawn.
+    pub dot: Option<String>,
+}
+
+/// A partial order graph that sequences are aligned into one at a time.
+///
+/// The graph keeps a copy of every sequence it was built from, so that graphs can be merged and their
+/// alignment reported with the original names.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{AlignmentParams, PoaGraph};
+///
+///     let mut graph = PoaGraph::new(&AlignmentParams::default());
+///     graph.add(b"ACGTT", b"FFFFF");
+///     graph.add(b"ACTT", b"FFFF");
+///     let consensus = graph.consensus();
+/// ```
+pub struct PoaGraph {
+    ptr: *mut c_void,
+    params: AlignmentParams,
+    // the matrix scoring substitutions instead of the match and mismatch scores, if set
+    matrix: Option<SubstitutionMatrix>,
+    names: Vec<String>,
+    seqs: Vec<Vec<u8>>,
+    quals: Vec<Vec<u8>>,
+}
+
+// the graph and its engine are owned exclusively by this handle
+unsafe impl Send for PoaGraph {}
+
+impl PoaGraph {
+    /// Creates an empty graph aligning sequences with `params`. With `params.wildcard_score`, the sequences are
+    /// aligned by the matrix engine of `with_matrix`, with a matrix of the match and mismatch scores in which
+    /// `N` scores the wildcard score against any base.
+    ///
+    /// Panics if a gap score is positive, which the alignment engine rejects, or if the wildcard score is out of
+    /// -128..=127, see `AlignmentParams::validate`.
+    pub fn new(params: &AlignmentParams) -> PoaGraph {
+        if let Some(matrix) = SubstitutionMatrix::wildcard(params) {
+            return PoaGraph::with_engine_matrix(params, &matrix.unwrap_or_else(|e| panic!("{}", e)));
+        }
+        crate::cpu::assert_cpu();
+        params.check_engine_scores();
+        let ptr = unsafe {
+            poa_graph_new(
+                params.alignment_type as i32,
+                params.match_score,
+                params.mismatch_score,
+                params.gap_open,
+                params.gap_extend,
+                params.gap2_open,
+                params.gap2_extend,
+                params.deterministic as i32,
+            )
+        };
+        PoaGraph::from_ptr(ptr, params, None)
+    }
+
+    /// Creates an empty graph scoring substitutions with `matrix` instead of the match and mismatch scores of
+    /// `params`, e.g. BLOSUM62 for protein consensus, see `try_poa_with_matrix`. SPOA aligns the sequences with
+    /// the matrix engine of the C++ shim, whose dynamic programming is that of SPOA's scalar engine in 32-bit
+    /// scores, with the alignment mode and gap scores of `params`. It keeps the whole matrices of each alignment
+    /// for the traceback, so adding a sequence whose alignment would take more than 2^28 cells (1 GiB) panics,
+    /// or fails with `Error::MatrixTooLarge` in `try_add`.
+    ///
+    /// Panics if a gap score is positive, which the alignment engine rejects, see `AlignmentParams::validate`,
+    /// or if `params.wildcard_score` is set, as the matrix scores `N` itself.
+    pub fn with_matrix(params: &AlignmentParams, matrix: &SubstitutionMatrix) -> PoaGraph {
+        if params.wildcard_score.is_some() {
+            panic!("A wildcard score cannot be combined with a substitution matrix, which scores N itself");
+        }
+        PoaGraph::with_engine_matrix(params, matrix)
+    }
+
+    // a graph aligning sequences with the matrix engine, without checking the wildcard score, which the
+    // matrices of `new` implement
+    fn with_engine_matrix(params: &AlignmentParams, matrix: &SubstitutionMatrix) -> PoaGraph {
+        crate::cpu::assert_cpu();
+        params.check_engine_scores();
+        let table = matrix.table();
+        let ptr = unsafe {
+            poa_graph_new_matrix(
+                params.alignment_type as i32,
+                params.gap_open,
+                params.gap_extend,
+                params.gap2_open,
+                params.gap2_extend,
+                table.as_ptr(),
+            )
+        };
+        PoaGraph::from_ptr(ptr, params, Some(matrix.clone()))
+    }
+
+    // the handle of a graph and engine allocated by the C++ side, holding no sequence yet
+    fn from_ptr(ptr: *mut c_void, params: &AlignmentParams, matrix: Option<SubstitutionMatrix>) -> PoaGraph {
+        PoaGraph {
+            ptr,
+            params: *params,
+            matrix,
+            names: vec![],
+            seqs: vec![],
+            quals: vec![],
+        }
+    }
+
+    // an empty graph aligning sequences as this one does
+    fn empty_like(&self) -> PoaGraph {
+        match &self.matrix {
+            Some(matrix) => PoaGraph::with_engine_matrix(&self.params, matrix),
+            None => PoaGraph::new(&self.params),
+        }
+    }
+
+    /// Returns the parameters the graph aligns sequences with.
+    pub fn params(&self) -> &AlignmentParams {
+        &self.params
+    }
+
+    /// Returns the matrix the graph scores substitutions with, if it was created by `with_matrix`, or with a
+    /// wildcard score by `new`.
+    pub fn matrix(&self) -> Option<&SubstitutionMatrix> {
+        self.matrix.as_ref()
+    }
+
+    /// Returns the number of sequences in the graph.
+    pub fn len(&self) -> usize {
+        self.seqs.len()
+    }
+
+    /// Returns true if no sequence was added to the graph.
+    pub fn is_empty(&self) -> bool {
+        self.seqs.is_empty()
+    }
+
+    /// Aligns a sequence into the graph, naming it `seq_<index>`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - the sequence (not null-terminated)
+    /// * `qual` - the qualities of the sequence (not null-terminated), weighting its bases
+    pub fn add(&mut self, seq: &[u8], qual: &[u8]) {
+        let name = format!("seq_{}", self.seqs.len());
+        self.add_named(name, seq, qual);
+    }
+
+    /// Aligns a named sequence into the graph, see `add`.
+    ///
+    /// Panics if the sequence and its qualities differ in length or hold a null byte, or if the scores are out
+    /// of the range of the alignment engine or may overflow, see `try_add`.
+    pub fn add_named(&mut self, name: String, seq: &[u8], qual: &[u8]) {
+        if let Err(e) = check_read(seq, qual).and_then(|()| self.check_scores(seq.len())) {
+            panic!("{}", e);
+        }
+        let seq = null_terminated(seq);
+        let qual = null_terminated(qual);
+        unsafe { poa_graph_add(self.ptr, seq.as_ptr(), qual.as_ptr()) };
+        self.names.push(name);
+        self.seqs.push(seq);
+        self.quals.push(qual);
+    }
+
+    // checks the CPU, and the scores of the graph, and for a matrix engine the size of its matrices, for the
+    // alignment of a sequence of `seq_len` bases
+    fn check_scores(&self, seq_len: usize) -> Result<(), Error> {
+        crate::check_cpu()?;
+        self.params.validate()?;
+        let graph_len = unsafe { poa_graph_num_nodes(self.ptr) } as usize;
+        match &self.matrix {
+            Some(matrix) => {
+                matrix.scoring_params(&self.params).check_overflow(seq_len, graph_len)?;
+                check_matrix_size(&self.params, seq_len, graph_len)
+            }
+            None => self.params.check_overflow(seq_len, graph_len),
+        }
+    }
+
+    /// Aligns a sequence into the graph like `add`, unless the scores are out of the range of the alignment
+    /// engine or may overflow for this sequence and the current graph, leaving the graph unchanged. A sequence
+    /// and qualities of different lengths, or holding a null byte, fail with `Error::InvalidInput`.
+    pub fn try_add(&mut self, seq: &[u8], qual: &[u8]) -> Result<(), Error> {
+        check_read(seq, qual)?;
+        self.check_scores(seq.len())?;
+        self.add(seq, qual);
+        Ok(())
+    }
+
+    /// Aligns sequences into the graph until the consensus converges: once `patience` consecutive additions
+    /// leave the consensus unchanged, the remaining sequences are not read.
+    ///
+    /// # Arguments
+    ///
+    /// * `reads` - the sequences and their qualities (not null-terminated), named `seq_<index>` like `add`
+    /// * `patience` - the number of consecutive additions that must leave the consensus unchanged
+    ///
+    /// # Returns
+    /// * returns the number of sequences taken from `reads`
+    pub fn add_until_converged<'a, I>(&mut self, reads: I, patience: usize) -> usize
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let mut used = 0;
+        let mut unchanged = 0;
+        let mut consensus = self.consensus();
+        for (seq, qual) in reads {
+            self.add(seq, qual);
+            used += 1;
+            let next = self.consensus();
+            if next == consensus {
+                unchanged += 1;
+                if unchanged >= patience {
+                    break;
+                }
+            } else {
+                unchanged = 0;
+                consensus = next;
+            }
+        }
+        used
+    }
+
+    /// Appends sequences to the graph and regenerates the consensus, reporting whether it changed. Only the
+    /// new sequences are aligned, so that a retained graph is updated as reads of its cluster keep arriving.
+    ///
+    /// # Arguments
+    ///
+    /// * `reads` - the sequences and their qualities (not null-terminated), named `seq_<index>` like `add`
+    pub fn append<'a, I>(&mut self, reads: I) -> ConsensusUpdate
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        let previous = self.consensus();
+        let mut added = 0;
+        for (seq, qual) in reads {
+            self.add(seq, qual);
+            added += 1;
+        }
+        let consensus = if added == 0 { previous.clone() } else { self.consensus() };
+        ConsensusUpdate {
+            changed: consensus != previous,
+            consensus,
+            added,
+        }
+    }
+
+    /// Aligns sequences into the graph one at a time like `add`, calling `callback` with the state of the
+    /// graph after each of them, e.g. to study or teach how the graph and its consensus depend on the order of
+    /// the sequences.
+    ///
+    /// # Arguments
+    ///
+    /// * `reads` - the sequences and their qualities (not null-terminated), named `seq_<index>` like `add`
+    /// * `dot_max_nodes` - the largest number of nodes of a graph given to `callback` in DOT format, or 0
+    ///   for none
+    /// * `callback` - called after each sequence is added
+    pub fn add_traced<'a, I, F>(&mut self, reads: I, dot_max_nodes: usize, mut callback: F)
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+        F: FnMut(&GraphStep),
+    {
+        for (seq, qual) in reads {
+            let before = unsafe { poa_graph_num_nodes(self.ptr) } as usize;
+            self.add(seq, qual);
+            let num_nodes = unsafe { poa_graph_num_nodes(self.ptr) } as usize;
+            let step = GraphStep {
+                seq_index: self.len() - 1,
+                num_nodes,
+                num_edges: unsafe { poa_graph_num_edges(self.ptr) } as usize,
+                new_nodes: num_nodes - before,
+                consensus: self.consensus(),
+                dot: if num_nodes <= dot_max_nodes { Some(self.dot()) } else { None },
+            };
+            callback(&step);
+        }
+    }
+
+    /// Removes all sequences from the graph. The alignment engine and its buffers are kept, so that a cleared
+    /// graph builds the next consensus without reallocating them.
+    pub fn clear(&mut self) {
+        unsafe { poa_graph_clear(self.ptr) };
+        self.names.clear();
+        self.seqs.clear();
+        self.quals.clear();
+    }
+
+    /// Adds a sequence whose bases are known to lie on given nodes of the graph, without aligning it.
+    /// `node_ids` holds the node of each base, or -1 for bases creating new nodes; a base differing from its
+    /// node creates a new node aligned to it. New nodes get consecutive ids, in the order of their bases,
+    /// following the nodes already in the graph.
+    pub(crate) fn add_on_nodes(&mut self, name: String, seq: &[u8], qual: &[u8], node_ids: &[i32]) {
+        let seq = null_terminated(seq);
+        let qual = null_terminated(qual);
+        unsafe { poa_graph_add_alignment(self.ptr, seq.as_ptr(), qual.as_ptr(), node_ids.as_ptr()) };
+        self.names.push(name);
+        self.seqs.push(seq);
+        self.quals.push(qual);
+    }
+
+    /// Returns the names of the sequences in the graph, in input order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the qualities of the sequence at `index` (not null-terminated).
+    pub(crate) fn qual(&self, index: usize) -> &[u8] {
+        let qual = &self.quals[index];
+        &qual[..qual.len() - 1]
+    }
+
+    /// Returns the node ids of the graph along with its edges and the path of every sequence through it.
+    pub fn topology(&self) -> GraphTopology {
+        // SPOA leaves the empty sequences out of its own, which are numbered without them
+        let mut seq_index = 0;
+        let paths = self
+            .seqs
+            .iter()
+            .map(|seq| {
+                let mut node_ids = vec![0i32; seq.len() - 1];
+                if !node_ids.is_empty() {
+                    unsafe { poa_graph_path(self.ptr, seq_index, node_ids.as_mut_ptr()) };
+                    seq_index += 1;
+                }
+                node_ids.into_iter().map(|id| id as u32).collect()
+            })
+            .collect();
+        GraphTopology {
+            paths,
+            ..self.nodes_and_edges()
+        }
+    }
+
+    // the topology of the graph without the paths of its sequences
+    fn nodes_and_edges(&self) -> GraphTopology {
+        let num_nodes = unsafe { poa_graph_num_nodes(self.ptr) } as usize;
+        let num_edges = unsafe { poa_graph_num_edges(self.ptr) } as usize;
+        let mut labels = vec![0u8; num_nodes];
+        let mut columns = vec![0i32; num_nodes];
+        let mut tails = vec![0i32; num_edges];
+        let mut heads = vec![0i32; num_edges];
+        let mut weights = vec![0i64; num_edges];
+        unsafe {
+            poa_graph_topology(
+                self.ptr,
+                labels.as_mut_ptr(),
+                columns.as_mut_ptr(),
+                tails.as_mut_ptr(),
+                heads.as_mut_ptr(),
+                weights.as_mut_ptr(),
+            )
+        };
+        GraphTopology {
+            labels,
+            columns: columns.into_iter().map(|id| id as u32).collect(),
+            edges: (0..num_edges)
+                .map(|i| GraphEdge {
+                    tail: tails[i] as u32,
+                    head: heads[i] as u32,
+                    weight: weights[i],
+                })
+                .collect(),
+            paths: vec![],
+        }
+    }
+
+    /// Returns the graph in Graphviz DOT format: each node labelled with its id and base, each edge with its
+    /// weight, the nodes of the consensus highlighted and the nodes of an alignment column joined by dotted
+    /// lines, as drawn by SPOA.
+    pub fn dot(&self) -> String {
+        self.dot_with(&GfaOptions::default())
+    }
+
+    /// Returns the graph in Graphviz DOT format like `dot`, with every unbranched run of nodes drawn as one node
+    /// spelling its bases (named and labelled after its first node) with `options.compact`, as compacted by
+    /// `write_compact_gfa`, so that the graphs of real clusters remain drawable. The edges lighter than
+    /// `options.min_edge_weight` or, with `options.remove_transitive`, bypassing a longer path through the other
+    /// edges are left out, as in `write_gfa_with`.
+    pub fn dot_with(&self, options: &GfaOptions) -> String {
+        let topology = self.topology();
+        let chains = if options.compact {
+            compact_chains(&topology)
+        } else {
+            (0..topology.labels.len() as u32).map(|id| vec![id]).collect()
+        };
+        topology_dot(&topology, &self.consensus_path(), &chains, &kept_edges(&topology, options))
+    }
+
+    /// Merges another graph into this one, e.g. to combine the graphs built from shards of the input in
+    /// parallel. The nodes of `other` are imported rather than its sequences re-aligned: the consensus of
+    /// `other` is aligned into this graph once, mapping each alignment column of `other` on its consensus to a
+    /// node of this graph, then every sequence of `other` is added along the nodes its own nodes map to, in the
+    /// order it was added to `other` and with its name and qualities. The nodes of `other` mapping to no node
+    /// are imported as new nodes, shared by the later sequences traversing them, so that merging into an empty
+    /// graph rebuilds `other`.
+    ///
+    /// A sequence only follows mapped nodes in the topological order of this graph, its bases mapped out of
+    /// order being imported as new nodes, so that the merged graph stays acyclic. Where the consensus of
+    /// `other` aligns poorly to this graph, the merged graph may thus differ from the graph built by adding
+    /// the sequences one at a time.
+    ///
+    /// Panics if the graphs align sequences with different parameters or substitution matrices.
+    pub fn merge(&mut self, other: &PoaGraph) {
+        if self.params != other.params || self.matrix != other.matrix {
+            panic!("Graphs aligning sequences with different parameters cannot be merged");
+        }
+        let topology = other.topology();
+        // the node of this graph each node of `other` was imported as, and the node each alignment column of
+        // `other` is aligned to
+        let mut node_of: HashMap<u32, u32> = HashMap::new();
+        let mut column_of: HashMap<u32, u32> = HashMap::new();
+        if unsafe { poa_graph_num_nodes(self.ptr) } > 0 {
+            let path = other.consensus_path();
+            let consensus: Vec<u8> = path.iter().map(|&id| topology.labels[id as usize]).collect();
+            for step in self.align(&consensus).path {
+                if let (Some(node), Some(pos)) = step {
+                    column_of.insert(topology.columns[path[pos as usize] as usize], node);
+                }
+            }
+        }
+
+        // SPOA numbers the paths of the non-empty sequences only
+        let mut seq_index = self.seqs.iter().filter(|seq| seq.len() > 1).count() as i32;
+        for (i, other_path) in topology.paths.iter().enumerate() {
+            let ranks = column_ranks(&self.nodes_and_edges());
+            let mut last_rank = None;
+            let node_ids: Vec<i32> = other_path
+                .iter()
+                .map(|id| {
+                    let target = node_of.get(id).or_else(|| column_of.get(&topology.columns[*id as usize]));
+                    match target.and_then(|&node| ranks[node as usize].map(|rank| (node, rank))) {
+                        Some((_, rank)) if last_rank >= Some(rank) => -1,
+                        Some((node, rank)) => {
+                            last_rank = Some(rank);
+                            node as i32
+                        }
+                        None => -1,
+                    }
+                })
+                .collect();
+            let seq = &other.seqs[i][..other.seqs[i].len() - 1];
+            self.add_on_nodes(other.names[i].clone(), seq, other.qual(i), &node_ids);
+            if other_path.is_empty() {
+                continue;
+            }
+
+            let mut imported = vec![0i32; other_path.len()];
+            unsafe { poa_graph_path(self.ptr, seq_index, imported.as_mut_ptr()) };
+            seq_index += 1;
+            for (&id, &node) in other_path.iter().zip(imported.iter()) {
+                node_of.insert(id, node as u32);
+                column_of.entry(topology.columns[id as usize]).or_insert(node as u32);
+            }
+        }
+    }
+
+    /// Returns the score of the best alignment of a sequence against the graph, leaving the graph unchanged.
+    /// The score follows the alignment mode and scores of the graph, so that scores against graphs built
+    /// with the same parameters are comparable, e.g. to assign a read to the closest of several clusters.
+    /// An empty graph scores 0.
+    ///
+    /// Unlike `align`, the score is computed without traceback, keeping only two rows of the dynamic
+    /// programming, so that scoring takes memory linear in the size of the graph.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - the sequence (not null-terminated)
+    pub fn score(&self, seq: &[u8]) -> i32 {
+        if unsafe { poa_graph_num_nodes(self.ptr) } == 0 {
+            return 0;
+        }
+        let seq = null_terminated(seq);
+        unsafe { poa_graph_score(self.ptr, seq.as_ptr()) }
+    }
+
+    /// Aligns a query against the graph without adding it, e.g. to map reads onto a consensus graph used as
+    /// a small reference. An empty graph leaves the whole query soft-clipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - the sequence (not null-terminated)
+    pub fn align(&self, query: &[u8]) -> GraphAlignment {
+        if unsafe { poa_graph_num_nodes(self.ptr) } == 0 || query.is_empty() {
+            return GraphAlignment {
+                score: 0,
+                path: vec![],
+                cigar: path_cigar(&[], query.len()),
+            };
+        }
+        let capacity = unsafe { poa_graph_num_nodes(self.ptr) } as usize + query.len();
+        let mut node_ids = vec![0i32; capacity];
+        let mut seq_ids = vec![0i32; capacity];
+        let mut score: i32 = 0;
+        let seq = null_terminated(query);
+        let len = unsafe {
+            poa_graph_align(self.ptr, seq.as_ptr(), node_ids.as_mut_ptr(), seq_ids.as_mut_ptr(), &mut score)
+        } as usize;
+
+        let id = |id: i32| if id < 0 { None } else { Some(id as u32) };
+        let path: Vec<(Option<u32>, Option<u32>)> = (0..len).map(|i| (id(node_ids[i]), id(seq_ids[i]))).collect();
+        GraphAlignment {
+            score,
+            cigar: path_cigar(&path, query.len()),
+            path,
+        }
+    }
+
+    /// Writes the dynamic programming matrices and the traceback of the alignment of a query against the
+    /// graph, e.g. to investigate why a read aligns pathologically. The matrices are those of the reference
+    /// engine of `NaiveBackend`, which follows SPOA's algorithm over the nodes of the graph, as SPOA's own are
+    /// not exposed: one tab-separated row per query position and one column per node in topological order, after
+    /// the virtual source `-`, with the matrices of each gap function after the overall scores `H`. Their size is
+    /// the product of the query length and the number of nodes, so it suits small alignments only.
+    ///
+    /// The reference engine scores matches and mismatches only, so graphs scoring substitutions with a matrix
+    /// fail with `io::ErrorKind::InvalidInput`.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - the destination of the dump, e.g. a file
+    /// * `query` - the sequence (not null-terminated)
+    #[cfg(feature = "debug-align")]
+    pub fn dump_alignment<W: std::io::Write>(&self, writer: W, query: &[u8]) -> std::io::Result<()> {
+        if self.matrix.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "alignment dumps of graphs scoring substitutions with a matrix",
+            ));
+        }
+        crate::naive::write_alignment_dump(writer, &self.topology(), query, &self.params)
+    }
+
+    /// Extracts the part of the graph spanning the consensus positions `range` as a new graph: the nodes
+    /// between the consensus nodes at the ends of the range, with their edges and the nodes aligned to them,
+    /// e.g. to re-analyze or export the region around a variant in isolation. The subgraph keeps the
+    /// parameters of the graph but none of its sequences, so that it scores and aligns queries and has a
+    /// consensus and a topology, without paths, while its alignment holds only the sequences added to it
+    /// afterwards. An empty range gives an empty graph.
+    ///
+    /// Panics if the range extends past the consensus.
+    pub fn subgraph(&self, range: Range<usize>) -> Subgraph {
+        let path = self.consensus_path();
+        if range.end > path.len() {
+            panic!("Consensus range {:?} out of range for a consensus of {} bases", range, path.len());
+        }
+        let graph = self.empty_like();
+        if range.is_empty() {
+            return Subgraph { graph, node_ids: vec![] };
+        }
+        let mut node_ids = vec![0i32; unsafe { poa_graph_num_nodes(self.ptr) } as usize];
+        let (begin, end) = (path[range.start] as i32, path[range.end - 1] as i32);
+        let len = unsafe { poa_graph_subgraph(self.ptr, graph.ptr, begin, end, node_ids.as_mut_ptr()) } as usize;
+        Subgraph {
+            graph,
+            node_ids: node_ids[..len].iter().map(|&id| id as u32).collect(),
+        }
+    }
+
+    /// Rebuilds the graph from the retained sequences, leaving out those at the given indices, e.g. to drop
+    /// outliers found in the alignment and regenerate the consensus. The kept sequences are aligned again in
+    /// their original order and keep their names.
+    ///
+    /// # Arguments
+    ///
+    /// * `excluded` - the indices of the sequences to leave out, in any order
+    pub fn rebuild_without(&self, excluded: &[usize]) -> PoaGraph {
+        if let Some(&index) = excluded.iter().find(|&&index| index >= self.len()) {
+            panic!("Excluded sequence index {} out of range for a graph of {} sequences", index, self.len());
+        }
+        let mut graph = self.empty_like();
+        for i in (0..self.len()).filter(|i| !excluded.contains(i)) {
+            let (seq, qual) = (&self.seqs[i], &self.quals[i]);
+            graph.add_named(self.names[i].clone(), &seq[..seq.len() - 1], &qual[..qual.len() - 1]);
+        }
+        graph
+    }
+
+    /// Returns the consensus of the sequences added so far.
+    pub fn consensus(&self) -> Vec<u8> {
+        unsafe {
+            let c_str = poa_graph_consensus(self.ptr);
+            let consensus = CStr::from_ptr(c_str).to_bytes().to_vec();
+            poa_free_string(c_str);
+            consensus
+        }
+    }
+
+    /// Returns the ids of the nodes spelling the consensus, see `topology`.
+    pub fn consensus_path(&self) -> Vec<u32> {
+        let num_nodes = unsafe { poa_graph_num_nodes(self.ptr) } as usize;
+        let mut node_ids = vec![0i32; num_nodes];
+        let len = unsafe { poa_graph_consensus_path(self.ptr, node_ids.as_mut_ptr()) } as usize;
+        node_ids[..len].iter().map(|&id| id as u32).collect()
+    }
+
+    /// Returns the multiple sequence alignment of the sequences added so far.
+    pub fn msa(&self) -> Msa {
+        if self.is_empty() {
+            return Msa::default();
+        }
+        let mut num_rows: i32 = 0;
+        let mut rows: Vec<Vec<u8>> = unsafe {
+            let c_rows = poa_graph_msa(self.ptr, &mut num_rows);
+            let rows = (0..num_rows as usize)
+                .map(|i| CStr::from_ptr(*c_rows.add(i)).to_bytes().to_vec())
+                .collect();
+            poa_free_rows(c_rows, num_rows);
+            rows
+        };
+        let consensus = rows.pop().unwrap_or_default();
+        let empty: Vec<bool> = self.seqs.iter().map(|seq| seq.len() == 1).collect();
+        Msa {
+            names: self.names.clone(),
+            rows: with_empty_rows(rows, &empty, consensus.len()),
+            consensus,
+        }
+    }
+
+    /// Returns the consensus, alignment and metrics of the sequences added so far.
+    pub fn result(&self) -> ConsensusResult {
+        ConsensusResult::from_msa(self.msa())
+    }
+}
+
+impl Drop for PoaGraph {
+    fn drop(&mut self) {
+        unsafe { poa_graph_free(self.ptr) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::AlignmentType;
+
+    const SEQS: [&str; 6] = ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"];
+
+    #[test]
+    fn test_incremental() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        for seq in SEQS.iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.consensus(), b"AATGCCCGTT".to_vec());
+        assert_eq!(graph.msa().names[5], "seq_5");
+    }
+
+    #[test]
+    fn test_add_until_converged() {
+        let mut reads: Vec<&str> = SEQS.to_vec();
+        reads.extend(vec!["AATGCCCGTT"; 10]);
+        reads.push("GGGGGGGGGG");
+        let quals: Vec<String> = reads.iter().map(|seq| "F".repeat(seq.len())).collect();
+
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        let used = graph.add_until_converged(
+            reads.iter().zip(quals.iter()).map(|(seq, qual)| (seq.as_bytes(), qual.as_bytes())),
+            3,
+        );
+
+        assert!(used < reads.len());
+        assert_eq!(graph.len(), used);
+        assert_eq!(graph.consensus(), b"AATGCCCGTT".to_vec());
+    }
+
+    #[test]
+    fn test_append() {
+        let quals: Vec<String> = SEQS.iter().map(|seq| "F".repeat(seq.len())).collect();
+        let reads: Vec<(&[u8], &[u8])> =
+            SEQS.iter().zip(quals.iter()).map(|(seq, qual)| (seq.as_bytes(), qual.as_bytes())).collect();
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+
+        let first = graph.append(reads[..1].iter().cloned());
+        let rest = graph.append(reads[1..].iter().cloned());
+        let repeat = graph.append(vec![(&b"AATGCCCGTT"[..], &b"FFFFFFFFFF"[..])]);
+
+        assert_eq!(first, ConsensusUpdate { consensus: b"ATTGCCCGTT".to_vec(), changed: true, added: 1 });
+        assert!(rest.changed);
+        assert_eq!(rest.consensus, b"AATGCCCGTT".to_vec());
+        assert!(!repeat.changed);
+        assert_eq!(graph.len(), 7);
+        assert!(!graph.append(vec![]).changed);
+    }
+
+    #[test]
+    fn test_add_traced() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        let reads: Vec<(&[u8], &[u8])> = vec![(b"ACGT", b"FFFF"), (b"ACGT", b"FFFF"), (b"AGGT", b"FFFF")];
+        let mut steps = vec![];
+        graph.add_traced(reads, 5, |step| steps.push(step.clone()));
+
+        let stats: Vec<(usize, usize, usize)> = steps.iter().map(|s| (s.seq_index, s.num_nodes, s.new_nodes)).collect();
+        // the mismatch of the last read creates a node aligned to the C
+        assert_eq!(stats, vec![(0, 4, 4), (1, 4, 0), (2, 5, 1)]);
+        assert_eq!(steps[2].consensus, b"ACGT".to_vec());
+        assert!(steps[1].dot.is_some());
+        assert!(steps[2].dot.is_some());
+    }
+
+    #[test]
+    fn test_topology_dot() {
+        let edge = |tail, head, weight| GraphEdge { tail, head, weight };
+        let topology = GraphTopology {
+            labels: b"ACGG".to_vec(),
+            columns: vec![0, 1, 2, 1],
+            edges: vec![edge(0, 1, 2), edge(1, 2, 2), edge(0, 3, 1), edge(3, 2, 1)],
+            paths: vec![],
+        };
+        let expected = "digraph poa {
+    rankdir = LR
+    0 [label = \"0|A\", style = filled, fillcolor = goldenrod1]
+    1 [label = \"1|C\", style = filled, fillcolor = goldenrod1]
+    2 [label = \"2|G\", style = filled, fillcolor = goldenrod1]
+    3 [label = \"3|G\"]
+    0 -> 1 [label = \"2\"]
+    1 -> 2 [label = \"2\"]
+    0 -> 3 [label = \"1\"]
+    3 -> 2 [label = \"1\"]
+    1 -> 3 [style = dotted, arrowhead = none]
+}
+";
+        let chains: Vec<Vec<u32>> = (0..4).map(|id| vec![id]).collect();
+        assert_eq!(topology_dot(&topology, &[0, 1, 2], &chains, &[true; 4]), expected);
+
+        let topology = GraphTopology {
+            labels: b"ACGTT".to_vec(),
+            columns: vec![0, 1, 2, 3, 4],
+            edges: vec![edge(0, 1, 2), edge(1, 2, 2), edge(2, 3, 1), edge(2, 4, 1)],
+            paths: vec![],
+        };
+        let expected = "digraph poa {
+    rankdir = LR
+    0 [label = \"0|ACG\", style = filled, fillcolor = goldenrod1]
+    3 [label = \"3|T\", style = filled, fillcolor = goldenrod1]
+    4 [label = \"4|T\"]
+    0 -> 3 [label = \"1\"]
+    0 -> 4 [label = \"1\"]
+}
+";
+        let chains = [vec![0, 1, 2], vec![3], vec![4]];
+        assert_eq!(topology_dot(&topology, &[0, 1, 2, 3], &chains, &[true; 4]), expected);
+        let pruned = topology_dot(&topology, &[0, 1, 2, 3], &chains, &[true, true, true, false]);
+        assert_eq!(pruned, expected.replace("    0 -> 4 [label = \"1\"]\n", ""));
+    }
+
+    #[test]
+    fn test_score() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        assert_eq!(graph.score(b"ACGT"), 0);
+        for seq in SEQS.iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+        let mut other = PoaGraph::new(&AlignmentParams::default());
+        other.add(b"GGCATTACCA", b"FFFFFFFFFF");
+
+        let score = graph.score(b"AATGCCCGTT");
+
+        assert_eq!(score, 10 * AlignmentParams::default().match_score);
+        assert!(graph.score(b"AATGCCGTT") < score);
+        assert!(other.score(b"AATGCCCGTT") < score);
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.consensus(), b"AATGCCCGTT".to_vec());
+    }
+
+    #[test]
+    fn test_score_matches_alignment() {
+        let queries: [&[u8]; 4] = [b"AATGCCCGTT", b"TTAATGCCGTTGG", b"GGGG", b"CCCGT"];
+        for &alignment_type in [AlignmentType::Global, AlignmentType::SemiGlobal, AlignmentType::Local].iter() {
+            let params = AlignmentParams {
+                alignment_type,
+                ..AlignmentParams::default()
+            };
+            let build = || {
+                let mut graph = PoaGraph::new(&params);
+                for seq in SEQS.iter() {
+                    graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+                }
+                graph
+            };
+            let graph = build();
+            let labels = graph.topology().labels;
+
+            for query in queries.iter() {
+                let score = graph.score(query);
+
+                assert_eq!(score, graph.align(query).score, "{:?} {:?}", alignment_type, query);
+                assert_eq!(graph.topology().labels, labels);
+
+                // once added, the query has a path of its own to align along
+                let mut added = build();
+                added.add(query, &vec![b'F'; query.len()]);
+                let rescored = added.score(query);
+                assert_eq!(rescored, added.align(query).score);
+                assert!(rescored >= score);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wildcard_score() {
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+        let mut graph = PoaGraph::new(&params);
+        assert!(graph.matrix().is_some());
+        graph.add(b"AATGCCCGTT", b"FFFFFFFFFF");
+
+        // the masked bases score 0 rather than as mismatches
+        assert_eq!(graph.score(b"AANNNNNGTT"), 5 * params.match_score);
+        assert_eq!(graph.rebuild_without(&[]).matrix(), graph.matrix());
+        assert!(PoaGraph::new(&AlignmentParams::default()).matrix().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard score")]
+    fn test_with_matrix_wildcard_score() {
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+        PoaGraph::with_matrix(&params, &SubstitutionMatrix::blosum62());
+    }
+
+    #[test]
+    fn test_path_cigar() {
+        let path = vec![
+            (Some(0), Some(2)),
+            (Some(1), Some(3)),
+            (None, Some(4)),
+            (Some(2), None),
+            (Some(3), Some(5)),
+        ];
+
+        assert_eq!(path_cigar(&path, 8), "2S2M1I1D1M2S");
+        assert_eq!(path_cigar(&path[..2], 4), "2S2M");
+        assert_eq!(path_cigar(&[], 5), "5S");
+        assert_eq!(path_cigar(&[], 0), "");
+    }
+
+    #[test]
+    fn test_align() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        for seq in SEQS.iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+        let topology = graph.topology();
+
+        let alignment = graph.align(b"AATGCCCGTT");
+
+        assert_eq!(alignment.score, graph.score(b"AATGCCCGTT"));
+        assert_eq!(alignment.cigar, "10M");
+        let spelled: Vec<u8> =
+            alignment.path.iter().map(|&(node, _)| topology.labels[node.unwrap() as usize]).collect();
+        assert_eq!(spelled, b"AATGCCCGTT".to_vec());
+        assert_eq!(graph.len(), 6);
+        assert_eq!(PoaGraph::new(&AlignmentParams::default()).align(b"ACGT").cigar, "4S");
+    }
+
+    #[test]
+    fn test_rebuild_without() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        for seq in SEQS.iter().chain(["GGGGGGAAAAAAGGG", "CCCCCC"].iter()) {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+
+        let rebuilt = graph.rebuild_without(&[7, 6]);
+
+        assert_eq!(graph.len(), 8);
+        assert_eq!(rebuilt.len(), 6);
+        assert_eq!(rebuilt.names()[5], "seq_5");
+        assert_eq!(rebuilt.consensus(), b"AATGCCCGTT".to_vec());
+        assert_eq!(graph.rebuild_without(&[0]).names()[0], "seq_1");
+    }
+
+    #[test]
+    fn test_subgraph() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        for seq in SEQS.iter() {
+            graph.add(seq.as_bytes(), "F".repeat(seq.len()).as_bytes());
+        }
+        let path = graph.consensus_path();
+
+        let subgraph = graph.subgraph(3..7);
+
+        assert_eq!(subgraph.graph.consensus(), b"GCCC".to_vec());
+        assert!(subgraph.graph.is_empty());
+        let labels = graph.topology().labels;
+        let sub_labels = subgraph.graph.topology().labels;
+        for (sub_id, &id) in subgraph.node_ids.iter().enumerate() {
+            assert_eq!(sub_labels[sub_id], labels[id as usize]);
+        }
+        assert!(path[3..7].iter().all(|id| subgraph.node_ids.contains(id)));
+        assert_eq!(subgraph.graph.align(b"GCCC").cigar, "4M");
+        assert!(graph.subgraph(2..2).node_ids.is_empty());
+    }
+
+    #[test]
+    fn test_try_add_invalid() {
+        let mut graph = PoaGraph::new(&AlignmentParams::default());
+        assert!(matches!(graph.try_add(b"ACG", b"FF"), Err(Error::InvalidInput { .. })));
+        assert!(matches!(graph.try_add(b"A\0G", b"FFF"), Err(Error::InvalidInput { .. })));
+        assert!(graph.is_empty());
+
+        // SPOA leaves empty sequences out of its graph, whose paths and rows are kept on the Rust side
+        graph.add(b"ACGT", b"FFFF");
+        graph.add(b"", b"");
+        graph.add(b"ACT", b"FFF");
+        let topology = graph.topology();
+        assert!(topology.paths[1].is_empty());
+        assert_eq!(topology.paths[2].len(), 3);
+        assert_eq!(graph.msa().rows[1], b"----".to_vec());
+        assert_eq!(graph.msa().rows[2], b"AC-T".to_vec());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_subgraph_out_of_range() {
+        PoaGraph::new(&AlignmentParams::default()).subgraph(0..1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rebuild_without_out_of_range() {
+        PoaGraph::new(&AlignmentParams::default()).rebuild_without(&[0]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let params = AlignmentParams::default();
+        let mut whole = PoaGraph::new(&params);
+        let mut shards = vec![PoaGraph::new(&params), PoaGraph::new(&params)];
+        for (i, seq) in SEQS.iter().enumerate() {
+            let qual = "F".repeat(seq.len());
+            whole.add(seq.as_bytes(), qual.as_bytes());
+            shards[i / 3].add_named(format!("read_{}", i), seq.as_bytes(), qual.as_bytes());
+        }
+        shards[1].add(b"", b"");
+
+        let second = shards.pop().unwrap();
+        let mut merged = shards.pop().unwrap();
+        merged.merge(&second);
+
+        assert_eq!(merged.len(), 7);
+        assert_eq!(merged.msa().names[4], "read_4");
+        assert_eq!(merged.msa().rows[6], b"-".repeat(merged.msa().consensus.len()));
+        assert_eq!(merged.consensus(), whole.consensus());
+        let num_nodes = |graph: &PoaGraph| graph.topology().labels.len();
+
+        // merging into an empty graph rebuilds the graph
+        let mut rebuilt = PoaGraph::new(&params);
+        rebuilt.merge(&second);
+        assert_eq!(num_nodes(&rebuilt), num_nodes(&second));
+        assert_eq!(rebuilt.msa().rows, second.msa().rows);
+    }
+
+    #[test]
+    fn test_column_ranks() {
+        let edge = |tail, head| GraphEdge { tail, head, weight: 1 };
+        // A -> C|G -> T, with C and G aligned
+        let topology = GraphTopology {
+            labels: b"ACTG".to_vec(),
+            columns: vec![0, 1, 2, 1],
+            edges: vec![edge(0, 1), edge(1, 2), edge(0, 3), edge(3, 2)],
+            paths: vec![],
+        };
+        assert_eq!(column_ranks(&topology), vec![Some(0), Some(1), Some(2), Some(1)]);
+
+        let cycle = GraphTopology {
+            edges: vec![edge(0, 1), edge(1, 0)],
+            ..topology
+        };
+        assert_eq!(column_ranks(&cycle)[..2], [None, None]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_different_params() {
+        let local = AlignmentParams {
+            alignment_type: crate::params::AlignmentType::Local,
+            ..AlignmentParams::default()
+        };
+        PoaGraph::new(&AlignmentParams::default()).merge(&PoaGraph::new(&local));
+    }
+}
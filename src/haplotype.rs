@@ -0,0 +1,231 @@
+//! Reconstruction of several haplotypes from a mixture of reads, e.g. a viral quasispecies.
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::msa::{Msa, GAP};
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// How reads are split into haplotypes by `poa_haplotypes`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HaplotypeOptions {
+    /// The minimum fraction of the reads a minor base must reach for a column to separate haplotypes, and
+    /// the minimum fraction of the reads a haplotype must gather to be reported.
+    pub min_abundance: f64,
+    /// The maximum number of haplotypes reported.
+    pub max_haplotypes: usize,
+}
+
+impl Default for HaplotypeOptions {
+    fn default() -> HaplotypeOptions {
+        HaplotypeOptions {
+            min_abundance: 0.1,
+            max_haplotypes: 8,
+        }
+    }
+}
+
+/// A haplotype supported by a subset of the reads.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Haplotype {
+    /// The consensus of the reads of the haplotype.
+    pub result: ConsensusResult,
+    /// The input indices of the reads assigned to the haplotype.
+    pub reads: Vec<usize>,
+    /// The fraction of all reads assigned to the haplotype.
+    pub abundance: f64,
+}
+
+// the alignment columns in which a base other than the consensus base reaches the minimum abundance
+fn informative_columns(msa: &Msa, min_abundance: f64) -> Vec<usize> {
+    let min_reads = (min_abundance * msa.num_rows() as f64).ceil().max(1.0) as usize;
+    (0..msa.num_columns())
+        .filter(|&col| {
+            let mut counts = [0usize; 256];
+            for row in &msa.rows {
+                counts[row[col] as usize] += 1;
+            }
+            counts
+                .iter()
+                .enumerate()
+                .any(|(base, &count)| base as u8 != GAP && base as u8 != msa.consensus[col] && count >= min_reads)
+        })
+        .collect()
+}
+
+// splits the rows of the alignment into haplotypes, most abundant first
+fn cluster_rows(msa: &Msa, options: &HaplotypeOptions) -> Vec<Vec<usize>> {
+    let all_rows: Vec<usize> = (0..msa.num_rows()).collect();
+    let columns = informative_columns(msa, options.min_abundance);
+    if columns.is_empty() || options.max_haplotypes < 2 {
+        return vec![all_rows];
+    }
+
+    // the bases of each row at the informative columns, None where the row has a gap or does not reach
+    let signatures: Vec<Vec<Option<u8>>> = msa
+        .rows
+        .iter()
+        .map(|row| columns.iter().map(|&col| Some(row[col]).filter(|&b| b != GAP)).collect())
+        .collect();
+
+    // haplotypes are seeded by the complete signatures carried by enough reads
+    let min_reads = (options.min_abundance * msa.num_rows() as f64).ceil().max(1.0) as usize;
+    let mut counts: HashMap<&[Option<u8>], usize> = HashMap::new();
+    for signature in signatures.iter().filter(|s| s.iter().all(|b| b.is_some())) {
+        *counts.entry(signature).or_insert(0) += 1;
+    }
+    let mut seeds: Vec<(&[Option<u8>], usize)> = counts.into_iter().filter(|&(_, n)| n >= min_reads).collect();
+    seeds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    seeds.truncate(options.max_haplotypes);
+    if seeds.len() < 2 {
+        return vec![all_rows];
+    }
+
+    // every row joins the seed it differs least from, over the columns it covers
+    let mut clusters = vec![vec![]; seeds.len()];
+    for (i, signature) in signatures.iter().enumerate() {
+        let distance = |seed: &[Option<u8>]| {
+            seed.iter()
+                .zip(signature.iter())
+                .filter(|(s, b)| b.is_some() && s != b)
+                .count()
+        };
+        let best = (0..seeds.len()).min_by_key(|&s| distance(seeds[s].0)).unwrap();
+        clusters[best].push(i);
+    }
+    clusters.retain(|rows| !rows.is_empty());
+    clusters.sort_by_key(|rows| std::cmp::Reverse(rows.len()));
+    clusters
+}
+
+/// Reconstructs the haplotypes of a mixture of reads, each with its own consensus and abundance.
+///
+/// All reads are aligned into one graph. The columns of the alignment in which the graph branches into a
+/// minor base carried by at least `min_abundance` of the reads separate the haplotypes: the combinations of
+/// bases taken by enough reads at these columns seed the haplotypes, and every read joins the seed whose
+/// path it shares most of these columns with. Each haplotype is then realigned on its own. Haplotypes differing
+/// only by indels are not separated.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form haplotypes from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - the minimum abundance and maximum number of haplotypes
+///
+/// # Returns
+/// * returns the haplotypes, most abundant first
+pub fn poa_haplotypes(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &HaplotypeOptions,
+) -> Vec<Haplotype> {
+    if seqs.is_empty() {
+        return vec![];
+    }
+    let msa = crate::msa_with_params(seqs, quals, None, params);
+    cluster_rows(&msa, options)
+        .into_iter()
+        .map(|reads| {
+            let hap_seqs: Vec<Vec<u8>> = reads.iter().map(|&i| seqs[i].clone()).collect();
+            let hap_quals: Vec<Vec<u8>> = reads.iter().map(|&i| quals[i].clone()).collect();
+            Haplotype {
+                result: crate::poa(&hap_seqs, &hap_quals, params),
+                abundance: reads.len() as f64 / seqs.len() as f64,
+                reads,
+            }
+        })
+        .collect()
+}
+
+/// Reconstructs the haplotypes of a mixture of reads like `poa_haplotypes`, unless the scores or the reads are
+/// rejected as by `try_poa`; as the reads of each haplotype are a subset of the reads, they are checked once.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form haplotypes from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - the minimum abundance and maximum number of haplotypes
+///
+/// # Returns
+/// * returns the haplotypes, most abundant first, or the reason the inputs are rejected
+pub fn try_poa_haplotypes(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &HaplotypeOptions,
+) -> Result<Vec<Haplotype>, Error> {
+    crate::check_poa_inputs(seqs, quals, params)?;
+    Ok(poa_haplotypes(seqs, quals, params, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msa(rows: &[&str], consensus: &str) -> Msa {
+        Msa {
+            names: (0..rows.len()).map(|i| format!("seq_{}", i)).collect(),
+            rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+            consensus: consensus.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_cluster_rows() {
+        let msa = msa(
+            &[
+                "ACGTACGTAC",
+                "ACGTACGTAC",
+                "ACGTACGAAC",
+                "ACTTACCTAC",
+                "ACTTACCTAC",
+                "--TTACC---",
+                "ACGTTCGTAC",
+            ],
+            "ACGTACGTAC",
+        );
+        let options = HaplotypeOptions {
+            min_abundance: 0.25,
+            ..HaplotypeOptions::default()
+        };
+
+        // the single errors stay below the minimum abundance, the partial read joins the second haplotype
+        assert_eq!(informative_columns(&msa, 0.25), vec![2, 6]);
+        assert_eq!(cluster_rows(&msa, &options), vec![vec![0, 1, 2, 6], vec![3, 4, 5]]);
+        assert_eq!(cluster_rows(&msa, &HaplotypeOptions { max_haplotypes: 1, ..options }).len(), 1);
+    }
+
+    #[test]
+    fn test_poa_haplotypes() {
+        let mut seqs = vec![];
+        for _ in 0..6 {
+            seqs.push(b"AATGCCCGTTAGCATCGGAT\0".to_vec());
+        }
+        for _ in 0..3 {
+            seqs.push(b"AATGCACGTTAGCATCTGAT\0".to_vec());
+        }
+        let quals = crate::quals_for(&seqs);
+
+        let haplotypes = poa_haplotypes(&seqs, &quals, &AlignmentParams::default(), &HaplotypeOptions::default());
+
+        assert_eq!(haplotypes.len(), 2);
+        assert_eq!(haplotypes[0].result.consensus, b"AATGCCCGTTAGCATCGGAT".to_vec());
+        assert_eq!(haplotypes[1].result.consensus, b"AATGCACGTTAGCATCTGAT".to_vec());
+        assert_eq!(haplotypes[1].reads, vec![6, 7, 8]);
+        assert!((haplotypes[0].abundance - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_try_poa_haplotypes() {
+        let seqs = vec![b"AATGCCCGTT\0".to_vec(), b"AATGCC".to_vec()];
+        let quals = vec![b"FFFFFFFFFF\0".to_vec(), b"FFFFFF".to_vec()];
+        let options = HaplotypeOptions::default();
+
+        let error = try_poa_haplotypes(&seqs, &quals, &AlignmentParams::default(), &options).unwrap_err();
+
+        assert!(matches!(error, Error::InvalidInput { .. }));
+        assert_eq!(try_poa_haplotypes(&[], &[], &AlignmentParams::default(), &options), Ok(vec![]));
+    }
+}
@@ -9,6 +9,152 @@ use libc::c_char;
 use std::ffi::CStr;
 use std::str;
 
+#[cfg(feature = "affinity")]
+mod affinity;
+mod amplicon;
+mod backend;
+#[cfg(feature = "bam")]
+mod bam;
+mod batch;
+mod bubble;
+mod cache;
+mod checkpoint;
+mod chimera;
+mod circular;
+#[cfg(feature = "arrow")]
+mod columnar;
+mod compare;
+mod cpu;
+mod crispr;
+mod dedup;
+mod dna;
+mod duplex;
+mod ends;
+mod error;
+#[cfg(feature = "polars")]
+mod frame;
+mod gfa;
+mod golden;
+mod graph;
+mod haplotype;
+mod matrix;
+mod modified;
+mod msa;
+#[cfg(any(feature = "naive", test))]
+mod naive;
+mod packed;
+mod paf;
+mod paired;
+mod pairwise;
+mod params;
+mod phase;
+mod polish;
+mod pool;
+#[cfg(feature = "wfa")]
+mod prealign;
+mod profile;
+mod qc;
+mod reads;
+mod realign;
+mod result;
+mod sam;
+mod sample;
+mod scheduler;
+mod scratch;
+#[cfg(feature = "serde")]
+mod serde_util;
+mod shard;
+mod simulate;
+mod strand;
+mod stream;
+mod tail;
+mod tune;
+mod umi;
+mod variant;
+mod vcf;
+
+#[cfg(feature = "affinity")]
+pub use crate::affinity::{numa_nodes, Affinity};
+pub use crate::amplicon::{poa_amplicon, AmpliconConsensus, PrimerOptions};
+pub use crate::backend::{poa_batch_with_backend, poa_with_backend, ColumnVoteBackend, ConsensusBackend, SpoaBackend};
+#[cfg(feature = "bam")]
+pub use crate::bam::{poa_bam_consensus, poa_polish_bam, BamConsensusOptions};
+#[cfg(feature = "affinity")]
+pub use crate::batch::poa_batch_parallel_with_affinity;
+pub use crate::batch::{
+    poa_batch, poa_batch_consensus_into, poa_batch_parallel, poa_batch_scheduled, poa_grouped, try_poa_batch,
+    try_poa_batch_parallel, try_poa_batch_scheduled, write_results_fasta, write_results_maf, BatchSummary, Cluster,
+    ClusterError, ClusterResult, Distribution, GroupConsensus,
+};
+pub use crate::bubble::{Bubble, BubbleBranch, BubblePopping};
+pub use crate::cache::{cache_key, params_key, poa_batch_cached, DiskCache, MemoryCache, ResultCache};
+pub use crate::checkpoint::{poa_batch_checkpointed, read_checkpoint};
+pub use crate::chimera::{poa_without_chimeras, split_chimeras, ChimeraAction, ChimeraOptions, ChimeraReport};
+pub use crate::circular::{poa_circular, CircularConsensus, DEFAULT_ANCHOR_LEN};
+#[cfg(feature = "arrow")]
+pub use crate::columnar::{
+    clusters_from_record_batch, poa_record_batch, reads_schema, results_schema, results_to_record_batch,
+    write_parquet,
+};
+pub use crate::compare::{compare_consensus, ConsensusComparison, ConsensusDifference};
+pub use crate::cpu::{check_cpu, compiled_simd_level};
+pub use crate::crispr::{analyze_edits, EditAnalysis, IndelAllele};
+pub use crate::dna::{complement, iupac_matches, reverse_complement};
+pub use crate::duplex::poa_duplex;
+pub use crate::ends::{poa_free_end_gaps, EndTrim};
+pub use crate::error::Error;
+#[cfg(feature = "polars")]
+pub use crate::frame::{poa_dataframe, results_to_dataframe};
+pub use crate::gfa::{GfaOptions, GFA_DEFAULT_QUAL};
+pub use crate::golden::{check_golden, golden_corpus, record_golden, GoldenCluster, GoldenOutcome};
+pub use crate::graph::{ConsensusUpdate, GraphAlignment, GraphEdge, GraphStep, GraphTopology, PoaGraph, Subgraph};
+pub use crate::haplotype::{poa_haplotypes, try_poa_haplotypes, Haplotype, HaplotypeOptions};
+pub use crate::matrix::{try_poa_with_matrix, MatrixBackend, SubstitutionMatrix};
+pub use crate::modified::{ModifiedAlphabet, ModifiedBase};
+pub use crate::msa::{Msa, PileupStyle, GAP};
+#[cfg(feature = "naive")]
+pub use crate::naive::NaiveBackend;
+pub use crate::packed::PackedMsa;
+pub use crate::paf::write_paf;
+pub use crate::paired::{
+    merge_pair, poa_paired, FragmentConsensus, MergeOptions, PairedConsensus, ReadPair, MAX_MERGED_QUAL,
+};
+pub use crate::params::{
+    AffineGaps, AlignmentParams, AlignmentType, ConvexGaps, DefaultGaps, GapModel, GapScores, LinearGaps,
+    ParamSchedule, ParamsBuilder, Preset, SoftMask,
+};
+pub use crate::phase::{poa_phased, PhaseOptions, PhasedConsensus, PhasedSite};
+pub use crate::polish::{poa_polish, PolishOptions, PolishedDraft};
+pub use crate::pool::{ConsensusPool, PoolInput};
+#[cfg(feature = "wfa")]
+pub use crate::prealign::{order_reads, poa_prealigned, wfa_edit_distance, PrealignOptions};
+pub use crate::profile::{Pssm, DNA_ALPHABET};
+pub use crate::qc::{poa_filtered, FilterCounts, ReadFilter};
+pub use crate::reads::ReadBatch;
+pub use crate::realign::{realign_window, RealignedWindow};
+pub use crate::result::{ConsensusResult, ErrorProfile, Metrics, ReadError, ReadErrorKind, ReadErrors};
+#[cfg(feature = "bam")]
+pub use crate::sam::{write_sam, SamWriter};
+pub use crate::sample::{
+    poa_bootstrap, poa_stream, poa_subsample_vote, reservoir_sample, BootstrapConsensus, SubsampleOptions,
+    VotedConsensus,
+};
+pub use crate::scheduler::{BatchOptions, BatchOrder};
+pub use crate::scratch::{poa_consensus_with_scratch, ConsensusScratch};
+pub use crate::shard::{merge_shards, shard_clusters, shard_of};
+pub use crate::simulate::{simulate_batch, simulate_cluster, SimulatedCluster, SimulationProfile};
+pub use crate::strand::{
+    strand_column_stats, strand_concordance, ColumnStrandStats, Strand, StrandConcordance, StrandCounts,
+};
+pub use crate::stream::{poa_grouped_stream, ConsensusStream, FastqReader, FastqRecord};
+pub use crate::tail::{find_tails, poa_trim_tails, trim_tails, TailOptions, TailTrim};
+pub use crate::tune::{poa_auto, tune_params, AUTO_TUNE_READS};
+pub use crate::umi::{directional_families, poa_umi_families, UmiFamily};
+pub use crate::variant::{call_variants, Variant, VariantKind};
+pub use crate::vcf::{write_vcf, VcfWriter};
+
+use crate::pairwise::null_terminated;
+
 extern "C" {
     fn poa_func(
         seqs: *const *const u8,
@@ -22,6 +168,39 @@ extern "C" {
         gap2_open: i32,
         gap2_extend: i32,
     ) -> *const c_char;
+
+    fn poa_msa_func(
+        seqs: *const *const u8,
+        quals: *const *const u8,
+        num_seqs: i32,
+        alignment_type: i32, // 0 = local, 1 = global, 2 = gapped
+        match_score: i32,
+        mismatch_score: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        deterministic: i32, // 1 = always use the scalar alignment engine
+        num_rows: *mut i32,
+    ) -> *mut *mut c_char;
+
+    fn poa_consensus_func(
+        seqs: *const *const u8,
+        quals: *const *const u8,
+        num_seqs: i32,
+        alignment_type: i32, // 0 = local, 1 = global, 2 = gapped
+        match_score: i32,
+        mismatch_score: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        deterministic: i32, // 1 = always use the scalar alignment engine
+    ) -> *mut c_char;
+
+    fn poa_free_rows(rows: *mut *mut c_char, num_rows: i32);
+
+    fn poa_free_string(string: *mut c_char);
 }
 
 /// Generates a consensus sequence from a list of sequences.
@@ -35,6 +214,9 @@ extern "C" {
 /// * `gap_open` - the gap open score for alignment
 /// * `gap_extend` - the gap extend score for alignment
 ///
+/// It always aligns with the SIMD engine selected for the host CPU, as it takes no `AlignmentParams`; use
+/// `poa_consensus_into` or `poa` with `AlignmentParams::deterministic` for results independent of the CPU.
+///
 /// # Returns
 /// * returns the consensus of the input sequences as a vector of u8
 ///
@@ -87,50 +269,382 @@ pub fn poa_consensus <'a>(
     if seqs.len() == 0 {
         return ""
     }
+    crate::cpu::assert_cpu();
+    params::check_engine_scores(&[gap_open, gap_extend, gap2_open, gap2_extend]);
+
+    let num_seqs = seqs.len() as i32;
+
+    let c_buf: *const c_char = scratch::with_scratch(|scratch| {
+        let (seq_ptrs, qual_ptrs) = scratch.input_ptrs(seqs, quals);
+        unsafe {
+            poa_func(
+                seq_ptrs.as_ptr(),
+                qual_ptrs.as_ptr(),
+                num_seqs,
+                alignment_type,
+                match_score,
+                mismatch_score,
+                gap_open,
+                gap_extend,
+                gap2_open,
+                gap2_extend,
+            )
+        }
+    });
+    let c_str: &CStr = unsafe { CStr::from_ptr(c_buf) };
+    let str_slice: &str = c_str.to_str().unwrap();
+
+    str_slice
+}
 
-    if seqs.len() != quals.len() {
-        panic!("Input sequences and qualities must be of same length");
+/// Generates a multiple sequence alignment from a list of sequences.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to align
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `names` - optional names of the sequences, kept on the corresponding rows of the alignment. Without names, the sequences are named `seq_<index>`
+/// * `alignment_type` - alignment mode: 0 = local, 1 = global, 2 = gapped
+/// * `match_score` - the match score for alignment
+/// * `mismatch_score` - the mismatch score for alignment
+/// * `gap_open` - the gap open score for alignment
+/// * `gap_extend` - the gap extend score for alignment
+/// * `gap2_open` - the gap open score of the second affine function
+/// * `gap2_extend` - the gap extend score of the second affine function
+///
+/// # Returns
+/// * returns the gapped rows of the input sequences, in input order, together with the gapped consensus
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::poa_msa;
+///
+///     let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///     let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///     let names = vec!["read_1".to_string(), "read_2".to_string()];
+///
+///     let msa = poa_msa(&seqs, &quals, Some(&names), 1, 5, -4, -3, -1, -3, -1);
+///     let mut fasta = vec![];
+///     msa.write_fasta(&mut fasta, true).unwrap();
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub fn poa_msa(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    names: Option<&[String]>,
+    alignment_type: i32,
+    match_score: i32,
+    mismatch_score: i32,
+    gap_open: i32,
+    gap_extend: i32,
+    gap2_open: i32,
+    gap2_extend: i32,
+) -> Msa {
+    msa_ffi(
+        seqs,
+        quals,
+        names,
+        alignment_type,
+        [match_score, mismatch_score, gap_open, gap_extend, gap2_open, gap2_extend],
+        false,
+        false,
+    )
+}
+
+// `poa_msa` with the alignment mode and scores taken from `params`
+pub(crate) fn msa_with_params(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    names: Option<&[String]>,
+    params: &AlignmentParams,
+) -> Msa {
+    scratch::check_inputs(seqs, quals);
+    if let Some((seqs, quals)) = params.soft_mask.apply(seqs, quals) {
+        let params = AlignmentParams {
+            soft_mask: SoftMask::Preserve,
+            ..*params
+        };
+        return msa_with_params(&seqs, &quals, names, &params);
     }
+    if params.wildcard_score.is_some() {
+        // the sequences end at their first null byte, as on the C++ side
+        let unterminated = |bytes: &[u8]| bytes.iter().position(|&b| b == b'\0').unwrap();
+        let mut graph = PoaGraph::new(params);
+        for (seq, qual) in seqs.iter().zip(quals.iter()) {
+            graph.add(&seq[..unterminated(seq)], &qual[..unterminated(qual)]);
+        }
+        let mut msa = graph.msa();
+        if let Some(names) = names {
+            if names.len() != seqs.len() {
+                panic!("Input sequences and names must be of same length");
+            }
+            msa.names = names.to_vec();
+        }
+        return msa;
+    }
+    msa_ffi(
+        seqs,
+        quals,
+        names,
+        params.alignment_type as i32,
+        [
+            params.match_score,
+            params.mismatch_score,
+            params.gap_open,
+            params.gap_extend,
+            params.gap2_open,
+            params.gap2_extend,
+        ],
+        params.deterministic,
+        params.collapse_duplicates,
+    )
+}
 
-    let num_seqs = seqs.len() as i32;
+fn msa_ffi(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    names: Option<&[String]>,
+    alignment_type: i32,
+    scores: [i32; 6],
+    deterministic: bool,
+    collapse_duplicates: bool,
+) -> Msa {
+    let names: Vec<String> = match names {
+        Some(names) => {
+            if names.len() != seqs.len() {
+                panic!("Input sequences and names must be of same length");
+            }
+            names.to_vec()
+        }
+        None => (0..seqs.len()).map(|i| format!("seq_{}", i)).collect(),
+    };
+
+    if seqs.is_empty() {
+        return Msa::default();
+    }
+    crate::cpu::assert_cpu();
+    params::check_engine_scores(&scores[2..]);
 
-    let mut seq_ptrs: Vec<*const u8> = Vec::with_capacity(seqs.len());
-    let mut qual_ptrs: Vec<*const u8> = Vec::with_capacity(quals.len());
+    if collapse_duplicates {
+        return dedup::collapsed_msa(seqs, quals, names, alignment_type, scores, deterministic);
+    }
 
-    for seq in seqs {
-        if !(seq[seq.len()-1] == '\0' as u8) {
-            panic!("Input sequences must be null terminated");
+    let mut num_rows: i32 = 0;
+    let c_rows = scratch::with_scratch(|scratch| {
+        let (seq_ptrs, qual_ptrs) = scratch.input_ptrs(seqs, quals);
+        unsafe {
+            poa_msa_func(
+                seq_ptrs.as_ptr(),
+                qual_ptrs.as_ptr(),
+                seqs.len() as i32,
+                alignment_type,
+                scores[0],
+                scores[1],
+                scores[2],
+                scores[3],
+                scores[4],
+                scores[5],
+                deterministic as i32,
+                &mut num_rows,
+            )
         }
-        seq_ptrs.push(seq.as_ptr());
+    });
+
+    let mut rows: Vec<Vec<u8>> = (0..num_rows as usize)
+        .map(|i| unsafe { CStr::from_ptr(*c_rows.add(i)) }.to_bytes().to_vec())
+        .collect();
+    unsafe { poa_free_rows(c_rows, num_rows) };
+
+    let consensus = rows.pop().unwrap_or_default();
+    let empty: Vec<bool> = seqs.iter().map(|seq| seq[0] == b'\0').collect();
+    Msa {
+        names,
+        rows: msa::with_empty_rows(rows, &empty, consensus.len()),
+        consensus,
+    }
+}
+
+/// Generates the consensus of a list of sequences together with their alignment and its metrics.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+///
+/// # Returns
+/// * returns the consensus, the multiple sequence alignment, and the coverage and identity metrics
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa, AlignmentParams};
+///
+///     let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///     let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///
+///     let result = poa(&seqs, &quals, &AlignmentParams::default());
+///     assert_eq!(result.metrics.num_reads, 2);
+///     assert_eq!(result.msa.rows.len(), 2);
+/// ```
+pub fn poa(seqs: &[Vec<u8>], quals: &[Vec<u8>], params: &AlignmentParams) -> ConsensusResult {
+    ConsensusResult::from_msa(msa_with_params(seqs, quals, None, params))
+}
+
+/// Generates the consensus of a list of sequences like `poa`, unless the scores are out of the range of the
+/// alignment engine or may overflow while aligning the sequences, the CPU lacks the SIMD instruction set
+/// SPOA was compiled for, see `check_cpu`, or the reads are not the null-terminated sequences and Phred+33
+/// qualities of equal lengths it takes (`Error::InvalidInput`, see `try_poa_batch`).
+///
+/// As the graph is built on the C++ side, overflow is checked against its largest possible size: the total
+/// length of the sequences.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{try_poa, AlignmentParams, Error};
+///
+///     let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///     let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///     let params = AlignmentParams {
+///         match_score: 200,
+///         ..AlignmentParams::default()
+///     };
+///
+///     assert!(matches!(try_poa(&seqs, &quals, &params), Err(Error::ScoreOutOfRange { .. })));
+/// ```
+pub fn try_poa(seqs: &[Vec<u8>], quals: &[Vec<u8>], params: &AlignmentParams) -> Result<ConsensusResult, Error> {
+    check_poa_inputs(seqs, quals, params)?;
+    Ok(poa(seqs, quals, params))
+}
+
+// the checks of `try_poa`, which also hold for any subset of the reads
+pub(crate) fn check_poa_inputs(seqs: &[Vec<u8>], quals: &[Vec<u8>], params: &AlignmentParams) -> Result<(), Error> {
+    check_cpu()?;
+    params.validate()?;
+    batch::check_reads(seqs, quals)?;
+    // the sequences are null terminated
+    let lens = seqs.iter().map(|seq| seq.len().saturating_sub(1));
+    let seq_len = lens.clone().max().unwrap_or(0);
+    params.check_overflow(seq_len, lens.sum())
+}
+
+/// Generates the consensus sequence of a list of sequences into a caller-provided buffer, which is cleared
+/// first, so that its allocation is reused across calls in loops over many clusters. No alignment or metrics
+/// are computed, unlike `poa`.
+///
+/// With `params.collapse_duplicates`, `params.wildcard_score` or soft-masking, the consensus is taken from
+/// `poa`, which allocates its alignment.
+/// # Arguments
+///
+/// * `consensus` - the buffer receiving the consensus
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_consensus_into, AlignmentParams};
+///
+///     let mut consensus = Vec::with_capacity(1024);
+///     for _ in 0..3 {
+///         let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///         let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///         poa_consensus_into(&mut consensus, &seqs, &quals, &AlignmentParams::default());
+///     }
+/// ```
+pub fn poa_consensus_into(consensus: &mut Vec<u8>, seqs: &[Vec<u8>], quals: &[Vec<u8>], params: &AlignmentParams) {
+    consensus.clear();
+    if seqs.is_empty() {
+        return;
     }
-    for qual in quals {
-        if !(qual[qual.len()-1] == '\0' as u8) {
-            panic!("Input qualities must be null terminated");
+    if params.collapse_duplicates || params.wildcard_score.is_some() || params.soft_mask != SoftMask::Preserve {
+        consensus.extend_from_slice(&poa(seqs, quals, params).consensus);
+        return;
+    }
+    crate::cpu::assert_cpu();
+    params.check_engine_scores();
+
+    let c_buf = scratch::with_scratch(|scratch| {
+        let (seq_ptrs, qual_ptrs) = scratch.input_ptrs(seqs, quals);
+        unsafe {
+            poa_consensus_func(
+                seq_ptrs.as_ptr(),
+                qual_ptrs.as_ptr(),
+                seqs.len() as i32,
+                params.alignment_type as i32,
+                params.match_score,
+                params.mismatch_score,
+                params.gap_open,
+                params.gap_extend,
+                params.gap2_open,
+                params.gap2_extend,
+                params.deterministic as i32,
+            )
         }
-        qual_ptrs.push(qual.as_ptr());
+    });
+    if c_buf.is_null() {
+        return;
     }
+    consensus.extend_from_slice(unsafe { CStr::from_ptr(c_buf) }.to_bytes());
+    unsafe { poa_free_string(c_buf) };
+}
 
-    let c_buf: *const c_char = unsafe {
-        poa_func(
-            seq_ptrs.as_ptr(),
-            qual_ptrs.as_ptr(),
-            num_seqs,
-            alignment_type,
-            match_score,
-            mismatch_score,
-            gap_open,
-            gap_extend,
-            gap2_open,
-            gap2_extend,
-        )
-    };
-    let c_str: &CStr = unsafe { CStr::from_ptr(c_buf) };
-    let str_slice: &str = c_str.to_str().unwrap();
+/// Generates the consensus of a list of sequences held as strings, like `poa`, converting them to the
+/// null-terminated byte vectors of the FFI internally.
+/// # Arguments
+///
+/// * `seqs` - the sequences to form a consensus from, as `&str` or `String`
+/// * `quals` - the qualities of the sequences, as `&str` or `String`
+/// * `params` - the alignment mode and scores
+///
+/// # Returns
+/// * returns the consensus, the multiple sequence alignment, and the coverage and identity metrics
+pub fn poa_str<S: AsRef<str>, Q: AsRef<str>>(seqs: &[S], quals: &[Q], params: &AlignmentParams) -> ConsensusResult {
+    let seqs: Vec<Vec<u8>> = seqs.iter().map(|seq| null_terminated(seq.as_ref().as_bytes())).collect();
+    let quals: Vec<Vec<u8>> = quals.iter().map(|qual| null_terminated(qual.as_ref().as_bytes())).collect();
+    poa(&seqs, &quals, params)
+}
 
-    str_slice
+/// Generates the consensus sequence of a list of sequences held as strings, see `poa_str`.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_consensus_str, AlignmentParams};
+///
+///     let seqs = ["ACGTT", "ACGTT", "ACTT"];
+///     let quals = vec!["FFFFF".to_string(), "FFFFF".to_string(), "FFFF".to_string()];
+///
+///     let consensus: String = poa_consensus_str(&seqs, &quals, &AlignmentParams::default());
+/// ```
+pub fn poa_consensus_str<S: AsRef<str>, Q: AsRef<str>>(seqs: &[S], quals: &[Q], params: &AlignmentParams) -> String {
+    // the consensus is spelled with the bases of the input strings
+    String::from_utf8_lossy(&poa_str(seqs, quals, params).consensus).into_owned()
 }
 
 
+// the qualities of null-terminated test reads, `F` for every base
+#[cfg(test)]
+pub(crate) fn quals_for<S: AsRef<[u8]>>(seqs: &[S]) -> Vec<Vec<u8>> {
+    seqs.iter()
+        .map(|seq| seq.as_ref().iter().map(|&b| if b == 0 { 0 } else { b'F' }).collect())
+        .collect()
+}
+
+// a test cluster of null-terminated reads with the qualities of `quals_for`
+#[cfg(test)]
+pub(crate) fn test_cluster(id: &str, seqs: &[&str]) -> Cluster {
+    let seqs: Vec<Vec<u8>> = seqs.iter().map(|seq| null_terminated(seq.as_bytes())).collect();
+    let quals = quals_for(&seqs);
+    Cluster {
+        id: id.to_string(),
+        seqs,
+        quals,
+        metadata: Default::default(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -263,6 +777,107 @@ mod tests {
         assert_eq!(consensus, expected);
     }
 
+    #[test]
+    fn test_msa_names() {
+        let seqs: Vec<Vec<u8>> = ["ATTGCCCGTT\0", "AATGCCGTT\0", "AATGCCCGAT\0", "AACGCCCGTC\0"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        let quals = crate::quals_for(&seqs);
+        let names: Vec<String> = (1..=4).map(|i| format!("read_{}", i)).collect();
+
+        let msa = poa_msa(&seqs, &quals, Some(&names), 1, 5, -4, -3, -1, -3, -1);
+
+        assert_eq!(msa.names, names);
+        assert_eq!(msa.num_rows(), 4);
+        for (row, seq) in msa.rows.iter().zip(seqs.iter()) {
+            assert_eq!(row.len(), msa.num_columns());
+            let ungapped: Vec<u8> = row.iter().cloned().filter(|&b| b != GAP).collect();
+            assert_eq!(&ungapped[..], &seq[..seq.len() - 1]);
+        }
+        assert_eq!(msa.ungapped_consensus(), poa_consensus(&seqs, &quals, 1, 5, -4, -3, -1, -3, -1).as_bytes());
+
+        let unnamed = poa_msa(&seqs, &quals, None, 1, 5, -4, -3, -1, -3, -1);
+        assert_eq!(unnamed.names[3], "seq_3");
+    }
+
+    #[test]
+    fn test_poa_result() {
+        let seqs: Vec<Vec<u8>> = ["ATTGCCCGTT\0", "AATGCCGTT\0", "AATGCCCGAT\0", "AACGCCCGTC\0", "AGTGCTCGTT\0", "AATGCTCGTT\0"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        let quals = crate::quals_for(&seqs);
+
+        let result = poa(&seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(result.metrics.num_reads, 6);
+        assert_eq!(result.metrics.consensus_len, 10);
+        assert!(result.metrics.identity.iter().all(|&identity| identity >= 0.8));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let seqs: Vec<Vec<u8>> = ["FNLKESWDDCQ\0", "FNLKPSWDCQ\0", "FNLKSPSWDDCQ\0", "FNLKASWCQ\0", "FLKPSWDDCQ\0", "FNLKPSWDADCQ\0"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        let quals = crate::quals_for(&seqs);
+        let params = AlignmentParams {
+            deterministic: true,
+            ..AlignmentParams::default()
+        };
+
+        let scalar = poa(&seqs, &quals, &params);
+        let simd = poa(&seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(scalar.consensus, b"FNLKPSWDDCQ".to_vec());
+        assert_eq!(scalar.msa, simd.msa);
+        assert_eq!(scalar, poa(&seqs, &quals, &params));
+        let mut consensus = vec![];
+        poa_consensus_into(&mut consensus, &seqs, &quals, &params);
+        assert_eq!(consensus, scalar.consensus);
+    }
+
+    #[test]
+    fn test_poa_consensus_str() {
+        let seqs = ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"];
+        let quals: Vec<String> = seqs.iter().map(|seq| "F".repeat(seq.len())).collect();
+
+        assert_eq!(poa_consensus_str(&seqs, &quals, &AlignmentParams::default()), "AATGCCCGTT");
+    }
+
+    #[test]
+    fn test_poa_wildcard() {
+        let seqs: Vec<Vec<u8>> = ["AATGCCCGTT", "AATGCCCGTT", "AANNNNNGTT", "AANNNNNNTT", "AATGCCCGTT"]
+            .iter()
+            .map(|seq| format!("{}\0", seq).into_bytes())
+            .collect();
+        let quals = crate::quals_for(&seqs);
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+
+        let result = poa(&seqs, &quals, &params);
+
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+        assert!(result.msa.rows.iter().all(|row| !row.contains(&GAP)));
+    }
+
+    #[test]
+    #[should_panic(expected = "null terminated")]
+    fn test_poa_wildcard_not_null_terminated() {
+        let seqs = vec![b"AATGCCCGTT".to_vec(), b"AANNNNNGTT".to_vec()];
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+
+        poa(&seqs, &seqs, &params);
+    }
+
     #[test]
     #[should_panic]
     fn test_not_null_terminated() {
@@ -281,4 +896,127 @@ mod tests {
         poa_consensus(&seqs, &seqs, 1, 5, -4, -3, -1, -3, -1);
 
     }
+
+    #[test]
+    fn test_empty_read() {
+        let seqs = vec![b"ACGT\0".to_vec(), b"\0".to_vec(), b"ACGT\0".to_vec()];
+        let quals = vec![b"FFFF\0".to_vec(), b"\0".to_vec(), b"FFFF\0".to_vec()];
+
+        // SPOA leaves empty reads out of its alignment, which keeps a row of gaps for them
+        for &collapse_duplicates in &[false, true] {
+            let params = AlignmentParams {
+                collapse_duplicates,
+                ..AlignmentParams::default()
+            };
+            let result = poa(&seqs, &quals, &params);
+            assert_eq!(result.consensus, b"ACGT".to_vec());
+            assert_eq!(result.msa.rows, vec![b"ACGT".to_vec(), b"----".to_vec(), b"ACGT".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_try_poa_invalid_input() {
+        let seqs = vec![b"ACGT\0".to_vec(), b"AC\0T\0".to_vec()];
+        let quals = vec![b"FFFF\0".to_vec(), b"FFFF\0".to_vec()];
+        let invalid = try_poa(&seqs, &quals, &AlignmentParams::default());
+        assert!(matches!(invalid, Err(Error::InvalidInput { .. })));
+
+        let params = AlignmentParams {
+            gap_open: 3,
+            ..AlignmentParams::default()
+        };
+        let positive_gap = try_poa(&seqs[..1], &quals[..1], &params);
+        assert!(matches!(positive_gap, Err(Error::InvalidParams { .. })));
+    }
+
+    #[test]
+    #[should_panic(expected = "Input sequence 1 and its qualities must be of same length")]
+    fn test_qualities_of_other_length() {
+        let seqs = vec![b"ACGT\0".to_vec(), b"ACT\0".to_vec()];
+        let quals = vec![b"FFFF\0".to_vec(), b"FFFF\0".to_vec()];
+        poa(&seqs, &quals, &AlignmentParams::default());
+    }
+
+    mod invariants {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+        use proptest::sample::{select, subsequence};
+
+        fn base() -> impl Strategy<Value = u8> {
+            select(&b"ACGT"[..])
+        }
+
+        fn alignment_type() -> impl Strategy<Value = AlignmentType> {
+            select(&[AlignmentType::Local, AlignmentType::Global, AlignmentType::SemiGlobal][..])
+        }
+
+        // a template and reads of it, the first unedited and the others each with a substitution, insertion
+        // or deletion, at positions at least 3 bases apart, so that the template is the majority everywhere
+        fn template_and_reads() -> impl Strategy<Value = (Vec<u8>, Vec<Vec<u8>>)> {
+            (vec(base(), 24..60), 3usize..8)
+                .prop_flat_map(|(template, num_reads)| {
+                    let positions: Vec<usize> = (1..template.len() - 1).step_by(3).collect();
+                    let edits = vec((0..3u8, base()), num_reads - 1);
+                    (Just(template), subsequence(positions, num_reads - 1), edits)
+                })
+                .prop_map(|(template, positions, edits)| {
+                    let mut reads = vec![template.clone()];
+                    for (&pos, &(op, base)) in positions.iter().zip(edits.iter()) {
+                        let mut read = template.clone();
+                        match op {
+                            0 if base == read[pos] => read[pos] = complement(base),
+                            0 => read[pos] = base,
+                            1 => read.insert(pos, base),
+                            _ => {
+                                read.remove(pos);
+                            }
+                        }
+                        reads.push(read);
+                    }
+                    (template, reads)
+                })
+        }
+
+        fn consensus(reads: &[Vec<u8>], params: &AlignmentParams) -> Vec<u8> {
+            let seqs: Vec<Vec<u8>> = reads.iter().map(|read| null_terminated(read)).collect();
+            let quals: Vec<Vec<u8>> = reads.iter().map(|read| null_terminated(&vec![b'F'; read.len()])).collect();
+            poa(&seqs, &quals, params).consensus
+        }
+
+        proptest! {
+            #[test]
+            fn prop_identical_reads(
+                read in vec(base(), 1..100),
+                copies in 1usize..6,
+                alignment_type in alignment_type()
+            ) {
+                let params = AlignmentParams {
+                    alignment_type,
+                    ..AlignmentParams::default()
+                };
+                prop_assert_eq!(consensus(&vec![read.clone(); copies], &params), read);
+            }
+
+            #[test]
+            fn prop_global_consensus_len((_, reads) in template_and_reads()) {
+                let len = consensus(&reads, &AlignmentParams::default()).len();
+                let min = reads.iter().map(|read| read.len()).min().unwrap();
+                let max = reads.iter().map(|read| read.len()).max().unwrap();
+                prop_assert!(min <= len && len <= max, "consensus of {} bases, reads of {} to {}", len, min, max);
+            }
+
+            #[test]
+            fn prop_permutation_stable(
+                (reads, permuted) in template_and_reads()
+                    .prop_flat_map(|(_, reads)| (Just(reads.clone()), Just(reads).prop_shuffle()))
+            ) {
+                let params = AlignmentParams {
+                    deterministic: true,
+                    ..AlignmentParams::default()
+                };
+                prop_assert_eq!(consensus(&permuted, &params), consensus(&reads, &params));
+            }
+        }
+    }
 }
@@ -0,0 +1,559 @@
+//! Substitution matrices scoring each pair of symbols, such as BLOSUM62 for protein consensus.
+use std::io::{self, BufRead};
+
+use crate::backend::ConsensusBackend;
+use crate::error::Error;
+use crate::graph::PoaGraph;
+use crate::msa::Msa;
+use crate::params::{AlignmentParams, GapScores};
+use crate::result::ConsensusResult;
+
+// the largest number of 32-bit cells of the dynamic programming matrices of an alignment by the matrix engine
+// of the C++ shim, which keeps them whole for the traceback: 1 GiB
+const MAX_MATRIX_CELLS: usize = 1 << 28;
+
+// the NCBI matrices, in the format of `read_ncbi`
+const BLOSUM62: &str = "
+   A  R  N  D  C  Q  E  G  H  I  L  K  M  F  P  S  T  W  Y  V  B  Z  X  *
+A  4 -1 -2 -2  0 -1 -1  0 -2 -1 -1 -1 -1 -2 -1  1  0 -3 -2  0 -2 -1  0 -4
+R -1  5  0 -2 -3  1  0 -2  0 -3 -2  2 -1 -3 -2 -1 -1 -3 -2 -3 -1  0 -1 -4
+N -2  0  6  1 -3  0  0  0  1 -3 -3  0 -2 -3 -2  1  0 -4 -2 -3  3  0 -1 -4
+D -2 -2  1  6 -3  0  2 -1 -1 -3 -4 -1 -3 -3 -1  0 -1 -4 -3 -3  4  1 -1 -4
+C  0 -3 -3 -3  9 -3 -4 -3 -3 -1 -1 -3 -1 -2 -3 -1 -1 -2 -2 -1 -3 -3 -2 -4
+Q -1  1  0  0 -3  5  2 -2  0 -3 -2  1  0 -3 -1  0 -1 -2 -1 -2  0  3 -1 -4
+E -1  0  0  2 -4  2  5 -2  0 -3 -3  1 -2 -3 -1  0 -1 -3 -2 -2  1  4 -1 -4
+G  0 -2  0 -1 -3 -2 -2  6 -2 -4 -4 -2 -3 -3 -2  0 -2 -2 -3 -3 -1 -2 -1 -4
+H -2  0  1 -1 -3  0  0 -2  8 -3 -3 -1 -2 -1 -2 -1 -2 -2  2 -3  0  0 -1 -4
+I -1 -3 -3 -3 -1 -3 -3 -4 -3  4  2 -3  1  0 -3 -2 -1 -3 -1  3 -3 -3 -1 -4
+L -1 -2 -3 -4 -1 -2 -3 -4 -3  2  4 -2  2  0 -3 -2 -1 -2 -1  1 -4 -3 -1 -4
+K -1  2  0 -1 -3  1  1 -2 -1 -3 -2  5 -1 -3 -1  0 -1 -3 -2 -2  0  1 -1 -4
+M -1 -1 -2 -3 -1  0 -2 -3 -2  1  2 -1  5  0 -2 -1 -1 -1 -1  1 -3 -1 -1 -4
+F -2 -3 -3 -3 -2 -3 -3 -3 -1  0  0 -3  0  6 -4 -2 -2  1  3 -1 -3 -3 -1 -4
+P -1 -2 -2 -1 -3 -1 -1 -2 -2 -3 -3 -1 -2 -4  7 -1 -1 -4 -3 -2 -2 -1 -2 -4
+S  1 -1  1  0 -1  0  0  0 -1 -2 -2  0 -1 -2 -1  4  1 -3 -2 -2  0  0  0 -4
+T  0 -1  0 -1 -1 -1 -1 -2 -2 -1 -1 -1 -1 -2 -1  1  5 -2 -2  0 -1 -1  0 -4
+W -3 -3 -4 -4 -2 -2 -3 -2 -2 -3 -2 -3 -1  1 -4 -3 -2 11  2 -3 -4 -3 -2 -4
+Y -2 -2 -2 -3 -2 -1 -2 -3  2 -1 -1 -2 -1  3 -3 -2 -2  2  7 -1 -3 -2 -1 -4
+V  0 -3 -3 -3 -1 -2 -2 -3 -3  3  1 -2  1 -1 -2 -2  0 -3 -1  4 -3 -2 -1 -4
+B -2 -1  3  4 -3  0  1 -1  0 -3 -4  0 -3 -3 -2  0 -1 -4 -3 -3  4  1 -1 -4
+Z -1  0  0  1 -3  3  4 -2  0 -3 -3  1 -1 -3 -1  0 -1 -3 -2 -2  1  4 -1 -4
+X  0 -1 -1 -1 -2 -1 -1 -1 -1 -1 -1 -1 -1 -1 -2  0  0 -2 -1 -1 -1 -1 -1 -4
+* -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4 -4  1
+";
+
+const BLOSUM80: &str = "
+   A  R  N  D  C  Q  E  G  H  I  L  K  M  F  P  S  T  W  Y  V  B  Z  X  *
+A  5 -2 -2 -2 -1 -1 -1  0 -2 -2 -2 -1 -1 -3 -1  1  0 -3 -2  0 -2 -1 -1 -6
+R -2  6 -1 -2 -4  1 -1 -3  0 -3 -3  2 -2 -4 -2 -1 -1 -4 -3 -3 -2  0 -1 -6
+N -2 -1  6  1 -3  0 -1 -1  0 -4 -4  0 -3 -4 -3  0  0 -4 -3 -4  4  0 -1 -6
+D -2 -2  1  6 -4 -1  1 -2 -2 -4 -5 -1 -4 -4 -2 -1 -1 -6 -4 -4  4  1 -2 -6
+C -1 -4 -3 -4  9 -4 -5 -4 -4 -2 -2 -4 -2 -3 -4 -2 -1 -3 -3 -1 -4 -4 -3 -6
+Q -1  1  0 -1 -4  6  2 -2  1 -3 -3  1  0 -4 -2  0 -1 -3 -2 -3  0  3 -1 -6
+E -1 -1 -1  1 -5  2  6 -3  0 -4 -4  1 -2 -4 -2  0 -1 -4 -3 -3  1  4 -1 -6
+G  0 -3 -1 -2 -4 -2 -3  6 -3 -5 -4 -2 -4 -4 -3 -1 -2 -4 -4 -4 -1 -3 -2 -6
+H -2  0  0 -2 -4  1  0 -3  8 -4 -3 -1 -2 -2 -3 -1 -2 -3  2 -4 -1  0 -2 -6
+I -2 -3 -4 -4 -2 -3 -4 -5 -4  5  1 -3  1 -1 -4 -3 -1 -3 -2  3 -4 -4 -2 -6
+L -2 -3 -4 -5 -2 -3 -4 -4 -3  1  4 -3  2  0 -3 -3 -2 -2 -2  1 -4 -3 -2 -6
+K -1  2  0 -1 -4  1  1 -2 -1 -3 -3  5 -2 -4 -1 -1 -1 -4 -3 -3 -1  1 -1 -6
+M -1 -2 -3 -4 -2  0 -2 -4 -2  1  2 -2  6  0 -3 -2 -1 -2 -2  1 -3 -2 -1 -6
+F -3 -4 -4 -4 -3 -4 -4 -4 -2 -1  0 -4  0  6 -4 -3 -2  0  3 -1 -4 -4 -2 -6
+P -1 -2 -3 -2 -4 -2 -2 -3 -3 -4 -3 -1 -3 -4  8 -1 -2 -5 -4 -3 -2 -2 -2 -6
+S  1 -1  0 -1 -2  0  0 -1 -1 -3 -3 -1 -2 -3 -1  5  1 -4 -2 -2  0  0 -1 -6
+T  0 -1  0 -1 -1 -1 -1 -2 -2 -1 -2 -1 -1 -2 -2  1  5 -4 -2  0 -1 -1 -1 -6
+W -3 -4 -4 -6 -3 -3 -4 -4 -3 -3 -2 -4 -2  0 -5 -4 -4 11  2 -3 -5 -4 -3 -6
+Y -2 -3 -3 -4 -3 -2 -3 -4  2 -2 -2 -3 -2  3 -4 -2 -2  2  7 -2 -3 -3 -2 -6
+V  0 -3 -4 -4 -1 -3 -3 -4 -4  3  1 -3  1 -1 -3 -2  0 -3 -2  4 -4 -3 -1 -6
+B -2 -2  4  4 -4  0  1 -1 -1 -4 -4 -1 -3 -4 -2  0 -1 -5 -3 -4  4  0 -2 -6
+Z -1  0  0  1 -4  3  4 -3  0 -4 -3  1 -2 -4 -2  0 -1 -4 -3 -3  0  4 -1 -6
+X -1 -1 -1 -2 -3 -1 -1 -2 -2 -2 -2 -1 -1 -2 -2 -1 -1 -3 -2 -1 -2 -1 -1 -6
+* -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6 -6  1
+";
+
+const PAM250: &str = "
+   A  R  N  D  C  Q  E  G  H  I  L  K  M  F  P  S  T  W  Y  V  B  Z  X  *
+A  2 -2  0  0 -2  0  0  1 -1 -1 -2 -1 -1 -3  1  1  1 -6 -3  0  0  0  0 -8
+R -2  6  0 -1 -4  1 -1 -3  2 -2 -3  3  0 -4  0  0 -1  2 -4 -2 -1  0 -1 -8
+N  0  0  2  2 -4  1  1  0  2 -2 -3  1 -2 -3  0  1  0 -4 -2 -2  2  1  0 -8
+D  0 -1  2  4 -5  2  3  1  1 -2 -4  0 -3 -6 -1  0  0 -7 -4 -2  3  3 -1 -8
+C -2 -4 -4 -5 12 -5 -5 -3 -3 -2 -6 -5 -5 -4 -3  0 -2 -8  0 -2 -4 -5 -3 -8
+Q  0  1  1  2 -5  4  2 -1  3 -2 -2  1 -1 -5  0 -1 -1 -5 -4 -2  1  3 -1 -8
+E  0 -1  1  3 -5  2  4  0  1 -2 -3  0 -2 -5 -1  0  0 -7 -4 -2  3  3 -1 -8
+G  1 -3  0  1 -3 -1  0  5 -2 -3 -4 -2 -3 -5  0  1  0 -7 -5 -1  0  0 -1 -8
+H -1  2  2  1 -3  3  1 -2  6 -2 -2  0 -2 -2  0 -1 -1 -3  0 -2  1  2 -1 -8
+I -1 -2 -2 -2 -2 -2 -2 -3 -2  5  2 -2  2  1 -2 -1  0 -5 -1  4 -2 -2 -1 -8
+L -2 -3 -3 -4 -6 -2 -3 -4 -2  2  6 -3  4  2 -3 -3 -2 -2 -1  2 -3 -3 -1 -8
+K -1  3  1  0 -5  1  0 -2  0 -2 -3  5  0 -5 -1  0  0 -3 -4 -2  1  0 -1 -8
+M -1  0 -2 -3 -5 -1 -2 -3 -2  2  4  0  6  0 -2 -2 -1 -4 -2  2 -2 -2 -1 -8
+F -3 -4 -3 -6 -4 -5 -5 -5 -2  1  2 -5  0  9 -5 -3 -3  0  7 -1 -4 -5 -2 -8
+P  1  0  0 -1 -3  0 -1  0  0 -2 -3 -1 -2 -5  6  1  0 -6 -5 -1 -1  0 -1 -8
+S  1  0  1  0  0 -1  0  1 -1 -1 -3  0 -2 -3  1  2  1 -2 -3 -1  0  0  0 -8
+T  1 -1  0  0 -2 -1  0  0 -1  0 -2  0 -1 -3  0  1  3 -5 -3  0  0 -1  0 -8
+W -6  2 -4 -7 -8 -5 -7 -7 -3 -5 -2 -3 -4  0 -6 -2 -5 17  0 -6 -5 -6 -4 -8
+Y -3 -4 -2 -4  0 -4 -4 -5  0 -1 -1 -4 -2  7 -5 -3 -3  0 10 -2 -3 -4 -2 -8
+V  0 -2 -2 -2 -2 -2 -2 -1 -2  4  2 -2  2 -1 -1 -1  0 -6 -2  4 -2 -2 -1 -8
+B  0 -1  2  3 -4  1  3  0  1 -2 -3  1 -2 -4 -1  0  0 -5 -3 -2  3  2 -1 -8
+Z  0  0  1  3 -5  3  3  0  2 -2 -3  0 -2 -5  0  0 -1 -6 -4 -2  2  3 -1 -8
+X  0 -1  0 -1 -3 -1 -1 -1 -1 -1 -1 -1 -1 -2 -1  0  0 -4 -2 -1 -1 -1 -1 -8
+* -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8 -8  1
+";
+
+/// A substitution matrix: the score of aligning each pair of symbols of an alphabet, in place of the match
+/// and mismatch scores.
+///
+/// A symbol outside the alphabet scores as its other case if the alphabet holds it, e.g. the lowercase
+/// residues of a protein, and otherwise as the lowest score of the matrix. Scores lie in -128..=127, like the
+/// match and mismatch scores of `AlignmentParams`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstitutionMatrix {
+    alphabet: Vec<u8>,
+    // the scores of the symbols of the alphabet, row by row
+    scores: Vec<i32>,
+    // the index in the alphabet of each byte, resolved through its other case for symbols outside of it
+    index: Vec<Option<usize>>,
+    min_score: i32,
+}
+
+impl SubstitutionMatrix {
+    /// Creates a matrix from the score of every pair of symbols of an alphabet. An alphabet holding a symbol
+    /// twice, scores that are not a symmetric matrix of the size of the alphabet, or scores out of -128..=127,
+    /// fail with `Error::InvalidParams`.
+    ///
+    /// # Arguments
+    ///
+    /// * `alphabet` - the symbols, such as residues or bases
+    /// * `scores` - the score of each symbol against each symbol, as rows in the order of the alphabet
+    pub fn new(alphabet: &[u8], scores: &[Vec<i32>]) -> Result<SubstitutionMatrix, Error> {
+        SubstitutionMatrix::build(alphabet, scores, true)
+    }
+
+    // a matrix scoring symbols outside the alphabet as the lowest score, whatever their case, for alphabets
+    // whose symbols of either case mean different things, e.g. `m` for 5mC but `M` for the IUPAC A or C
+    pub(crate) fn new_case_sensitive(alphabet: &[u8], scores: &[Vec<i32>]) -> Result<SubstitutionMatrix, Error> {
+        SubstitutionMatrix::build(alphabet, scores, false)
+    }
+
+    fn build(alphabet: &[u8], scores: &[Vec<i32>], fold_case: bool) -> Result<SubstitutionMatrix, Error> {
+        let invalid = |reason: String| Err(Error::InvalidParams { reason });
+        let n = alphabet.len();
+        if n == 0 {
+            return invalid("empty substitution matrix".to_string());
+        }
+        if scores.len() != n || scores.iter().any(|row| row.len() != n) {
+            return invalid(format!("substitution matrix not of {} by {} scores", n, n));
+        }
+        let mut index = vec![None; 256];
+        for (i, &symbol) in alphabet.iter().enumerate() {
+            if index[symbol as usize].replace(i).is_some() {
+                return invalid(format!("symbol {} twice in the substitution matrix", symbol as char));
+            }
+        }
+        for i in 0..n {
+            if let Some(j) = scores[i].iter().position(|score| !(-128..=127).contains(score)) {
+                let (a, b) = (alphabet[i] as char, alphabet[j] as char);
+                return invalid(format!("substitution score {} of {}/{} out of -128..=127", scores[i][j], a, b));
+            }
+            for j in 0..i {
+                if scores[i][j] != scores[j][i] {
+                    let (a, b) = (alphabet[i] as char, alphabet[j] as char);
+                    return invalid(format!("substitution matrix scoring {}/{} and {}/{} differently", a, b, b, a));
+                }
+            }
+        }
+        // symbols outside the alphabet through their other case
+        for byte in 0..=255u8 {
+            if fold_case && index[byte as usize].is_none() {
+                let other = if byte.is_ascii_lowercase() {
+                    byte.to_ascii_uppercase()
+                } else {
+                    byte.to_ascii_lowercase()
+                };
+                index[byte as usize] = index[other as usize];
+            }
+        }
+        let scores: Vec<i32> = scores.iter().flatten().cloned().collect();
+        Ok(SubstitutionMatrix {
+            alphabet: alphabet.to_vec(),
+            min_score: scores.iter().cloned().min().unwrap(),
+            scores,
+            index,
+        })
+    }
+
+    /// Reads a matrix in the format of the NCBI matrix files: `#` comment lines, a header line of the
+    /// symbols, then a line per symbol, of the symbol followed by its scores.
+    pub fn read_ncbi<R: BufRead>(reader: R) -> io::Result<SubstitutionMatrix> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut alphabet: Option<Vec<u8>> = None;
+        let mut row_symbols = vec![];
+        let mut scores = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if alphabet.is_none() {
+                if fields.iter().any(|field| field.len() != 1) {
+                    return Err(invalid(format!("substitution matrix header not of single symbols: {}", line)));
+                }
+                alphabet = Some(fields.iter().map(|field| field.as_bytes()[0]).collect());
+                continue;
+            }
+            if fields[0].len() != 1 {
+                return Err(invalid(format!("substitution matrix row not starting with a symbol: {}", line)));
+            }
+            row_symbols.push(fields[0].as_bytes()[0]);
+            let row: Result<Vec<i32>, _> = fields[1..].iter().map(|field| field.parse::<i32>()).collect();
+            scores.push(row.map_err(|e| invalid(format!("invalid substitution score: {}", e)))?);
+        }
+        let alphabet = alphabet.ok_or_else(|| invalid("empty substitution matrix".to_string()))?;
+        if row_symbols != alphabet {
+            return Err(invalid("substitution matrix rows not in the order of its header".to_string()));
+        }
+        SubstitutionMatrix::new(&alphabet, &scores).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The BLOSUM62 matrix of amino acids, the default of protein BLAST.
+    pub fn blosum62() -> SubstitutionMatrix {
+        SubstitutionMatrix::read_ncbi(BLOSUM62.as_bytes()).unwrap()
+    }
+
+    /// The BLOSUM80 matrix of amino acids, for closely related proteins.
+    pub fn blosum80() -> SubstitutionMatrix {
+        SubstitutionMatrix::read_ncbi(BLOSUM80.as_bytes()).unwrap()
+    }
+
+    /// The PAM250 matrix of amino acids, for distantly related proteins.
+    pub fn pam250() -> SubstitutionMatrix {
+        SubstitutionMatrix::read_ncbi(PAM250.as_bytes()).unwrap()
+    }
+
+    /// Returns the symbols of the matrix.
+    pub fn alphabet(&self) -> &[u8] {
+        &self.alphabet
+    }
+
+    /// Returns the score of aligning two symbols.
+    pub fn score(&self, a: u8, b: u8) -> i32 {
+        match (self.index[a as usize], self.index[b as usize]) {
+            (Some(i), Some(j)) => self.scores[i * self.alphabet.len() + j],
+            _ => self.min_score,
+        }
+    }
+
+    // the matrix of the match and mismatch scores of `params` over every byte, `N` (or `n`) scoring
+    // `params.wildcard_score` against any byte, None without a wildcard score
+    pub(crate) fn wildcard(params: &AlignmentParams) -> Option<Result<SubstitutionMatrix, Error>> {
+        let wildcard = params.wildcard_score?;
+        let alphabet: Vec<u8> = (0..=255u8).collect();
+        let scores: Vec<Vec<i32>> = alphabet
+            .iter()
+            .map(|&a| {
+                alphabet
+                    .iter()
+                    .map(|&b| match () {
+                        _ if a.eq_ignore_ascii_case(&b'N') || b.eq_ignore_ascii_case(&b'N') => wildcard,
+                        _ if a == b => params.match_score,
+                        _ => params.mismatch_score,
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(SubstitutionMatrix::new_case_sensitive(&alphabet, &scores))
+    }
+
+    // the score of every pair of bytes, row by row, as the matrix engine of the C++ shim takes them
+    pub(crate) fn table(&self) -> Vec<i32> {
+        (0..=255u8).flat_map(|a| (0..=255u8).map(move |b| self.score(a, b))).collect()
+    }
+
+    // the parameters whose match and mismatch scores bound the scores of the matrix, for the overflow check
+    pub(crate) fn scoring_params(&self, params: &AlignmentParams) -> AlignmentParams {
+        AlignmentParams {
+            match_score: self.scores.iter().cloned().max().unwrap(),
+            mismatch_score: self.min_score,
+            ..*params
+        }
+    }
+}
+
+// checks that the matrices of the alignment of a sequence of `seq_len` bases against a graph of `graph_len`
+// nodes by the matrix engine fit its size limit: the overall scores and the insertions and deletions of each
+// gap function, one cell per query position (plus one) and node (plus one)
+pub(crate) fn check_matrix_size(params: &AlignmentParams, seq_len: usize, graph_len: usize) -> Result<(), Error> {
+    let gap_functions = match params.gap_model() {
+        GapScores::Linear { .. } | GapScores::Affine { .. } => 1,
+        GapScores::Convex { .. } => 2,
+    };
+    let cells = (seq_len as u128 + 1) * (graph_len as u128 + 1) * (1 + 2 * gap_functions);
+    if cells > MAX_MATRIX_CELLS as u128 {
+        return Err(Error::MatrixTooLarge {
+            seq_len,
+            graph_len,
+            max_cells: MAX_MATRIX_CELLS,
+        });
+    }
+    Ok(())
+}
+
+/// Generates the consensus of a list of sequences like `try_poa`, scoring substitutions with `matrix` instead of
+/// the match and mismatch scores of `params`, e.g. BLOSUM62 for protein consensus. SPOA builds the graph and the
+/// consensus, aligning each sequence with the matrix engine of the C++ shim, see `PoaGraph::with_matrix`.
+///
+/// The matrix is not a field of `AlignmentParams`, which is `Copy` and hashed as a whole, so the scores are
+/// checked for overflow with the highest and lowest scores of the matrix in place of the match and mismatch
+/// scores. The matrix engine keeps the whole matrices of each alignment for the traceback, so a sequence whose
+/// alignment against the graph would take more than 2^28 cells (1 GiB) fails with `Error::MatrixTooLarge`.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and gap scores
+/// * `matrix` - the matrix scoring substitutions
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{try_poa_with_matrix, AlignmentParams, Preset, SubstitutionMatrix};
+///
+///     let seqs = vec![b"MQIFVKTLTG\0".to_vec(), b"MQIFVRTLTG\0".to_vec(), b"MQLFVKTLTG\0".to_vec()];
+///     let quals = vec![b"IIIIIIIIII\0".to_vec(); 3];
+///     let params = AlignmentParams::preset(Preset::Protein);
+///
+///     let result = try_poa_with_matrix(&seqs, &quals, &params, &SubstitutionMatrix::blosum62()).unwrap();
+///     assert_eq!(result.consensus, b"MQIFVKTLTG".to_vec());
+/// ```
+pub fn try_poa_with_matrix(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    matrix: &SubstitutionMatrix,
+) -> Result<ConsensusResult, Error> {
+    crate::check_cpu()?;
+    params.validate()?;
+    if params.wildcard_score.is_some() {
+        return Err(Error::InvalidParams {
+            reason: "a wildcard score cannot be combined with a substitution matrix, which scores N itself".to_string(),
+        });
+    }
+    crate::batch::check_reads(seqs, quals)?;
+    // the sequences are null terminated
+    let lens = seqs.iter().map(|seq| seq.len().saturating_sub(1));
+    let seq_len = lens.clone().max().unwrap_or(0);
+    matrix.scoring_params(params).check_overflow(seq_len, lens.sum())?;
+    let mut graph = PoaGraph::with_matrix(params, matrix);
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        graph.try_add(&seq[..seq.len() - 1], &qual[..qual.len() - 1])?;
+    }
+    Ok(graph.result())
+}
+
+/// The backend scoring substitutions with a matrix, e.g. for protein consensus: SPOA builds the graph and the
+/// consensus, aligning each sequence with the matrix engine of the C++ shim, see `PoaGraph::with_matrix`. The
+/// gap scores and alignment mode are those of the parameters, whose match and mismatch scores are ignored and
+/// which must not set a wildcard score, as the matrix scores `N` itself.
+///
+/// SPOA's own engines only score matches and mismatches, so the matrix engine runs the dynamic programming of
+/// its scalar engine, without SIMD, over every node of the graph.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_with_backend, AlignmentParams, MatrixBackend, Preset, SubstitutionMatrix};
+///
+///     let backend = MatrixBackend::new(SubstitutionMatrix::blosum62());
+///     let seqs: Vec<&[u8]> = vec![b"MQIFVKTLTG", b"MQIFVRTLTG", b"MQLFVKTLTG"];
+///     let quals: Vec<&[u8]> = vec![b"IIIIIIIIII"; 3];
+///     let result = poa_with_backend(&backend, &seqs, &quals, &AlignmentParams::preset(Preset::Protein));
+///     assert_eq!(result.consensus, b"MQIFVKTLTG".to_vec());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixBackend {
+    /// The matrix scoring substitutions.
+    pub matrix: SubstitutionMatrix,
+}
+
+impl MatrixBackend {
+    /// Creates the backend scoring substitutions with `matrix`.
+    pub fn new(matrix: SubstitutionMatrix) -> MatrixBackend {
+        MatrixBackend { matrix }
+    }
+}
+
+impl ConsensusBackend for MatrixBackend {
+    fn msa(&self, seqs: &[&[u8]], quals: &[&[u8]], params: &AlignmentParams) -> Msa {
+        if seqs.len() != quals.len() {
+            panic!("Input sequences and qualities must be of same length");
+        }
+        let mut graph = PoaGraph::with_matrix(params, &self.matrix);
+        for (seq, qual) in seqs.iter().zip(quals.iter()) {
+            graph.add(seq, qual);
+        }
+        graph.msa()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::poa_with_backend;
+    use crate::params::AlignmentType;
+
+    #[test]
+    fn test_presets() {
+        let presets = [SubstitutionMatrix::blosum62(), SubstitutionMatrix::blosum80(), SubstitutionMatrix::pam250()];
+        for matrix in presets.iter() {
+            assert_eq!(matrix.alphabet(), &b"ARNDCQEGHILKMFPSTWYVBZX*"[..]);
+        }
+        let blosum62 = SubstitutionMatrix::blosum62();
+        assert_eq!((blosum62.score(b'W', b'W'), blosum62.score(b'A', b'A')), (11, 4));
+        assert_eq!((blosum62.score(b'I', b'V'), blosum62.score(b'W', b'D')), (3, -4));
+        assert_eq!(SubstitutionMatrix::pam250().score(b'C', b'C'), 12);
+        // lowercase residues score as uppercase ones, unknown symbols as the lowest score
+        assert_eq!(blosum62.score(b'w', b'W'), 11);
+        assert_eq!(blosum62.score(b'J', b'A'), -4);
+    }
+
+    #[test]
+    fn test_new() {
+        let matrix = SubstitutionMatrix::new(b"AC", &[vec![2, -1], vec![-1, 3]]).unwrap();
+        assert_eq!((matrix.score(b'C', b'C'), matrix.score(b'a', b'C')), (3, -1));
+
+        assert!(SubstitutionMatrix::new(b"AC", &[vec![2, -1], vec![0, 3]]).is_err());
+        assert!(SubstitutionMatrix::new(b"AA", &[vec![2, -1], vec![-1, 3]]).is_err());
+        assert!(SubstitutionMatrix::new(b"AC", &[vec![2, -1]]).is_err());
+        assert!(SubstitutionMatrix::new(b"AC", &[vec![2, -1], vec![-1, 128]]).is_err());
+        assert!(SubstitutionMatrix::new(b"A", &[vec![-128]]).is_ok());
+        assert!(SubstitutionMatrix::read_ncbi(&b"# comment\n   A  C\nA  2 -1\nC -1  3\n"[..]).is_ok());
+        assert!(SubstitutionMatrix::read_ncbi(&b"   A  C\nC -1  3\nA  2 -1\n"[..]).is_err());
+        assert!(SubstitutionMatrix::read_ncbi(&b"   A  C\nA  2 -1\nC -1 300\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_matrix_backend() {
+        // V is closer to I than to L in BLOSUM62, so it is aligned under the I
+        let seqs: Vec<&[u8]> = vec![b"MKLIAK", b"MKVAK"];
+        let quals: Vec<&[u8]> = vec![b"IIIIII", b"IIIII"];
+        let params = AlignmentParams::preset(crate::params::Preset::Protein);
+        let backend = MatrixBackend::new(SubstitutionMatrix::blosum62());
+
+        let result = poa_with_backend(&backend, &seqs, &quals, &params);
+
+        assert_eq!(result.msa.rows, vec![b"MKLIAK".to_vec(), b"MK-VAK".to_vec()]);
+        assert_eq!(result.consensus, b"MKLIAK".to_vec());
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert!(SubstitutionMatrix::wildcard(&AlignmentParams::default()).is_none());
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+        let matrix = SubstitutionMatrix::wildcard(&params).unwrap().unwrap();
+
+        assert_eq!((matrix.score(b'A', b'A'), matrix.score(b'a', b'A'), matrix.score(b'C', b'G')), (5, -4, -4));
+        assert_eq!((matrix.score(b'N', b'G'), matrix.score(b'T', b'n'), matrix.score(b'N', b'N')), (0, 0, 0));
+        let out_of_range = AlignmentParams {
+            wildcard_score: Some(200),
+            ..params
+        };
+        assert!(SubstitutionMatrix::wildcard(&out_of_range).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_table() {
+        let matrix = SubstitutionMatrix::new(b"AC", &[vec![2, -1], vec![-1, 3]]).unwrap();
+        let table = matrix.table();
+
+        assert_eq!(table.len(), 256 * 256);
+        assert_eq!(table[b'C' as usize * 256 + b'C' as usize], 3);
+        assert_eq!(table[b'a' as usize * 256 + b'C' as usize], -1);
+        assert_eq!(table[b'G' as usize * 256 + b'A' as usize], -1);
+        let params = matrix.scoring_params(&AlignmentParams::default());
+        assert_eq!((params.match_score, params.mismatch_score), (3, -1));
+        assert_eq!(params.gap_open, AlignmentParams::default().gap_open);
+    }
+
+    #[test]
+    fn test_try_poa_with_matrix() {
+        let seqs = vec![b"MKLIAK\0".to_vec(), b"MKVAK\0".to_vec()];
+        let quals = vec![b"IIIIII\0".to_vec(), b"IIIII\0".to_vec()];
+        let params = AlignmentParams::preset(crate::params::Preset::Protein);
+        let blosum62 = SubstitutionMatrix::blosum62();
+
+        let result = try_poa_with_matrix(&seqs, &quals, &params, &blosum62).unwrap();
+        assert_eq!(result.msa.rows, vec![b"MKLIAK".to_vec(), b"MK-VAK".to_vec()]);
+
+        let mut graph = PoaGraph::with_matrix(&params, &blosum62);
+        graph.add(b"MKLIAK", b"IIIIII");
+        assert_eq!(graph.matrix(), Some(&blosum62));
+        assert_eq!(graph.rebuild_without(&[]).matrix(), Some(&blosum62));
+        assert_eq!(graph.align(b"MKVAK").score, graph.score(b"MKVAK"));
+
+        assert!(matches!(
+            try_poa_with_matrix(&seqs, &quals[..1], &params, &blosum62),
+            Err(Error::InvalidInput { .. })
+        ));
+        let wildcard = AlignmentParams {
+            wildcard_score: Some(0),
+            ..params
+        };
+        assert!(matches!(
+            try_poa_with_matrix(&seqs, &quals, &wildcard, &blosum62),
+            Err(Error::InvalidParams { .. })
+        ));
+        // the matrices of the second read against the graph of the first exceed the limit
+        let seqs = vec![[&[b'M'; 10000][..], b"\0"].concat(); 2];
+        let quals = vec![[&[b'I'; 10000][..], b"\0"].concat(); 2];
+        assert!(matches!(
+            try_poa_with_matrix(&seqs, &quals, &params, &blosum62),
+            Err(Error::MatrixTooLarge {
+                seq_len: 10000,
+                graph_len: 10000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_matrix_size() {
+        let params = AlignmentParams::default();
+        assert!(check_matrix_size(&params, 1000, 1000).is_ok());
+        assert!(matches!(
+            check_matrix_size(&params, 1 << 14, 1 << 14),
+            Err(Error::MatrixTooLarge { max_cells: MAX_MATRIX_CELLS, .. })
+        ));
+        assert!(check_matrix_size(&params, usize::MAX, 1).is_err());
+    }
+
+    // a matrix of the match and mismatch scores aligns like SPOA's own engines, whose dynamic programming the
+    // matrix engine follows
+    #[test]
+    fn test_matrix_engine_matches_spoa() {
+        let seqs: [&[u8]; 6] = [b"ATTGCCCGTT", b"AATGCCGTT", b"AATGCCCGAT", b"AACGCCCGTC", b"AGTGCTCGTT", b"AATGCTCGTT"];
+        let queries: [&[u8]; 3] = [b"AATGCCCGTT", b"TTAATGCCGTTGG", b"GGGG"];
+        for &alignment_type in [AlignmentType::Local, AlignmentType::Global, AlignmentType::SemiGlobal].iter() {
+            let params = AlignmentParams {
+                alignment_type,
+                ..AlignmentParams::default()
+            };
+            let scores: Vec<Vec<i32>> = (0..4)
+                .map(|i| (0..4).map(|j| if i == j { params.match_score } else { params.mismatch_score }).collect())
+                .collect();
+            let matrix = SubstitutionMatrix::new(b"ACGT", &scores).unwrap();
+            let mut spoa = PoaGraph::new(&params);
+            let mut engine = PoaGraph::with_matrix(&params, &matrix);
+            for seq in seqs.iter() {
+                spoa.add(seq, &vec![b'F'; seq.len()]);
+                engine.add(seq, &vec![b'F'; seq.len()]);
+            }
+
+            assert_eq!(engine.consensus(), spoa.consensus());
+            for query in queries.iter() {
+                assert_eq!(engine.score(query), spoa.score(query), "{:?} {:?}", alignment_type, query);
+                assert_eq!(engine.align(query).score, spoa.align(query).score);
+            }
+        }
+    }
+}
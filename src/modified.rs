@@ -0,0 +1,197 @@
+//! Alphabets of bases extended with modified bases, for the consensus of reads whose basecaller emits
+//! modified-base calls inline, e.g. 5mC as a symbol of its own.
+use crate::error::Error;
+use crate::matrix::SubstitutionMatrix;
+
+/// A modified base, emitted inline as a symbol of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModifiedBase {
+    /// The symbol of the modified base in the reads, e.g. `m` for 5mC, following the codes of the SAM `MM`
+    /// tag.
+    pub symbol: u8,
+    /// The canonical base it modifies, e.g. `C`.
+    pub canonical: u8,
+}
+
+impl ModifiedBase {
+    /// 5-methylcytosine, `m`.
+    pub const FIVE_MC: ModifiedBase = ModifiedBase {
+        symbol: b'm',
+        canonical: b'C',
+    };
+    /// 5-hydroxymethylcytosine, `h`.
+    pub const FIVE_HMC: ModifiedBase = ModifiedBase {
+        symbol: b'h',
+        canonical: b'C',
+    };
+    /// N6-methyladenine, `a`.
+    pub const SIX_MA: ModifiedBase = ModifiedBase {
+        symbol: b'a',
+        canonical: b'A',
+    };
+}
+
+/// The bases `ACGT` extended with modified bases, see `ModifiedAlphabet::matrix`.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_with_backend, AlignmentParams, MatrixBackend, ModifiedAlphabet, ModifiedBase};
+///
+///     let alphabet = ModifiedAlphabet::new(vec![ModifiedBase::FIVE_MC]);
+///     let backend = MatrixBackend::new(alphabet.matrix(5, -4, 2).unwrap());
+///     let seqs: Vec<&[u8]> = vec![b"ACmGT", b"ACmGT", b"ACCGT"];
+///     let quals: Vec<&[u8]> = vec![b"FFFFF"; 3];
+///     let result = poa_with_backend(&backend, &seqs, &quals, &AlignmentParams::default());
+///     assert_eq!(alphabet.canonical(&result.consensus), (b"ACCGT".to_vec(), vec![(2, b'm')]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ModifiedAlphabet {
+    /// The modified bases.
+    pub modifications: Vec<ModifiedBase>,
+}
+
+impl ModifiedAlphabet {
+    /// Creates the alphabet of `ACGT` and the modified bases.
+    pub fn new(modifications: Vec<ModifiedBase>) -> ModifiedAlphabet {
+        ModifiedAlphabet { modifications }
+    }
+
+    /// Returns the symbols of the alphabet: `ACGT`, then the modified bases in order.
+    pub fn symbols(&self) -> Vec<u8> {
+        let mut symbols = b"ACGT".to_vec();
+        symbols.extend(self.modifications.iter().map(|modification| modification.symbol));
+        symbols
+    }
+
+    /// Returns the substitution matrix of the alphabet, to align with a `MatrixBackend`: equal symbols match,
+    /// a modified base aligned to its canonical base scores between a match and a mismatch, so that modified
+    /// and unmodified copies of a base share a column, and other symbols mismatch. A symbol modifying a base
+    /// other than `ACGT`, or repeating another symbol, or a canonical score not between the mismatch and match
+    /// scores, fails with `Error::InvalidParams`. For other scores, e.g. between two modifications of the same
+    /// base, build a `SubstitutionMatrix` over `symbols` instead.
+    ///
+    /// Unlike `SubstitutionMatrix::new`, the matrix does not score symbols outside the alphabet as their other
+    /// case, as the case of a symbol tells modified bases apart from the IUPAC codes, e.g. `m` for 5mC and `M`
+    /// for A or C: symbols other than `ACGT` and the modified bases, lowercase bases included, score as a
+    /// mismatch.
+    ///
+    /// # Arguments
+    ///
+    /// * `match_score` - the score of two equal symbols
+    /// * `mismatch_score` - the score of two unrelated symbols
+    /// * `canonical_score` - the score of a modified base aligned to its canonical base
+    pub fn matrix(
+        &self,
+        match_score: i32,
+        mismatch_score: i32,
+        canonical_score: i32,
+    ) -> Result<SubstitutionMatrix, Error> {
+        if let Some(modification) = self.modifications.iter().find(|m| !b"ACGT".contains(&m.canonical)) {
+            return Err(Error::InvalidParams {
+                reason: format!(
+                    "modified base {} of {}, not a canonical base",
+                    modification.symbol as char, modification.canonical as char
+                ),
+            });
+        }
+        if canonical_score < mismatch_score || canonical_score > match_score {
+            return Err(Error::InvalidParams {
+                reason: format!(
+                    "canonical score {} not between the mismatch score {} and the match score {}",
+                    canonical_score, mismatch_score, match_score
+                ),
+            });
+        }
+        let symbols = self.symbols();
+        let canonical = |symbol: u8| self.canonical_base(symbol).unwrap_or(symbol);
+        let scores: Vec<Vec<i32>> = symbols
+            .iter()
+            .map(|&a| {
+                symbols
+                    .iter()
+                    .map(|&b| {
+                        if a == b {
+                            match_score
+                        } else if a == canonical(b) || b == canonical(a) {
+                            canonical_score
+                        } else {
+                            mismatch_score
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        SubstitutionMatrix::new_case_sensitive(&symbols, &scores)
+    }
+
+    /// Returns the canonical sequence of a sequence holding modified bases, e.g. a consensus, with the
+    /// position and symbol of each of its modified bases.
+    pub fn canonical(&self, seq: &[u8]) -> (Vec<u8>, Vec<(usize, u8)>) {
+        let mut modified = vec![];
+        let canonical = seq
+            .iter()
+            .enumerate()
+            .map(|(i, &symbol)| match self.canonical_base(symbol) {
+                Some(base) => {
+                    modified.push((i, symbol));
+                    base
+                }
+                None => symbol,
+            })
+            .collect();
+        (canonical, modified)
+    }
+
+    // the canonical base of a modified base, None for other symbols
+    fn canonical_base(&self, symbol: u8) -> Option<u8> {
+        self.modifications
+            .iter()
+            .find(|modification| modification.symbol == symbol)
+            .map(|modification| modification.canonical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::poa_with_backend;
+    use crate::matrix::MatrixBackend;
+    use crate::params::AlignmentParams;
+
+    #[test]
+    fn test_matrix() {
+        let alphabet = ModifiedAlphabet::new(vec![ModifiedBase::FIVE_MC, ModifiedBase::FIVE_HMC]);
+        assert_eq!(alphabet.symbols(), b"ACGTmh".to_vec());
+        let matrix = alphabet.matrix(5, -4, 2).unwrap();
+        assert_eq!((matrix.score(b'm', b'm'), matrix.score(b'm', b'C'), matrix.score(b'C', b'h')), (5, 2, 2));
+        // two modifications of a base, or a modification and another base, mismatch
+        assert_eq!((matrix.score(b'm', b'h'), matrix.score(b'm', b'G')), (-4, -4));
+        // the IUPAC M and H are not 5mC and 5hmC, nor is a lowercase c a C
+        assert_eq!((matrix.score(b'M', b'm'), matrix.score(b'H', b'C'), matrix.score(b'c', b'C')), (-4, -4, -4));
+        assert!(alphabet.matrix(5, -4, 6).is_err());
+        assert!(alphabet.matrix(5, -4, -5).is_err());
+
+        let invalid = ModifiedAlphabet::new(vec![ModifiedBase {
+            symbol: b'x',
+            canonical: b'N',
+        }]);
+        assert!(invalid.matrix(5, -4, 2).is_err());
+        assert!(ModifiedAlphabet::new(vec![ModifiedBase::FIVE_MC; 2]).matrix(5, -4, 2).is_err());
+    }
+
+    #[test]
+    fn test_modified_consensus() {
+        let alphabet = ModifiedAlphabet::new(vec![ModifiedBase::FIVE_MC, ModifiedBase::SIX_MA]);
+        let backend = MatrixBackend::new(alphabet.matrix(5, -4, 2).unwrap());
+        let seqs: Vec<&[u8]> = vec![b"ACmGTAAC", b"ACmGTaAC", b"ACCGTaAC", b"ACmGTaAC"];
+        let quals: Vec<&[u8]> = vec![b"FFFFFFFF"; 4];
+
+        let result = poa_with_backend(&backend, &seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, b"ACmGTaAC".to_vec());
+        // modified and unmodified copies of a base share a column
+        assert!(result.msa.rows.iter().all(|row| row.len() == 8));
+        assert_eq!(alphabet.canonical(&result.consensus), (b"ACCGTAAC".to_vec(), vec![(2, b'm'), (5, b'a')]));
+    }
+}
@@ -0,0 +1,352 @@
+//! Multiple sequence alignments produced by the partial order graph.
+use std::io::{self, BufRead, Write};
+
+/// The gap character used in the rows of a `Msa`.
+pub const GAP: u8 = b'-';
+
+/// How `Msa::pileup` renders the alignment rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PileupStyle {
+    /// Plain text, bases matching the consensus are shown as `.` so that differences stand out.
+    Plain,
+    /// All bases are shown, differences from the consensus are highlighted in red with ANSI escape codes.
+    Ansi,
+}
+
+const ANSI_RED: &str = "\x1b[1;31m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// A multiple sequence alignment of a set of sequences, as generated by SPOA.
+///
+/// All rows (including the consensus) have the same length; gaps are represented by `-`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Msa {
+    /// The name of each aligned sequence, in input order.
+    pub names: Vec<String>,
+    /// The gapped row of each sequence, in input order.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seqs_as_str"))]
+    pub rows: Vec<Vec<u8>>,
+    /// The gapped consensus row.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+}
+
+impl Msa {
+    /// Returns the number of aligned sequences (excluding the consensus).
+    pub fn num_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns the number of alignment columns.
+    pub fn num_columns(&self) -> usize {
+        self.consensus.len()
+    }
+
+    /// Returns the consensus sequence with gaps removed.
+    pub fn ungapped_consensus(&self) -> Vec<u8> {
+        self.consensus.iter().cloned().filter(|&b| b != GAP).collect()
+    }
+
+    /// Writes the alignment in FASTA format, one gapped record per sequence named after its input name.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - the destination of the FASTA records
+    /// * `include_consensus` - if true, the gapped consensus is written as a last record named `Consensus`
+    pub fn write_fasta<W: Write>(&self, mut writer: W, include_consensus: bool) -> io::Result<()> {
+        for (name, row) in self.names.iter().zip(self.rows.iter()) {
+            write_fasta_record(&mut writer, name, row)?;
+        }
+        if include_consensus {
+            write_fasta_record(&mut writer, "Consensus", &self.consensus)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the alignment as one MAF block, see `write_results_maf`: the gapped consensus first, as
+    /// `<prefix>.Consensus`, then one `s` line per sequence named `<prefix>.<name>`, each spanning its whole
+    /// sequence on the forward strand. Sequences without any base are left out, as MAF lines need one, and
+    /// nothing is written for an alignment without any base, as a block needs at least one line.
+    pub fn write_maf_block<W: Write>(&self, mut writer: W, prefix: &str) -> io::Result<()> {
+        let has_base = |row: &Vec<u8>| row.iter().any(|&b| b != GAP);
+        if !has_base(&self.consensus) && !self.rows.iter().any(has_base) {
+            return Ok(());
+        }
+        writeln!(writer, "a")?;
+        let mut write_line = |name: &str, row: &[u8]| {
+            let len = row.iter().filter(|&&b| b != GAP).count();
+            if len == 0 {
+                return Ok(());
+            }
+            writeln!(writer, "s {}.{} 0 {} + {} {}", prefix, name, len, len, String::from_utf8_lossy(row))
+        };
+        write_line("Consensus", &self.consensus)?;
+        for (name, row) in self.names.iter().zip(self.rows.iter()) {
+            write_line(name, row)?;
+        }
+        writeln!(writer)
+    }
+
+    /// Reads an alignment written by `write_fasta` with its consensus: every record is a row except the last
+    /// one, which must be named `Consensus`. Records may span several lines.
+    pub fn read_fasta<R: BufRead>(reader: R) -> io::Result<Msa> {
+        let mut names = vec![];
+        let mut rows: Vec<Vec<u8>> = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(name) = line.strip_prefix('>') {
+                names.push(name.to_string());
+                rows.push(vec![]);
+            } else if let Some(row) = rows.last_mut() {
+                row.extend_from_slice(line.trim_end().as_bytes());
+            } else if !line.trim().is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "FASTA data before the first record"));
+            }
+        }
+        if names.last().map(|name| name.as_str()) != Some("Consensus") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Consensus record"));
+        }
+        names.pop();
+        let consensus = rows.pop().unwrap();
+        if rows.iter().any(|row| row.len() != consensus.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "alignment rows differ in length"));
+        }
+        Ok(Msa { names, rows, consensus })
+    }
+
+    /// Shifts the deletions of each row relative to the consensus as far left as they represent the same
+    /// bases, e.g. within homopolymers and tandem repeats, so that an event found in several clusters is
+    /// placed identically. Insertion columns are shared by all rows and left where SPOA placed them; the
+    /// metrics of a `ConsensusResult` holding the alignment are not updated.
+    pub fn left_align_gaps(&mut self) {
+        for row in self.rows.iter_mut() {
+            left_align_gaps(row, &self.consensus);
+        }
+    }
+}
+
+/// Shifts each run of gaps of `row` facing bases of `other` to the left while the alignment stays the same up
+/// to the placement of the gaps: the bases of `other` before and at the end of the run must be equal, and
+/// match the base of `row` moved to the end of the run. Columns where both rows hold gaps are skipped.
+pub(crate) fn left_align_gaps(row: &mut [u8], other: &[u8]) {
+    let mut col = 0;
+    while col < row.len() {
+        if row[col] != GAP {
+            col += 1;
+            continue;
+        }
+        let mut start = col;
+        while col < row.len() && row[col] == GAP {
+            col += 1;
+        }
+        let mut end = col;
+        while let Some(last) = (start..end).rev().find(|&c| other[c] != GAP) {
+            match (0..start).rev().find(|&p| row[p] != GAP || other[p] != GAP) {
+                Some(p) if row[p] == other[p] && other[p] == other[last] => {
+                    row[last] = row[p];
+                    row[p] = GAP;
+                    start = p;
+                    end = last;
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+// the rows of the sequences flagged `empty` or not, given the rows SPOA generates, which leave out the empty
+// sequences it skips: each of them gets a row of `num_columns` gaps
+pub(crate) fn with_empty_rows(rows: Vec<Vec<u8>>, empty: &[bool], num_columns: usize) -> Vec<Vec<u8>> {
+    let mut rows = rows.into_iter();
+    empty
+        .iter()
+        .map(|&empty| if empty { vec![GAP; num_columns] } else { rows.next().unwrap_or_default() })
+        .collect()
+}
+
+impl Msa {
+    /// Renders the alignment for terminal inspection: a ruler with the (1-based) consensus positions,
+    /// the gapped consensus, and one row per sequence with its differences from the consensus highlighted.
+    pub fn pileup(&self, style: PileupStyle) -> String {
+        let width = self.names.iter().map(|name| name.len()).chain(Some("Consensus".len())).max().unwrap() + 2;
+
+        // ruler with a tick every 10 consensus bases, labelled with the consensus position
+        let mut labels = vec![b' '; self.num_columns()];
+        let mut ticks = vec![b' '; self.num_columns()];
+        let mut position = 0;
+        for (col, &base) in self.consensus.iter().enumerate() {
+            if base == GAP {
+                continue;
+            }
+            position += 1;
+            if position % 10 == 1 {
+                ticks[col] = b'|';
+                for (i, digit) in position.to_string().bytes().enumerate() {
+                    if col + i < labels.len() {
+                        labels[col + i] = digit;
+                    }
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for line in [labels, ticks].iter() {
+            out.push_str(&format!("{:width$}{}\n", "", String::from_utf8_lossy(line).trim_end(), width = width));
+        }
+        out.push_str(&format!("{:width$}{}\n", "Consensus", String::from_utf8_lossy(&self.consensus), width = width));
+
+        for (name, row) in self.names.iter().zip(self.rows.iter()) {
+            out.push_str(&format!("{:width$}", name, width = width));
+            for (&base, &cons) in row.iter().zip(self.consensus.iter()) {
+                match style {
+                    PileupStyle::Plain if base == cons && base != GAP => out.push('.'),
+                    PileupStyle::Ansi if base != cons => {
+                        out.push_str(ANSI_RED);
+                        out.push(base as char);
+                        out.push_str(ANSI_RESET);
+                    }
+                    _ => out.push(base as char),
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl Msa {
+    /// Returns the alignment as a matrix of bytes with one row per sequence, in input order, and one column
+    /// per alignment column; gaps are `GAP`. The consensus is not included, see `consensus_array`.
+    pub fn to_array(&self) -> ndarray::Array2<u8> {
+        let mut array = ndarray::Array2::from_elem((self.num_rows(), self.num_columns()), GAP);
+        for (mut out, row) in array.rows_mut().into_iter().zip(self.rows.iter()) {
+            out.assign(&ndarray::ArrayView1::from(row.as_slice()));
+        }
+        array
+    }
+
+    /// Returns the gapped consensus row as an array, one byte per alignment column.
+    pub fn consensus_array(&self) -> ndarray::Array1<u8> {
+        ndarray::Array1::from(self.consensus.clone())
+    }
+}
+
+fn write_fasta_record<W: Write>(writer: &mut W, name: &str, seq: &[u8]) -> io::Result<()> {
+    writeln!(writer, ">{}", name)?;
+    writer.write_all(seq)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Msa {
+        Msa {
+            names: vec!["read_a".to_string(), "read_b".to_string()],
+            rows: vec![b"AC-GT".to_vec(), b"ACTGT".to_vec()],
+            consensus: b"AC-GT".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_write_fasta() {
+        let mut out = vec![];
+        example().write_fasta(&mut out, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), ">read_a\nAC-GT\n>read_b\nACTGT\n");
+    }
+
+    #[test]
+    fn test_write_maf_block() {
+        let mut msa = example();
+        msa.names.push("empty".to_string());
+        msa.rows.push(b"-----".to_vec());
+        let mut out = vec![];
+        msa.write_maf_block(&mut out, "umi_1").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a\ns umi_1.Consensus 0 4 + 4 AC-GT\ns umi_1.read_a 0 4 + 4 AC-GT\ns umi_1.read_b 0 5 + 5 ACTGT\n\n"
+        );
+        let mut out = vec![];
+        Msa::default().write_maf_block(&mut out, "umi_2").unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_pileup() {
+        let pileup = example().pileup(PileupStyle::Plain);
+        let lines: Vec<&str> = pileup.lines().collect();
+
+        assert_eq!(lines[0], "           1");
+        assert_eq!(lines[1], "           |");
+        assert_eq!(lines[2], "Consensus  AC-GT");
+        assert_eq!(lines[3], "read_a     ..-..");
+        assert_eq!(lines[4], "read_b     ..T..");
+
+        let colored = example().pileup(PileupStyle::Ansi);
+        assert!(colored.contains("read_b     AC\x1b[1;31mT\x1b[0mGT"));
+    }
+
+    #[test]
+    fn test_write_fasta_with_consensus() {
+        let msa = example();
+        let mut out = vec![];
+        msa.write_fasta(&mut out, true).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with(">Consensus\nAC-GT\n"));
+        assert_eq!(msa.ungapped_consensus(), b"ACGT".to_vec());
+        assert_eq!(msa.num_columns(), 5);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_to_array() {
+        let array = example().to_array();
+
+        assert_eq!(array.dim(), (2, 5));
+        assert_eq!(array[[1, 2]], b'T');
+        assert_eq!(array.column(2).iter().filter(|&&base| base == GAP).count(), 1);
+        assert_eq!(example().consensus_array().len(), 5);
+    }
+
+    #[test]
+    fn test_left_align_gaps() {
+        let mut msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"ACC-CT".to_vec(), b"ACGAG-".to_vec(), b"A-TCCT".to_vec()],
+            consensus: b"ACC-CT".to_vec(),
+        };
+        msa.rows[0] = b"AC--CT".to_vec();
+
+        msa.left_align_gaps();
+
+        assert_eq!(msa.rows[0], b"A-C-CT".to_vec());
+        assert_eq!(msa.rows[1], b"ACGAG-".to_vec());
+        assert_eq!(msa.rows[2], b"A-TCCT".to_vec());
+
+        let (mut row, other) = (b"ACAGCAG---".to_vec(), b"ACAGCAGCAG".to_vec());
+        left_align_gaps(&mut row, &other);
+        assert_eq!(row, b"A---CAGCAG".to_vec());
+    }
+
+    #[test]
+    fn test_with_empty_rows() {
+        let rows = vec![b"AC-".to_vec(), b"A-T".to_vec()];
+        let rows = with_empty_rows(rows, &[true, false, false, true], 3);
+        assert_eq!(rows, vec![b"---".to_vec(), b"AC-".to_vec(), b"A-T".to_vec(), b"---".to_vec()]);
+    }
+
+    #[test]
+    fn test_read_fasta() {
+        let msa = example();
+        let mut out = vec![];
+        msa.write_fasta(&mut out, true).unwrap();
+
+        assert_eq!(Msa::read_fasta(&out[..]).unwrap(), msa);
+        assert_eq!(Msa::read_fasta(&b">Consensus\nAC\n-GT\n"[..]).unwrap().consensus, b"AC-GT".to_vec());
+        assert!(Msa::read_fasta(&b">read_a\nAC-GT\n"[..]).is_err());
+        assert!(Msa::read_fasta(&b">read_a\nACGT\n>Consensus\nAC-GT\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,730 @@
+//! A reference partial order alignment in plain Rust, slow but simple, for differential testing of SPOA and
+//! of the matrix engine of the C++ shim.
+#[cfg(feature = "debug-align")]
+use std::collections::BTreeMap;
+#[cfg(feature = "debug-align")]
+use std::io::{self, Write};
+
+use crate::backend::ConsensusBackend;
+#[cfg(feature = "debug-align")]
+use crate::graph::GraphTopology;
+use crate::msa::{Msa, GAP};
+use crate::params::{AlignmentParams, AlignmentType, GapScores};
+
+// a score no alignment reaches, low enough for gap scores to be added to it without overflow
+const NEG_INF: i64 = i64::MIN / 4;
+
+struct Node {
+    base: u8,
+    // the tail and weight of each edge into the node, and the head and weight of each edge out of it
+    in_edges: Vec<(usize, i64)>,
+    out_edges: Vec<(usize, i64)>,
+    // the nodes holding another base at the same position
+    aligned: Vec<usize>,
+}
+
+// an alignment step: the aligned node and query position, the node being None for an insertion in the query
+// and the position None for a deletion
+type Step = (Option<usize>, Option<usize>);
+
+// the DP matrices of an alignment, one row per query position (plus one) and one column per node in
+// topological order (plus one for the virtual source)
+struct Matrices {
+    width: usize,
+    h: Vec<i64>,
+    // the gap matrices of each affine function: insertions in the query, then deletions
+    e: Vec<Vec<i64>>,
+    f: Vec<Vec<i64>>,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    // the node ids in topological order, aligned nodes next to each other
+    rank: Vec<usize>,
+    // the nodes spelling each sequence
+    paths: Vec<Vec<usize>>,
+}
+
+// the (open, extend) score of each affine gap function, with the gap model SPOA selects for the scores
+fn gap_functions(params: &AlignmentParams) -> Vec<(i64, i64)> {
+    match params.gap_model() {
+        GapScores::Linear { g } => vec![(g as i64, g as i64)],
+        GapScores::Affine { open, extend } => vec![(open as i64, extend as i64)],
+        GapScores::Convex {
+            open1,
+            extend1,
+            open2,
+            extend2,
+        } => vec![(open1 as i64, extend1 as i64), (open2 as i64, extend2 as i64)],
+    }
+}
+
+// the score of aligning two bases, N matching any base with the wildcard score if one is set
+fn substitution(params: &AlignmentParams, a: u8, b: u8) -> i64 {
+    match params.wildcard_score {
+        Some(score) if a.eq_ignore_ascii_case(&b'N') || b.eq_ignore_ascii_case(&b'N') => score as i64,
+        _ if a == b => params.match_score as i64,
+        _ => params.mismatch_score as i64,
+    }
+}
+
+impl Graph {
+    fn add_node(&mut self, base: u8) -> usize {
+        self.nodes.push(Node {
+            base,
+            in_edges: vec![],
+            out_edges: vec![],
+            aligned: vec![],
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_edge(&mut self, tail: usize, head: usize, weight: i64) {
+        match self.nodes[tail].out_edges.iter().position(|&(h, _)| h == head) {
+            Some(i) => {
+                self.nodes[tail].out_edges[i].1 += weight;
+                let j = self.nodes[head].in_edges.iter().position(|&(t, _)| t == tail).unwrap();
+                self.nodes[head].in_edges[j].1 += weight;
+            }
+            None => {
+                self.nodes[tail].out_edges.push((head, weight));
+                self.nodes[head].in_edges.push((tail, weight));
+            }
+        }
+    }
+
+    // the columns of the predecessors of the node at a column, the virtual source for nodes without
+    fn predecessors(&self, columns: &[usize], col: usize) -> Vec<usize> {
+        let node = &self.nodes[self.rank[col - 1]];
+        if node.in_edges.is_empty() {
+            return vec![0];
+        }
+        node.in_edges.iter().map(|&(tail, _)| columns[tail]).collect()
+    }
+
+    // aligns a query against the graph, returning the steps of the best alignment in graph order
+    fn align(&self, query: &[u8], params: &AlignmentParams) -> Vec<Step> {
+        if self.nodes.is_empty() || query.is_empty() {
+            return vec![];
+        }
+        let (m, preds, gaps) = self.fill(query, params);
+        match self.end(&m, query.len(), params) {
+            Some(end) => self.traceback(&m, &preds, &gaps, end, query, params),
+            None => vec![],
+        }
+    }
+
+    // the DP matrices of a query against the graph, the predecessor columns of each column and the gap
+    // functions
+    fn fill(&self, query: &[u8], params: &AlignmentParams) -> (Matrices, Vec<Vec<usize>>, Vec<(i64, i64)>) {
+        let gaps = gap_functions(params);
+        let local = params.alignment_type == AlignmentType::Local;
+        let free_start = params.alignment_type != AlignmentType::Global;
+        let mut columns = vec![0; self.nodes.len()];
+        for (r, &id) in self.rank.iter().enumerate() {
+            columns[id] = r + 1;
+        }
+        let preds: Vec<Vec<usize>> = (0..=self.rank.len())
+            .map(|col| {
+                if col == 0 {
+                    vec![]
+                } else {
+                    self.predecessors(&columns, col)
+                }
+            })
+            .collect();
+        let score = |i: usize, col: usize| substitution(params, query[i - 1], self.nodes[self.rank[col - 1]].base);
+
+        let width = self.rank.len() + 1;
+        let size = (query.len() + 1) * width;
+        let mut m = Matrices {
+            width,
+            h: vec![NEG_INF; size],
+            e: vec![vec![NEG_INF; size]; gaps.len()],
+            f: vec![vec![NEG_INF; size]; gaps.len()],
+        };
+        m.h[0] = 0;
+        for i in 0..=query.len() {
+            for (col, col_preds) in preds.iter().enumerate() {
+                let at = i * width + col;
+                if i == 0 && col == 0 {
+                    continue;
+                }
+                let mut best = NEG_INF;
+                for (k, &(open, extend)) in gaps.iter().enumerate() {
+                    if i > 0 {
+                        m.e[k][at] = i64::max(m.h[at - width] + open, m.e[k][at - width] + extend);
+                    }
+                    if col > 0 {
+                        for &p in col_preds {
+                            let from = i * width + p;
+                            m.f[k][at] = m.f[k][at].max(i64::max(m.h[from] + open, m.f[k][from] + extend));
+                        }
+                    }
+                    best = best.max(m.e[k][at]).max(m.f[k][at]);
+                }
+                if i > 0 && col > 0 {
+                    for &p in col_preds {
+                        best = best.max(m.h[(i - 1) * width + p] + score(i, col));
+                    }
+                }
+                m.h[at] = if (free_start && (i == 0 || col == 0)) || (local && best < 0) {
+                    0
+                } else {
+                    best
+                };
+            }
+        }
+        (m, preds, gaps)
+    }
+
+    // the cell the best alignment ends in: the best sink in the last row, anywhere for local alignment, and
+    // also in the last column of the sinks for semi-global alignment
+    fn end(&self, m: &Matrices, query_len: usize, params: &AlignmentParams) -> Option<(usize, usize)> {
+        let width = m.width;
+        let sink = |col: usize| col > 0 && self.nodes[self.rank[col - 1]].out_edges.is_empty();
+        let mut end = None;
+        for i in 0..=query_len {
+            for col in 1..width {
+                let candidate = match params.alignment_type {
+                    AlignmentType::Global => i == query_len && sink(col),
+                    AlignmentType::SemiGlobal => i == query_len || sink(col),
+                    AlignmentType::Local => i > 0,
+                };
+                let better = |(bi, bc): (usize, usize)| m.h[i * width + col] > m.h[bi * width + bc];
+                if candidate && end.map_or(true, better) {
+                    end = Some((i, col));
+                }
+            }
+        }
+        end
+    }
+
+    fn traceback(
+        &self,
+        m: &Matrices,
+        preds: &[Vec<usize>],
+        gaps: &[(i64, i64)],
+        (mut i, mut col): (usize, usize),
+        query: &[u8],
+        params: &AlignmentParams,
+    ) -> Vec<Step> {
+        let width = m.width;
+        let local = params.alignment_type == AlignmentType::Local;
+        let free_start = params.alignment_type != AlignmentType::Global;
+        let node = |col: usize| self.rank[col - 1];
+        // the matrix of the current cell: None for H, else the gap function and whether it is a deletion
+        let mut state: Option<(usize, bool)> = None;
+        let mut steps = vec![];
+        loop {
+            let at = i * width + col;
+            match state {
+                None => {
+                    if (i == 0 && col == 0) || (free_start && (i == 0 || col == 0)) || (local && m.h[at] == 0) {
+                        break;
+                    }
+                    if i > 0 && col > 0 {
+                        let s = substitution(params, query[i - 1], self.nodes[node(col)].base);
+                        if let Some(&p) = preds[col].iter().find(|&&p| m.h[(i - 1) * width + p] + s == m.h[at]) {
+                            steps.push((Some(node(col)), Some(i - 1)));
+                            i -= 1;
+                            col = p;
+                            continue;
+                        }
+                    }
+                    state = match (0..gaps.len()).find(|&k| m.e[k][at] == m.h[at]) {
+                        Some(k) => Some((k, false)),
+                        None => Some(((0..gaps.len()).find(|&k| m.f[k][at] == m.h[at]).unwrap(), true)),
+                    };
+                }
+                Some((k, false)) => {
+                    steps.push((None, Some(i - 1)));
+                    if m.h[at - width] + gaps[k].0 == m.e[k][at] {
+                        state = None;
+                    }
+                    i -= 1;
+                }
+                Some((k, true)) => {
+                    steps.push((Some(node(col)), None));
+                    let (open, extend) = gaps[k];
+                    match preds[col].iter().find(|&&p| m.h[i * width + p] + open == m.f[k][at]) {
+                        Some(&p) => {
+                            state = None;
+                            col = p;
+                        }
+                        None => {
+                            col = *preds[col]
+                                .iter()
+                                .find(|&&p| m.f[k][i * width + p] + extend == m.f[k][at])
+                                .unwrap()
+                        }
+                    }
+                }
+            }
+        }
+        steps.reverse();
+        steps
+    }
+
+    // adds a sequence along an alignment, weighting its edges by the qualities of the bases they join
+    fn add_alignment(&mut self, steps: &[Step], seq: &[u8], qual: &[u8]) {
+        let weights: Vec<i64> = qual.iter().map(|&q| q.saturating_sub(33) as i64).collect();
+        let mut aligned: Vec<Option<usize>> = vec![None; seq.len()];
+        for &(node, pos) in steps {
+            if let (Some(node), Some(pos)) = (node, pos) {
+                aligned[pos] = Some(node);
+            }
+        }
+        let mut path = Vec::with_capacity(seq.len());
+        for (pos, &base) in seq.iter().enumerate() {
+            let id = match aligned[pos] {
+                None => self.add_node(base),
+                Some(node) if self.nodes[node].base == base => node,
+                Some(node) => match self.nodes[node].aligned.iter().find(|&&a| self.nodes[a].base == base) {
+                    Some(&a) => a,
+                    None => {
+                        let id = self.add_node(base);
+                        let group: Vec<usize> =
+                            Some(node).into_iter().chain(self.nodes[node].aligned.clone()).collect();
+                        for a in group {
+                            self.nodes[id].aligned.push(a);
+                            self.nodes[a].aligned.push(id);
+                        }
+                        id
+                    }
+                },
+            };
+            if let Some(&prev) = path.last() {
+                self.add_edge(prev, id, weights[pos - 1] + weights[pos]);
+            }
+            path.push(id);
+        }
+        self.paths.push(path);
+        self.topological_sort();
+    }
+
+    // sorts the nodes as SPOA does: depth first from each node in id order, aligned nodes placed together
+    fn topological_sort(&mut self) {
+        self.rank.clear();
+        let mut marks = vec![0u8; self.nodes.len()];
+        let mut ignored = vec![false; self.nodes.len()];
+        for id in 0..self.nodes.len() {
+            if marks[id] != 0 {
+                continue;
+            }
+            let mut stack = vec![id];
+            while let Some(&curr) = stack.last() {
+                let mut valid = true;
+                if marks[curr] != 2 {
+                    for &(tail, _) in &self.nodes[curr].in_edges {
+                        if marks[tail] != 2 {
+                            stack.push(tail);
+                            valid = false;
+                        }
+                    }
+                    if !ignored[curr] {
+                        for &a in &self.nodes[curr].aligned {
+                            if marks[a] != 2 {
+                                stack.push(a);
+                                ignored[a] = true;
+                                valid = false;
+                            }
+                        }
+                    }
+                    if valid {
+                        marks[curr] = 2;
+                        if !ignored[curr] {
+                            self.rank.push(curr);
+                            self.rank.extend_from_slice(&self.nodes[curr].aligned);
+                        }
+                    } else {
+                        marks[curr] = 1;
+                    }
+                }
+                if valid {
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    // the heaviest edge into a node, ties going to the heavier predecessor, skipping dropped predecessors
+    // (of score -1) when completing a branch
+    fn relax(&self, id: usize, scores: &mut [i64], preds: &mut [Option<usize>], skip_dropped: bool) {
+        for &(tail, weight) in &self.nodes[id].in_edges {
+            if skip_dropped && scores[tail] == -1 {
+                continue;
+            }
+            if scores[id] < weight || (scores[id] == weight && preds[id].map_or(true, |p| scores[p] <= scores[tail])) {
+                scores[id] = weight;
+                preds[id] = Some(tail);
+            }
+        }
+        if let Some(p) = preds[id] {
+            scores[id] += scores[p];
+        }
+    }
+
+    // the heaviest bundle path, completed from the heaviest node to a sink as SPOA does
+    fn consensus(&self) -> Vec<usize> {
+        if self.rank.is_empty() {
+            return vec![];
+        }
+        let mut scores = vec![-1i64; self.nodes.len()];
+        let mut preds: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut max = self.rank[0];
+        for &id in &self.rank {
+            self.relax(id, &mut scores, &mut preds, false);
+            if scores[max] < scores[id] {
+                max = id;
+            }
+        }
+
+        let mut ranks = vec![0; self.nodes.len()];
+        for (r, &id) in self.rank.iter().enumerate() {
+            ranks[id] = r;
+        }
+        while !self.nodes[max].out_edges.is_empty() {
+            // branch completion: the nodes joining the branches out of the heaviest node from elsewhere are
+            // dropped, and the heaviest path is extended beyond it
+            for &(head, _) in &self.nodes[max].out_edges {
+                for &(tail, _) in &self.nodes[head].in_edges {
+                    if tail != max {
+                        scores[tail] = -1;
+                    }
+                }
+            }
+            let mut best: Option<usize> = None;
+            for &id in &self.rank[ranks[max] + 1..] {
+                scores[id] = -1;
+                preds[id] = None;
+                self.relax(id, &mut scores, &mut preds, true);
+                if preds[id].is_some() && best.map_or(true, |b| scores[b] < scores[id]) {
+                    best = Some(id);
+                }
+            }
+            max = match best {
+                Some(best) => best,
+                None => break,
+            };
+        }
+
+        let mut path = vec![max];
+        while let Some(p) = preds[*path.last().unwrap()] {
+            path.push(p);
+        }
+        path.reverse();
+        path
+    }
+
+    fn msa(&self) -> Msa {
+        let mut columns = vec![usize::MAX; self.nodes.len()];
+        let mut num_columns = 0;
+        for &id in &self.rank {
+            columns[id] = match self.nodes[id].aligned.iter().find(|&&a| columns[a] != usize::MAX) {
+                Some(&a) => columns[a],
+                None => {
+                    num_columns += 1;
+                    num_columns - 1
+                }
+            };
+        }
+        let row = |path: &[usize]| {
+            let mut row = vec![GAP; num_columns];
+            for &id in path {
+                row[columns[id]] = self.nodes[id].base;
+            }
+            row
+        };
+        Msa {
+            names: (0..self.paths.len()).map(|i| format!("seq_{}", i)).collect(),
+            rows: self.paths.iter().map(|path| row(path)).collect(),
+            consensus: row(&self.consensus()),
+        }
+    }
+}
+
+// the graph of the topology of a `PoaGraph`, with the same node ids
+#[cfg(feature = "debug-align")]
+fn from_topology(topology: &GraphTopology) -> Graph {
+    let mut graph = Graph::default();
+    for &base in &topology.labels {
+        graph.add_node(base);
+    }
+    for edge in &topology.edges {
+        graph.add_edge(edge.tail as usize, edge.head as usize, edge.weight);
+    }
+    // the nodes of an alignment column are aligned to each other
+    let mut columns: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (id, &col) in topology.columns.iter().enumerate() {
+        columns.entry(col).or_default().push(id);
+    }
+    for ids in columns.values() {
+        for &id in ids {
+            graph.nodes[id].aligned = ids.iter().cloned().filter(|&other| other != id).collect();
+        }
+    }
+    graph.topological_sort();
+    graph
+}
+
+// writes the DP matrices, one tab-separated row per query position and one column per node in topological
+// order, and the traceback of the alignment of a query against the graph of a topology
+#[cfg(feature = "debug-align")]
+pub(crate) fn write_alignment_dump<W: Write>(
+    mut writer: W,
+    topology: &GraphTopology,
+    query: &[u8],
+    params: &AlignmentParams,
+) -> io::Result<()> {
+    let graph = from_topology(topology);
+    writeln!(
+        writer,
+        "# {:?} alignment of {} ({} bases) against a graph of {} nodes",
+        params.alignment_type,
+        String::from_utf8_lossy(query),
+        query.len(),
+        graph.nodes.len()
+    )?;
+    if graph.nodes.is_empty() || query.is_empty() {
+        return Ok(());
+    }
+    let (m, preds, gaps) = graph.fill(query, params);
+    let label = |col: usize| match col {
+        0 => "-".to_string(),
+        col => format!("{}:{}", graph.rank[col - 1], graph.nodes[graph.rank[col - 1]].base as char),
+    };
+
+    writeln!(writer, "## columns")?;
+    for col in 1..m.width {
+        let preds: Vec<String> = preds[col].iter().map(|&p| label(p)).collect();
+        writeln!(writer, "{}\t{}\tpredecessors {}", col, label(col), preds.join(","))?;
+    }
+    let mut matrices = vec![("H".to_string(), &m.h)];
+    for (k, &(open, extend)) in gaps.iter().enumerate() {
+        matrices.push((format!("E{} (insertions, open {} extend {})", k + 1, open, extend), &m.e[k]));
+        matrices.push((format!("F{} (deletions, open {} extend {})", k + 1, open, extend), &m.f[k]));
+    }
+    for (name, matrix) in matrices {
+        writeln!(writer, "## {}", name)?;
+        let header: Vec<String> = (0..m.width).map(label).collect();
+        writeln!(writer, "\t{}", header.join("\t"))?;
+        for (i, row) in matrix.chunks(m.width).enumerate() {
+            let base = if i == 0 { '-' } else { query[i - 1] as char };
+            let scores: Vec<String> = row
+                .iter()
+                .map(|&score| if score <= NEG_INF / 2 { "-inf".to_string() } else { score.to_string() })
+                .collect();
+            writeln!(writer, "{}\t{}", base, scores.join("\t"))?;
+        }
+    }
+
+    writeln!(writer, "## traceback")?;
+    let (i, col) = match graph.end(&m, query.len(), params) {
+        Some(end) => end,
+        None => return Ok(()),
+    };
+    writeln!(writer, "end\trow {}\tcolumn {}\tscore {}", i, col, m.h[i * m.width + col])?;
+    for step in graph.traceback(&m, &preds, &gaps, (i, col), query, params) {
+        match step {
+            (Some(id), Some(pos)) => writeln!(
+                writer,
+                "M\tnode {}:{}\tquery {}:{}",
+                id, graph.nodes[id].base as char, pos, query[pos] as char
+            )?,
+            (None, Some(pos)) => writeln!(writer, "I\t\tquery {}:{}", pos, query[pos] as char)?,
+            (Some(id), None) => writeln!(writer, "D\tnode {}:{}\t", id, graph.nodes[id].base as char)?,
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// A reference partial order alignment engine written in plain Rust, following SPOA's algorithms: each
+/// sequence is aligned against the graph by a full dynamic programming over all its nodes, with SPOA's
+/// linear, affine or convex gap model, then added to it, and the consensus is the heaviest bundle path.
+///
+/// It is much slower than SPOA and meant for differential testing, e.g. when upgrading the bundled SPOA
+/// version, and honours `AlignmentParams::wildcard_score` like the matrix engine of the C++ shim. Ties between
+/// equally scoring alignments may be broken differently than by SPOA, so results agree on all but ambiguous
+/// inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NaiveBackend;
+
+impl ConsensusBackend for NaiveBackend {
+    fn msa(&self, seqs: &[&[u8]], quals: &[&[u8]], params: &AlignmentParams) -> Msa {
+        if seqs.len() != quals.len() {
+            panic!("Input sequences and qualities must be of same length");
+        }
+        let mut graph = Graph::default();
+        for (seq, qual) in seqs.iter().zip(quals.iter()) {
+            if seq.len() != qual.len() {
+                panic!("Input sequence and qualities must be of same length");
+            }
+            let steps = graph.align(seq, params);
+            graph.add_alignment(&steps, seq, qual);
+        }
+        graph.msa()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{poa_with_backend, SpoaBackend};
+    use crate::sample::SplitMix64;
+
+    fn quals(seqs: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        seqs.iter().map(|seq| vec![b'F'; seq.len()]).collect()
+    }
+
+    // reads of a random template with substitutions, insertions and deletions at the given rate each
+    fn random_cluster(rng: &mut SplitMix64, len: usize, depth: usize, error_rate: f64) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let template: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.below(4)]).collect();
+        let threshold = (error_rate * 1000.0) as usize;
+        let reads = (0..depth)
+            .map(|_| {
+                let mut read = vec![];
+                for &base in &template {
+                    match rng.below(1000) {
+                        x if x < threshold => read.push(b"ACGT"[rng.below(4)]),
+                        x if x < 2 * threshold => {}
+                        x if x < 3 * threshold => read.extend_from_slice(&[base, b"ACGT"[rng.below(4)]]),
+                        _ => read.push(base),
+                    }
+                }
+                read
+            })
+            .collect();
+        (template, reads)
+    }
+
+    #[test]
+    fn test_naive_backend() {
+        let seqs: Vec<Vec<u8>> = [
+            "ATTGCCCGTT",
+            "AATGCCGTT",
+            "AATGCCCGAT",
+            "AACGCCCGTC",
+            "AGTGCTCGTT",
+            "AATGCTCGTT",
+        ]
+        .iter()
+        .map(|s| s.as_bytes().to_vec())
+        .collect();
+        let quals = quals(&seqs);
+        let seqs: Vec<&[u8]> = seqs.iter().map(|s| s.as_slice()).collect();
+        let quals: Vec<&[u8]> = quals.iter().map(|q| q.as_slice()).collect();
+
+        let result = poa_with_backend(&NaiveBackend, &seqs, &quals, &AlignmentParams::default());
+
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(result.msa.rows.len(), 6);
+        assert!(result.msa.rows.iter().all(|row| row.len() == result.msa.num_columns()));
+        let ungapped: Vec<u8> = result.msa.rows[1].iter().cloned().filter(|&b| b != GAP).collect();
+        assert_eq!(ungapped, b"AATGCCGTT".to_vec());
+    }
+
+    #[test]
+    fn test_naive_alignment_modes() {
+        let mut graph = Graph::default();
+        let seq = b"GGACGTACGT";
+        graph.add_alignment(&[], seq, &vec![b'F'; seq.len()]);
+        let params = AlignmentParams::default();
+
+        let global = graph.align(b"ACGTACGT", &params);
+        assert_eq!(global.iter().filter(|step| step.1.is_none()).count(), 2);
+        let local = graph.align(
+            b"TTACGTAC",
+            &AlignmentParams {
+                alignment_type: AlignmentType::Local,
+                ..params
+            },
+        );
+        assert_eq!(local.first(), Some(&(Some(2), Some(2))));
+        assert_eq!(local.len(), 6);
+    }
+
+    #[cfg(feature = "debug-align")]
+    #[test]
+    fn test_write_alignment_dump() {
+        use crate::graph::GraphEdge;
+
+        let edge = |tail, head| GraphEdge { tail, head, weight: 1 };
+        let topology = GraphTopology {
+            labels: b"ACGT".to_vec(),
+            columns: vec![0, 1, 2, 3],
+            edges: vec![edge(0, 1), edge(1, 2), edge(2, 3)],
+            paths: vec![vec![0, 1, 2, 3]],
+        };
+        let mut dump = vec![];
+        write_alignment_dump(&mut dump, &topology, b"AGT", &AlignmentParams::default()).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "# Global alignment of AGT (3 bases) against a graph of 4 nodes");
+        assert!(lines.contains(&"2\t1:C\tpredecessors 0:A"));
+        assert!(lines.contains(&"\t-\t0:A\t1:C\t2:G\t3:T"));
+        // the first row of a global alignment is a deletion of the nodes
+        assert!(lines.contains(&"-\t0\t-3\t-4\t-5\t-6"));
+        let traceback = lines.iter().position(|&line| line == "## traceback").unwrap();
+        let expected = [
+            "end\trow 3\tcolumn 4\tscore 12",
+            "M\tnode 0:A\tquery 0:A",
+            "D\tnode 1:C\t",
+            "M\tnode 2:G\tquery 1:G",
+            "M\tnode 3:T\tquery 2:T",
+        ];
+        assert_eq!(&lines[traceback + 1..], &expected[..]);
+    }
+
+    #[test]
+    fn test_wildcard_score() {
+        let params = AlignmentParams {
+            wildcard_score: Some(0),
+            ..AlignmentParams::default()
+        };
+        assert_eq!(substitution(&params, b'n', b'G'), 0);
+        assert_eq!(substitution(&params, b'T', b'N'), 0);
+        assert_eq!(substitution(&params, b'T', b'T'), 5);
+        assert_eq!(substitution(&AlignmentParams::default(), b'N', b'G'), -4);
+
+        // a masked base aligns to the base it hides rather than leaving a gap next to it
+        let mut graph = Graph::default();
+        graph.add_alignment(&[], b"AAAAGCTTTT", &[b'F'; 10]);
+        let steps = graph.align(b"AAAANTTTT", &params);
+        assert!(steps.contains(&(Some(4), Some(4))) || steps.contains(&(Some(5), Some(4))));
+    }
+
+    // differential test against SPOA: both engines recover the template of random low-error clusters
+    #[test]
+    fn test_naive_matches_spoa() {
+        let mut rng = SplitMix64::new(7);
+        for params in [
+            AlignmentParams::default(),
+            AlignmentParams {
+                gap_open: -8,
+                gap_extend: -4,
+                gap2_open: -24,
+                gap2_extend: -1,
+                ..AlignmentParams::default()
+            },
+        ]
+        .iter()
+        {
+            for _ in 0..10 {
+                let (template, reads) = random_cluster(&mut rng, 60, 12, 0.01);
+                let quals = quals(&reads);
+                let seqs: Vec<&[u8]> = reads.iter().map(|s| s.as_slice()).collect();
+                let quals: Vec<&[u8]> = quals.iter().map(|q| q.as_slice()).collect();
+
+                let naive = poa_with_backend(&NaiveBackend, &seqs, &quals, params);
+                let spoa = poa_with_backend(&SpoaBackend, &seqs, &quals, params);
+
+                assert_eq!(naive.consensus, template);
+                assert_eq!(spoa.consensus, naive.consensus);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! PAF output of the alignments of the reads of one or more clusters to their consensus, for long-read QC tools.
+use std::io::{self, Write};
+
+use crate::batch::{Cluster, ClusterResult};
+use crate::params::AlignmentParams;
+use crate::sam::{realign, Placement};
+
+// one PAF line, with the edit distance and the CIGAR (without soft clips) as NM and cg tags
+fn write_record<W: Write>(
+    writer: &mut W,
+    name: &str,
+    read_len: usize,
+    target: &str,
+    target_len: usize,
+    placement: &Placement,
+) -> io::Result<()> {
+    let mut cigar = placement.cigar.as_str();
+    if placement.clip_start > 0 {
+        cigar = &cigar[format!("{}S", placement.clip_start).len()..];
+    }
+    if placement.clip_end > 0 {
+        cigar = &cigar[..cigar.len() - format!("{}S", placement.clip_end).len()];
+    }
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t+\t{}\t{}\t{}\t{}\t{}\t{}\t255\tNM:i:{}\tcg:Z:{}",
+        name,
+        read_len,
+        placement.clip_start,
+        read_len - placement.clip_end,
+        target,
+        target_len,
+        placement.pos - 1,
+        placement.pos - 1 + placement.ref_len,
+        placement.matches,
+        placement.alignment_len,
+        placement.edit_distance,
+        cigar
+    )
+}
+
+/// Writes the alignment of every read to the consensus of its cluster in PAF format, one line per read with
+/// a base aligned to the consensus, in input order. Reads are named `<cluster id>_<index>` as in `write_sam`
+/// and realigned globally to the consensus; the target of each line is the cluster identifier.
+/// # Arguments
+///
+/// * `writer` - the destination of the PAF
+/// * `clusters` - the clusters
+/// * `results` - the result of each cluster, in the order of `clusters`, e.g. as returned by `poa_batch`
+/// * `params` - the scores of the realignment
+pub fn write_paf<W: Write>(
+    mut writer: W,
+    clusters: &[Cluster],
+    results: &[ClusterResult],
+    params: &AlignmentParams,
+) -> io::Result<()> {
+    if clusters.len() != results.len() {
+        panic!("Input clusters and results must be of same length");
+    }
+    for (cluster, result) in clusters.iter().zip(results.iter()) {
+        let consensus = &result.result.consensus;
+        for (i, seq) in cluster.seqs.iter().enumerate() {
+            // the sequences are null terminated
+            let seq = &seq[..seq.len().saturating_sub(1)];
+            if let Some(placement) = realign(consensus, seq, params) {
+                let name = format!("{}_{}", cluster.id, i);
+                write_record(&mut writer, &name, seq.len(), &cluster.id, consensus.len(), &placement)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_record() {
+        let placement = Placement {
+            pos: 3,
+            ref_len: 5,
+            clip_start: 2,
+            clip_end: 1,
+            cigar: "2S2M1D2M1S".to_string(),
+            edit_distance: 2,
+            matches: 3,
+            alignment_len: 5,
+        };
+        let mut out = vec![];
+
+        write_record(&mut out, "umi_1_0", 7, "umi_1", 10, &placement).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "umi_1_0\t7\t2\t6\t+\tumi_1\t10\t2\t7\t3\t5\t255\tNM:i:2\tcg:Z:2M1D2M\n"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Consensus of paired-end reads, merging the mates of overlapping pairs.
+use crate::dna::reverse_complement;
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// The highest Phred quality given to bases supported by both mates.
+pub const MAX_MERGED_QUAL: u8 = 93;
+
+/// A read pair, both mates in sequencing orientation (R2 is the reverse strand of the fragment).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReadPair {
+    /// The sequence of the first mate (not null-terminated).
+    pub r1: Vec<u8>,
+    /// The qualities of the first mate (not null-terminated).
+    pub q1: Vec<u8>,
+    /// The sequence of the second mate (not null-terminated).
+    pub r2: Vec<u8>,
+    /// The qualities of the second mate (not null-terminated).
+    pub q2: Vec<u8>,
+}
+
+/// Options controlling the merging of overlapping mates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergeOptions {
+    /// The minimum number of overlapping bases for mates to be merged.
+    pub min_overlap: usize,
+    /// The maximum fraction of mismatching bases in the overlap.
+    pub max_mismatch_rate: f64,
+}
+
+impl Default for MergeOptions {
+    fn default() -> MergeOptions {
+        MergeOptions {
+            min_overlap: 10,
+            max_mismatch_rate: 0.1,
+        }
+    }
+}
+
+/// Combines two observations of the same base, as Phred+33 qualities: agreeing bases sum their qualities (up
+/// to `MAX_MERGED_QUAL`), disagreeing bases keep the better supported base with the difference of qualities.
+pub(crate) fn combine_bases(base1: u8, qual1: u8, base2: u8, qual2: u8) -> (u8, u8) {
+    let (q1, q2) = (qual1.saturating_sub(33), qual2.saturating_sub(33));
+    if base1 == base2 {
+        (base1, (q1 as u16 + q2 as u16).min(MAX_MERGED_QUAL as u16) as u8 + 33)
+    } else if q1 >= q2 {
+        (base1, (q1 - q2).max(2) + 33)
+    } else {
+        (base2, (q2 - q1).max(2) + 33)
+    }
+}
+
+/// Merges the mates of a pair overlapping by at least `options.min_overlap` bases.
+///
+/// The longest overlap of the end of R1 with the start of the reverse complemented R2 is used, overlapping
+/// bases being combined by quality. Pairs whose insert is shorter than a read (the mates extending past each
+/// other's start) are not merged.
+///
+/// # Returns
+/// * returns the merged fragment and its qualities, or `None` if the mates do not overlap
+pub fn merge_pair(pair: &ReadPair, options: &MergeOptions) -> Option<(Vec<u8>, Vec<u8>)> {
+    let rc2 = reverse_complement(&pair.r2);
+    let rq2: Vec<u8> = pair.q2.iter().rev().cloned().collect();
+    let (len1, len2) = (pair.r1.len(), rc2.len());
+
+    let overlap = (options.min_overlap.max(1)..=len1.min(len2)).rev().find(|&overlap| {
+        let mismatches = pair.r1[len1 - overlap..]
+            .iter()
+            .zip(rc2[..overlap].iter())
+            .filter(|(a, b)| a != b)
+            .count();
+        mismatches as f64 <= options.max_mismatch_rate * overlap as f64
+    })?;
+
+    let mut seq = pair.r1[..len1 - overlap].to_vec();
+    let mut qual = pair.q1[..len1 - overlap].to_vec();
+    for i in 0..overlap {
+        let j = len1 - overlap + i;
+        let (base, q) = combine_bases(pair.r1[j], pair.q1[j], rc2[i], rq2[i]);
+        seq.push(base);
+        qual.push(q);
+    }
+    seq.extend_from_slice(&rc2[overlap..]);
+    qual.extend_from_slice(&rq2[overlap..]);
+    Some((seq, qual))
+}
+
+/// The consensus of a family of read pairs from the same fragment.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum FragmentConsensus {
+    /// Most pairs overlapped: the consensus of the merged fragments.
+    Merged(ConsensusResult),
+    /// Most pairs did not overlap: the consensus of each mate, R2 being reverse complemented into the
+    /// orientation of R1 so that both consensuses read along the fragment.
+    Linked {
+        /// The consensus of the first mates.
+        r1: ConsensusResult,
+        /// The consensus of the reverse complemented second mates.
+        r2: ConsensusResult,
+    },
+}
+
+/// The consensus of a family of read pairs, with how many of its pairs were merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedConsensus {
+    /// The consensus of the fragment.
+    pub consensus: FragmentConsensus,
+    /// The number of pairs whose mates were merged.
+    pub merged_pairs: usize,
+    /// The number of pairs whose mates did not overlap.
+    pub unmerged_pairs: usize,
+}
+
+/// Generates one consensus per fragment from the read pairs of a family (e.g. sharing a UMI).
+///
+/// Overlapping mates are merged first so that the overlap is counted once per pair. If at least half the
+/// pairs merge, the consensus of the merged fragments is returned and the pairs that did not merge are left
+/// out. Otherwise, the consensus of each mate is generated separately and returned linked.
+/// # Arguments
+///
+/// * `pairs` - the read pairs of the fragment
+/// * `params` - the alignment mode and scores
+/// * `options` - the overlap requirements for merging mates
+pub fn poa_paired(pairs: &[ReadPair], params: &AlignmentParams, options: &MergeOptions) -> PairedConsensus {
+    let merged: Vec<(Vec<u8>, Vec<u8>)> = pairs.iter().filter_map(|pair| merge_pair(pair, options)).collect();
+    let (merged_pairs, unmerged_pairs) = (merged.len(), pairs.len() - merged.len());
+
+    let consensus = if !pairs.is_empty() && 2 * merged_pairs >= pairs.len() {
+        FragmentConsensus::Merged(poa_reads(merged.iter().map(|(seq, qual)| (&seq[..], &qual[..])), params))
+    } else {
+        let rc2: Vec<(Vec<u8>, Vec<u8>)> = pairs
+            .iter()
+            .map(|pair| (reverse_complement(&pair.r2), pair.q2.iter().rev().cloned().collect()))
+            .collect();
+        FragmentConsensus::Linked {
+            r1: poa_reads(pairs.iter().map(|pair| (&pair.r1[..], &pair.q1[..])), params),
+            r2: poa_reads(rc2.iter().map(|(seq, qual)| (&seq[..], &qual[..])), params),
+        }
+    };
+
+    PairedConsensus {
+        consensus,
+        merged_pairs,
+        unmerged_pairs,
+    }
+}
+
+fn poa_reads<'a, I: Iterator<Item = (&'a [u8], &'a [u8])>>(reads: I, params: &AlignmentParams) -> ConsensusResult {
+    let (seqs, quals): (Vec<Vec<u8>>, Vec<Vec<u8>>) =
+        reads.map(|(seq, qual)| (null_terminated(seq), null_terminated(qual))).unzip();
+    crate::poa(&seqs, &quals, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_pair() {
+        // fragment ACGTACGGTTCAGCATTAGC, 14bp mates overlapping by 8
+        let pair = ReadPair {
+            r1: b"ACGTACGGTTCAGC".to_vec(),
+            q1: b"IIIIIIIIIII#II".to_vec(),
+            r2: reverse_complement(b"GGTTCAGTATTAGC"),
+            q2: b"IIIIIIIIIIIIII".to_vec(),
+        };
+        let options = MergeOptions {
+            min_overlap: 5,
+            max_mismatch_rate: 0.2,
+        };
+
+        let (seq, qual) = merge_pair(&pair, &options).unwrap();
+        assert_eq!(seq, b"ACGTACGGTTCAGCATTAGC".to_vec());
+        assert_eq!(qual.len(), seq.len());
+        // agreeing overlap bases add up, the mismatch keeps one of the bases with a minimal quality
+        assert_eq!(qual[6], 80 + 33);
+        assert_eq!(qual[11], 42 + 33);
+        assert_eq!(qual[13], b'#');
+        assert_eq!(qual[14], b'I');
+
+        let strict = MergeOptions {
+            min_overlap: 10,
+            max_mismatch_rate: 0.0,
+        };
+        assert_eq!(merge_pair(&pair, &strict), None);
+    }
+
+    #[test]
+    fn test_combine_bases() {
+        assert_eq!(combine_bases(b'A', b'+', b'A', b'+'), (b'A', b'5'));
+        assert_eq!(combine_bases(b'A', b'5', b'C', b'+'), (b'A', b'+'));
+        assert_eq!(combine_bases(b'A', b'+', b'C', b'+'), (b'A', b'#'));
+    }
+}
@@ -0,0 +1,42 @@
+//! Pairwise global alignment, computed as the two-row alignment of a SPOA graph.
+use crate::msa::left_align_gaps;
+use crate::params::{AlignmentParams, AlignmentType};
+
+/// Globally aligns `b` against `a`, returning the gapped rows of `a` and `b`.
+pub(crate) fn align_pair(a: &[u8], b: &[u8], params: &AlignmentParams) -> (Vec<u8>, Vec<u8>) {
+    if a.is_empty() || b.is_empty() {
+        // SPOA needs at least one base per sequence, the alignment is trivial anyway
+        let len = a.len().max(b.len());
+        let pad = |s: &[u8]| s.iter().cloned().chain(std::iter::repeat(crate::GAP)).take(len).collect();
+        return (pad(a), pad(b));
+    }
+
+    let seqs = vec![null_terminated(a), null_terminated(b)];
+    let quals: Vec<Vec<u8>> = seqs
+        .iter()
+        .map(|s| s.iter().map(|&c| if c == 0 { 0 } else { b'F' }).collect())
+        .collect();
+
+    let params = AlignmentParams {
+        alignment_type: AlignmentType::Global,
+        ..*params
+    };
+    let mut msa = crate::msa_with_params(&seqs, &quals, None, &params);
+    let row_b = msa.rows.pop().unwrap();
+    let row_a = msa.rows.pop().unwrap();
+    (row_a, row_b)
+}
+
+/// Left-aligns the indels of a pairwise alignment, see `msa::left_align_gaps`.
+pub(crate) fn left_align_pair(row_a: &mut [u8], row_b: &mut [u8]) {
+    left_align_gaps(row_a, row_b);
+    left_align_gaps(row_b, row_a);
+}
+
+/// Returns a copy of `seq` with a trailing null byte, as expected by the FFI functions.
+pub(crate) fn null_terminated(seq: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(seq.len() + 1);
+    out.extend_from_slice(seq);
+    out.push(b'\0');
+    out
+}
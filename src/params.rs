@@ -0,0 +1,932 @@
+//! Alignment parameters handed to the SPOA alignment engine.
+use crate::error::Error;
+use crate::graph::PoaGraph;
+use crate::matrix::SubstitutionMatrix;
+use std::marker::PhantomData;
+
+/// The alignment mode of the SPOA alignment engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AlignmentType {
+    /// Local (Smith-Waterman) alignment.
+    #[cfg_attr(feature = "serde", serde(alias = "local"))]
+    Local = 0,
+    /// Global (Needleman-Wunsch) alignment.
+    #[cfg_attr(feature = "serde", serde(alias = "global"))]
+    Global = 1,
+    /// Semi-global (overlap) alignment.
+    #[cfg_attr(feature = "serde", serde(alias = "semi_global"))]
+    SemiGlobal = 2,
+}
+
+/// The treatment of lowercase (soft-masked) bases, which SPOA otherwise aligns as symbols distinct from their
+/// uppercase bases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SoftMask {
+    /// Sequences are aligned as given, `a` mismatching `A`.
+    #[cfg_attr(feature = "serde", serde(alias = "preserve"))]
+    Preserve,
+    /// Sequences are uppercased before alignment.
+    #[cfg_attr(feature = "serde", serde(alias = "fold"))]
+    Fold,
+    /// Sequences are uppercased before alignment and the Phred quality of their soft-masked bases is capped
+    /// at `max_qual`, so that they weigh less in the consensus.
+    #[cfg_attr(feature = "serde", serde(alias = "down_weight"))]
+    DownWeight {
+        /// The highest Phred quality (without the +33 offset) of a soft-masked base.
+        max_qual: u8,
+    },
+}
+
+// sequences and their qualities
+type Reads = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+impl SoftMask {
+    // the uppercased sequences and their qualities, or None if they are aligned as given
+    pub(crate) fn apply(&self, seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> Option<Reads> {
+        let max_qual = match *self {
+            SoftMask::Preserve => return None,
+            SoftMask::Fold => None,
+            SoftMask::DownWeight { max_qual } => Some(max_qual.saturating_add(33)),
+        };
+        let folded = seqs.iter().map(|seq| seq.to_ascii_uppercase()).collect();
+        let quals = match max_qual {
+            Some(max_qual) => seqs
+                .iter()
+                .zip(quals.iter())
+                .map(|(seq, qual)| {
+                    qual.iter()
+                        .enumerate()
+                        .map(|(i, &q)| match seq.get(i) {
+                            Some(b) if b.is_ascii_lowercase() => q.min(max_qual),
+                            _ => q,
+                        })
+                        .collect()
+                })
+                .collect(),
+            None => quals.to_vec(),
+        };
+        Some((folded, quals))
+    }
+}
+
+/// The alignment mode and scores used to build the partial order graph.
+///
+/// The defaults are the scores used throughout the examples of this crate: global alignment,
+/// match 5, mismatch -4, and gaps of -3/-1 for both affine functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct AlignmentParams {
+    /// The alignment mode.
+    pub alignment_type: AlignmentType,
+    /// The match score for alignment.
+    pub match_score: i32,
+    /// The mismatch score for alignment.
+    pub mismatch_score: i32,
+    /// The gap open score for alignment.
+    pub gap_open: i32,
+    /// The gap extend score for alignment.
+    pub gap_extend: i32,
+    /// The gap open score of the second affine function.
+    pub gap2_open: i32,
+    /// The gap extend score of the second affine function.
+    pub gap2_extend: i32,
+    /// If true, alignments always run on SPOA's scalar engine instead of the SIMD engine selected for the
+    /// host CPU, so that results are bit-identical across SSE/AVX/NEON nodes at some cost in speed. Every
+    /// function taking the parameters honours it; the legacy `poa_consensus`, which takes bare scores, does not.
+    pub deterministic: bool,
+    /// If true, byte-identical sequences are aligned into the graph once, weighted by the summed qualities of
+    /// their copies. The alignment still holds one row per input sequence, so that coverage and identity
+    /// count every copy.
+    pub collapse_duplicates: bool,
+    /// If set, an `N` (or `n`) in a sequence matches any base with this score, typically 0, instead of
+    /// scoring as a mismatch, also in `PoaGraph` and thus `ConsensusPool`.
+    ///
+    /// SPOA's engines only score matches and mismatches, so setting it aligns every read, e.g. in `poa`,
+    /// `poa_batch` and `PoaGraph`, with the matrix engine of the C++ shim, scoring `N` against any base with
+    /// this score, without collapsing duplicates, see `PoaGraph::with_matrix`. That engine runs a full dynamic
+    /// programming of each read against all the nodes of the graph, without SIMD, and is much slower than
+    /// SPOA's: keep it for small clusters, or mask the `N`s of the reads instead.
+    pub wildcard_score: Option<i32>,
+    /// The treatment of lowercase (soft-masked) bases, applied by the functions taking these parameters;
+    /// `PoaGraph` aligns sequences as given. The alignment rows hold the uppercased bases.
+    pub soft_mask: SoftMask,
+}
+
+impl Default for AlignmentParams {
+    fn default() -> AlignmentParams {
+        AlignmentParams {
+            alignment_type: AlignmentType::Global,
+            match_score: 5,
+            mismatch_score: -4,
+            gap_open: -3,
+            gap_extend: -1,
+            gap2_open: -3,
+            gap2_extend: -1,
+            deterministic: false,
+            collapse_duplicates: false,
+            wildcard_score: None,
+            soft_mask: SoftMask::Preserve,
+        }
+    }
+}
+
+/// Scores suited to the reads of a sequencing platform, see `AlignmentParams::preset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Preset {
+    /// Oxford Nanopore reads, with frequent indels of any length: SPOA's own convex defaults.
+    Ont,
+    /// PacBio HiFi reads, accurate but for homopolymer indels: rare mismatches, long gaps seldom opened.
+    Hifi,
+    /// Illumina reads, with rare indels: affine gaps costly to open.
+    Illumina,
+    /// Protein sequences, with the affine gaps of BLAST's defaults: BLAST's gap costs of 11 to open and 1 to
+    /// extend charge a gap of `n` residues 11 + n, a first position of -12 and -1 for each further one in SPOA's
+    /// scores. Align proteins with the BLOSUM62 matrix of BLAST, see `try_poa_with_matrix` and
+    /// `SubstitutionMatrix::blosum62`, as SPOA's own engines only score identical residues as matches.
+    Protein,
+}
+
+impl std::str::FromStr for Preset {
+    type Err = String;
+
+    /// Parses the lowercase name of a preset: `ont`, `hifi`, `illumina` or `protein`.
+    fn from_str(name: &str) -> Result<Preset, String> {
+        match name {
+            "ont" => Ok(Preset::Ont),
+            "hifi" => Ok(Preset::Hifi),
+            "illumina" => Ok(Preset::Illumina),
+            "protein" => Ok(Preset::Protein),
+            _ => Err(format!("unknown preset {}, expected ont, hifi, illumina or protein", name)),
+        }
+    }
+}
+
+/// Alignment parameters chosen by the depth of a cluster, e.g. relaxed gap penalties for shallow families and
+/// strict ones for deep families, see `poa_batch_scheduled`.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{AlignmentParams, ParamSchedule};
+///
+///     let relaxed = AlignmentParams {
+///         gap_open: -2,
+///         ..AlignmentParams::default()
+///     };
+///     let strict = AlignmentParams {
+///         gap_open: -12,
+///         ..AlignmentParams::default()
+///     };
+///     // relaxed below 5 reads, strict from 30 reads on and the defaults in between
+///     let schedule = ParamSchedule::new(relaxed)
+///         .from_depth(5, AlignmentParams::default())
+///         .from_depth(30, strict);
+///
+///     assert_eq!(schedule.params(3).gap_open, -2);
+///     assert_eq!(schedule.params(12).gap_open, -3);
+///     assert_eq!(schedule.params(30).gap_open, -12);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParamSchedule {
+    /// The parameters of the clusters shallower than every step.
+    pub base: AlignmentParams,
+    /// The steps of the schedule, each a depth and the parameters of the clusters of at least as many reads.
+    pub steps: Vec<(usize, AlignmentParams)>,
+}
+
+impl ParamSchedule {
+    /// Returns a schedule applying `base` at every depth.
+    pub fn new(base: AlignmentParams) -> ParamSchedule {
+        ParamSchedule { base, steps: vec![] }
+    }
+
+    /// Returns the schedule with `params` applying to the clusters of `min_depth` reads and more, up to the
+    /// next step.
+    pub fn from_depth(mut self, min_depth: usize, params: AlignmentParams) -> ParamSchedule {
+        self.steps.push((min_depth, params));
+        self
+    }
+
+    /// Returns the parameters of a cluster of `depth` reads: those of the deepest step it reaches, the last
+    /// added of steps of the same depth, or the base parameters.
+    pub fn params(&self, depth: usize) -> &AlignmentParams {
+        self.steps
+            .iter()
+            .filter(|(min_depth, _)| *min_depth <= depth)
+            .max_by_key(|(min_depth, _)| *min_depth)
+            .map_or(&self.base, |(_, params)| params)
+    }
+
+    /// Checks every parameter set of the schedule, see `AlignmentParams::validate`.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.base.validate()?;
+        self.steps.iter().try_for_each(|(_, params)| params.validate())
+    }
+}
+
+// panics on the gap scores, as handed to the FFI, that the SPOA alignment engine rejects, see
+// `AlignmentParams::check_engine_scores`
+pub(crate) fn check_engine_scores(gap_scores: &[i32]) {
+    if let Some(score) = gap_scores.iter().find(|&&score| score as i8 > 0) {
+        panic!("Gap scores must not be positive, got {}", score);
+    }
+}
+
+// margin kept from the bounds of 32-bit scores, as SPOA does for its own lane selection
+const SCORE_MARGIN: i64 = 1024;
+
+impl AlignmentParams {
+    /// Returns a builder starting from the default parameters, whose gap model is chosen at most once and
+    /// checked against the model SPOA selects for the gap scores when the parameters are built.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{AlignmentParams, AlignmentType};
+    ///
+    ///     let params = AlignmentParams::builder()
+    ///         .alignment_type(AlignmentType::Local)
+    ///         .convex_gaps((-4, -2), (-24, -1))
+    ///         .build()
+    ///         .unwrap();
+    ///     assert_eq!(params.gap2_open, -24);
+    ///
+    ///     // SPOA scores gaps extending at a higher cost than they open as linear gaps
+    ///     assert!(AlignmentParams::builder().affine_gaps(-1, -3).build().is_err());
+    /// ```
+    pub fn builder() -> ParamsBuilder<DefaultGaps> {
+        ParamsBuilder {
+            params: AlignmentParams::default(),
+            gaps: PhantomData,
+        }
+    }
+
+    /// Reads parameters from a JSON object or a TOML table of the fields of `AlignmentParams`, missing fields
+    /// taking their default values, and validates them like `ParamsBuilder::build`, so that parameter sets can
+    /// be versioned with a pipeline: scores out of the range of the engine, positive gap scores, and a match
+    /// score that is not positive and higher than the mismatch score fail with `ErrorKind::InvalidData`. The
+    /// format is told by the first character: `{` for JSON.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{AlignmentParams, AlignmentType};
+    ///
+    ///     let toml = "alignment_type = \"semi_global\"\nmatch_score = 2\nmismatch_score = -4\n";
+    ///     let params = AlignmentParams::from_reader(toml.as_bytes()).unwrap();
+    ///     assert_eq!(params.alignment_type, AlignmentType::SemiGlobal);
+    ///
+    ///     let json = r#"{"gap_open": -8, "gap_extend": -2, "deterministic": true}"#;
+    ///     assert!(AlignmentParams::from_reader(json.as_bytes()).unwrap().deterministic);
+    /// ```
+    #[cfg(feature = "config")]
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> std::io::Result<AlignmentParams> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let mut config = String::new();
+        reader.read_to_string(&mut config)?;
+        let params: AlignmentParams = if config.trim_start().starts_with('{') {
+            serde_json::from_str(&config).map_err(|e| invalid(e.to_string()))?
+        } else {
+            toml::from_str(&config).map_err(|e| invalid(e.to_string()))?
+        };
+        params
+            .validate()
+            .and_then(|()| params.check_match_scores())
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(params)
+    }
+
+    /// Returns the gap model SPOA scores gaps with under the gap scores: linear gaps of `gap_open` when a gap
+    /// extends at no lower score than it opens, affine gaps of the first function unless the second opens
+    /// lower and extends higher, and convex gaps otherwise.
+    pub fn gap_model(&self) -> GapScores {
+        let (g, e, q, c) = (self.gap_open, self.gap_extend, self.gap2_open, self.gap2_extend);
+        if g >= e {
+            GapScores::Linear { g }
+        } else if g <= q || e >= c {
+            GapScores::Affine { open: g, extend: e }
+        } else {
+            GapScores::Convex {
+                open1: g,
+                extend1: e,
+                open2: q,
+                extend2: c,
+            }
+        }
+    }
+
+    /// Returns the parameters with the gap scores of a gap model, see `GapScores::scores`, or the error of
+    /// `GapScores::validate` if SPOA would not score gaps with the model.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{AlignmentParams, GapScores};
+    ///
+    ///     let model = GapScores::Affine { open: -8, extend: -2 };
+    ///     let params = AlignmentParams::default().with_gap_model(model).unwrap();
+    ///     assert_eq!(params.gap_model(), model);
+    ///     assert!(AlignmentParams::default().with_gap_model(GapScores::Affine { open: -2, extend: -8 }).is_err());
+    /// ```
+    pub fn with_gap_model(&self, model: GapScores) -> Result<AlignmentParams, Error> {
+        model.validate()?;
+        Ok(self.with_gap_scores(model))
+    }
+
+    // the parameters with the gap scores of a gap model, valid or not
+    fn with_gap_scores(&self, model: GapScores) -> AlignmentParams {
+        let [gap_open, gap_extend, gap2_open, gap2_extend] = model.scores();
+        AlignmentParams {
+            gap_open,
+            gap_extend,
+            gap2_open,
+            gap2_extend,
+            ..*self
+        }
+    }
+
+    /// Returns the scores of a preset, with global alignment and the other options at their defaults.
+    pub fn preset(preset: Preset) -> AlignmentParams {
+        let (match_score, mismatch_score, gap_open, gap_extend, gap2_open, gap2_extend) = match preset {
+            Preset::Ont => (5, -4, -8, -6, -10, -4),
+            Preset::Hifi => (5, -8, -8, -4, -24, -1),
+            Preset::Illumina => (5, -4, -12, -2, -12, -2),
+            Preset::Protein => (4, -2, -12, -1, -12, -1),
+        };
+        AlignmentParams {
+            match_score,
+            mismatch_score,
+            gap_open,
+            gap_extend,
+            gap2_open,
+            gap2_extend,
+            ..AlignmentParams::default()
+        }
+    }
+
+    fn scores(&self) -> [(&'static str, i32); 6] {
+        [
+            ("match_score", self.match_score),
+            ("mismatch_score", self.mismatch_score),
+            ("gap_open", self.gap_open),
+            ("gap_extend", self.gap_extend),
+            ("gap2_open", self.gap2_open),
+            ("gap2_extend", self.gap2_extend),
+        ]
+    }
+
+    /// Checks that every score fits the 8-bit scores of the SPOA alignment engine, which would otherwise
+    /// silently truncate it, and returns `Error::InvalidParams` for a positive gap score, which the engine
+    /// rejects.
+    pub fn validate(&self) -> Result<(), Error> {
+        let (scores, range) = (self.scores(), i8::MIN as i32..=i8::MAX as i32);
+        if let Some(&(name, value)) = scores.iter().find(|(_, value)| !range.contains(value)) {
+            return Err(Error::ScoreOutOfRange { name, value });
+        }
+        if let Some(value) = self.wildcard_score.filter(|value| !range.contains(value)) {
+            return Err(Error::ScoreOutOfRange {
+                name: "wildcard_score",
+                value,
+            });
+        }
+        match scores[2..].iter().find(|(_, value)| *value > 0) {
+            Some(&(name, value)) => Err(Error::InvalidParams {
+                reason: format!("{} {} must not be positive", name, value),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // returns `Error::InvalidParams` unless a match scores positive and higher than a mismatch, as checked by
+    // `ParamsBuilder::build` and `from_reader`
+    fn check_match_scores(&self) -> Result<(), Error> {
+        let invalid = |reason: String| Err(Error::InvalidParams { reason });
+        if self.match_score <= 0 {
+            return invalid(format!("match_score {} must be positive", self.match_score));
+        }
+        if self.mismatch_score >= self.match_score {
+            return invalid(format!(
+                "mismatch_score {} must be lower than match_score {}",
+                self.mismatch_score, self.match_score
+            ));
+        }
+        Ok(())
+    }
+
+    // panics on gap scores the SPOA alignment engine rejects, positive once truncated to its 8-bit scores, by
+    // throwing a C++ exception across the FFI boundary, which aborts the process
+    pub(crate) fn check_engine_scores(&self) {
+        check_engine_scores(&[self.gap_open, self.gap_extend, self.gap2_open, self.gap2_extend]);
+    }
+
+    /// Checks that aligning a sequence of `seq_len` bases against a graph of `graph_len` nodes cannot
+    /// overflow 32-bit scores.
+    ///
+    /// The SIMD engine runs in 16-bit lanes and widens them to 32 bits by itself when needed, but aborts the
+    /// process past 32 bits, and the scalar engine used in deterministic mode silently overflows.
+    pub fn check_overflow(&self, seq_len: usize, graph_len: usize) -> Result<(), Error> {
+        let (i, j) = (seq_len as i64, graph_len as i64);
+        let gap = |len: i64| match len {
+            0 => 0,
+            len => i64::min(
+                self.gap_open as i64 + (len - 1) * self.gap_extend as i64,
+                self.gap2_open as i64 + (len - 1) * self.gap2_extend as i64,
+            ),
+        };
+        // the lowest score of an alignment, all mismatches or all gaps, and the highest, all matches
+        let lowest = i64::min(self.mismatch_score.min(0) as i64 * i.min(j) + gap((i - j).abs()), gap(i) + gap(j));
+        let highest = self.match_score.max(0) as i64 * i.min(j);
+        if lowest < i32::MIN as i64 + SCORE_MARGIN || highest > i32::MAX as i64 - SCORE_MARGIN {
+            return Err(Error::ScoreOverflow { seq_len, graph_len });
+        }
+        Ok(())
+    }
+}
+
+/// A gap model and its scores, chosen explicitly rather than inferred by SPOA from the four gap scores of
+/// `AlignmentParams`, see `AlignmentParams::with_gap_model`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GapScores {
+    /// Linear gaps, every gap position scoring `g`.
+    Linear { g: i32 },
+    /// Affine gaps, scoring `open` for their first position and `extend` for each further one.
+    Affine { open: i32, extend: i32 },
+    /// Convex gaps, scored by the better of two affine functions: typically the first for short gaps and the
+    /// second, opening lower and extending higher, for long ones.
+    Convex {
+        open1: i32,
+        extend1: i32,
+        open2: i32,
+        extend2: i32,
+    },
+}
+
+impl GapScores {
+    /// Returns the `gap_open`, `gap_extend`, `gap2_open` and `gap2_extend` scores of the model, expanded as
+    /// the overloads of SPOA's `AlignmentEngine::Create` taking one or two gap scores expand theirs, so that
+    /// the engine created from them scores gaps with this model once the model is valid.
+    pub fn scores(&self) -> [i32; 4] {
+        match *self {
+            GapScores::Linear { g } => [g, g, g, g],
+            GapScores::Affine { open, extend } => [open, extend, open, extend],
+            GapScores::Convex {
+                open1,
+                extend1,
+                open2,
+                extend2,
+            } => [open1, extend1, open2, extend2],
+        }
+    }
+
+    /// Checks that the scores are not positive and fit the 8-bit scores of the SPOA alignment engine, and
+    /// that SPOA scores gaps with this model: affine gaps must extend at a higher score than they open, and
+    /// the second function of convex gaps must open lower and be cheaper to extend than the first. Returns
+    /// `Error::ScoreOutOfRange` or `Error::InvalidParams` otherwise.
+    pub fn validate(&self) -> Result<(), Error> {
+        AlignmentParams::default().with_gap_scores(*self).validate()?;
+        self.check().map_err(|reason| Error::InvalidParams { reason })
+    }
+
+    // checks that SPOA selects this model for its scores
+    fn check(&self) -> Result<(), String> {
+        match *self {
+            GapScores::Linear { .. } => Ok(()),
+            GapScores::Affine { open, extend } => {
+                if open >= extend {
+                    return Err(format!(
+                        "affine gap_open {} must be lower than gap_extend {}, \
+                         SPOA would otherwise score linear gaps of {}",
+                        open, extend, open
+                    ));
+                }
+                Ok(())
+            }
+            GapScores::Convex {
+                open1,
+                extend1,
+                open2,
+                extend2,
+            } => {
+                GapScores::Affine {
+                    open: open1,
+                    extend: extend1,
+                }
+                .check()?;
+                if open2 >= extend2 {
+                    return Err(format!(
+                        "convex gap2_open {} must be lower than gap2_extend {}",
+                        open2, extend2
+                    ));
+                }
+                // SPOA falls back to the first function when it is never the worse of the two on long gaps
+                if open1 <= open2 || extend1 >= extend2 {
+                    return Err(format!(
+                        "the second gap function ({}, {}) must open lower and extend higher than the first ({}, {}), \
+                         SPOA would otherwise score affine gaps with the first",
+                        open2, extend2, open1, extend1
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// The gap model of a `ParamsBuilder` as a type, checked against the model SPOA selects for its gap scores.
+pub trait GapModel: sealed::Sealed {
+    /// Checks that SPOA scores gaps with this model under the given gap scores.
+    fn check(params: &AlignmentParams) -> Result<(), String>;
+}
+
+/// The default gaps of -3/-1 for both affine functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DefaultGaps;
+
+/// Linear gaps, every gap position scoring the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinearGaps;
+
+/// Affine gaps, scored by their opening and each extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AffineGaps;
+
+/// Convex gaps, scored by the better of two affine functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConvexGaps;
+
+impl sealed::Sealed for DefaultGaps {}
+impl sealed::Sealed for LinearGaps {}
+impl sealed::Sealed for AffineGaps {}
+impl sealed::Sealed for ConvexGaps {}
+
+impl GapModel for DefaultGaps {
+    fn check(_params: &AlignmentParams) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl GapModel for LinearGaps {
+    fn check(_params: &AlignmentParams) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl GapModel for AffineGaps {
+    fn check(params: &AlignmentParams) -> Result<(), String> {
+        GapScores::Affine {
+            open: params.gap_open,
+            extend: params.gap_extend,
+        }
+        .check()
+    }
+}
+
+impl GapModel for ConvexGaps {
+    fn check(params: &AlignmentParams) -> Result<(), String> {
+        GapScores::Convex {
+            open1: params.gap_open,
+            extend1: params.gap_extend,
+            open2: params.gap2_open,
+            extend2: params.gap2_extend,
+        }
+        .check()
+    }
+}
+
+/// A builder of `AlignmentParams`, see `AlignmentParams::builder`.
+///
+/// SPOA infers the gap model from the gap scores and silently falls back to a simpler one when they are
+/// inconsistent, e.g. to linear gaps when a gap extends at a higher cost than it opens. The builder takes the
+/// gap scores of an explicit model instead, chosen at most once as enforced by its type parameter, and
+/// `build` rejects scores that SPOA would not use as given, as well as options that would be silently
+/// ignored, such as collapsing duplicates with a wildcard score. `build_graph` also rejects a wildcard score
+/// for a graph aligning with a substitution matrix, on which `PoaGraph::with_matrix` panics, and soft masking,
+/// which graphs do not apply.
+///
+/// Qualities are not an option: SPOA always weighs the bases by the qualities they are added with, reads
+/// without qualities being added with uniform ones, so that there is no unweighted mode to conflict with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamsBuilder<G> {
+    params: AlignmentParams,
+    gaps: PhantomData<G>,
+}
+
+impl<G> ParamsBuilder<G> {
+    fn gaps<H>(self, gap_open: i32, gap_extend: i32, gap2_open: i32, gap2_extend: i32) -> ParamsBuilder<H> {
+        ParamsBuilder {
+            params: AlignmentParams {
+                gap_open,
+                gap_extend,
+                gap2_open,
+                gap2_extend,
+                ..self.params
+            },
+            gaps: PhantomData,
+        }
+    }
+
+    /// Sets the alignment mode.
+    pub fn alignment_type(mut self, alignment_type: AlignmentType) -> Self {
+        self.params.alignment_type = alignment_type;
+        self
+    }
+
+    /// Sets the match score, which must be positive.
+    pub fn match_score(mut self, match_score: i32) -> Self {
+        self.params.match_score = match_score;
+        self
+    }
+
+    /// Sets the mismatch score, which must be lower than the match score.
+    pub fn mismatch_score(mut self, mismatch_score: i32) -> Self {
+        self.params.mismatch_score = mismatch_score;
+        self
+    }
+
+    /// Sets whether alignments run on SPOA's scalar engine, see `AlignmentParams::deterministic`.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.params.deterministic = deterministic;
+        self
+    }
+
+    /// Sets whether identical sequences are aligned once, see `AlignmentParams::collapse_duplicates`.
+    pub fn collapse_duplicates(mut self, collapse_duplicates: bool) -> Self {
+        self.params.collapse_duplicates = collapse_duplicates;
+        self
+    }
+
+    /// Sets the score of an `N` aligned to any base, see `AlignmentParams::wildcard_score`.
+    pub fn wildcard_score(mut self, wildcard_score: i32) -> Self {
+        self.params.wildcard_score = Some(wildcard_score);
+        self
+    }
+
+    /// Sets the treatment of lowercase bases, see `AlignmentParams::soft_mask`.
+    pub fn soft_mask(mut self, soft_mask: SoftMask) -> Self {
+        self.params.soft_mask = soft_mask;
+        self
+    }
+}
+
+impl ParamsBuilder<DefaultGaps> {
+    /// Scores every gap position `gap`.
+    pub fn linear_gaps(self, gap: i32) -> ParamsBuilder<LinearGaps> {
+        self.gaps(gap, gap, gap, gap)
+    }
+
+    /// Scores gaps `open` for their first position and `extend` for each further one.
+    pub fn affine_gaps(self, open: i32, extend: i32) -> ParamsBuilder<AffineGaps> {
+        self.gaps(open, extend, open, extend)
+    }
+
+    /// Scores gaps by the better of two affine functions, each as an (open, extend) pair: typically the first
+    /// for short gaps and the second, opening lower and extending higher, for long ones.
+    pub fn convex_gaps(self, first: (i32, i32), second: (i32, i32)) -> ParamsBuilder<ConvexGaps> {
+        self.gaps(first.0, first.1, second.0, second.1)
+    }
+}
+
+impl<G: GapModel> ParamsBuilder<G> {
+    /// Returns the parameters, or `Error::InvalidParams` if SPOA would not score alignments as they
+    /// specify, and `Error::ScoreOutOfRange` if a score does not fit its 8-bit scores.
+    pub fn build(self) -> Result<AlignmentParams, Error> {
+        let params = self.params;
+        params.validate()?;
+        params.check_match_scores()?;
+        G::check(&params).map_err(|reason| Error::InvalidParams { reason })?;
+        if params.wildcard_score.is_some() && params.collapse_duplicates {
+            return Err(Error::InvalidParams {
+                reason: "duplicates cannot be collapsed with a wildcard score, whose matrix engine aligns every read"
+                    .to_string(),
+            });
+        }
+        Ok(params)
+    }
+
+    /// Builds the parameters like `build`, then a graph aligning sequences with them, see `PoaGraph::new`, or
+    /// with `matrix` if any, see `PoaGraph::with_matrix`. Returns the errors of `build`, `Error::UnsupportedCpu`
+    /// like `check_cpu`, or `Error::InvalidParams` if a wildcard score is combined with a matrix or if soft-masked
+    /// bases are not preserved, as graphs align sequences as given.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{AlignmentParams, SubstitutionMatrix};
+    ///
+    ///     let blosum62 = SubstitutionMatrix::blosum62();
+    ///     let mut graph = AlignmentParams::builder().affine_gaps(-12, -1).build_graph(Some(&blosum62)).unwrap();
+    ///     graph.add(b"MQIFVKTLTG", b"IIIIIIIIII");
+    ///
+    ///     let wildcard = AlignmentParams::builder().wildcard_score(0);
+    ///     assert!(wildcard.build_graph(Some(&blosum62)).is_err());
+    /// ```
+    pub fn build_graph(self, matrix: Option<&SubstitutionMatrix>) -> Result<PoaGraph, Error> {
+        let params = self.build()?;
+        crate::check_cpu()?;
+        if params.soft_mask != SoftMask::Preserve {
+            return Err(Error::InvalidParams {
+                reason: "soft-masked bases cannot be folded or down-weighted by a graph, which aligns them as given"
+                    .to_string(),
+            });
+        }
+        match matrix {
+            Some(_) if params.wildcard_score.is_some() => Err(Error::InvalidParams {
+                reason: "a wildcard score cannot be combined with a substitution matrix, which scores N itself"
+                    .to_string(),
+            }),
+            Some(matrix) => Ok(PoaGraph::with_matrix(&params, matrix)),
+            None => Ok(PoaGraph::new(&params)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(AlignmentParams::default().validate(), Ok(()));
+
+        let params = AlignmentParams {
+            gap_extend: -200,
+            ..AlignmentParams::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(Error::ScoreOutOfRange {
+                name: "gap_extend",
+                value: -200
+            })
+        );
+        let params = AlignmentParams {
+            gap2_open: 2,
+            ..AlignmentParams::default()
+        };
+        let reason = "gap2_open 2 must not be positive".to_string();
+        assert_eq!(params.validate(), Err(Error::InvalidParams { reason }));
+    }
+
+    #[test]
+    fn test_param_schedule() {
+        let params = |gap_open| AlignmentParams {
+            gap_open,
+            ..AlignmentParams::default()
+        };
+        // steps may be added in any order
+        let schedule = ParamSchedule::new(params(-1)).from_depth(30, params(-12)).from_depth(5, params(-3));
+
+        assert_eq!(schedule.params(0).gap_open, -1);
+        assert_eq!(schedule.params(4).gap_open, -1);
+        assert_eq!(schedule.params(5).gap_open, -3);
+        assert_eq!(schedule.params(29).gap_open, -3);
+        assert_eq!(schedule.params(1000).gap_open, -12);
+        assert_eq!(schedule.validate(), Ok(()));
+        assert!(schedule.from_depth(10, params(-200)).validate().is_err());
+    }
+
+    #[test]
+    fn test_check_overflow() {
+        let params = AlignmentParams {
+            match_score: 100,
+            gap_extend: -100,
+            gap2_extend: -100,
+            ..AlignmentParams::default()
+        };
+
+        assert_eq!(AlignmentParams::default().check_overflow(100_000, 1_000_000), Ok(()));
+        assert_eq!(params.check_overflow(100_000, 1_000_000), Ok(()));
+        assert_eq!(
+            params.check_overflow(10_000_000, 30_000_000),
+            Err(Error::ScoreOverflow {
+                seq_len: 10_000_000,
+                graph_len: 30_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn test_soft_mask() {
+        let seqs = vec![b"ACgt\0".to_vec()];
+        let quals = vec![b"IIII\0".to_vec()];
+
+        assert_eq!(SoftMask::Preserve.apply(&seqs, &quals), None);
+        assert_eq!(SoftMask::Fold.apply(&seqs, &quals), Some((vec![b"ACGT\0".to_vec()], quals.clone())));
+        assert_eq!(
+            SoftMask::DownWeight { max_qual: 5 }.apply(&seqs, &quals),
+            Some((vec![b"ACGT\0".to_vec()], vec![b"II&&\0".to_vec()]))
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!("hifi".parse(), Ok(Preset::Hifi));
+        assert!("pacbio".parse::<Preset>().is_err());
+        // every preset holds valid scores for the gap model it is meant to use
+        for &preset in [Preset::Ont, Preset::Hifi, Preset::Illumina, Preset::Protein].iter() {
+            let params = AlignmentParams::preset(preset);
+            assert_eq!(params.validate(), Ok(()));
+            let built = match preset {
+                Preset::Ont | Preset::Hifi => ConvexGaps::check(&params),
+                Preset::Illumina | Preset::Protein => AffineGaps::check(&params),
+            };
+            assert_eq!(built, Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let params = AlignmentParams::builder().linear_gaps(-2).deterministic(true).build().unwrap();
+        assert_eq!((params.gap_open, params.gap_extend, params.gap2_open), (-2, -2, -2));
+        assert!(params.deterministic);
+        assert_eq!(AlignmentParams::builder().build(), Ok(AlignmentParams::default()));
+
+        let invalid = |result: Result<AlignmentParams, Error>| match result {
+            Err(Error::InvalidParams { reason }) => reason,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(invalid(AlignmentParams::builder().affine_gaps(-1, -3).build()).contains("linear gaps"));
+        assert!(invalid(AlignmentParams::builder().convex_gaps((-4, -2), (-2, -1)).build()).contains("affine gaps"));
+        assert!(invalid(AlignmentParams::builder().mismatch_score(6).build()).contains("mismatch_score"));
+        assert!(invalid(AlignmentParams::builder().linear_gaps(2).build()).contains("gap_open 2"));
+        assert!(matches!(
+            AlignmentParams::builder().match_score(300).build(),
+            Err(Error::ScoreOutOfRange { .. })
+        ));
+        let collapsed_wildcard = AlignmentParams::builder().wildcard_score(0).collapse_duplicates(true);
+        assert!(invalid(collapsed_wildcard.build()).contains("collapsed"));
+
+        let invalid_graph = |result: Result<PoaGraph, Error>| match result {
+            Err(Error::InvalidParams { reason }) => reason,
+            Err(other) => panic!("unexpected {:?}", other),
+            Ok(_) => panic!("unexpected graph"),
+        };
+        let wildcard = AlignmentParams::builder().wildcard_score(0);
+        assert!(invalid_graph(wildcard.build_graph(Some(&SubstitutionMatrix::blosum62()))).contains("wildcard"));
+        assert!(invalid_graph(collapsed_wildcard.build_graph(None)).contains("collapsed"));
+        assert!(invalid_graph(AlignmentParams::builder().soft_mask(SoftMask::Fold).build_graph(None)).contains("soft"));
+    }
+
+    #[test]
+    fn test_gap_model() {
+        assert_eq!(AlignmentParams::default().gap_model(), GapScores::Affine { open: -3, extend: -1 });
+        let convex = GapScores::Convex {
+            open1: -8,
+            extend1: -6,
+            open2: -10,
+            extend2: -4,
+        };
+        assert_eq!(AlignmentParams::preset(Preset::Ont).gap_model(), convex);
+        for model in [GapScores::Linear { g: -2 }, GapScores::Affine { open: -8, extend: -2 }, convex].iter() {
+            assert_eq!(model.validate(), Ok(()));
+            assert_eq!(AlignmentParams::default().with_gap_model(*model).unwrap().gap_model(), *model);
+        }
+        assert_eq!(GapScores::Linear { g: -2 }.scores(), [-2, -2, -2, -2]);
+
+        let invalid = |model: GapScores| match model.validate() {
+            Err(Error::InvalidParams { reason }) => reason,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(invalid(GapScores::Affine { open: -1, extend: -3 }).contains("linear gaps"));
+        let cheaper_to_open = GapScores::Convex {
+            open1: -4,
+            extend1: -2,
+            open2: -2,
+            extend2: -1,
+        };
+        assert!(invalid(cheaper_to_open).contains("affine gaps"));
+        assert!(invalid(GapScores::Linear { g: 2 }).contains("gap_open 2"));
+        assert!(matches!(GapScores::Linear { g: -200 }.validate(), Err(Error::ScoreOutOfRange { .. })));
+        let params = AlignmentParams::default().with_gap_model(cheaper_to_open);
+        assert_eq!(params, Err(cheaper_to_open.validate().unwrap_err()));
+    }
+
+    #[test]
+    #[cfg(feature = "config")]
+    fn test_from_reader() {
+        let toml = "alignment_type = \"Local\"\ngap_open = -8\nsoft_mask = { down_weight = { max_qual = 10 } }\n";
+        let params = AlignmentParams::from_reader(toml.as_bytes()).unwrap();
+        assert_eq!(params.alignment_type, AlignmentType::Local);
+        assert_eq!(params.gap_open, -8);
+        assert_eq!(params.soft_mask, SoftMask::DownWeight { max_qual: 10 });
+        assert_eq!(params.match_score, 5);
+
+        let json = r#"{"soft_mask": "fold", "wildcard_score": 0}"#;
+        let params = AlignmentParams::from_reader(json.as_bytes()).unwrap();
+        assert_eq!((params.soft_mask, params.wildcard_score), (SoftMask::Fold, Some(0)));
+
+        let kind = |config: &str| AlignmentParams::from_reader(config.as_bytes()).unwrap_err().kind();
+        assert_eq!(kind("match = 5\n"), std::io::ErrorKind::InvalidData);
+        assert_eq!(kind("{\"mismatch_score\": 6}"), std::io::ErrorKind::InvalidData);
+    }
+}
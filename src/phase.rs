@@ -0,0 +1,414 @@
+//! Read-backed phasing of the heterozygous columns of an alignment into the consensuses of the haplotypes of
+//! a diploid or polyploid set of reads.
+use std::collections::HashMap;
+
+use crate::msa::{Msa, GAP};
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// How the heterozygous columns phased by `poa_phased` are called.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseOptions {
+    /// The minimum fraction of the reads covering a column that each of its two alleles must reach.
+    pub min_allele_fraction: f64,
+    /// The minimum number of reads covering a heterozygous column.
+    pub min_depth: usize,
+    /// The maximum number of haplotypes the reads are split into.
+    pub ploidy: usize,
+}
+
+impl Default for PhaseOptions {
+    fn default() -> PhaseOptions {
+        PhaseOptions {
+            min_allele_fraction: 0.2,
+            min_depth: 4,
+            ploidy: 2,
+        }
+    }
+}
+
+/// A heterozygous alignment column and the phase of its alleles.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhasedSite {
+    /// The alignment column.
+    pub column: usize,
+    /// The allele of each haplotype.
+    pub alleles: Vec<u8>,
+    /// The phase block of the site. The sites of a block are linked by the reads spanning them; the phases
+    /// of different blocks are unrelated.
+    pub block: usize,
+    /// The fraction of the reads linking the site to the previous sites of its block that disagree with its
+    /// phase, an estimate of the probability of a switch error at the site (0 for the first site of a block).
+    /// Above two haplotypes, all sites are in one block and this is the fraction of the reads of the
+    /// haplotypes covering the site that disagree with the allele of their haplotype.
+    pub switch_error: f64,
+}
+
+/// The consensuses of the haplotypes of a diploid or polyploid set of reads.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhasedConsensus {
+    /// The consensus of the reads of each haplotype.
+    pub haplotypes: Vec<ConsensusResult>,
+    /// The input indices of the reads assigned to each haplotype.
+    pub reads: Vec<Vec<usize>>,
+    /// The input indices of the reads covering no heterozygous site, or as close to several haplotypes.
+    pub unassigned: Vec<usize>,
+    /// The heterozygous sites, ordered by column.
+    pub sites: Vec<PhasedSite>,
+}
+
+impl PhasedConsensus {
+    /// Returns the number of reads of each haplotype.
+    pub fn read_counts(&self) -> Vec<usize> {
+        self.reads.iter().map(|reads| reads.len()).collect()
+    }
+
+    /// Returns the mean switch error estimate of the sites phased against previous sites of their block, or 0
+    /// if there are none.
+    pub fn switch_error_rate(&self) -> f64 {
+        let linked: Vec<f64> = self
+            .sites
+            .windows(2)
+            .filter(|pair| pair[0].block == pair[1].block)
+            .map(|pair| pair[1].switch_error)
+            .collect();
+        if linked.is_empty() {
+            return 0.0;
+        }
+        linked.iter().sum::<f64>() / linked.len() as f64
+    }
+}
+
+// the first and last column of each row, or None for empty rows
+fn row_spans(msa: &Msa) -> Vec<Option<(usize, usize)>> {
+    msa.rows
+        .iter()
+        .map(|row| Some((row.iter().position(|&b| b != GAP)?, row.iter().rposition(|&b| b != GAP)?)))
+        .collect()
+}
+
+fn covers(span: Option<(usize, usize)>, col: usize) -> bool {
+    matches!(span, Some((first, last)) if first <= col && col <= last)
+}
+
+// the columns in which at least two bases are frequent enough among the covering reads, and these bases, most
+// frequent first and at most `ploidy` of them
+fn heterozygous_columns(msa: &Msa, spans: &[Option<(usize, usize)>], options: &PhaseOptions) -> Vec<(usize, Vec<u8>)> {
+    let mut columns = vec![];
+    for col in 0..msa.num_columns() {
+        let mut counts = [0usize; 256];
+        let mut depth = 0;
+        for (row, &span) in msa.rows.iter().zip(spans.iter()) {
+            if covers(span, col) {
+                counts[row[col] as usize] += 1;
+                depth += 1;
+            }
+        }
+        let min_count = (options.min_allele_fraction * depth as f64).ceil().max(1.0) as usize;
+        let mut bases: Vec<u8> = (0..=255u8).filter(|&b| b != GAP && counts[b as usize] >= min_count).collect();
+        bases.sort_by(|&a, &b| counts[b as usize].cmp(&counts[a as usize]).then(a.cmp(&b)));
+        bases.truncate(options.ploidy);
+        if depth >= options.min_depth && bases.len() >= 2 {
+            columns.push((col, bases));
+        }
+    }
+    columns
+}
+
+// the heterozygous sites of the alignment and the haplotype of each row: 1 for the first, -1 for the second
+// and 0 for unassigned rows
+fn phase_rows(msa: &Msa, options: &PhaseOptions) -> (Vec<PhasedSite>, Vec<i8>) {
+    let options = PhaseOptions { ploidy: 2, ..*options };
+    let columns = heterozygous_columns(msa, &row_spans(msa), &options);
+
+    // the allele of each row at each site: 1 for the most frequent base, -1 for the second, 0 otherwise
+    let alleles: Vec<Vec<i8>> = msa
+        .rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|(col, bases)| match row[*col] {
+                    b if b == bases[0] => 1,
+                    b if b == bases[1] => -1,
+                    _ => 0,
+                })
+                .collect()
+        })
+        .collect();
+
+    // each site is phased by the votes of the reads linking it to the previous sites of its block; a site no
+    // read links starts a new block
+    let mut phases = vec![1i8; columns.len()];
+    let mut sites = vec![];
+    // the agreement of each row with the phases of the previous sites of the block
+    let mut linked = vec![0i32; msa.num_rows()];
+    let mut block = 0;
+    for (s, (column, bases)) in columns.iter().enumerate() {
+        let (mut cis, mut trans) = (0usize, 0usize);
+        for (row, &link) in alleles.iter().zip(linked.iter()) {
+            match row[s] as i32 * link.signum() {
+                1 => cis += 1,
+                -1 => trans += 1,
+                _ => {}
+            }
+        }
+        if s > 0 && cis + trans == 0 {
+            block += 1;
+            linked.iter_mut().for_each(|link| *link = 0);
+        }
+        phases[s] = if trans > cis { -1 } else { 1 };
+        for (row, link) in alleles.iter().zip(linked.iter_mut()) {
+            *link += (row[s] * phases[s]) as i32;
+        }
+        sites.push(PhasedSite {
+            column: *column,
+            alleles: if phases[s] == 1 { vec![bases[0], bases[1]] } else { vec![bases[1], bases[0]] },
+            block,
+            switch_error: if cis + trans == 0 { 0.0 } else { cis.min(trans) as f64 / (cis + trans) as f64 },
+        });
+    }
+
+    let haplotypes = alleles
+        .iter()
+        .map(|row| {
+            let score: i32 = row.iter().zip(phases.iter()).map(|(&a, &p)| (a * p) as i32).sum();
+            score.signum() as i8
+        })
+        .collect();
+    (sites, haplotypes)
+}
+
+// the heterozygous sites of the alignment, the haplotype of each row (None for unassigned rows) and the number
+// of haplotypes, the rows split into at most `ploidy` haplotypes by their alleles at the sites
+fn cluster_rows(msa: &Msa, options: &PhaseOptions) -> (Vec<PhasedSite>, Vec<Option<usize>>, usize) {
+    let spans = row_spans(msa);
+    let columns = heterozygous_columns(msa, &spans, options);
+    if columns.is_empty() {
+        return (vec![], vec![Some(0); msa.num_rows()], 1);
+    }
+
+    // the allele of each row at each site, None where the row does not reach or carries another base
+    let signatures: Vec<Vec<Option<u8>>> = msa
+        .rows
+        .iter()
+        .zip(spans.iter())
+        .map(|(row, &span)| {
+            columns
+                .iter()
+                .map(|(col, bases)| Some(row[*col]).filter(|b| covers(span, *col) && bases.contains(b)))
+                .collect()
+        })
+        .collect();
+
+    // haplotypes are seeded by the alleles carried over all sites by enough reads
+    let min_reads = (options.min_allele_fraction * msa.num_rows() as f64).ceil().max(1.0) as usize;
+    let mut counts: HashMap<&[Option<u8>], usize> = HashMap::new();
+    for signature in signatures.iter().filter(|s| s.iter().all(|b| b.is_some())) {
+        *counts.entry(signature).or_insert(0) += 1;
+    }
+    let mut seeds: Vec<(&[Option<u8>], usize)> = counts.into_iter().filter(|&(_, n)| n >= min_reads).collect();
+    seeds.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    seeds.truncate(options.ploidy);
+    if seeds.is_empty() {
+        return (vec![], vec![Some(0); msa.num_rows()], 1);
+    }
+
+    // every row covering a site joins the seed it differs least from, unless several seeds are as close
+    let haplotypes: Vec<Option<usize>> = signatures
+        .iter()
+        .map(|signature| {
+            if signature.iter().all(|b| b.is_none()) {
+                return None;
+            }
+            let distances: Vec<usize> = seeds
+                .iter()
+                .map(|(seed, _)| seed.iter().zip(signature.iter()).filter(|(s, b)| b.is_some() && s != b).count())
+                .collect();
+            let best = *distances.iter().min().unwrap();
+            match distances.iter().filter(|&&d| d == best).count() {
+                1 => distances.iter().position(|&d| d == best),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let sites = columns
+        .iter()
+        .enumerate()
+        .map(|(s, (column, _))| {
+            let (mut covering, mut discordant) = (0, 0);
+            for (signature, haplotype) in signatures.iter().zip(haplotypes.iter()) {
+                if let (Some(allele), Some(h)) = (signature[s], haplotype) {
+                    covering += 1;
+                    discordant += (seeds[*h].0[s] != Some(allele)) as usize;
+                }
+            }
+            PhasedSite {
+                column: *column,
+                alleles: seeds.iter().map(|(seed, _)| seed[s].unwrap()).collect(),
+                block: 0,
+                switch_error: if covering == 0 { 0.0 } else { discordant as f64 / covering as f64 },
+            }
+        })
+        .collect();
+    (sites, haplotypes, seeds.len())
+}
+
+/// Generates the consensuses of the haplotypes of a diploid or polyploid set of reads, phasing heterozygous
+/// columns by the reads spanning several of them instead of calling each column on its own.
+///
+/// All reads are aligned into one graph. The columns where a second base reaches `min_allele_fraction` of
+/// the covering reads are heterozygous sites. With a `ploidy` of 2, sites are phased in order: the reads
+/// linking a site to the previous sites of its phase block vote for the phase most consistent with their
+/// alleles, the minority of the votes estimating the switch error at the site; a site no read links starts a
+/// new block. Every read then joins the haplotype it shares most alleles with and each haplotype is realigned
+/// on its own; the haplotypes of different blocks are paired arbitrarily. With a higher `ploidy`, the
+/// combinations of alleles carried over all the sites by at least `min_allele_fraction` of the reads seed up
+/// to `ploidy` haplotypes instead, and every read joins the seed it shares most alleles with. Without
+/// heterozygous sites, all `ploidy` haplotypes are the consensus of all the reads.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to phase
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - the minimum allele fraction and depth of the heterozygous sites, and the ploidy
+///
+/// # Returns
+/// * returns the haplotype consensuses, the reads of each and the phased sites
+pub fn poa_phased(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &PhaseOptions,
+) -> PhasedConsensus {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let msa = crate::msa_with_params(seqs, quals, None, params);
+    let (sites, haplotypes, num_haplotypes) = if options.ploidy == 2 {
+        let (sites, haplotypes) = phase_rows(&msa, options);
+        let haplotypes = haplotypes
+            .into_iter()
+            .map(|haplotype| match haplotype {
+                1 => Some(0),
+                -1 => Some(1),
+                _ => None,
+            })
+            .collect();
+        (sites, haplotypes, 2)
+    } else {
+        cluster_rows(&msa, options)
+    };
+    if sites.is_empty() {
+        let ploidy = options.ploidy.max(1);
+        let result = crate::poa(seqs, quals, params);
+        let reads: Vec<usize> = (0..seqs.len()).collect();
+        return PhasedConsensus {
+            haplotypes: vec![result; ploidy],
+            reads: vec![reads; ploidy],
+            unassigned: vec![],
+            sites,
+        };
+    }
+    let members =
+        |haplotype: Option<usize>| -> Vec<usize> { (0..seqs.len()).filter(|&i| haplotypes[i] == haplotype).collect() };
+    let reads: Vec<Vec<usize>> = (0..num_haplotypes).map(|h| members(Some(h))).collect();
+    let haplotype_results = reads
+        .iter()
+        .map(|reads| {
+            let hap_seqs: Vec<Vec<u8>> = reads.iter().map(|&i| seqs[i].clone()).collect();
+            let hap_quals: Vec<Vec<u8>> = reads.iter().map(|&i| quals[i].clone()).collect();
+            crate::poa(&hap_seqs, &hap_quals, params)
+        })
+        .collect();
+    PhasedConsensus {
+        haplotypes: haplotype_results,
+        reads,
+        unassigned: members(None),
+        sites,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_rows() {
+        let rows = ["ACGTA", "ACGTA", "ACGTA", "AGGAA", "AGGAA", "AGGAA", "ACGAA", "--GA-"];
+        let msa = Msa {
+            names: (0..rows.len()).map(|i| i.to_string()).collect(),
+            rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+            consensus: b"ACGAA".to_vec(),
+        };
+
+        let (sites, haplotypes) = phase_rows(&msa, &PhaseOptions::default());
+
+        assert_eq!(sites.len(), 2);
+        assert_eq!((sites[0].column, &sites[0].alleles[..], sites[0].block), (1, &b"CG"[..], 0));
+        // the recombinant read is the only one linking the two sites in cis
+        assert_eq!((sites[1].column, &sites[1].alleles[..], sites[1].block), (3, &b"TA"[..], 0));
+        assert!((sites[1].switch_error - 1.0 / 7.0).abs() < 1e-9);
+        // the recombinant read carries one allele of each haplotype, the last read covers a single site
+        assert_eq!(haplotypes, vec![1, 1, 1, -1, -1, -1, 0, -1]);
+
+        let phased = PhasedConsensus {
+            sites,
+            ..Default::default()
+        };
+        assert!((phased.switch_error_rate() - 1.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cluster_rows() {
+        let rows = [
+            "ACGTA", "ACGTA", "ACGTA", "AGGAA", "AGGAA", "AGGAA", "ATGCA", "ATGCA", "ATGCA", "-CG--", "AGGTA",
+        ];
+        let msa = Msa {
+            names: (0..rows.len()).map(|i| i.to_string()).collect(),
+            rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+            consensus: b"ACGTA".to_vec(),
+        };
+        let options = PhaseOptions {
+            ploidy: 3,
+            ..PhaseOptions::default()
+        };
+
+        let (sites, haplotypes, num_haplotypes) = cluster_rows(&msa, &options);
+
+        assert_eq!(num_haplotypes, 3);
+        assert_eq!(sites.iter().map(|site| site.column).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(sites[0].alleles, b"CGT".to_vec());
+        assert_eq!(sites[1].alleles, b"TAC".to_vec());
+        assert!(sites.iter().all(|site| site.block == 0 && site.switch_error == 0.0));
+        // the partial read covers a single site; the recombinant read is as close to two haplotypes
+        let expected = vec![0, 0, 0, 1, 1, 1, 2, 2, 2].into_iter().map(Some).chain(vec![Some(0), None]);
+        assert_eq!(haplotypes, expected.collect::<Vec<_>>());
+
+        // a ploidy of 1 has no heterozygous sites
+        let options = PhaseOptions {
+            ploidy: 1,
+            ..PhaseOptions::default()
+        };
+        assert_eq!(cluster_rows(&msa, &options), (vec![], vec![Some(0); rows.len()], 1));
+    }
+
+    #[test]
+    fn test_phase_blocks() {
+        // no read spans both sites
+        let rows = ["AC---", "AC---", "AG---", "AG---", "---TA", "---TA", "---AA", "---AA"];
+        let msa = Msa {
+            names: (0..rows.len()).map(|i| i.to_string()).collect(),
+            rows: rows.iter().map(|row| row.as_bytes().to_vec()).collect(),
+            consensus: b"ACGAA".to_vec(),
+        };
+
+        let (sites, _) = phase_rows(&msa, &PhaseOptions::default());
+
+        assert_eq!(sites.iter().map(|site| site.block).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(sites[1].switch_error, 0.0);
+    }
+}
@@ -1,5 +1,404 @@
 #include <string.h>
+#include <algorithm>
+#include <limits>
 #include "spoa/spoa.hpp"
+#include "sisd_alignment_engine.hpp"
+
+// the gap model spoa::AlignmentEngine::Create selects for the gap scores
+static spoa::AlignmentSubtype gap_subtype(int g, int e, int q, int c) {
+    return g >= e ? spoa::AlignmentSubtype::kLinear :
+        (g <= q || e >= c ? spoa::AlignmentSubtype::kAffine : spoa::AlignmentSubtype::kConvex);
+}
+
+// create the alignment engine, either the SIMD engine chosen by SPOA for the host CPU or, in
+// deterministic mode, the scalar engine whose results do not depend on the instruction set
+static std::unique_ptr<spoa::AlignmentEngine> create_engine(int l, int m, int n, int g, int e, int q, int c,
+    int deterministic) {
+
+    if (!deterministic) {
+        return spoa::AlignmentEngine::Create(static_cast<spoa::AlignmentType>(l), // Alignment mode
+                                             (int8_t) m, // match
+                                             (int8_t) n, // mismatch
+                                             (int8_t) g, // gap open
+                                             (int8_t) e, // gap extension
+                                             (int8_t) q, // second gap open
+                                             (int8_t) c // second gap extension
+                                             );
+    }
+
+    auto subtype = gap_subtype(g, e, q, c);
+    if (subtype == spoa::AlignmentSubtype::kLinear) {
+        e = g;
+    } else if (subtype == spoa::AlignmentSubtype::kAffine) {
+        q = g;
+        c = e;
+    }
+    return spoa::SisdAlignmentEngine::Create(static_cast<spoa::AlignmentType>(l), subtype,
+                                             (int8_t) m, (int8_t) n, (int8_t) g, (int8_t) e, (int8_t) q, (int8_t) c);
+}
+
+using GapFunctions = std::vector<std::pair<std::int32_t, std::int32_t>>;
+
+// the (open, extend) score of each affine gap function of a gap model, as SPOA's engines apply them
+static GapFunctions gap_functions(spoa::AlignmentSubtype subtype, int g, int e, int q, int c) {
+    GapFunctions gaps;
+    if (subtype == spoa::AlignmentSubtype::kLinear) {
+        gaps.emplace_back(g, g);
+    } else {
+        gaps.emplace_back(g, e);
+    }
+    if (subtype == spoa::AlignmentSubtype::kConvex) {
+        gaps.emplace_back(q, c);
+    }
+    return gaps;
+}
+
+// the columns of the dynamic programming of a sequence against a graph: one per node in topological order,
+// after the virtual source, with the columns of the predecessors of each node (the source for nodes without)
+// and whether it is a sink
+struct GraphColumns {
+    std::vector<std::vector<std::size_t>> preds;
+    std::vector<bool> sinks;
+};
+
+static GraphColumns graph_columns(const spoa::Graph& graph) {
+    const auto& rank_to_node = graph.rank_to_node();
+    GraphColumns columns;
+    columns.preds.resize(rank_to_node.size() + 1);
+    columns.sinks.resize(rank_to_node.size() + 1, false);
+    std::vector<std::size_t> column_of(graph.nodes().size());
+    for (std::size_t r = 0; r < rank_to_node.size(); ++r) {
+        column_of[rank_to_node[r]->id] = r + 1;
+    }
+    for (std::size_t col = 1; col < columns.preds.size(); ++col) {
+        const auto* node = rank_to_node[col - 1];
+        if (node->inedges.empty()) {
+            columns.preds[col].push_back(0);
+        }
+        for (const auto& edge: node->inedges) {
+            columns.preds[col].push_back(column_of[edge->tail->id]);
+        }
+        columns.sinks[col] = node->outedges.empty();
+    }
+    return columns;
+}
+
+// whether the best alignment may end at a cell: at a sink in the last row for global alignment, also anywhere
+// in the last row or at a sink for semi-global alignment, and anywhere past the first row for local alignment
+static bool end_cell(spoa::AlignmentType mode, bool last_row, bool sink, std::size_t i) {
+    return mode == spoa::AlignmentType::kNW ? last_row && sink :
+        (mode == spoa::AlignmentType::kOV ? last_row || sink : i > 0);
+}
+
+// a score no alignment reaches, low enough for gap scores to be added to it without overflow
+static const std::int32_t kNegativeInfinity = std::numeric_limits<std::int32_t>::min() / 4;
+
+// the score of the best alignment of a sequence against a graph by the dynamic programming of SPOA's scalar
+// engine, `substitution` scoring a base of the sequence against the base of a node, keeping only the current
+// and previous rows of each matrix and no traceback, so that scoring a sequence takes memory linear in the
+// size of the graph
+template <typename Substitution>
+static std::int32_t best_score(spoa::AlignmentType mode, const GapFunctions& gaps, const char* sequence,
+    std::uint32_t sequence_len, const spoa::Graph& graph, Substitution substitution) {
+
+    if (graph.nodes().empty() || sequence_len == 0) {
+        return 0;
+    }
+    const bool local = mode == spoa::AlignmentType::kSW;
+    const bool free_start = mode != spoa::AlignmentType::kNW;
+    const auto& rank_to_node = graph.rank_to_node();
+    const auto columns = graph_columns(graph);
+    const std::size_t width = columns.preds.size();
+
+    std::vector<std::int32_t> h(width, kNegativeInfinity), previous_h(width, kNegativeInfinity);
+    std::vector<std::vector<std::int32_t>> e(gaps.size(), std::vector<std::int32_t>(width, kNegativeInfinity));
+    std::vector<std::vector<std::int32_t>> f = e;
+    bool found = false;
+    std::int32_t best = 0;
+    for (std::size_t i = 0; i <= sequence_len; ++i) {
+        std::swap(h, previous_h);
+        for (std::size_t col = 0; col < width; ++col) {
+            if (i == 0 && col == 0) {
+                h[0] = 0;
+                continue;
+            }
+            std::int32_t cell = kNegativeInfinity;
+            for (std::size_t k = 0; k < gaps.size(); ++k) {
+                // the insertions of the previous row are overwritten in place, column by column
+                e[k][col] = i > 0 ? std::max(previous_h[col] + gaps[k].first, e[k][col] + gaps[k].second) :
+                    kNegativeInfinity;
+                f[k][col] = kNegativeInfinity;
+                if (col > 0) {
+                    for (auto p: columns.preds[col]) {
+                        f[k][col] = std::max(f[k][col], std::max(h[p] + gaps[k].first, f[k][p] + gaps[k].second));
+                    }
+                }
+                cell = std::max(cell, std::max(e[k][col], f[k][col]));
+            }
+            if (i > 0 && col > 0) {
+                std::int32_t s = substitution((unsigned char) sequence[i - 1],
+                    (unsigned char) graph.decoder(rank_to_node[col - 1]->code));
+                for (auto p: columns.preds[col]) {
+                    cell = std::max(cell, previous_h[p] + s);
+                }
+            }
+            h[col] = (free_start && (i == 0 || col == 0)) || (local && cell < 0) ? 0 : cell;
+            if (col > 0 && end_cell(mode, i == sequence_len, columns.sinks[col], i) && (!found || h[col] > best)) {
+                found = true;
+                best = h[col];
+            }
+        }
+    }
+    return best;
+}
+
+// an alignment engine scoring each pair of bases with a substitution matrix, which SPOA's engines cannot: the
+// dynamic programming of SPOA's scalar engine, of the sequence against every node of the graph in topological
+// order, with the same alignment modes and linear, affine or convex gap model, in 32-bit scores; the Rust side
+// checks before each alignment that its scores cannot overflow and that its matrices fit the size limit
+class MatrixAlignmentEngine : public spoa::AlignmentEngine {
+public:
+    MatrixAlignmentEngine(spoa::AlignmentType type, spoa::AlignmentSubtype subtype, int g, int e, int q, int c,
+        const int* scores)
+        : spoa::AlignmentEngine(type, subtype, 0, 0, (std::int8_t) g, (std::int8_t) e, (std::int8_t) q,
+              (std::int8_t) c),
+          mode_(type), scores_(scores, scores + 256 * 256) {
+
+        gaps_ = gap_functions(subtype, g, e, q, c);
+    }
+
+    using spoa::AlignmentEngine::Align;
+
+    void Prealloc(std::uint32_t, std::uint8_t) override {}
+
+    spoa::Alignment Align(const char* sequence, std::uint32_t sequence_len, const spoa::Graph& graph,
+        std::int32_t* score) override;
+
+    // the score of the best alignment, without traceback, see best_score
+    std::int32_t Score(const char* sequence, std::uint32_t sequence_len, const spoa::Graph& graph) const {
+        return best_score(mode_, gaps_, sequence, sequence_len, graph,
+            [this](unsigned char a, unsigned char b) { return (std::int32_t) scores_[a * 256 + b]; });
+    }
+
+private:
+    spoa::AlignmentType mode_;
+    // the score of each pair of bytes, the base of the sequence first
+    std::vector<int> scores_;
+    GapFunctions gaps_;
+};
+
+spoa::Alignment MatrixAlignmentEngine::Align(const char* sequence, std::uint32_t sequence_len,
+    const spoa::Graph& graph, std::int32_t* score) {
+
+    if (graph.nodes().empty() || sequence_len == 0) {
+        return spoa::Alignment();
+    }
+    const std::int32_t neg_inf = kNegativeInfinity;
+    const bool local = mode_ == spoa::AlignmentType::kSW;
+    const bool free_start = mode_ != spoa::AlignmentType::kNW;
+    const auto& rank_to_node = graph.rank_to_node();
+    const auto columns = graph_columns(graph);
+    const auto& preds = columns.preds;
+    const std::size_t width = preds.size();
+    auto substitution = [&](std::size_t i, std::size_t col) {
+        auto base = (unsigned char) sequence[i - 1];
+        return (std::int32_t) scores_[base * 256 + graph.decoder(rank_to_node[col - 1]->code)];
+    };
+
+    // the scores of each cell, and of the insertions (E) and deletions (F) of each gap function
+    const std::size_t size = (sequence_len + 1) * width;
+    std::vector<std::int32_t> h(size, neg_inf);
+    std::vector<std::vector<std::int32_t>> e(gaps_.size(), std::vector<std::int32_t>(size, neg_inf));
+    std::vector<std::vector<std::int32_t>> f(gaps_.size(), std::vector<std::int32_t>(size, neg_inf));
+    h[0] = 0;
+    for (std::size_t i = 0; i <= sequence_len; ++i) {
+        for (std::size_t col = 0; col < width; ++col) {
+            std::size_t at = i * width + col;
+            if (i == 0 && col == 0) {
+                continue;
+            }
+            std::int32_t best = neg_inf;
+            for (std::size_t k = 0; k < gaps_.size(); ++k) {
+                if (i > 0) {
+                    e[k][at] = std::max(h[at - width] + gaps_[k].first, e[k][at - width] + gaps_[k].second);
+                }
+                if (col > 0) {
+                    for (auto p: preds[col]) {
+                        std::size_t from = i * width + p;
+                        f[k][at] = std::max(f[k][at], std::max(h[from] + gaps_[k].first, f[k][from] + gaps_[k].second));
+                    }
+                }
+                best = std::max(best, std::max(e[k][at], f[k][at]));
+            }
+            if (i > 0 && col > 0) {
+                std::int32_t s = substitution(i, col);
+                for (auto p: preds[col]) {
+                    best = std::max(best, h[(i - 1) * width + p] + s);
+                }
+            }
+            h[at] = (free_start && (i == 0 || col == 0)) || (local && best < 0) ? 0 : best;
+        }
+    }
+
+    // the best end: a sink in the last row, also the last row or a sink for semi-global alignment, anywhere
+    // for local alignment
+    bool found = false;
+    std::size_t i = 0, col = 0;
+    for (std::size_t r = 0; r <= sequence_len; ++r) {
+        for (std::size_t c = 1; c < width; ++c) {
+            bool candidate = end_cell(mode_, r == sequence_len, columns.sinks[c], r);
+            if (candidate && (!found || h[r * width + c] > h[i * width + col])) {
+                found = true;
+                i = r;
+                col = c;
+            }
+        }
+    }
+    if (score != nullptr) {
+        *score = (std::int32_t) h[i * width + col];
+    }
+
+    // the traceback, from H (state -1) or the insertions or deletions of a gap function
+    spoa::Alignment alignment;
+    int state = -1;
+    bool deletion = false;
+    while (true) {
+        std::size_t at = i * width + col;
+        if (state < 0) {
+            if ((i == 0 && col == 0) || (free_start && (i == 0 || col == 0)) || (local && h[at] == 0)) {
+                break;
+            }
+            bool matched = false;
+            if (i > 0 && col > 0) {
+                std::int32_t s = substitution(i, col);
+                for (auto p: preds[col]) {
+                    if (h[(i - 1) * width + p] + s == h[at]) {
+                        alignment.emplace_back(rank_to_node[col - 1]->id, i - 1);
+                        --i;
+                        col = p;
+                        matched = true;
+                        break;
+                    }
+                }
+            }
+            if (matched) {
+                continue;
+            }
+            for (std::size_t k = 0; k < gaps_.size() && state < 0; ++k) {
+                if (e[k][at] == h[at]) {
+                    state = (int) k;
+                    deletion = false;
+                }
+            }
+            for (std::size_t k = 0; k < gaps_.size() && state < 0; ++k) {
+                if (f[k][at] == h[at]) {
+                    state = (int) k;
+                    deletion = true;
+                }
+            }
+            if (state < 0) {
+                break;
+            }
+        } else if (!deletion) {
+            alignment.emplace_back(-1, i - 1);
+            if (h[at - width] + gaps_[state].first == e[state][at]) {
+                state = -1;
+            }
+            --i;
+        } else {
+            alignment.emplace_back(rank_to_node[col - 1]->id, -1);
+            std::size_t next = col;
+            for (auto p: preds[col]) {
+                if (h[i * width + p] + gaps_[state].first == f[state][at]) {
+                    next = p;
+                    state = -1;
+                    break;
+                }
+            }
+            if (state >= 0) {
+                for (auto p: preds[col]) {
+                    if (f[state][i * width + p] + gaps_[state].second == f[state][at]) {
+                        next = p;
+                        break;
+                    }
+                }
+            }
+            if (next == col) {
+                break;
+            }
+            col = next;
+        }
+    }
+    std::reverse(alignment.begin(), alignment.end());
+    return alignment;
+}
+
+// align every sequence (weighted by its qualities, or by explicit per-base weights if given) into a fresh graph
+static spoa::Graph build_graph(char** seqs, char** quals, unsigned** weights, int num_seqs,
+    int l, int m, int n, int g, int e, int q, int c, int deterministic) {
+
+    // populate the list of sequences & qualities
+    std::vector<std::string> sequences;
+    std::vector<std::string> qualities;
+    for (int i = 0; i < num_seqs; i++){
+        sequences.push_back((std::string) seqs[i]);
+        if (quals != NULL) {
+            qualities.push_back((std::string) quals[i]);
+        }
+    }
+
+    auto alignment_engine = create_engine(l, m, n, g, e, q, c, deterministic);
+
+    spoa::Graph graph{};
+
+    // add each of the real sequences (e.g. noisy sequence reads) to the graph
+    // for (const auto& it: sequences) {
+    //     auto alignment = alignment_engine->Align(it, graph);
+    //     graph.AddAlignment(alignment, it);
+    // }
+    for (int i = 0; i < num_seqs; ++i) {
+        const auto& it = sequences[i];
+        auto alignment = alignment_engine->Align(it, graph);
+        if (weights != NULL) {
+            std::vector<std::uint32_t> we(weights[i], weights[i] + it.size());
+            graph.AddAlignment(alignment, it, we);
+        } else {
+            graph.AddAlignment(alignment, it, qualities[i]);
+        }
+    }
+
+    return graph;
+}
+
+// copy a std::string into a heap allocated, null-terminated buffer
+static char* copy_string(const std::string& str) {
+    char *c_str;
+    c_str = new char [str.size() + 1];
+    strcpy (c_str, str.c_str());
+    return c_str;
+}
+
+// an alignment engine together with the graph it aligns into, handed to Rust as an opaque pointer
+struct PoaGraph {
+    std::unique_ptr<spoa::AlignmentEngine> engine;
+    spoa::Graph graph;
+    // the alignment mode, gap functions and match and mismatch scores of the engine, to score sequences
+    // without it, or the engine scoring substitutions with a matrix
+    spoa::AlignmentType type;
+    GapFunctions gaps;
+    int m, n;
+    const MatrixAlignmentEngine* matrix = nullptr;
+};
+
+// copy a list of strings into a heap allocated array of null-terminated buffers
+static char** copy_rows(const std::vector<std::string>& strs, int* num_rows) {
+    char **rows = new char* [strs.size()];
+    for (size_t i = 0; i < strs.size(); ++i) {
+        rows[i] = copy_string(strs[i]);
+    }
+    *num_rows = (int) strs.size();
+    return rows;
+}
 
 extern "C" {
 
@@ -11,44 +410,235 @@ extern "C" {
             return (unsigned) 0;
         }
 
-        // populate the list of sequences & qualities
-        std::vector<std::string> sequences;
-        std::vector<std::string> qualities;
-        for (int i = 0; i < num_seqs; i++){
-            sequences.push_back((std::string) seqs[i]);
-            qualities.push_back((std::string) quals[i]);
+        auto graph = build_graph(seqs, quals, NULL, num_seqs, l, m, n, g, e, q, c, 0);
+
+        // generate the consensus sequence, assign it to the allocated memory block, and return the consensus length.
+        auto cns = graph.GenerateConsensus();
+
+        // copy consensus sequence
+        return copy_string(cns);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    char** poa_msa_func(char** seqs, char** quals, int num_seqs,
+        int l, int m, int n, int g, int e, int q, int c, int deterministic, int* num_rows) {
+
+        *num_rows = 0;
+        if (num_seqs == 0) {
+            return NULL;
         }
 
-        auto alignment_engine = spoa::AlignmentEngine::Create(static_cast<spoa::AlignmentType>(l), // Alignment mode
-                                                              (int8_t) m, // match
-                                                              (int8_t) n, // mismatch
-                                                              (int8_t) g, // gap open
-                                                              (int8_t) e, // gap extension
-                                                              (int8_t) q, // second gap open
-                                                              (int8_t) c // second gap extension
-                                                              );
+        auto graph = build_graph(seqs, quals, NULL, num_seqs, l, m, n, g, e, q, c, deterministic);
 
-        spoa::Graph graph{};
+        // one gapped row per input sequence, followed by the gapped consensus
+        auto msa = graph.GenerateMultipleSequenceAlignment(true);
 
-        // add each of the real sequences (e.g. noisy sequence reads) to the graph
-        // for (const auto& it: sequences) {
-        //     auto alignment = alignment_engine->Align(it, graph);
-        //     graph.AddAlignment(alignment, it);
-        // }
-        for (int i = 0; i < num_seqs; ++i) {
-            const auto& it = sequences[i];
-            const auto& qu = qualities[i];
-            auto alignment = alignment_engine->Align(it, graph);
-            graph.AddAlignment(alignment, it, qu);
+        return copy_rows(msa, num_rows);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    char** poa_msa_weighted_func(char** seqs, unsigned** weights, int num_seqs,
+        int l, int m, int n, int g, int e, int q, int c, int deterministic, int* num_rows) {
+
+        *num_rows = 0;
+        if (num_seqs == 0) {
+            return NULL;
         }
 
-        // generate the consensus sequence, assign it to the allocated memory block, and return the consensus length.
-        auto cns = graph.GenerateConsensus();
+        auto graph = build_graph(seqs, NULL, weights, num_seqs, l, m, n, g, e, q, c, deterministic);
+        return copy_rows(graph.GenerateMultipleSequenceAlignment(true), num_rows);
+    }
 
-        // copy consensus sequence
-        char *cons_str;
-        cons_str = new char [cns.size() + 1];
-        strcpy (cons_str, cns.c_str());
-        return cons_str;
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    char* poa_consensus_func(char** seqs, char** quals, int num_seqs,
+        int l, int m, int n, int g, int e, int q, int c, int deterministic) {
+
+        if (num_seqs == 0) {
+            return NULL;
+        }
+
+        auto graph = build_graph(seqs, quals, NULL, num_seqs, l, m, n, g, e, q, c, deterministic);
+        return copy_string(graph.GenerateConsensus());
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_free_rows(char** rows, int num_rows) {
+        for (int i = 0; i < num_rows; ++i) {
+            delete[] rows[i];
+        }
+        delete[] rows;
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_free_string(char* str) {
+        delete[] str;
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void* poa_graph_new(int l, int m, int n, int g, int e, int q, int c, int deterministic) {
+        auto poa_graph = new PoaGraph();
+        poa_graph->engine = create_engine(l, m, n, g, e, q, c, deterministic);
+        poa_graph->type = static_cast<spoa::AlignmentType>(l);
+        poa_graph->gaps = gap_functions(gap_subtype(g, e, q, c), g, e, q, c);
+        poa_graph->m = m;
+        poa_graph->n = n;
+        return poa_graph;
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void* poa_graph_new_matrix(int l, int g, int e, int q, int c, const int* scores) {
+        auto poa_graph = new PoaGraph();
+        auto engine = new MatrixAlignmentEngine(static_cast<spoa::AlignmentType>(l), gap_subtype(g, e, q, c), g, e,
+            q, c, scores);
+        poa_graph->engine = std::unique_ptr<spoa::AlignmentEngine>(engine);
+        poa_graph->matrix = engine;
+        return poa_graph;
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_free(void* graph) {
+        delete static_cast<PoaGraph*>(graph);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_clear(void* graph) {
+        static_cast<PoaGraph*>(graph)->graph.Clear();
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_add(void* graph, const char* seq, const char* qual) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        std::string sequence(seq);
+        std::string quality(qual);
+        auto alignment = poa_graph->engine->Align(sequence, poa_graph->graph);
+        poa_graph->graph.AddAlignment(alignment, sequence, quality);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_score(void* graph, const char* seq) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        auto len = (std::uint32_t) strlen(seq);
+        if (poa_graph->matrix != nullptr) {
+            return (int) poa_graph->matrix->Score(seq, len, poa_graph->graph);
+        }
+        int m = poa_graph->m, n = poa_graph->n;
+        return (int) best_score(poa_graph->type, poa_graph->gaps, seq, len, poa_graph->graph,
+            [m, n](unsigned char a, unsigned char b) { return (std::int32_t) (a == b ? m : n); });
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_align(void* graph, const char* seq, int* node_ids, int* seq_ids, int* score) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        std::int32_t alignment_score = 0;
+        auto alignment = poa_graph->engine->Align(seq, (std::uint32_t) strlen(seq), poa_graph->graph, &alignment_score);
+        for (size_t i = 0; i < alignment.size(); ++i) {
+            node_ids[i] = (int) alignment[i].first;
+            seq_ids[i] = (int) alignment[i].second;
+        }
+        *score = (int) alignment_score;
+        return (int) alignment.size();
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    char* poa_graph_consensus(void* graph) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        return copy_string(poa_graph->graph.GenerateConsensus());
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_consensus_into(void* graph, char* consensus) {
+        auto cns = static_cast<PoaGraph*>(graph)->graph.GenerateConsensus();
+        memcpy(consensus, cns.data(), cns.size());
+        return (int) cns.size();
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_consensus_path(void* graph, int* node_ids) {
+        auto& g = static_cast<PoaGraph*>(graph)->graph;
+        g.GenerateConsensus();
+        const auto& path = g.consensus();
+        for (size_t i = 0; i < path.size(); ++i) {
+            node_ids[i] = (int) path[i]->id;
+        }
+        return (int) path.size();
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    char** poa_graph_msa(void* graph, int* num_rows) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        return copy_rows(poa_graph->graph.GenerateMultipleSequenceAlignment(true), num_rows);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_add_alignment(void* graph, const char* seq, const char* qual, const int* node_ids) {
+        auto poa_graph = static_cast<PoaGraph*>(graph);
+        std::string sequence(seq);
+        std::string quality(qual);
+
+        spoa::Alignment alignment;
+        bool aligned = false;
+        for (size_t i = 0; i < sequence.size(); ++i) {
+            alignment.emplace_back(node_ids[i], (int32_t) i);
+            aligned |= node_ids[i] != -1;
+        }
+        if (!aligned) {
+            alignment.clear();
+        }
+        poa_graph->graph.AddAlignment(alignment, sequence, quality);
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_num_nodes(void* graph) {
+        return (int) static_cast<PoaGraph*>(graph)->graph.nodes().size();
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_num_edges(void* graph) {
+        int num_edges = 0;
+        for (const auto& node: static_cast<PoaGraph*>(graph)->graph.nodes()) {
+            num_edges += (int) node->outedges.size();
+        }
+        return num_edges;
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_topology(void* graph, char* labels, int* columns, int* tails, int* heads, long long* weights) {
+        auto& g = static_cast<PoaGraph*>(graph)->graph;
+        size_t edge = 0;
+        for (const auto& node: g.nodes()) {
+            labels[node->id] = (char) g.decoder(node->code);
+            columns[node->id] = (int) node->id;
+            for (const auto& it: node->aligned_nodes) {
+                columns[node->id] = std::min(columns[node->id], (int) it->id);
+            }
+            for (const auto& it: node->outedges) {
+                tails[edge] = (int) it->tail->id;
+                heads[edge] = (int) it->head->id;
+                weights[edge] = (long long) it->weight;
+                ++edge;
+            }
+        }
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    void poa_graph_path(void* graph, int seq_index, int* node_ids) {
+        auto& g = static_cast<PoaGraph*>(graph)->graph;
+        auto node = g.sequences()[seq_index];
+        for (size_t i = 0; node != nullptr; ++i) {
+            node_ids[i] = (int) node->id;
+            node = node->Successor(seq_index);
+        }
+    }
+
+    // see the C header file (poa_func.h) for detailed descriptions of each argument
+    int poa_graph_subgraph(void* graph, void* subgraph, int begin, int end, int* node_ids) {
+        auto& g = static_cast<PoaGraph*>(graph)->graph;
+        auto& s = static_cast<PoaGraph*>(subgraph)->graph;
+        std::vector<const spoa::Graph::Node*> subgraph_to_graph;
+        s = g.Subgraph((std::uint32_t) begin, (std::uint32_t) end, &subgraph_to_graph);
+        for (size_t i = 0; i < s.nodes().size(); ++i) {
+            node_ids[i] = (int) subgraph_to_graph[i]->id;
+        }
+        return (int) s.nodes().size();
     }
 }
@@ -16,6 +16,141 @@ unsigned poa_func(char** seqs,  // the sequences (null-terminated) to perform mu
                   int c,        // gap extension penalty of the second affine function (must be non-positivie), e.g. -1
                   );
 
+char** poa_msa_func(char** seqs,  // the sequences (null-terminated) to perform multiple-sequence-alignment with.
+                    char** quals, // the qualities (null-terminated) used to weight each sequence.
+                    int num_seqs, // the number of sequences being multiply aligned
+                    int l,        // alignment mode: 0 = local align, 1 = global align, 2 = semi-global
+                    int m,        // score for matching bases, e.g. 5
+                    int n,        // score for mismatching bases, e.g. -4
+                    int g,        // gap opening penalty (must be non-positive), e.g. -3
+                    int e,        // gap extension penalty (must be non-positive), e.g. -1
+                    int q,        // gap opening penalty of the second affine function (must be non-positive), e.g. -3
+                    int c,        // gap extension penalty of the second affine function (must be non-positive), e.g. -1
+                    int deterministic, // 1 = always use the scalar alignment engine, for results independent of the CPU
+                    int* num_rows // out: the number of returned rows (num_seqs gapped sequences followed by the gapped consensus)
+                    );
+
+char** poa_msa_weighted_func(char** seqs,      // the sequences (null-terminated) to perform multiple-sequence-alignment with.
+                             unsigned** weights, // the weight of each base of each sequence
+                             int num_seqs,  // the number of sequences being multiply aligned
+                             int l,         // alignment mode: 0 = local align, 1 = global align, 2 = semi-global
+                             int m,         // score for matching bases, e.g. 5
+                             int n,         // score for mismatching bases, e.g. -4
+                             int g,         // gap opening penalty (must be non-positive), e.g. -3
+                             int e,         // gap extension penalty (must be non-positive), e.g. -1
+                             int q,         // gap opening penalty of the second affine function (must be non-positive), e.g. -3
+                             int c,         // gap extension penalty of the second affine function (must be non-positive), e.g. -1
+                             int deterministic, // 1 = always use the scalar alignment engine, for results independent of the CPU
+                             int* num_rows  // out: the number of returned rows (num_seqs gapped sequences followed by the gapped consensus)
+                             );
+
+char* poa_consensus_func(char** seqs,  // the sequences (null-terminated) to form a consensus from.
+                         char** quals, // the qualities (null-terminated) used to weight each sequence.
+                         int num_seqs, // the number of sequences
+                         int l,        // alignment mode: 0 = local align, 1 = global align, 2 = semi-global
+                         int m,        // score for matching bases, e.g. 5
+                         int n,        // score for mismatching bases, e.g. -4
+                         int g,        // gap opening penalty (must be non-positive), e.g. -3
+                         int e,        // gap extension penalty (must be non-positive), e.g. -1
+                         int q,        // gap opening penalty of the second affine function (must be non-positive), e.g. -3
+                         int c,        // gap extension penalty of the second affine function (must be non-positive), e.g. -1
+                         int deterministic // 1 = always use the scalar alignment engine, for results independent of the CPU
+                         );  // returns the consensus, to be released with poa_free_string, or NULL without sequences
+
+void poa_free_rows(char** rows,   // rows returned by poa_msa_func or poa_msa_weighted_func
+                   int num_rows   // the number of rows
+                   );
+
+void poa_free_string(char* str);  // a string returned by one of the poa_graph functions
+
+void* poa_graph_new(int l,        // alignment mode: 0 = local align, 1 = global align, 2 = semi-global
+                    int m,        // score for matching bases, e.g. 5
+                    int n,        // score for mismatching bases, e.g. -4
+                    int g,        // gap opening penalty (must be non-positive), e.g. -3
+                    int e,        // gap extension penalty (must be non-positive), e.g. -1
+                    int q,        // gap opening penalty of the second affine function (must be non-positive), e.g. -3
+                    int c,        // gap extension penalty of the second affine function (must be non-positive), e.g. -1
+                    int deterministic // 1 = always use the scalar alignment engine
+                    );            // returns an empty graph, to be released with poa_graph_free
+
+void* poa_graph_new_matrix(int l,        // alignment mode: 0 = local align, 1 = global align, 2 = semi-global
+                           int g,        // gap opening penalty (must be non-positive), e.g. -3
+                           int e,        // gap extension penalty (must be non-positive), e.g. -1
+                           int q,        // gap opening penalty of the second affine function (must be non-positive)
+                           int c,        // gap extension penalty of the second affine function (must be non-positive)
+                           const int* scores // the score of each pair of bytes (256 x 256, the query byte first),
+                                             // copied by the engine
+                           );            // returns an empty graph scoring substitutions with the matrix, to be
+                                         // released with poa_graph_free
+
+void poa_graph_free(void* graph);
+
+void poa_graph_clear(void* graph);  // removes all sequences from the graph, keeping its alignment engine
+
+void poa_graph_add(void* graph,       // a graph returned by poa_graph_new
+                   const char* seq,   // the sequence (null-terminated) to align into the graph
+                   const char* qual   // the qualities (null-terminated) weighting the bases of the sequence
+                   );
+
+int poa_graph_score(void* graph,     // a graph returned by poa_graph_new
+                    const char* seq  // the sequence (null-terminated) to align against the graph, without adding it
+                    );               // returns the alignment score (0 for an empty graph), by a dynamic
+                                     // programming keeping two rows and no traceback
+
+int poa_graph_align(void* graph,     // a graph returned by poa_graph_new
+                    const char* seq, // the sequence (null-terminated) to align against the graph, without adding it
+                    int* node_ids,   // out: the node of each alignment step, or -1 for an insertion
+                                     // (room for poa_graph_num_nodes plus the sequence length values)
+                    int* seq_ids,    // out: the sequence position of each alignment step, or -1 for a deletion
+                    int* score       // out: the alignment score
+                    );               // returns the number of alignment steps
+
+char* poa_graph_consensus(void* graph);  // returns the consensus of the graph, to be released with poa_free_string
+
+int poa_graph_consensus_into(void* graph,     // a graph returned by poa_graph_new
+                             char* consensus  // out: the consensus, not null-terminated
+                                              // (room for poa_graph_num_nodes bytes)
+                             );               // returns the length of the consensus
+
+int poa_graph_consensus_path(void* graph,  // a graph returned by poa_graph_new
+                             int* node_ids  // out: the nodes spelling the consensus (at most poa_graph_num_nodes values)
+                             );             // returns the length of the consensus
+
+char** poa_graph_msa(void* graph,     // a graph returned by poa_graph_new
+                     int* num_rows    // out: the number of returned rows (one per sequence, followed by the consensus)
+                     );               // rows are released with poa_free_rows
+
+void poa_graph_add_alignment(void* graph,          // a graph returned by poa_graph_new
+                             const char* seq,      // the sequence (null-terminated) to add to the graph
+                             const char* qual,     // the qualities (null-terminated) weighting the bases of the sequence
+                             const int* node_ids   // the graph node of each base of the sequence, or -1 to create a new node.
+                                                   // a base differing from its node creates a new node aligned to it.
+                             );
+
+int poa_graph_num_nodes(void* graph);
+int poa_graph_num_edges(void* graph);
+
+void poa_graph_topology(void* graph,       // a graph returned by poa_graph_new
+                        char* labels,      // out: the base of each node (poa_graph_num_nodes values)
+                        int* columns,      // out: the smallest id among each node and the nodes aligned to it
+                        int* tails,        // out: the tail node of each edge (poa_graph_num_edges values)
+                        int* heads,        // out: the head node of each edge
+                        long long* weights // out: the weight of each edge
+                        );
+
+void poa_graph_path(void* graph,     // a graph returned by poa_graph_new
+                    int seq_index,   // the index of a sequence of the graph
+                    int* node_ids    // out: the node of each base of the sequence
+                    );
+
+int poa_graph_subgraph(void* graph,     // a graph returned by poa_graph_new
+                       void* subgraph,  // a graph returned by poa_graph_new, replaced by the subgraph
+                       int begin,       // the first node of the subgraph
+                       int end,         // the last node of the subgraph
+                       int* node_ids    // out: the node of `graph` of each node of the subgraph
+                                        // (at most poa_graph_num_nodes of `graph` values)
+                       );               // returns the number of nodes of the subgraph
+
 
 #ifdef __cplusplus
 }
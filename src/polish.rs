@@ -0,0 +1,292 @@
+//! Polishing of draft sequences by the consensus of the reads spanning each of their windows, as in Racon.
+use crate::dna::reverse_complement;
+use crate::msa::GAP;
+use crate::pairwise::null_terminated;
+use crate::params::{AlignmentParams, AlignmentType};
+
+/// Options of `poa_polish`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolishOptions {
+    /// The length of the windows of a draft, each polished on its own.
+    pub window_len: usize,
+    /// The minimum number of reads spanning a window for it to be polished; windows with fewer reads keep the
+    /// draft sequence.
+    pub min_coverage: usize,
+}
+
+impl Default for PolishOptions {
+    fn default() -> PolishOptions {
+        PolishOptions {
+            window_len: 500,
+            min_coverage: 2,
+        }
+    }
+}
+
+/// A polished draft sequence.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PolishedDraft {
+    /// The polished sequence.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub sequence: Vec<u8>,
+    /// The number of windows of the draft.
+    pub windows: usize,
+    /// The number of windows spanned by enough reads to be polished.
+    pub polished_windows: usize,
+}
+
+// the sequences and qualities of read segments, null terminated
+type Segments = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+// the draft, strand, CIGAR operations and number of matching bases of the alignment of a read
+type Overlap = (usize, bool, Vec<(u8, usize)>, usize);
+
+// the read segments spanning each window of a draft
+pub(crate) struct DraftWindows {
+    len: usize,
+    window_len: usize,
+    segments: Vec<Segments>,
+}
+
+impl DraftWindows {
+    pub(crate) fn new(len: usize, window_len: usize) -> DraftWindows {
+        let window_len = window_len.max(1);
+        DraftWindows {
+            len,
+            window_len,
+            segments: vec![(vec![], vec![]); (len + window_len - 1) / window_len],
+        }
+    }
+
+    // the draft position at which window `k` starts, or the draft length past the last window
+    fn boundary(&self, k: usize) -> usize {
+        (k * self.window_len).min(self.len)
+    }
+
+    // adds the segments of a read aligned from the 0-based draft position `start` by the given CIGAR operations
+    // to the windows it spans from end to end; insertions at a window boundary belong to the window before it
+    pub(crate) fn add_read<I: IntoIterator<Item = (u8, usize)>>(
+        &mut self,
+        start: usize,
+        ops: I,
+        seq: &[u8],
+        qual: &[u8],
+    ) {
+        let num_windows = self.segments.len();
+        // the read position at each window boundary
+        let mut read_at = vec![None; num_windows + 1];
+        let mut next = (start + self.window_len - 1) / self.window_len;
+        let (mut ref_pos, mut read_pos) = (start, 0);
+        // the draft span of the aligned read bases, and the read position past the last of them
+        let (mut first, mut last, mut read_end) = (None, 0, 0);
+        for (op, len) in ops {
+            match op {
+                b'M' | b'=' | b'X' | b'D' | b'N' => {
+                    for _ in 0..len {
+                        while next <= num_windows && self.boundary(next) == ref_pos {
+                            read_at[next] = Some(read_pos);
+                            next += 1;
+                        }
+                        if op != b'D' && op != b'N' {
+                            first.get_or_insert(ref_pos);
+                            last = ref_pos + 1;
+                            read_pos += 1;
+                            read_end = read_pos;
+                        }
+                        ref_pos += 1;
+                    }
+                }
+                b'I' | b'S' => read_pos += len,
+                _ => {}
+            }
+        }
+        let first = match first {
+            Some(first) => first,
+            None => return,
+        };
+        // the window ending with the read ends with its last aligned base, without the clipped bases after it
+        if let Some(k) = (0..=num_windows).find(|&k| self.boundary(k) == last) {
+            read_at[k] = Some(read_end);
+        }
+        for k in 0..num_windows {
+            if first > self.boundary(k) || self.boundary(k + 1) > last {
+                continue;
+            }
+            if let (Some(from), Some(to)) = (read_at[k], read_at[k + 1]) {
+                if from < to && to <= seq.len() && to <= qual.len() {
+                    self.segments[k].0.push(null_terminated(&seq[from..to]));
+                    self.segments[k].1.push(null_terminated(&qual[from..to]));
+                }
+            }
+        }
+    }
+
+    // the draft, each window spanned by enough reads replaced by the consensus of its reads and draft sequence
+    pub(crate) fn polish(self, draft: &[u8], params: &AlignmentParams, options: &PolishOptions) -> PolishedDraft {
+        let bounds: Vec<usize> = (0..=self.segments.len()).map(|k| self.boundary(k)).collect();
+        let mut polished = PolishedDraft {
+            windows: self.segments.len(),
+            ..Default::default()
+        };
+        for (k, (mut seqs, mut quals)) in self.segments.into_iter().enumerate() {
+            let window = &draft[bounds[k]..bounds[k + 1]];
+            if seqs.len() < options.min_coverage.max(1) {
+                polished.sequence.extend_from_slice(window);
+                continue;
+            }
+            // the draft seeds the graph of the window, weighing as one read of the lowest quality
+            seqs.insert(0, null_terminated(window));
+            quals.insert(0, null_terminated(&vec![b'"'; window.len()]));
+            let result = crate::poa(&seqs, &quals, params);
+            polished.sequence.extend_from_slice(&result.consensus);
+            polished.polished_windows += 1;
+        }
+        polished
+    }
+}
+
+// the CIGAR operations of a read aligned to a draft with free end gaps, one per alignment column, and the
+// number of matching bases
+fn overlap_ops(draft: &[u8], read: &[u8], params: &AlignmentParams) -> (Vec<(u8, usize)>, usize) {
+    if draft.is_empty() || read.is_empty() {
+        return (vec![], 0);
+    }
+    let seqs = vec![null_terminated(draft), null_terminated(read)];
+    let quals: Vec<Vec<u8>> = seqs
+        .iter()
+        .map(|s| s.iter().map(|&c| if c == 0 { 0 } else { b'F' }).collect())
+        .collect();
+    let params = AlignmentParams {
+        alignment_type: AlignmentType::SemiGlobal,
+        ..*params
+    };
+    let msa = crate::msa_with_params(&seqs, &quals, None, &params);
+    if msa.rows.len() != 2 {
+        return (vec![], 0);
+    }
+    let (mut ops, mut matches) = (vec![], 0);
+    for (&d, &r) in msa.rows[0].iter().zip(msa.rows[1].iter()) {
+        match (d != GAP, r != GAP) {
+            (true, true) => {
+                ops.push((b'M', 1));
+                matches += (d == r) as usize;
+            }
+            (true, false) => ops.push((b'D', 1)),
+            (false, true) => ops.push((b'I', 1)),
+            (false, false) => {}
+        }
+    }
+    (ops, matches)
+}
+
+/// Polishes draft sequences, e.g. the contigs of an assembly, with the reads they were assembled from.
+///
+/// Each read is aligned with free end gaps, on both strands, to every draft and placed on the draft and strand
+/// it matches most bases of; reads matching fewer than half of their bases are left out. The drafts are cut
+/// into windows of `window_len` bases, and each window spanned from end to end by at least `min_coverage`
+/// reads is replaced by the consensus of the draft window and of the read segments aligned to it. Aligning
+/// every read to every draft is quadratic in their lengths, which suits amplicons and short contigs; reads
+/// already mapped to an assembly are better placed by their mappings with `poa_polish_bam`.
+/// # Arguments
+///
+/// * `drafts` - the draft sequences, not null-terminated
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) of the reads
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the reads
+/// * `params` - the alignment mode and scores of the consensus of each window
+/// * `options` - the window length and minimum coverage
+///
+/// # Returns
+/// * returns the polished drafts, in input order
+pub fn poa_polish(
+    drafts: &[&[u8]],
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &PolishOptions,
+) -> Vec<PolishedDraft> {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let mut windows: Vec<DraftWindows> = drafts
+        .iter()
+        .map(|draft| DraftWindows::new(draft.len(), options.window_len))
+        .collect();
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        // the sequences are null terminated
+        let seq = &seq[..seq.len().saturating_sub(1)];
+        let qual = &qual[..qual.len().saturating_sub(1)];
+        let rc_seq = reverse_complement(seq);
+        let rc_qual: Vec<u8> = qual.iter().rev().cloned().collect();
+        // the draft, strand and alignment of the read matching most of its bases
+        let mut best: Option<Overlap> = None;
+        for (i, draft) in drafts.iter().enumerate() {
+            for &reverse in &[false, true] {
+                let read = if reverse { &rc_seq[..] } else { seq };
+                let (ops, matches) = overlap_ops(draft, read, params);
+                if best.as_ref().map_or(true, |best| matches > best.3) {
+                    best = Some((i, reverse, ops, matches));
+                }
+            }
+        }
+        match best {
+            Some((i, false, ops, matches)) if 2 * matches >= seq.len() => windows[i].add_read(0, ops, seq, qual),
+            Some((i, true, ops, matches)) if 2 * matches >= seq.len() => windows[i].add_read(0, ops, &rc_seq, &rc_qual),
+            _ => {}
+        }
+    }
+    windows
+        .into_iter()
+        .zip(drafts.iter())
+        .map(|(windows, draft)| windows.polish(draft, params, options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_read() {
+        let mut windows = DraftWindows::new(10, 4);
+        // 2 clipped read bases, then the draft bases 2..8 with an insertion after the draft base 3 and a
+        // deletion of the draft base 5, then 2 clipped bases
+        let seq = b"xxABiCDEyy";
+        let qual = b"0123456789";
+        let ops = vec![(b'S', 2), (b'M', 2), (b'I', 1), (b'M', 1), (b'D', 1), (b'M', 2), (b'S', 2)];
+        windows.add_read(2, ops, seq, qual);
+
+        // only the window 4..8 is spanned from end to end
+        assert!(windows.segments[0].0.is_empty());
+        assert_eq!(windows.segments[1].0, vec![b"CDE\0".to_vec()]);
+        assert_eq!(windows.segments[1].1, vec![b"567\0".to_vec()]);
+        assert!(windows.segments[2].0.is_empty());
+
+        // a read spanning the whole draft, ending at its last base without the bases clipped after it
+        let mut windows = DraftWindows::new(10, 4);
+        windows.add_read(
+            0,
+            vec![(b'M', 3), (b'I', 1), (b'M', 7), (b'S', 1)],
+            b"ABCiDEFGHIJz",
+            b"............",
+        );
+        let segments: Vec<&[u8]> = windows.segments.iter().map(|s| &s.0[0][..]).collect();
+        assert_eq!(segments, vec![&b"ABCiD\0"[..], b"EFGH\0", b"IJ\0"]);
+    }
+
+    #[test]
+    fn test_polish_windows() {
+        let draft = b"ACGTACGTAC";
+        let mut windows = DraftWindows::new(draft.len(), 4);
+        windows.add_read(0, vec![(b'M', 10)], draft, b"FFFFFFFFFF");
+        let options = PolishOptions {
+            window_len: 4,
+            min_coverage: 2,
+        };
+
+        // windows with fewer reads than the minimum coverage keep the draft
+        let polished = windows.polish(draft, &AlignmentParams::default(), &options);
+        assert_eq!(polished.sequence, draft.to_vec());
+        assert_eq!((polished.windows, polished.polished_windows), (3, 0));
+    }
+}
@@ -0,0 +1,263 @@
+//! A pool of consensus workers fed and drained over channels, for streaming pipelines.
+use std::thread::JoinHandle;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+#[cfg(feature = "affinity")]
+use crate::affinity::Affinity;
+use crate::batch::{check_reads, panicked, Cluster, ClusterError, ClusterResult};
+use crate::error::Error;
+use crate::graph::PoaGraph;
+use crate::params::AlignmentParams;
+use crate::scheduler::{pin_worker, worker_threads};
+
+/// A pool of worker threads generating the consensus of the clusters sent to it.
+///
+/// Each worker reuses a single graph and alignment engine for all the clusters it processes. Clusters are
+/// sent over a bounded input channel and results are received, in completion order, over a bounded output
+/// channel, a cluster whose reads the alignment engine rejects, see `PoaGraph::try_add`, or whose consensus
+/// panics failing on its own with a `ClusterError` without stopping its worker: producers block while `capacity` clusters are waiting, and workers block while `capacity`
+/// results are not received, so that neither side outruns the other. Results must thus be received on
+/// another thread than the one sending the clusters, or `finish` be called once all clusters are sent.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{AlignmentParams, Cluster, ConsensusPool};
+///
+///     let pool = ConsensusPool::new(&AlignmentParams::default(), 2, 16);
+///     let input = pool.input();
+///     std::thread::spawn(move || {
+///         let cluster = Cluster {
+///             id: "umi_1".to_string(),
+///             seqs: vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()],
+///             quals: vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()],
+///             metadata: Default::default(),
+///         };
+///         input.send(cluster).unwrap();
+///     });
+///     let results = pool.finish();
+/// ```
+pub struct ConsensusPool {
+    input: Option<PoolInput>,
+    output: Receiver<Result<ClusterResult, ClusterError>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ConsensusPool {
+    /// Starts a pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - the alignment mode and scores, shared by all clusters
+    /// * `threads` - the number of worker threads, or 0 to use the available parallelism
+    /// * `capacity` - the number of clusters, and of results, the channels hold before blocking their senders
+    ///
+    /// Panics if a gap score is positive, like `PoaGraph::new`.
+    pub fn new(params: &AlignmentParams, threads: usize, capacity: usize) -> ConsensusPool {
+        ConsensusPool::start(params, vec![None; worker_threads(threads)], capacity)
+    }
+
+    /// Starts a pool like `new`, its workers placed on the CPUs of the host by `affinity`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{Affinity, AlignmentParams, ConsensusPool};
+    ///
+    ///     // one worker per CPU, spread over the NUMA nodes
+    ///     let pool = ConsensusPool::with_affinity(&AlignmentParams::default(), 0, 16, &Affinity::NumaNodes);
+    ///     let results = pool.finish();
+    /// ```
+    #[cfg(feature = "affinity")]
+    pub fn with_affinity(
+        params: &AlignmentParams,
+        threads: usize,
+        capacity: usize,
+        affinity: &Affinity,
+    ) -> ConsensusPool {
+        ConsensusPool::start(params, affinity.worker_cpus(worker_threads(threads)), capacity)
+    }
+
+    // starts a worker for each entry of `worker_cpus`, pinned to its CPUs if any
+    fn start(params: &AlignmentParams, worker_cpus: Vec<Option<Vec<usize>>>, capacity: usize) -> ConsensusPool {
+        // the workers would panic on their graphs otherwise
+        crate::cpu::assert_cpu();
+        params.check_engine_scores();
+        let (input, clusters) = bounded::<Cluster>(capacity);
+        let (results, output) = bounded::<Result<ClusterResult, ClusterError>>(capacity);
+        let workers = worker_cpus
+            .into_iter()
+            .enumerate()
+            .map(|(worker, cpus)| {
+                let (clusters, results, params) = (clusters.clone(), results.clone(), *params);
+                std::thread::spawn(move || {
+                    // the graph and buffers of a pinned worker are allocated on its own CPUs
+                    pin_worker(worker, cpus.as_ref());
+                    let mut graph = PoaGraph::new(&params);
+                    // the reads of the clusters were checked by `PoolInput::send`
+                    for cluster in clusters.iter() {
+                        graph.clear();
+                        let consensus = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            for (seq, qual) in cluster.seqs.iter().zip(cluster.quals.iter()) {
+                                graph.try_add(&seq[..seq.len() - 1], &qual[..qual.len() - 1])?;
+                            }
+                            Ok(graph.result())
+                        }));
+                        let result = match consensus {
+                            Ok(Ok(result)) => Ok(ClusterResult::new(&cluster, result)),
+                            Ok(Err(error)) => Err(ClusterError {
+                                id: cluster.id.clone(),
+                                error,
+                            }),
+                            Err(payload) => {
+                                // the graph may be left inconsistent by the panic
+                                graph = PoaGraph::new(&params);
+                                Err(ClusterError {
+                                    id: cluster.id.clone(),
+                                    error: panicked(payload),
+                                })
+                            }
+                        };
+                        // the receiving side is gone, nothing is left to do
+                        if results.send(result).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        ConsensusPool {
+            input: Some(PoolInput { sender: input }),
+            output,
+            workers,
+        }
+    }
+
+    /// Returns a sender of clusters to the pool, to be moved to a producer thread. The pool stops once
+    /// `finish` was called and all the senders are dropped.
+    pub fn input(&self) -> PoolInput {
+        self.input.clone().expect("the input of a running pool is open")
+    }
+
+    /// Returns a receiver of the results of the pool, to be moved to a consumer thread. Its iterator ends
+    /// once the pool stopped and all the results were received.
+    pub fn output(&self) -> Receiver<Result<ClusterResult, ClusterError>> {
+        self.output.clone()
+    }
+
+    /// Closes the input of the pool and waits for its workers, returning the results not received yet, in
+    /// completion order.
+    pub fn finish(mut self) -> Vec<Result<ClusterResult, ClusterError>> {
+        drop(self.input.take());
+        // the results must be drained for blocked workers to complete
+        let results: Vec<_> = self.output.iter().collect();
+        for worker in self.workers.drain(..) {
+            if let Err(panic) = worker.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+        results
+    }
+}
+
+/// A sender of clusters to a `ConsensusPool`, see `ConsensusPool::input`.
+#[derive(Clone)]
+pub struct PoolInput {
+    sender: Sender<Cluster>,
+}
+
+impl PoolInput {
+    /// Sends a cluster to the pool, blocking while the input channel is full.
+    ///
+    /// The reads are checked before the cluster is queued: reads that are not null-terminated sequences and
+    /// Phred+33 qualities of the same length fail with `Error::InvalidInput`, and the cluster is not sent.
+    /// Sending to a pool whose workers have stopped, e.g. after one of them panicked, fails with
+    /// `Error::Panicked`.
+    pub fn send(&self, cluster: Cluster) -> Result<(), ClusterError> {
+        if let Err(error) = check_reads(&cluster.seqs, &cluster.quals) {
+            return Err(ClusterError { id: cluster.id, error });
+        }
+        self.sender.send(cluster).map_err(|failed| ClusterError {
+            id: failed.0.id,
+            error: Error::Panicked {
+                message: "the workers of the pool have stopped".to_string(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(id: usize, seqs: &[&str]) -> Cluster {
+        crate::test_cluster(&format!("cluster_{}", id), seqs)
+    }
+
+    #[test]
+    fn test_consensus_pool() {
+        let pool = ConsensusPool::new(&AlignmentParams::default(), 2, 1);
+        let input = pool.input();
+        let producer = std::thread::spawn(move || {
+            for i in 0..6 {
+                input.send(cluster(i, &["AATGCCCGTT", "AATGCCGTT", "AATGCCCGTT"])).unwrap();
+            }
+        });
+        let output = pool.output();
+        let consumer = std::thread::spawn(move || output.iter().take(2).count());
+
+        assert_eq!(consumer.join().unwrap(), 2);
+        producer.join().unwrap();
+        let mut results = pool.finish();
+
+        assert_eq!(results.len(), 4);
+        results.sort_by(|a, b| a.as_ref().unwrap().id.cmp(&b.as_ref().unwrap().id));
+        assert!(results.iter().all(|result| result.as_ref().unwrap().result.consensus == b"AATGCCCGTT".to_vec()));
+    }
+
+    #[test]
+    fn test_pool_cluster_failure() {
+        let params = AlignmentParams {
+            match_score: 200,
+            ..AlignmentParams::default()
+        };
+        let pool = ConsensusPool::new(&params, 1, 4);
+        let input = pool.input();
+        for i in 0..2 {
+            input.send(cluster(i, &["AATGCCCGTT", "AATGCCGTT"])).unwrap();
+        }
+        drop(input);
+
+        // each cluster fails on its own, the worker going on with the next
+        let results = pool.finish();
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(ClusterError { error: Error::ScoreOutOfRange { .. }, .. }))));
+    }
+
+    #[test]
+    fn test_pool_input() {
+        let pool = ConsensusPool::new(&AlignmentParams::default(), 1, 4);
+        let input = pool.input();
+        let mut unterminated = cluster(0, &["AATGCCCGTT"]);
+        unterminated.seqs[0].pop();
+        let empty = Cluster {
+            seqs: vec![vec![]],
+            quals: vec![vec![]],
+            ..cluster(1, &[])
+        };
+        let mut mismatched = cluster(2, &["AATGCCCGTT"]);
+        mismatched.quals.clear();
+
+        for invalid in [unterminated, empty, mismatched] {
+            let id = invalid.id.clone();
+            let failure = input.send(invalid).unwrap_err();
+            assert_eq!(failure.id, id);
+            assert!(matches!(failure.error, Error::InvalidInput { .. }));
+        }
+        drop(input);
+        assert!(pool.finish().is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Wavefront pre-alignment of reads against a provisional consensus, ordering the reads and dropping outliers
+//! before the partial order alignment.
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// How reads are screened before the partial order alignment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrealignOptions {
+    /// The maximum edit distance of a read to the provisional consensus, relative to the longer of the two;
+    /// reads further away are outliers left out of the consensus.
+    pub max_divergence: f64,
+}
+
+impl Default for PrealignOptions {
+    fn default() -> PrealignOptions {
+        PrealignOptions { max_divergence: 0.3 }
+    }
+}
+
+/// Returns the edit distance of two sequences, computed with the wavefront algorithm in O((n + m) * d) time
+/// for a distance d.
+pub fn wfa_edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let (n, m) = (a.len() as i64, b.len() as i64);
+    // the furthest position reached in `b` on each diagonal k = j - i, stored at index k + score
+    let extend = |k: i64, mut j: i64| {
+        while j < m && j - k < n && a[(j - k) as usize] == b[j as usize] {
+            j += 1;
+        }
+        j
+    };
+    let target = m - n;
+    let mut wavefront = vec![extend(0, 0)];
+    let mut score: i64 = 0;
+    loop {
+        if target.abs() <= score && wavefront[(target + score) as usize] >= m {
+            return score as usize;
+        }
+        let get = |k: i64| {
+            if k.abs() <= score {
+                wavefront[(k + score) as usize]
+            } else {
+                i64::MIN / 2
+            }
+        };
+        let next: Vec<i64> = (-score - 1..=score + 1)
+            .map(|k| {
+                // mismatch and insertion (in `b`) move along `b`, deletion moves along `a` only
+                let j = (get(k) + 1).max(get(k - 1) + 1).max(get(k + 1)).min(m).min(n + k);
+                if j < k.max(0) {
+                    return i64::MIN / 2;
+                }
+                extend(k, j)
+            })
+            .collect();
+        wavefront = next;
+        score += 1;
+    }
+}
+
+/// Orders reads by their edit distance to a provisional consensus, closest first, and sets outliers apart.
+/// # Arguments
+///
+/// * `seqs` - the sequences (not null-terminated)
+/// * `provisional` - the provisional consensus
+/// * `options` - the divergence above which reads are outliers
+///
+/// # Returns
+/// * returns the indices of the kept reads, closest first (ties in input order), and those of the outliers
+pub fn order_reads(seqs: &[&[u8]], provisional: &[u8], options: &PrealignOptions) -> (Vec<usize>, Vec<usize>) {
+    let mut scored = vec![];
+    let mut outliers = vec![];
+    for (i, seq) in seqs.iter().enumerate() {
+        let distance = wfa_edit_distance(seq, provisional);
+        if distance as f64 > options.max_divergence * seq.len().max(provisional.len()) as f64 {
+            outliers.push(i);
+        } else {
+            scored.push((distance, i));
+        }
+    }
+    scored.sort();
+    (scored.into_iter().map(|(_, i)| i).collect(), outliers)
+}
+
+// the number of reads among which the provisional consensus is chosen
+const MEDOID_CANDIDATES: usize = 16;
+
+// the read among the first `MEDOID_CANDIDATES` with the smallest summed edit distance to the others
+fn medoid(reads: &[&[u8]]) -> usize {
+    let candidates = &reads[..reads.len().min(MEDOID_CANDIDATES)];
+    (0..candidates.len())
+        .min_by_key(|&i| candidates.iter().map(|other| wfa_edit_distance(candidates[i], other)).sum::<usize>())
+        .unwrap_or(0)
+}
+
+/// Generates the consensus of reads pre-aligned against a provisional consensus: the medoid of the first
+/// reads, closest to all the others. Reads are aligned into the graph closest first, so that the graph is
+/// seeded by the most representative reads, and outliers are left out.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - the divergence above which reads are outliers
+///
+/// # Returns
+/// * returns the consensus of the kept reads, their alignment rows being in pre-alignment order, and the
+///   input indices of the outliers
+pub fn poa_prealigned(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &PrealignOptions,
+) -> (ConsensusResult, Vec<usize>) {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    if seqs.is_empty() {
+        return (ConsensusResult::default(), vec![]);
+    }
+    let reads: Vec<&[u8]> = seqs.iter().map(|seq| &seq[..seq.len().saturating_sub(1)]).collect();
+    let provisional = reads[medoid(&reads)];
+
+    let (order, outliers) = order_reads(&reads, provisional, options);
+    let ordered_seqs: Vec<Vec<u8>> = order.iter().map(|&i| seqs[i].clone()).collect();
+    let ordered_quals: Vec<Vec<u8>> = order.iter().map(|&i| quals[i].clone()).collect();
+    let names: Vec<String> = order.iter().map(|i| format!("seq_{}", i)).collect();
+    let msa = crate::msa_with_params(&ordered_seqs, &ordered_quals, Some(&names), params);
+    (ConsensusResult::from_msa(msa), outliers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wfa_edit_distance() {
+        assert_eq!(wfa_edit_distance(b"", b""), 0);
+        assert_eq!(wfa_edit_distance(b"ACGT", b"ACGT"), 0);
+        assert_eq!(wfa_edit_distance(b"ACGT", b""), 4);
+        assert_eq!(wfa_edit_distance(b"", b"AC"), 2);
+        assert_eq!(wfa_edit_distance(b"ACGT", b"AGT"), 1);
+        assert_eq!(wfa_edit_distance(b"AGT", b"ACGT"), 1);
+        assert_eq!(wfa_edit_distance(b"kitten", b"sitting"), 3);
+        assert_eq!(wfa_edit_distance(b"ATTGCCCGTT", b"AATGCTCGTT"), 2);
+        assert_eq!(wfa_edit_distance(b"GGGG", b"CCCCCC"), 6);
+    }
+
+    #[test]
+    fn test_order_reads() {
+        let seqs: Vec<&[u8]> = vec![b"AATGCCGTT", b"AATGCCCGTT", b"GGGGGGTTTAAA", b"AATGCTCGTA"];
+
+        let (order, outliers) = order_reads(&seqs, b"AATGCCCGTT", &PrealignOptions::default());
+
+        assert_eq!(order, vec![1, 0, 3]);
+        assert_eq!(outliers, vec![2]);
+        assert_eq!(medoid(&seqs), 0);
+    }
+
+    #[test]
+    fn test_poa_prealigned() {
+        let seqs: Vec<Vec<u8>> =
+            ["ATTGCCCGTT\0", "AATGCCGTT\0", "AATGCCCGAT\0", "AACGCCCGTC\0", "AGTGCTCGTT\0", "AATGCTCGTT\0", "CCCCAAAAGGGG\0"]
+                .iter()
+                .map(|s| s.as_bytes().to_vec())
+                .collect();
+        let quals = crate::quals_for(&seqs);
+
+        let (result, outliers) = poa_prealigned(&seqs, &quals, &AlignmentParams::default(), &PrealignOptions::default());
+
+        assert_eq!(outliers, vec![6]);
+        assert_eq!(result.metrics.num_reads, 6);
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+    }
+}
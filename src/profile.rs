@@ -0,0 +1,143 @@
+//! Position-specific scoring matrices of the consensus, for profile searches and HMM construction.
+use std::io::{self, Write};
+
+use crate::error::Error;
+use crate::msa::{Msa, GAP};
+
+/// The nucleotide alphabet of `Msa::pssm`.
+pub const DNA_ALPHABET: &[u8] = b"ACGT";
+
+/// A position-specific scoring matrix: the log-odds score of each letter at each consensus position.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Pssm {
+    /// The letters scored at each position, in score order.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub alphabet: Vec<u8>,
+    /// The consensus base of each position.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The number of reads holding a letter of the alphabet at each position.
+    pub depths: Vec<usize>,
+    /// The log2-odds score of each letter at each position, against a uniform background.
+    pub scores: Vec<Vec<f64>>,
+}
+
+impl Pssm {
+    /// Returns the number of consensus positions.
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Returns true if the matrix has no position.
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Writes the matrix as tab-separated text: a header naming the letters, then one line per position with
+    /// its 1-based position, consensus base, depth and letter scores.
+    pub fn write_tsv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let letters: Vec<String> = self.alphabet.iter().map(|&letter| (letter as char).to_string()).collect();
+        writeln!(writer, "pos\tconsensus\tdepth\t{}", letters.join("\t"))?;
+        for (pos, scores) in self.scores.iter().enumerate() {
+            let scores: Vec<String> = scores.iter().map(|score| format!("{:.3}", score)).collect();
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                pos + 1,
+                self.consensus[pos] as char,
+                self.depths[pos],
+                scores.join("\t")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Msa {
+    /// Returns the position-specific scoring matrix of the consensus: for each consensus base, the log2-odds of
+    /// the letters of the reads in its column against a uniform background. Gaps and letters outside the
+    /// alphabet are not counted; `pseudocount` reads spread over the background keep unseen letters finite.
+    /// An empty alphabet, or a pseudocount that is not positive, which would score unseen letters and columns
+    /// of gaps as infinite or NaN, fails with `Error::InvalidParams`.
+    ///
+    /// # Arguments
+    ///
+    /// * `alphabet` - the letters to score (upper case), e.g. `DNA_ALPHABET`
+    /// * `pseudocount` - the weight of the background added to the letter counts of each position
+    pub fn pssm(&self, alphabet: &[u8], pseudocount: f64) -> Result<Pssm, Error> {
+        if alphabet.is_empty() {
+            return Err(Error::InvalidParams {
+                reason: "empty PSSM alphabet".to_string(),
+            });
+        }
+        if pseudocount.is_nan() || pseudocount <= 0.0 {
+            return Err(Error::InvalidParams {
+                reason: format!("PSSM pseudocount {} must be positive", pseudocount),
+            });
+        }
+        let background = 1.0 / alphabet.len() as f64;
+        let mut pssm = Pssm {
+            alphabet: alphabet.to_vec(),
+            ..Pssm::default()
+        };
+        for (col, &cons) in self.consensus.iter().enumerate() {
+            if cons == GAP {
+                continue;
+            }
+            let mut counts = vec![0usize; alphabet.len()];
+            for row in &self.rows {
+                let base = row[col].to_ascii_uppercase();
+                if let Some(letter) = alphabet.iter().position(|&letter| letter == base) {
+                    counts[letter] += 1;
+                }
+            }
+            let depth: usize = counts.iter().sum();
+            let total = depth as f64 + pseudocount;
+            pssm.scores.push(
+                counts
+                    .iter()
+                    .map(|&count| ((count as f64 + pseudocount * background) / total / background).log2())
+                    .collect(),
+            );
+            pssm.consensus.push(cons);
+            pssm.depths.push(depth);
+        }
+        Ok(pssm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pssm() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string()],
+            rows: vec![b"AC-G".to_vec(), b"AC-G".to_vec(), b"ATTG".to_vec(), b"a--N".to_vec()],
+            consensus: b"AC-G".to_vec(),
+        };
+
+        let pssm = msa.pssm(DNA_ALPHABET, 1.0).unwrap();
+
+        assert_eq!(pssm.len(), 3);
+        assert_eq!(pssm.consensus, b"ACG".to_vec());
+        assert_eq!(pssm.depths, vec![4, 3, 3]);
+        // A in 4 of 4 reads: (4 + 0.25) / 5 / 0.25
+        assert!((pssm.scores[0][0] - 3.4f64.log2()).abs() < 1e-9);
+        assert!((pssm.scores[0][1] - 0.2f64.log2()).abs() < 1e-9);
+        assert!(pssm.scores[1][1] > 0.0 && pssm.scores[1][3] > 0.0 && pssm.scores[1][1] > pssm.scores[1][3]);
+
+        let mut out = vec![];
+        pssm.write_tsv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "pos\tconsensus\tdepth\tA\tC\tG\tT");
+        assert_eq!(lines[1], "1\tA\t4\t1.766\t-2.322\t-2.322\t-2.322");
+
+        assert!(msa.pssm(DNA_ALPHABET, 0.0).is_err());
+        assert!(msa.pssm(DNA_ALPHABET, f64::NAN).is_err());
+        assert!(msa.pssm(b"", 1.0).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Quality control of reads before they enter the graph.
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// Per-read filters keeping obviously broken reads, such as truncated reads, concatemers or reads of mostly
+/// `N`, out of the graph. The default filter keeps every read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadFilter {
+    /// The minimum length of a read.
+    pub min_len: usize,
+    /// The maximum length of a read.
+    pub max_len: usize,
+    /// The minimum mean Phred quality of a read, averaged over the error probabilities of its bases as
+    /// basecallers report it, so that a few low-quality bases weigh more than in the mean of the qualities.
+    pub min_mean_qual: f64,
+    /// The maximum fraction of the bases of a read that are `N` (or `n`).
+    pub max_n_fraction: f64,
+}
+
+impl Default for ReadFilter {
+    fn default() -> ReadFilter {
+        ReadFilter {
+            min_len: 0,
+            max_len: usize::MAX,
+            min_mean_qual: 0.0,
+            max_n_fraction: 1.0,
+        }
+    }
+}
+
+/// The number of reads kept and rejected by a `ReadFilter`, each rejected read counted under the first
+/// filter it fails, in the order of the fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FilterCounts {
+    /// The number of reads kept.
+    pub passed: usize,
+    /// The number of reads shorter than `min_len`.
+    pub too_short: usize,
+    /// The number of reads longer than `max_len`.
+    pub too_long: usize,
+    /// The number of reads with a larger fraction of `N` than `max_n_fraction`.
+    pub too_many_n: usize,
+    /// The number of reads of a lower mean quality than `min_mean_qual`.
+    pub low_quality: usize,
+}
+
+impl FilterCounts {
+    /// Returns the number of rejected reads.
+    pub fn rejected(&self) -> usize {
+        self.too_short + self.too_long + self.too_many_n + self.low_quality
+    }
+}
+
+impl std::ops::AddAssign for FilterCounts {
+    fn add_assign(&mut self, other: FilterCounts) {
+        self.passed += other.passed;
+        self.too_short += other.too_short;
+        self.too_long += other.too_long;
+        self.too_many_n += other.too_many_n;
+        self.low_quality += other.low_quality;
+    }
+}
+
+// the mean Phred quality of a read, from the mean error probability of its Phred+33 qualities
+fn mean_qual(qual: &[u8]) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let error: f64 = qual.iter().map(|&q| 10f64.powf(-(q.saturating_sub(33) as f64) / 10.0)).sum();
+    -10.0 * (error / qual.len() as f64).log10()
+}
+
+// the filter a read fails first
+enum Rejection {
+    TooShort,
+    TooLong,
+    TooManyN,
+    LowQuality,
+}
+
+impl ReadFilter {
+    // the filter a read, without its null terminator, fails first, or None if the read is kept
+    fn reject(&self, seq: &[u8], qual: &[u8]) -> Option<Rejection> {
+        if seq.len() < self.min_len {
+            return Some(Rejection::TooShort);
+        }
+        if seq.len() > self.max_len {
+            return Some(Rejection::TooLong);
+        }
+        let n = seq.iter().filter(|&&b| b == b'N' || b == b'n').count();
+        if !seq.is_empty() && n as f64 / seq.len() as f64 > self.max_n_fraction {
+            return Some(Rejection::TooManyN);
+        }
+        if mean_qual(qual) < self.min_mean_qual {
+            return Some(Rejection::LowQuality);
+        }
+        None
+    }
+
+    /// Returns whether a read passes the filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - the sequence of the read, not null-terminated
+    /// * `qual` - the qualities (Phred+33) of the read, not null-terminated
+    pub fn passes(&self, seq: &[u8], qual: &[u8]) -> bool {
+        self.reject(seq, qual).is_none()
+    }
+
+    /// Returns the reads passing the filters, in input order, and the counts of kept and rejected reads.
+    /// # Arguments
+    ///
+    /// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) of the reads
+    /// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the reads
+    pub fn apply(&self, seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, FilterCounts) {
+        let (kept, counts) = self.kept(seqs, quals);
+        let kept_seqs = kept.iter().map(|&i| seqs[i].clone()).collect();
+        let kept_quals = kept.iter().map(|&i| quals[i].clone()).collect();
+        (kept_seqs, kept_quals, counts)
+    }
+
+    // the indices of the null-terminated reads passing the filters, and the counts of kept and rejected reads
+    fn kept(&self, seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> (Vec<usize>, FilterCounts) {
+        if seqs.len() != quals.len() {
+            panic!("Input sequences and qualities must be of same length");
+        }
+        let mut kept = vec![];
+        let mut counts = FilterCounts::default();
+        for (i, (seq, qual)) in seqs.iter().zip(quals.iter()).enumerate() {
+            // the sequences are null terminated
+            let rejection = self.reject(&seq[..seq.len().saturating_sub(1)], &qual[..qual.len().saturating_sub(1)]);
+            match rejection {
+                Some(Rejection::TooShort) => counts.too_short += 1,
+                Some(Rejection::TooLong) => counts.too_long += 1,
+                Some(Rejection::TooManyN) => counts.too_many_n += 1,
+                Some(Rejection::LowQuality) => counts.low_quality += 1,
+                None => {
+                    counts.passed += 1;
+                    kept.push(i);
+                }
+            }
+        }
+        (kept, counts)
+    }
+}
+
+/// Generates the consensus of the reads passing a filter, like `poa`.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `filter` - the filters of the reads
+///
+/// # Returns
+/// * returns the consensus of the reads kept and the counts of kept and rejected reads; the rows of the
+///   alignment are those of the kept reads, each named `seq_<i>` after its index `i` among the input reads
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_filtered, AlignmentParams, ReadFilter};
+///
+///     let seqs = vec![b"NNNNA\0".to_vec(), b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///     let quals = vec![b"FFFFF\0".to_vec(), b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///     let filter = ReadFilter {
+///         max_n_fraction: 0.5,
+///         ..ReadFilter::default()
+///     };
+///
+///     let (result, counts) = poa_filtered(&seqs, &quals, &AlignmentParams::default(), &filter);
+///     assert_eq!((counts.passed, counts.too_many_n), (2, 1));
+///     assert_eq!(result.metrics.num_reads, 2);
+///     assert_eq!(result.msa.names, vec!["seq_1".to_string(), "seq_2".to_string()]);
+/// ```
+pub fn poa_filtered(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    filter: &ReadFilter,
+) -> (ConsensusResult, FilterCounts) {
+    let (kept, counts) = filter.kept(seqs, quals);
+    let kept_seqs: Vec<Vec<u8>> = kept.iter().map(|&i| seqs[i].clone()).collect();
+    let kept_quals: Vec<Vec<u8>> = kept.iter().map(|&i| quals[i].clone()).collect();
+    let mut result = crate::poa(&kept_seqs, &kept_quals, params);
+    result.msa.names = kept.iter().map(|i| format!("seq_{}", i)).collect();
+    (result, counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean_qual() {
+        assert!((mean_qual(b"55555") - 20.0).abs() < 1e-9);
+        // one base of quality 0 dominates the error probability of the read
+        assert!(mean_qual(b"!IIII") < 7.0);
+        assert_eq!(mean_qual(b""), 0.0);
+    }
+
+    #[test]
+    fn test_read_filter() {
+        let filter = ReadFilter {
+            min_len: 3,
+            max_len: 6,
+            min_mean_qual: 15.0,
+            max_n_fraction: 0.25,
+        };
+        let reads: Vec<(&[u8], &[u8])> = vec![
+            (b"ACGT", b"IIII"),
+            (b"AC", b"II"),
+            (b"ACGTACG", b"IIIIIII"),
+            (b"ACNN", b"IIII"),
+            (b"ACGT", b"++++"),
+            (b"ACGN", b"IIII"),
+        ];
+        let seqs: Vec<Vec<u8>> = reads.iter().map(|(seq, _)| [seq, &b"\0"[..]].concat()).collect();
+        let quals: Vec<Vec<u8>> = reads.iter().map(|(_, qual)| [qual, &b"\0"[..]].concat()).collect();
+
+        let (kept, kept_quals, counts) = filter.apply(&seqs, &quals);
+        assert_eq!(filter.kept(&seqs, &quals).0, vec![0, 5]);
+
+        assert_eq!(kept, vec![b"ACGT\0".to_vec(), b"ACGN\0".to_vec()]);
+        assert_eq!(kept_quals.len(), 2);
+        let expected = FilterCounts {
+            passed: 2,
+            too_short: 1,
+            too_long: 1,
+            too_many_n: 1,
+            low_quality: 1,
+        };
+        assert_eq!(counts, expected);
+        assert_eq!(counts.rejected(), 4);
+        assert!(filter.passes(b"ACGT", b"IIII"));
+        assert!(ReadFilter::default().passes(b"", b""));
+    }
+}
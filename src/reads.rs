@@ -0,0 +1,228 @@
+//! Compact in-memory storage of many reads, for holding the inputs of large batches.
+use crate::graph::PoaGraph;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+// the nibble escaping a quality step outside -7..=7, followed by the two nibbles of the quality
+const ESCAPE: u8 = 15;
+// the quality the first step of each read starts from
+const QUAL_ORIGIN: u8 = b'!';
+
+fn base_code(base: u8) -> Option<u8> {
+    match base {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// A batch of reads stored compactly: bases are packed in 2 bits and qualities delta-encoded in 4-bit steps.
+///
+/// Bases other than upper-case `A`, `C`, `G` and `T` (such as `N`) are kept aside as exceptions and
+/// qualities changing by more than 7 from one base to the next take 12 bits, so that reads are stored
+/// losslessly. Binned qualities, as produced by current Illumina and ONT basecallers, mostly take 4 bits.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{AlignmentParams, ReadBatch};
+///
+///     let mut batch = ReadBatch::new();
+///     batch.push(b"ACGTT", b"FFFFF");
+///     batch.push(b"ACNTT", b"FF#FF");
+///     let result = batch.consensus(&AlignmentParams::default());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReadBatch {
+    bases: Vec<u8>,
+    exceptions: Vec<(u64, u8)>,
+    quals: Vec<u8>,
+    // the offset of the first base and of the first quality nibble of each read, then of the end
+    offsets: Vec<(u64, u64)>,
+}
+
+impl ReadBatch {
+    /// Creates an empty batch.
+    pub fn new() -> ReadBatch {
+        ReadBatch {
+            offsets: vec![(0, 0)],
+            ..ReadBatch::default()
+        }
+    }
+
+    /// Returns the number of reads of the batch.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    /// Returns true if the batch holds no read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push_nibble(&mut self, pos: &mut u64, nibble: u8) {
+        if *pos % 2 == 0 {
+            self.quals.push(nibble);
+        } else {
+            *self.quals.last_mut().unwrap() |= nibble << 4;
+        }
+        *pos += 1;
+    }
+
+    fn nibble(&self, pos: u64) -> u8 {
+        (self.quals[(pos / 2) as usize] >> (4 * (pos % 2))) & 0xf
+    }
+
+    /// Appends a read to the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - the sequence (not null-terminated)
+    /// * `qual` - the Phred+33 qualities of the sequence (not null-terminated)
+    pub fn push(&mut self, seq: &[u8], qual: &[u8]) {
+        if seq.len() != qual.len() {
+            panic!("Input sequence and qualities must be of same length");
+        }
+        if self.offsets.is_empty() {
+            self.offsets.push((0, 0));
+        }
+        let (mut base_pos, mut qual_pos) = *self.offsets.last().unwrap();
+
+        for &base in seq {
+            let code = base_code(base).unwrap_or_else(|| {
+                self.exceptions.push((base_pos, base));
+                0
+            });
+            if base_pos % 4 == 0 {
+                self.bases.push(0);
+            }
+            *self.bases.last_mut().unwrap() |= code << (2 * (base_pos % 4));
+            base_pos += 1;
+        }
+
+        let mut previous = QUAL_ORIGIN;
+        for &q in qual {
+            let step = q as i16 - previous as i16;
+            if (-7..=7).contains(&step) {
+                self.push_nibble(&mut qual_pos, (step + 7) as u8);
+            } else {
+                self.push_nibble(&mut qual_pos, ESCAPE);
+                self.push_nibble(&mut qual_pos, q & 0xf);
+                self.push_nibble(&mut qual_pos, q >> 4);
+            }
+            previous = q;
+        }
+        // reads start on a byte of qualities, so that the nibbles of two reads never share a byte
+        qual_pos += qual_pos % 2;
+        self.offsets.push((base_pos, qual_pos));
+    }
+
+    /// Returns the sequence and qualities of the read at `index` (not null-terminated).
+    pub fn get(&self, index: usize) -> (Vec<u8>, Vec<u8>) {
+        if index >= self.len() {
+            panic!("Read index {} out of range for a batch of {} reads", index, self.len());
+        }
+        let (start, qual_start) = self.offsets[index];
+        let end = self.offsets[index + 1].0;
+
+        let mut seq: Vec<u8> = (start..end)
+            .map(|pos| b"ACGT"[((self.bases[(pos / 4) as usize] >> (2 * (pos % 4))) & 0b11) as usize])
+            .collect();
+        let first = self.exceptions.partition_point(|&(pos, _)| pos < start);
+        for &(pos, base) in self.exceptions[first..].iter().take_while(|&&(pos, _)| pos < end) {
+            seq[(pos - start) as usize] = base;
+        }
+
+        let mut qual = Vec::with_capacity(seq.len());
+        let (mut pos, mut previous) = (qual_start, QUAL_ORIGIN);
+        while qual.len() < seq.len() {
+            let nibble = self.nibble(pos);
+            let q = if nibble == ESCAPE {
+                pos += 2;
+                self.nibble(pos - 1) | (self.nibble(pos) << 4)
+            } else {
+                (previous as i16 + nibble as i16 - 7) as u8
+            };
+            pos += 1;
+            qual.push(q);
+            previous = q;
+        }
+        (seq, qual)
+    }
+
+    /// Returns an iterator over the sequences and qualities of the reads (not null-terminated), decoding one
+    /// read at a time, see `iter_null_terminated` for the streaming consensus functions.
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    /// Returns an iterator over the null-terminated sequences and qualities of the reads, decoding one read at
+    /// a time; it can be handed to the streaming consensus functions, e.g. `poa_stream`.
+    pub fn iter_null_terminated(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.iter().map(|(mut seq, mut qual)| {
+            seq.push(b'\0');
+            qual.push(b'\0');
+            (seq, qual)
+        })
+    }
+
+    /// Returns the number of bytes taken by the encoded reads.
+    pub fn encoded_len(&self) -> usize {
+        self.bases.len() + self.quals.len() + self.exceptions.len() * 9 + self.offsets.len() * 16
+    }
+
+    /// Generates the consensus of the reads of the batch, decoding them one at a time into the graph.
+    pub fn consensus(&self, params: &AlignmentParams) -> ConsensusResult {
+        let mut graph = PoaGraph::new(params);
+        for (seq, qual) in self.iter() {
+            graph.add(&seq, &qual);
+        }
+        graph.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_batch() {
+        let reads: Vec<(&[u8], &[u8])> = vec![
+            (b"ACGTTGCA", b"FFFFFFFF"),
+            (b"ACNTtG", b"FF#F:+"),
+            (b"", b""),
+            (b"GGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGG", b"IIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIII"),
+        ];
+        let mut batch = ReadBatch::new();
+        for (seq, qual) in &reads {
+            batch.push(seq, qual);
+        }
+
+        assert_eq!(batch.len(), 4);
+        for (i, (seq, qual)) in batch.iter().enumerate() {
+            assert_eq!((seq.as_slice(), qual.as_slice()), reads[i]);
+        }
+        assert_eq!(batch.exceptions, vec![(10, b'N'), (12, b't')]);
+        // 52 bases in 2 bits, 52 quality steps in 4 bits plus the escapes of the first and changed qualities
+        assert_eq!(batch.bases.len(), 13);
+        assert!(batch.quals.len() < 40);
+        assert!(ReadBatch::default().is_empty());
+    }
+
+    #[test]
+    fn test_iter_null_terminated() {
+        let mut batch = ReadBatch::new();
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"].iter() {
+            batch.push(seq.as_bytes(), &vec![b'F'; seq.len()]);
+        }
+        let (seq, qual) = batch.iter_null_terminated().next().unwrap();
+        assert_eq!((seq.as_slice(), qual.as_slice()), (&b"ATTGCCCGTT\0"[..], &b"FFFFFFFFFF\0"[..]));
+
+        let result = crate::poa_stream(batch.iter_null_terminated(), None, 0, &AlignmentParams::default());
+
+        assert_eq!(result.metrics.num_reads, 6);
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+    }
+}
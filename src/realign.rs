@@ -0,0 +1,116 @@
+//! Local realignment of a window of a consensus, e.g. to clean up a messy indel region with stricter scores.
+use std::ops::Range;
+
+use crate::msa::{Msa, GAP};
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// A consensus whose window was realigned, see `realign_window`.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RealignedWindow {
+    /// The consensus with the window replaced by the consensus of its realignment.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The realignment of the read segments spanning the window.
+    pub window: ConsensusResult,
+}
+
+// the segments (null terminated) of the rows spanning the alignment columns from `first` to `last` from end to
+// end, and their qualities, taken from the qualities (null terminated) of the rows
+fn window_segments(msa: &Msa, quals: &[Vec<u8>], first: usize, last: usize) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let (mut seg_seqs, mut seg_quals) = (vec![], vec![]);
+    for (row, qual) in msa.rows.iter().zip(quals.iter()) {
+        let spans = row[..=first].iter().any(|&b| b != GAP) && row[last..].iter().any(|&b| b != GAP);
+        if !spans {
+            continue;
+        }
+        // the read position of the first column of the window
+        let start = row[..first].iter().filter(|&&b| b != GAP).count();
+        let seq: Vec<u8> = row[first..=last].iter().cloned().filter(|&b| b != GAP).collect();
+        let end = start + seq.len();
+        if end < qual.len() {
+            seg_quals.push(null_terminated(&qual[start..end]));
+            seg_seqs.push(null_terminated(&seq));
+        }
+    }
+    (seg_seqs, seg_quals)
+}
+
+/// Realigns the reads of a consensus within a window of consensus positions, e.g. with stricter gap scores
+/// around a messy indel region, and replaces the window of the consensus by the consensus of the realignment.
+///
+/// The segments of the reads spanning the window from end to end are taken from the alignment of the result,
+/// bases inserted right before or after the window left out, and aligned again on their own. Without such
+/// reads, the window is kept.
+/// # Arguments
+///
+/// * `result` - the consensus result, whose alignment holds the reads
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the reads, in the
+///   order of the rows of the alignment
+/// * `range` - the consensus positions of the window
+/// * `params` - the alignment mode and scores of the realignment
+///
+/// # Returns
+/// * returns the consensus with the window replaced and the realignment of the window
+///
+/// Panics if the window extends past the consensus.
+pub fn realign_window(
+    result: &ConsensusResult,
+    quals: &[Vec<u8>],
+    range: Range<usize>,
+    params: &AlignmentParams,
+) -> RealignedWindow {
+    if range.end > result.consensus.len() {
+        panic!(
+            "Window {:?} out of range for a consensus of {} bases",
+            range,
+            result.consensus.len()
+        );
+    }
+    if quals.len() != result.msa.num_rows() {
+        panic!("Input qualities must be given for each row of the alignment");
+    }
+    let columns: Vec<usize> = (0..result.msa.num_columns())
+        .filter(|&col| result.msa.consensus[col] != GAP)
+        .collect();
+    let (seqs, seg_quals) = if range.is_empty() {
+        (vec![], vec![])
+    } else {
+        window_segments(&result.msa, quals, columns[range.start], columns[range.end - 1])
+    };
+    if seqs.is_empty() {
+        return RealignedWindow {
+            consensus: result.consensus.clone(),
+            window: ConsensusResult::default(),
+        };
+    }
+    let window = crate::poa(&seqs, &seg_quals, params);
+    let mut consensus = result.consensus[..range.start].to_vec();
+    consensus.extend_from_slice(&window.consensus);
+    consensus.extend_from_slice(&result.consensus[range.end..]);
+    RealignedWindow { consensus, window }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_segments() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"ACG-TA".to_vec(), b"-CGGT-".to_vec(), b"---GTA".to_vec()],
+            consensus: b"ACG-TA".to_vec(),
+        };
+        let quals = vec![b"01234\0".to_vec(), b"5678\0".to_vec(), b"9:;\0".to_vec()];
+
+        // the window of the alignment columns 1 to 4, consensus positions 1 to 3
+        let (seqs, seg_quals) = window_segments(&msa, &quals, 1, 4);
+
+        // the last read starts within the window
+        assert_eq!(seqs, vec![b"CGT\0".to_vec(), b"CGGT\0".to_vec()]);
+        assert_eq!(seg_quals, vec![b"123\0".to_vec(), b"5678\0".to_vec()]);
+    }
+}
@@ -0,0 +1,479 @@
+//! Consensus results and the alignment metrics derived from them.
+use std::ops::{Bound, RangeBounds};
+
+use crate::error::Error;
+use crate::msa::{Msa, GAP};
+use crate::scratch::with_scratch;
+
+/// Summary statistics of a multiple sequence alignment with respect to its consensus.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Metrics {
+    /// The number of aligned reads.
+    pub num_reads: usize,
+    /// The length of the (ungapped) consensus.
+    pub consensus_len: usize,
+    /// The number of reads covering each consensus base.
+    pub coverage: Vec<usize>,
+    /// The mean of `coverage`.
+    pub mean_coverage: f64,
+    /// The identity of each read to the consensus, computed over the aligned span of the read.
+    pub identity: Vec<f64>,
+    /// The mean of `identity`.
+    pub mean_identity: f64,
+    /// The lowest support of a consensus base, see `ConsensusResult::support`, or 0 for an empty consensus: a
+    /// confidence score of the consensus as a whole, low wherever the reads split between several paths.
+    pub min_support: f64,
+    /// The error profile of the reads with respect to the consensus.
+    pub errors: ErrorProfile,
+}
+
+/// The mismatches, insertions and deletions of reads with respect to the consensus.
+///
+/// Rates are relative to the number of consensus bases spanned by the reads.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorProfile {
+    /// The number of consensus bases spanned by the reads.
+    pub aligned_bases: usize,
+    /// The number of read bases differing from the consensus base.
+    pub mismatches: usize,
+    /// The number of read bases missing from the consensus.
+    pub insertions: usize,
+    /// The number of consensus bases missing from the reads.
+    pub deletions: usize,
+}
+
+impl ErrorProfile {
+    /// Computes the error profile of a gapped row over its aligned span.
+    pub fn from_row(row: &[u8], consensus: &[u8]) -> ErrorProfile {
+        let mut profile = ErrorProfile::default();
+        let (first, last) = match (row.iter().position(|&b| b != GAP), row.iter().rposition(|&b| b != GAP)) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return profile,
+        };
+        for col in first..=last {
+            match (row[col], consensus[col]) {
+                (GAP, GAP) => {}
+                (_, GAP) => profile.insertions += 1,
+                (GAP, _) => {
+                    profile.aligned_bases += 1;
+                    profile.deletions += 1;
+                }
+                (base, cons) => {
+                    profile.aligned_bases += 1;
+                    if base != cons {
+                        profile.mismatches += 1;
+                    }
+                }
+            }
+        }
+        profile
+    }
+
+    fn rate(&self, count: usize) -> f64 {
+        if self.aligned_bases == 0 {
+            return 0.0;
+        }
+        count as f64 / self.aligned_bases as f64
+    }
+
+    /// Returns the number of mismatches per aligned base.
+    pub fn mismatch_rate(&self) -> f64 {
+        self.rate(self.mismatches)
+    }
+
+    /// Returns the number of inserted bases per aligned base.
+    pub fn insertion_rate(&self) -> f64 {
+        self.rate(self.insertions)
+    }
+
+    /// Returns the number of deleted bases per aligned base.
+    pub fn deletion_rate(&self) -> f64 {
+        self.rate(self.deletions)
+    }
+
+    /// Returns the total number of errors per aligned base.
+    pub fn error_rate(&self) -> f64 {
+        self.rate(self.mismatches + self.insertions + self.deletions)
+    }
+}
+
+/// The kind of a difference between a read and the consensus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ReadErrorKind {
+    /// A read base differing from the consensus base.
+    Mismatch,
+    /// A read base missing from the consensus.
+    Insertion,
+    /// A consensus base missing from the read.
+    Deletion,
+}
+
+/// A one-base difference between a read and the consensus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReadError {
+    /// The kind of the error.
+    pub kind: ReadErrorKind,
+    /// The 0-based consensus position of the affected base. Insertions are placed before this base.
+    pub consensus_pos: usize,
+    /// The 0-based read position of the affected base. Deletions are placed before this base.
+    pub read_pos: usize,
+    /// The read base, or the consensus base missing from the read for deletions.
+    pub base: u8,
+}
+
+/// The differences between one read and the consensus, over the aligned span of the read.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReadErrors {
+    /// The name of the read in the alignment.
+    pub name: String,
+    /// The counts of the errors of the read.
+    pub profile: ErrorProfile,
+    /// The errors of the read, in alignment order.
+    pub errors: Vec<ReadError>,
+}
+
+impl ReadErrors {
+    /// Computes the errors of a gapped row with respect to the consensus of its alignment.
+    pub fn from_row(name: &str, row: &[u8], consensus: &[u8]) -> ReadErrors {
+        let mut errors = vec![];
+        let (mut read_pos, mut consensus_pos) = (0, 0);
+        let first = row.iter().position(|&b| b != GAP).unwrap_or(row.len());
+        let last = row.iter().rposition(|&b| b != GAP).map_or(0, |last| last + 1);
+        for col in 0..row.len() {
+            // gaps before the first and after the last base of the read are not deletions
+            let spanned = (first..last).contains(&col);
+            match (row[col], consensus[col]) {
+                (GAP, GAP) => {}
+                (base, GAP) => {
+                    errors.push(ReadError {
+                        kind: ReadErrorKind::Insertion,
+                        consensus_pos,
+                        read_pos,
+                        base,
+                    });
+                    read_pos += 1;
+                }
+                (GAP, cons) => {
+                    if spanned {
+                        errors.push(ReadError {
+                            kind: ReadErrorKind::Deletion,
+                            consensus_pos,
+                            read_pos,
+                            base: cons,
+                        });
+                    }
+                    consensus_pos += 1;
+                }
+                (base, cons) => {
+                    if base != cons {
+                        errors.push(ReadError {
+                            kind: ReadErrorKind::Mismatch,
+                            consensus_pos,
+                            read_pos,
+                            base,
+                        });
+                    }
+                    read_pos += 1;
+                    consensus_pos += 1;
+                }
+            }
+        }
+        ReadErrors {
+            name: name.to_string(),
+            profile: ErrorProfile::from_row(row, consensus),
+            errors,
+        }
+    }
+}
+
+impl std::ops::AddAssign for ErrorProfile {
+    fn add_assign(&mut self, other: ErrorProfile) {
+        self.aligned_bases += other.aligned_bases;
+        self.mismatches += other.mismatches;
+        self.insertions += other.insertions;
+        self.deletions += other.deletions;
+    }
+}
+
+impl Metrics {
+    /// Computes the metrics of an alignment.
+    pub fn from_msa(msa: &Msa) -> Metrics {
+        let coverage: Vec<usize> = with_scratch(|scratch| {
+            scratch.consensus_columns(msa);
+            scratch
+                .columns
+                .iter()
+                .map(|&col| msa.rows.iter().filter(|row| row[col] != GAP).count())
+                .collect()
+        });
+        let identity: Vec<f64> = msa.rows.iter().map(|row| row_identity(row, &msa.consensus)).collect();
+        let mut errors = ErrorProfile::default();
+        for row in &msa.rows {
+            errors += ErrorProfile::from_row(row, &msa.consensus);
+        }
+
+        Metrics {
+            num_reads: msa.num_rows(),
+            consensus_len: coverage.len(),
+            mean_coverage: mean(coverage.iter().map(|&c| c as f64)),
+            mean_identity: mean(identity.iter().cloned()),
+            min_support: base_support(msa).into_iter().reduce(f64::min).unwrap_or(0.0),
+            coverage,
+            identity,
+            errors,
+        }
+    }
+}
+
+// fraction of the aligned columns between the first and last base of the row that match the consensus
+fn row_identity(row: &[u8], consensus: &[u8]) -> f64 {
+    let first = match row.iter().position(|&b| b != GAP) {
+        Some(first) => first,
+        None => return 0.0,
+    };
+    let last = row.iter().rposition(|&b| b != GAP).unwrap();
+
+    let mut matches = 0;
+    let mut columns = 0;
+    for col in first..=last {
+        if row[col] == GAP && consensus[col] == GAP {
+            continue;
+        }
+        if row[col] == consensus[col] {
+            matches += 1;
+        }
+        columns += 1;
+    }
+    matches as f64 / columns as f64
+}
+
+// the fraction of the reads spanning each consensus base whose aligned base agrees with it
+fn base_support(msa: &Msa) -> Vec<f64> {
+    with_scratch(|scratch| {
+        scratch.consensus_columns(msa);
+        scratch.row_spans(msa);
+        let spans = &scratch.spans;
+        scratch
+            .columns
+            .iter()
+            .map(|&col| {
+                let (mut spanning, mut agreeing) = (0, 0);
+                for (row, span) in msa.rows.iter().zip(spans.iter()) {
+                    if matches!(span, Some((first, last)) if *first <= col && col <= *last) {
+                        spanning += 1;
+                        agreeing += (row[col] == msa.consensus[col]) as usize;
+                    }
+                }
+                if spanning == 0 {
+                    0.0
+                } else {
+                    agreeing as f64 / spanning as f64
+                }
+            })
+            .collect()
+    })
+}
+
+fn mean<I: ExactSizeIterator<Item = f64>>(values: I) -> f64 {
+    let n = values.len();
+    if n == 0 {
+        return 0.0;
+    }
+    values.sum::<f64>() / n as f64
+}
+
+/// The consensus of a set of sequences together with their alignment and its metrics.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConsensusResult {
+    /// The consensus sequence.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The multiple sequence alignment the consensus was generated from.
+    pub msa: Msa,
+    /// The metrics of the alignment.
+    pub metrics: Metrics,
+}
+
+impl ConsensusResult {
+    /// Builds the result of an alignment, computing the consensus and metrics from it.
+    pub fn from_msa(msa: Msa) -> ConsensusResult {
+        ConsensusResult {
+            consensus: msa.ungapped_consensus(),
+            metrics: Metrics::from_msa(&msa),
+            msa,
+        }
+    }
+
+    /// Returns, for each consensus base, the fraction of the reads spanning it whose aligned base agrees with
+    /// it; reads with a deletion there count as disagreeing.
+    pub fn support(&self) -> Vec<f64> {
+        base_support(&self.msa)
+    }
+
+    /// Returns the mismatches, insertions and deletions of every read with respect to the consensus, with
+    /// their positions, in the order of the rows of the alignment, e.g. to compare basecallers or chemistries.
+    pub fn read_errors(&self) -> Vec<ReadErrors> {
+        self.msa
+            .names
+            .iter()
+            .zip(self.msa.rows.iter())
+            .map(|(name, row)| ReadErrors::from_row(name, row, &self.msa.consensus))
+            .collect()
+    }
+
+    /// Returns the support of each consensus base (see `support`) encoded as one digit per base, `0` to `9`
+    /// for a support below 10%, below 20% and so on up to at least 90%, e.g. to be stored in a FASTA header
+    /// or a BAM tag next to the consensus.
+    pub fn support_string(&self) -> String {
+        self.support()
+            .iter()
+            .map(|&support| (b'0' + ((support * 10.0) as u8).min(9)) as char)
+            .collect()
+    }
+
+    /// Checks the length of the consensus against the range of lengths expected of it, e.g. the length of an
+    /// amplicon give or take its indels, so that clusters of primer dimers or chimeric reads are flagged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///     use rust_spoa::{poa, AlignmentParams, Error};
+    ///
+    ///     let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+    ///     let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+    ///     let result = poa(&seqs, &quals, &AlignmentParams::default());
+    ///
+    ///     let too_short = Error::ConsensusTooShort {
+    ///         got: result.consensus.len(),
+    ///         expected_min: 100,
+    ///     };
+    ///     assert_eq!(result.check_len(100..=120), Err(too_short));
+    ///     assert_eq!(result.check_len(1..), Ok(()));
+    /// ```
+    pub fn check_len<R: RangeBounds<usize>>(&self, expected: R) -> Result<(), Error> {
+        let got = self.consensus.len();
+        match expected.start_bound() {
+            Bound::Included(&min) if got < min => return Err(Error::ConsensusTooShort { got, expected_min: min }),
+            Bound::Excluded(&min) if got <= min => {
+                return Err(Error::ConsensusTooShort {
+                    got,
+                    expected_min: min + 1,
+                })
+            }
+            _ => {}
+        }
+        match expected.end_bound() {
+            Bound::Included(&max) if got > max => Err(Error::ConsensusTooLong { got, expected_max: max }),
+            Bound::Excluded(&max) if got >= max => Err(Error::ConsensusTooLong {
+                got,
+                expected_max: max.saturating_sub(1),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_support() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"ACGT-".to_vec(), b"AC-TA".to_vec(), b"--GA-".to_vec()],
+            consensus: b"ACGT-".to_vec(),
+        };
+        let result = ConsensusResult::from_msa(msa);
+
+        assert_eq!(result.support(), vec![1.0, 1.0, 2.0 / 3.0, 2.0 / 3.0]);
+        assert_eq!(result.support_string(), "9966");
+        assert_eq!(result.metrics.min_support, 2.0 / 3.0);
+        assert_eq!(ConsensusResult::default().support_string(), "");
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let errors = ReadErrors::from_row("a", b"-CC-GA", b"ACGT-A");
+
+        let expected = vec![
+            ReadError {
+                kind: ReadErrorKind::Mismatch,
+                consensus_pos: 2,
+                read_pos: 1,
+                base: b'C',
+            },
+            ReadError {
+                kind: ReadErrorKind::Deletion,
+                consensus_pos: 3,
+                read_pos: 2,
+                base: b'T',
+            },
+            ReadError {
+                kind: ReadErrorKind::Insertion,
+                consensus_pos: 4,
+                read_pos: 2,
+                base: b'G',
+            },
+        ];
+        assert_eq!(errors.name, "a");
+        assert_eq!(errors.errors, expected);
+        let profile = errors.profile;
+        assert_eq!((profile.aligned_bases, profile.mismatches, profile.insertions, profile.deletions), (4, 1, 1, 1));
+    }
+
+    #[test]
+    fn test_check_len() {
+        let result = ConsensusResult {
+            consensus: b"ACGT".to_vec(),
+            ..Default::default()
+        };
+
+        assert_eq!(result.check_len(3..=4), Ok(()));
+        assert_eq!(result.check_len(5..), Err(Error::ConsensusTooShort { got: 4, expected_min: 5 }));
+        assert_eq!(result.check_len(..4), Err(Error::ConsensusTooLong { got: 4, expected_max: 3 }));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            rows: vec![b"ACGT-".to_vec(), b"AC-TA".to_vec(), b"--GT-".to_vec()],
+            consensus: b"ACGT-".to_vec(),
+        };
+        let metrics = Metrics::from_msa(&msa);
+
+        assert_eq!(metrics.num_reads, 3);
+        assert_eq!(metrics.consensus_len, 4);
+        assert_eq!(metrics.coverage, vec![2, 2, 2, 3]);
+        assert_eq!(metrics.mean_coverage, 2.25);
+        assert_eq!(metrics.identity, vec![1.0, 0.6, 1.0]);
+        assert_eq!(metrics.min_support, 2.0 / 3.0);
+
+        let errors = metrics.errors;
+        assert_eq!((errors.aligned_bases, errors.mismatches, errors.insertions, errors.deletions), (10, 0, 1, 1));
+        assert_eq!(errors.error_rate(), 0.2);
+        assert_eq!(errors.deletion_rate(), 0.1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialize() {
+        let msa = Msa {
+            names: vec!["a".to_string()],
+            rows: vec![b"AC-T".to_vec()],
+            consensus: b"AC-T".to_vec(),
+        };
+        let json = serde_json::to_value(ConsensusResult::from_msa(msa)).unwrap();
+
+        assert_eq!(json["consensus"], "ACT");
+        assert_eq!(json["msa"]["rows"][0], "AC-T");
+        assert_eq!(json["metrics"]["coverage"], serde_json::json!([1, 1, 1]));
+    }
+}
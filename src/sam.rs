@@ -0,0 +1,284 @@
+//! SAM output of the reads of one or more clusters realigned to their consensus, e.g. for inspection in IGV.
+#[cfg(feature = "bam")]
+use std::io::{self, Write};
+#[cfg(feature = "bam")]
+use std::num::NonZeroUsize;
+
+#[cfg(feature = "bam")]
+use noodles::core::Position;
+#[cfg(feature = "bam")]
+use noodles::sam;
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::io::Write as _;
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::record::cigar::op::{Kind, Op};
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::record::data::field::Tag;
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::record::Flags;
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::record_buf::data::field::Value;
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::record_buf::{Cigar, QualityScores, Sequence};
+#[cfg(feature = "bam")]
+use noodles::sam::alignment::RecordBuf;
+#[cfg(feature = "bam")]
+use noodles::sam::header::record::value::map::{self, header, program, Program, ReferenceSequence};
+#[cfg(feature = "bam")]
+use noodles::sam::header::record::value::Map;
+
+#[cfg(feature = "bam")]
+use crate::batch::{Cluster, ClusterResult};
+use crate::msa::GAP;
+use crate::pairwise::{align_pair, left_align_pair};
+use crate::params::AlignmentParams;
+
+/// The placement of a read against the consensus, as written in a SAM or PAF record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Placement {
+    // the 1-based position of the first aligned consensus base
+    pub(crate) pos: usize,
+    // the number of consensus bases spanned by the alignment
+    pub(crate) ref_len: usize,
+    // the number of read bases soft clipped at the start and at the end
+    pub(crate) clip_start: usize,
+    pub(crate) clip_end: usize,
+    pub(crate) cigar: String,
+    // the edit distance to the consensus, soft clips excluded
+    pub(crate) edit_distance: usize,
+    // the number of matching bases and of alignment columns, soft clips excluded
+    pub(crate) matches: usize,
+    pub(crate) alignment_len: usize,
+}
+
+// the placement of a read from its pairwise alignment to the consensus, or None if no base is aligned:
+// insertions before the first and after the last aligned base are soft clipped and deletions there dropped
+fn placement(consensus_row: &[u8], read_row: &[u8]) -> Option<Placement> {
+    let ops: Vec<(u8, bool)> = consensus_row
+        .iter()
+        .zip(read_row.iter())
+        .filter_map(|(&c, &r)| match (c != GAP, r != GAP) {
+            (true, true) => Some((b'M', c != r)),
+            (false, true) => Some((b'I', true)),
+            (true, false) => Some((b'D', true)),
+            (false, false) => None,
+        })
+        .collect();
+    let first = ops.iter().position(|&(op, _)| op == b'M')?;
+    let last = ops.iter().rposition(|&(op, _)| op == b'M').unwrap();
+
+    let clip = |ops: &[(u8, bool)]| ops.iter().filter(|&&(op, _)| op == b'I').count();
+    let mut runs: Vec<(usize, u8)> = vec![];
+    let push = |runs: &mut Vec<(usize, u8)>, len: usize, op: u8| match runs.last_mut() {
+        Some(run) if run.1 == op => run.0 += len,
+        _ if len > 0 => runs.push((len, op)),
+        _ => {}
+    };
+    push(&mut runs, clip(&ops[..first]), b'S');
+    for &(op, _) in &ops[first..last + 1] {
+        push(&mut runs, 1, op);
+    }
+    push(&mut runs, clip(&ops[last + 1..]), b'S');
+
+    let aligned = &ops[first..last + 1];
+    Some(Placement {
+        pos: ops[..first].iter().filter(|&&(op, _)| op == b'D').count() + 1,
+        ref_len: aligned.iter().filter(|&&(op, _)| op != b'I').count(),
+        clip_start: clip(&ops[..first]),
+        clip_end: clip(&ops[last + 1..]),
+        cigar: runs.iter().map(|(len, op)| format!("{}{}", len, *op as char)).collect(),
+        edit_distance: aligned.iter().filter(|&&(_, edit)| edit).count(),
+        matches: aligned.iter().filter(|&&(_, edit)| !edit).count(),
+        alignment_len: aligned.len(),
+    })
+}
+
+// the placement of a read globally realigned to the consensus, indels left-aligned
+pub(crate) fn realign(consensus: &[u8], seq: &[u8], params: &AlignmentParams) -> Option<Placement> {
+    let (mut consensus_row, mut read_row) = align_pair(consensus, seq, params);
+    left_align_pair(&mut consensus_row, &mut read_row);
+    placement(&consensus_row, &read_row)
+}
+
+// the CIGAR of a placement, from its run-length encoded string
+#[cfg(feature = "bam")]
+fn cigar(placement: &Placement) -> Cigar {
+    let mut len = 0;
+    placement
+        .cigar
+        .bytes()
+        .filter_map(|b| {
+            if b.is_ascii_digit() {
+                len = len * 10 + (b - b'0') as usize;
+                return None;
+            }
+            let kind = match b {
+                b'M' => Kind::Match,
+                b'I' => Kind::Insertion,
+                b'D' => Kind::Deletion,
+                _ => Kind::SoftClip,
+            };
+            let op = Op::new(kind, len);
+            len = 0;
+            Some(op)
+        })
+        .collect()
+}
+
+/// Writes reads realigned to the consensus of their cluster as SAM records, the consensus of each cluster
+/// being a reference sequence. BAM files can be made from the output with `samtools view -b`.
+#[cfg(feature = "bam")]
+pub struct SamWriter<W: Write> {
+    writer: sam::io::Writer<W>,
+    header: sam::Header,
+}
+
+#[cfg(feature = "bam")]
+impl<W: Write> SamWriter<W> {
+    /// Creates a writer and writes the SAM header. Empty consensuses, which cannot be reference sequences,
+    /// are left out of it and the reads of their clusters are written as unmapped.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - the destination of the SAM
+    /// * `references` - the name and length of each consensus that reads will be written for
+    pub fn new(writer: W, references: &[(&str, usize)]) -> io::Result<SamWriter<W>> {
+        let mut hd = Map::<map::Header>::default();
+        hd.other_fields_mut().insert(header::tag::SORT_ORDER, header::sort_order::UNSORTED.into());
+        let mut pg = Map::<Program>::default();
+        pg.other_fields_mut().insert(program::tag::NAME, "rust-spoa".into());
+        let mut builder = sam::Header::builder().set_header(hd);
+        for (name, len) in references {
+            if let Some(len) = NonZeroUsize::new(*len) {
+                builder = builder.add_reference_sequence(*name, Map::<ReferenceSequence>::new(len));
+            }
+        }
+        let header = builder.add_program("rust-spoa", pg).build();
+
+        let mut writer = sam::io::Writer::new(writer);
+        writer.write_header(&header)?;
+        Ok(SamWriter { writer, header })
+    }
+
+    /// Realigns the reads of a cluster to its consensus and writes one record per read, in input order, with
+    /// its original qualities and its edit distance to the consensus as `NM` tag. Reads are named
+    /// `<cluster id>_<index>`; reads without any base aligned to the consensus are written as unmapped.
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster` - the cluster, whose identifier is the name of the reference
+    /// * `consensus` - the consensus of the cluster
+    /// * `params` - the scores of the realignment, which is global
+    pub fn write_cluster(&mut self, cluster: &Cluster, consensus: &[u8], params: &AlignmentParams) -> io::Result<()> {
+        if cluster.seqs.len() != cluster.quals.len() {
+            panic!("Input sequences and qualities must be of same length");
+        }
+        for (i, (seq, qual)) in cluster.seqs.iter().zip(cluster.quals.iter()).enumerate() {
+            // the sequences are null terminated
+            let seq = &seq[..seq.len().saturating_sub(1)];
+            let qual = &qual[..qual.len().saturating_sub(1)];
+            let name = format!("{}_{}", cluster.id, i);
+            let placement = if consensus.is_empty() { None } else { realign(consensus, seq, params) };
+            self.write_record(&name, &cluster.id, placement, seq, qual)?;
+        }
+        Ok(())
+    }
+
+    fn write_record(
+        &mut self,
+        name: &str,
+        reference: &str,
+        placement: Option<Placement>,
+        seq: &[u8],
+        qual: &[u8],
+    ) -> io::Result<()> {
+        let mut builder = RecordBuf::builder()
+            .set_name(name)
+            .set_sequence(Sequence::from(seq.to_vec()))
+            .set_quality_scores(QualityScores::from(qual.iter().map(|q| q.saturating_sub(33)).collect::<Vec<u8>>()));
+        builder = match placement {
+            Some(placement) => {
+                let id = self.header.reference_sequences().get_index_of(reference.as_bytes()).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("unknown reference sequence {}", reference))
+                })?;
+                let data = vec![(Tag::EDIT_DISTANCE, Value::from(placement.edit_distance as i32))];
+                builder
+                    .set_flags(Flags::empty())
+                    .set_reference_sequence_id(id)
+                    .set_alignment_start(Position::new(placement.pos).unwrap())
+                    .set_cigar(cigar(&placement))
+                    .set_data(data.into_iter().collect())
+            }
+            None => builder.set_flags(Flags::UNMAPPED),
+        };
+        self.writer.write_alignment_record(&self.header, &builder.build())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
+
+/// Writes a complete SAM holding the reads of every cluster realigned to its consensus; the reads of clusters
+/// with an empty consensus are written as unmapped.
+/// # Arguments
+///
+/// * `writer` - the destination of the SAM
+/// * `clusters` - the clusters
+/// * `results` - the result of each cluster, in the order of `clusters`, e.g. as returned by `poa_batch`
+/// * `params` - the scores of the realignment
+#[cfg(feature = "bam")]
+pub fn write_sam<W: Write>(
+    writer: W,
+    clusters: &[Cluster],
+    results: &[ClusterResult],
+    params: &AlignmentParams,
+) -> io::Result<()> {
+    if clusters.len() != results.len() {
+        panic!("Input clusters and results must be of same length");
+    }
+    let references: Vec<(&str, usize)> = results.iter().map(|r| (r.id.as_str(), r.result.consensus.len())).collect();
+    let mut sam = SamWriter::new(writer, &references)?;
+    for (cluster, result) in clusters.iter().zip(results.iter()) {
+        sam.write_cluster(cluster, &result.result.consensus, params)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placement() {
+        let placed = placement(b"-ACGTAC-GT--", b"TA-GTTCAG-CC").unwrap();
+
+        assert_eq!(placed.pos, 1);
+        assert_eq!(placed.cigar, "1S1M1D4M1I1M2S");
+        assert_eq!(placed.edit_distance, 3);
+        assert_eq!((placed.clip_start, placed.clip_end, placed.ref_len), (1, 2, 7));
+        assert_eq!((placed.matches, placed.alignment_len), (5, 8));
+        // leading deletions move the read, trailing ones are dropped
+        let placed = placement(b"ACGTACGT", b"--GTAC--").unwrap();
+        assert_eq!((placed.pos, placed.cigar.as_str()), (3, "4M"));
+        assert_eq!(placement(b"ACGT--", b"----TT"), None);
+    }
+
+    #[cfg(feature = "bam")]
+    #[test]
+    fn test_sam_records() {
+        let mut sam = SamWriter::new(vec![], &[("umi_1", 8), ("umi_2", 0)]).unwrap();
+        sam.write_record("umi_1_0", "umi_1", placement(b"ACGTACGT", b"--GTAC--"), b"GTAC", b"FFFF").unwrap();
+        sam.write_record("umi_1_1", "umi_1", None, b"", b"").unwrap();
+        sam.write_record("umi_2_0", "umi_2", placement(b"ACGT", b"ACGT"), b"ACGT", b"FFFF").unwrap_err();
+        let out = String::from_utf8(sam.into_inner()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        // the empty consensus is not a reference sequence
+        assert_eq!(lines[..3], ["@HD\tVN:1.6\tSO:unsorted", "@SQ\tSN:umi_1\tLN:8", "@PG\tID:rust-spoa\tPN:rust-spoa"]);
+        assert_eq!(lines[3], "umi_1_0\t0\tumi_1\t3\t255\t4M\t*\t0\t0\tGTAC\tFFFF\tNM:i:0");
+        assert_eq!(lines[4], "umi_1_1\t4\t*\t0\t255\t*\t*\t0\t0\t*\t*");
+        assert_eq!(lines.len(), 5);
+    }
+}
@@ -0,0 +1,365 @@
+//! Consensus of random subsamples of the reads: subsampling of clusters too deep to align in full, capping of
+//! streamed clusters, and bootstrap estimates of the confidence of a consensus.
+use crate::msa::{Msa, GAP};
+use crate::pairwise::{align_pair, null_terminated};
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+// the SplitMix64 generator: small, seedable and good enough to draw reads at random
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> SplitMix64 {
+        SplitMix64(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number drawn uniformly in 0..n
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // a number drawn uniformly in [0, 1)
+    pub(crate) fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// How deep clusters are subsampled by `poa_subsample_vote`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubsampleOptions {
+    /// The number of reads of each subsample. Clusters of at most this many reads are aligned in full.
+    pub sample_size: usize,
+    /// The number of subsamples, each giving one consensus to vote on.
+    pub replicates: usize,
+    /// The seed of the random generator, so that runs are reproducible.
+    pub seed: u64,
+}
+
+impl Default for SubsampleOptions {
+    fn default() -> SubsampleOptions {
+        SubsampleOptions {
+            sample_size: 200,
+            replicates: 5,
+            seed: 0,
+        }
+    }
+}
+
+/// The majority-vote consensus of the consensuses of several subsamples.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VotedConsensus {
+    /// The voted consensus.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub consensus: Vec<u8>,
+    /// The fraction of the subsample consensuses agreeing with each base of the voted consensus.
+    pub stability: Vec<f64>,
+    /// The consensus of each subsample.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seqs_as_str"))]
+    pub replicates: Vec<Vec<u8>>,
+}
+
+// `size` distinct indices drawn at random from 0..n, in increasing order
+pub(crate) fn subsample(n: usize, size: usize, rng: &mut SplitMix64) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..n).collect();
+    let size = size.min(n);
+    for i in 0..size {
+        let j = i + rng.below(n - i);
+        indices.swap(i, j);
+    }
+    indices.truncate(size);
+    indices.sort_unstable();
+    indices
+}
+
+// the fraction of the rows agreeing with each (ungapped) consensus base
+pub(crate) fn column_agreement(msa: &Msa) -> Vec<f64> {
+    (0..msa.num_columns())
+        .filter(|&col| msa.consensus[col] != GAP)
+        .map(|col| {
+            let agreeing = msa.rows.iter().filter(|row| row[col] == msa.consensus[col]).count();
+            agreeing as f64 / msa.num_rows().max(1) as f64
+        })
+        .collect()
+}
+
+/// Generates the consensus of a deep cluster from random subsamples of its reads, bounding the runtime.
+///
+/// Each subsample of `sample_size` reads gives one consensus; the voted consensus is the consensus of the
+/// subsample consensuses, aligned as a partial order graph, and its stability the fraction of subsample
+/// consensuses carrying each of its bases. Clusters no deeper than `sample_size` are aligned in full once,
+/// with a stability of 1.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - the size and number of subsamples
+pub fn poa_subsample_vote(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &SubsampleOptions,
+) -> VotedConsensus {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    if seqs.len() <= options.sample_size || options.replicates < 2 {
+        let consensus = crate::poa(seqs, quals, params).consensus;
+        return VotedConsensus {
+            stability: vec![1.0; consensus.len()],
+            replicates: vec![consensus.clone()],
+            consensus,
+        };
+    }
+
+    let mut rng = SplitMix64::new(options.seed);
+    let replicates: Vec<Vec<u8>> = (0..options.replicates)
+        .map(|_| {
+            let sample = subsample(seqs.len(), options.sample_size, &mut rng);
+            let sample_seqs: Vec<Vec<u8>> = sample.iter().map(|&i| seqs[i].clone()).collect();
+            let sample_quals: Vec<Vec<u8>> = sample.iter().map(|&i| quals[i].clone()).collect();
+            crate::poa(&sample_seqs, &sample_quals, params).consensus
+        })
+        .collect();
+
+    // every subsample consensus gets the same weight in the vote
+    let votes: Vec<Vec<u8>> = replicates
+        .iter()
+        .filter(|consensus| !consensus.is_empty())
+        .map(|consensus| null_terminated(consensus))
+        .collect();
+    let vote_quals: Vec<Vec<u8>> = votes
+        .iter()
+        .map(|vote| vote.iter().map(|&b| if b == 0 { 0 } else { b'F' }).collect())
+        .collect();
+    let msa = crate::msa_with_params(&votes, &vote_quals, None, params);
+    VotedConsensus {
+        consensus: msa.ungapped_consensus(),
+        stability: column_agreement(&msa),
+        replicates,
+    }
+}
+
+/// Draws a uniform random sample of at most `capacity` items from a stream, holding no more than `capacity`
+/// items in memory (reservoir sampling).
+/// # Arguments
+///
+/// * `items` - the stream to sample from
+/// * `capacity` - the maximum number of items kept
+/// * `seed` - the seed of the random generator, so that runs are reproducible
+///
+/// # Returns
+/// * returns the sampled items in stream order; all of them if the stream holds at most `capacity` items
+pub fn reservoir_sample<T, I: IntoIterator<Item = T>>(items: I, capacity: usize, seed: u64) -> Vec<T> {
+    let mut rng = SplitMix64::new(seed);
+    let mut reservoir: Vec<(usize, T)> = Vec::with_capacity(capacity);
+    for (i, item) in items.into_iter().enumerate() {
+        if reservoir.len() < capacity {
+            reservoir.push((i, item));
+        } else {
+            let j = rng.below(i + 1);
+            if j < capacity {
+                reservoir[j] = (i, item);
+            }
+        }
+    }
+    reservoir.sort_by_key(|&(i, _)| i);
+    reservoir.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Generates the consensus of a streamed cluster, capping the number of reads without buffering the whole
+/// cluster: with a cap, the reads are drawn from the stream by `reservoir_sample`.
+/// # Arguments
+///
+/// * `reads` - the sequences and qualities (each as a null-terminated vector of u8) of the cluster
+/// * `max_reads` - the maximum number of reads aligned, or None to align all of them
+/// * `seed` - the seed of the random generator, so that runs are reproducible
+/// * `params` - the alignment mode and scores
+pub fn poa_stream<I>(reads: I, max_reads: Option<usize>, seed: u64, params: &AlignmentParams) -> ConsensusResult
+where
+    I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+    let reads: Vec<(Vec<u8>, Vec<u8>)> = match max_reads {
+        Some(cap) => reservoir_sample(reads, cap, seed),
+        None => reads.into_iter().collect(),
+    };
+    let (seqs, quals): (Vec<Vec<u8>>, Vec<Vec<u8>>) = reads.into_iter().unzip();
+    crate::poa(&seqs, &quals, params)
+}
+
+/// A consensus with the bootstrap confidence of each of its bases.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BootstrapConsensus {
+    /// The consensus of all reads.
+    pub result: ConsensusResult,
+    /// The fraction of the bootstrap replicates whose consensus carries each base of the consensus.
+    pub confidence: Vec<f64>,
+    /// The number of bootstrap replicates.
+    pub replicates: usize,
+}
+
+// counts, for each base of the reference row, whether the replicate row carries the same base
+fn add_agreement(ref_row: &[u8], replicate_row: &[u8], agreeing: &mut [usize]) {
+    let mut pos = 0;
+    for (&r, &b) in ref_row.iter().zip(replicate_row.iter()) {
+        if r != GAP {
+            agreeing[pos] += (r == b) as usize;
+            pos += 1;
+        }
+    }
+}
+
+/// Estimates the confidence of the consensus by bootstrap: the reads are resampled with replacement
+/// `replicates` times, the consensus of every replicate is aligned against the consensus of all reads, and the
+/// confidence of each consensus base is the fraction of replicates carrying it.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `replicates` - the number of bootstrap replicates, e.g. 100
+/// * `seed` - the seed of the random generator, so that runs are reproducible
+pub fn poa_bootstrap(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    replicates: usize,
+    seed: u64,
+) -> BootstrapConsensus {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let result = crate::poa(seqs, quals, params);
+    if seqs.is_empty() || replicates == 0 {
+        return BootstrapConsensus {
+            confidence: vec![0.0; result.consensus.len()],
+            result,
+            replicates: 0,
+        };
+    }
+
+    let mut rng = SplitMix64::new(seed);
+    let mut agreeing = vec![0; result.consensus.len()];
+    for _ in 0..replicates {
+        let sample: Vec<usize> = (0..seqs.len()).map(|_| rng.below(seqs.len())).collect();
+        let sample_seqs: Vec<Vec<u8>> = sample.iter().map(|&i| seqs[i].clone()).collect();
+        let sample_quals: Vec<Vec<u8>> = sample.iter().map(|&i| quals[i].clone()).collect();
+        let consensus = crate::poa(&sample_seqs, &sample_quals, params).consensus;
+        let (ref_row, replicate_row) = align_pair(&result.consensus, &consensus, params);
+        add_agreement(&ref_row, &replicate_row, &mut agreeing);
+    }
+
+    BootstrapConsensus {
+        confidence: agreeing.iter().map(|&n| n as f64 / replicates as f64).collect(),
+        result,
+        replicates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsample() {
+        let mut rng = SplitMix64::new(7);
+        let sample = subsample(100, 10, &mut rng);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert!(sample.iter().all(|&i| i < 100));
+
+        assert_ne!(sample, subsample(100, 10, &mut rng));
+        assert_eq!(sample, subsample(100, 10, &mut SplitMix64::new(7)));
+        assert_eq!(subsample(3, 10, &mut rng), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_reservoir_sample() {
+        let sample = reservoir_sample(0..1000, 10, 5);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        // late items are drawn as well as early ones
+        assert!(sample.iter().any(|&i| i >= 500));
+        assert_eq!(sample, reservoir_sample(0..1000, 10, 5));
+        assert_eq!(reservoir_sample(0..3, 10, 5), vec![0, 1, 2]);
+        assert!(reservoir_sample(0..3, 0, 5).is_empty());
+    }
+
+    #[test]
+    fn test_poa_stream() {
+        let reads = (0..50).map(|_| (b"AATGCCCGTT\0".to_vec(), b"FFFFFFFFFF\0".to_vec()));
+
+        let result = poa_stream(reads, Some(8), 0, &AlignmentParams::default());
+
+        assert_eq!(result.metrics.num_reads, 8);
+        assert_eq!(result.consensus, b"AATGCCCGTT".to_vec());
+    }
+
+    #[test]
+    fn test_column_agreement() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string()],
+            rows: vec![b"AC-T".to_vec(), b"AGGT".to_vec()],
+            consensus: b"AC-T".to_vec(),
+        };
+        assert_eq!(column_agreement(&msa), vec![1.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_poa_subsample_vote() {
+        let mut seqs = vec![];
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"].iter() {
+            for _ in 0..10 {
+                seqs.push(format!("{}\0", seq).into_bytes());
+            }
+        }
+        let quals = crate::quals_for(&seqs);
+        let options = SubsampleOptions {
+            sample_size: 30,
+            replicates: 3,
+            seed: 1,
+        };
+
+        let voted = poa_subsample_vote(&seqs, &quals, &AlignmentParams::default(), &options);
+
+        assert_eq!(voted.replicates.len(), 3);
+        assert_eq!(voted.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(voted.stability.len(), voted.consensus.len());
+    }
+
+    #[test]
+    fn test_add_agreement() {
+        let mut agreeing = vec![0; 4];
+        add_agreement(b"AC-GT", b"ACAG-", &mut agreeing);
+        add_agreement(b"ACGT", b"TCGT", &mut agreeing);
+        assert_eq!(agreeing, vec![1, 2, 2, 1]);
+    }
+
+    #[test]
+    fn test_poa_bootstrap() {
+        let mut seqs = vec![];
+        for seq in ["ATTGCCCGTT", "AATGCCGTT", "AATGCCCGAT", "AACGCCCGTC", "AGTGCTCGTT", "AATGCTCGTT"].iter() {
+            seqs.push(format!("{}\0", seq).into_bytes());
+        }
+        let quals = crate::quals_for(&seqs);
+
+        let bootstrap = poa_bootstrap(&seqs, &quals, &AlignmentParams::default(), 20, 3);
+
+        assert_eq!(bootstrap.result.consensus, b"AATGCCCGTT".to_vec());
+        assert_eq!(bootstrap.confidence.len(), 10);
+        // every read starts with A
+        assert_eq!(bootstrap.confidence[0], 1.0);
+        assert!(bootstrap.confidence.iter().all(|&c| (0.0..=1.0).contains(&c)));
+        assert_eq!(bootstrap, poa_bootstrap(&seqs, &quals, &AlignmentParams::default(), 20, 3));
+    }
+}
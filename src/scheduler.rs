@@ -0,0 +1,195 @@
+//! Work-stealing scheduling of the clusters of a batch over worker threads.
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// The order in which the workers of a batch take up its clusters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchOrder {
+    /// Clusters are taken up in input order.
+    Input,
+    /// Clusters are taken up from the largest, in total read length times longest read, the size of the
+    /// alignment matrices, so that a few huge clusters start right away rather than serializing the tail of
+    /// the batch. Clusters of equal size keep their input order.
+    #[default]
+    LargestFirst,
+}
+
+/// How the clusters of a batch are spread over worker threads, see `poa_batch_parallel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchOptions {
+    /// The number of worker threads, or 0 to use the available parallelism.
+    pub threads: usize,
+    /// The order in which clusters are taken up.
+    pub order: BatchOrder,
+}
+
+// the estimated cost of aligning a cluster of reads, of lengths `lens`
+pub(crate) fn cluster_cost<I: IntoIterator<Item = usize>>(lens: I) -> usize {
+    let (total, longest) = lens.into_iter().fold((0usize, 0usize), |(total, longest), len| {
+        (total + len, longest.max(len))
+    });
+    total.saturating_mul(longest)
+}
+
+// the number of worker threads, 0 standing for the available parallelism
+pub(crate) fn worker_threads(threads: usize) -> usize {
+    match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+// pins the calling worker to its CPUs, if any, warning on stderr if it cannot be
+#[cfg(feature = "affinity")]
+pub(crate) fn pin_worker(worker: usize, cpus: Option<&Vec<usize>>) {
+    if let Some(cpus) = cpus {
+        if let Err(error) = crate::affinity::pin_current_thread(cpus) {
+            eprintln!("warning: worker {} could not be pinned to CPUs {:?}: {}", worker, cpus, error);
+        }
+    }
+}
+
+#[cfg(not(feature = "affinity"))]
+pub(crate) fn pin_worker(_worker: usize, _cpus: Option<&Vec<usize>>) {}
+
+// runs `f` on every item, of the estimated costs `costs`, over worker threads, each pinned to the CPUs of its
+// entry of `worker_cpus` if any and with a state created by `init` on its thread once pinned, and returns the
+// results in item order
+//
+// items are dealt round-robin in the order of the options to a queue per worker, so that each worker starts on
+// one of the first; workers take the front of their own queue, and once it is empty steal the front of the
+// queue of another worker
+pub(crate) fn run_stealing<S, T, I, F>(
+    costs: &[usize],
+    options: &BatchOptions,
+    worker_cpus: &[Option<Vec<usize>>],
+    init: I,
+    f: F,
+) -> Vec<T>
+where
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, usize) -> T + Sync,
+    T: Send,
+{
+    let mut order: Vec<usize> = (0..costs.len()).collect();
+    if options.order == BatchOrder::LargestFirst {
+        order.sort_by_key(|&i| Reverse(costs[i]));
+    }
+    let threads = worker_threads(options.threads).min(costs.len()).max(1);
+    let queues: Vec<Mutex<VecDeque<usize>>> = (0..threads)
+        .map(|w| Mutex::new(order.iter().skip(w).step_by(threads).cloned().collect()))
+        .collect();
+
+    let mut results: Vec<Option<T>> = (0..costs.len()).map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|w| {
+                let (queues, init, f) = (&queues, &init, &f);
+                scope.spawn(move || {
+                    pin_worker(w, worker_cpus.get(w).and_then(|cpus| cpus.as_ref()));
+                    let mut state = init();
+                    let mut done = vec![];
+                    while let Some(i) = next_item(queues, w) {
+                        done.push((i, f(&mut state, i)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        // the panic of a worker is resumed once the others stopped, with its own payload
+        let mut panic = None;
+        for worker in workers {
+            match worker.join() {
+                Ok(done) => {
+                    for (i, result) in done {
+                        results[i] = Some(result);
+                    }
+                }
+                Err(payload) => panic = panic.or(Some(payload)),
+            }
+        }
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
+    });
+    results.into_iter().map(|result| result.unwrap()).collect()
+}
+
+// the next item of worker `w`, from its own queue or stolen from the next worker with items left
+fn next_item(queues: &[Mutex<VecDeque<usize>>], w: usize) -> Option<usize> {
+    (0..queues.len()).find_map(|k| queues[(w + k) % queues.len()].lock().unwrap().pop_front())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_cost() {
+        assert_eq!(cluster_cost(vec![10, 30, 20]), 60 * 30);
+        assert_eq!(cluster_cost(vec![]), 0);
+    }
+
+    #[test]
+    fn test_run_stealing() {
+        let costs = [1, 5, 3, 5, 0];
+        let run = |threads: usize, order: BatchOrder| {
+            let started = Mutex::new(vec![]);
+            let results = run_stealing(
+                &costs,
+                &BatchOptions { threads, order },
+                &[],
+                || 0,
+                |count: &mut usize, i| {
+                    started.lock().unwrap().push(i);
+                    *count += 1;
+                    i * 10
+                },
+            );
+            (results, started.into_inner().unwrap())
+        };
+
+        // a single worker takes up the items in the order of the options
+        assert_eq!(
+            run(1, BatchOrder::LargestFirst),
+            (vec![0, 10, 20, 30, 40], vec![1, 3, 2, 0, 4])
+        );
+        assert_eq!(run(1, BatchOrder::Input).1, vec![0, 1, 2, 3, 4]);
+        // results are in item order whichever worker processed them
+        let (results, mut started) = run(3, BatchOrder::LargestFirst);
+        assert_eq!(results, vec![0, 10, 20, 30, 40]);
+        started.sort_unstable();
+        assert_eq!(started, vec![0, 1, 2, 3, 4]);
+        assert!(run_stealing(&[], &BatchOptions::default(), &[], || (), |_, i| i).is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "affinity", target_os = "linux"))]
+    fn test_run_stealing_pinned() {
+        let allowed = || {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            assert_eq!(unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) }, 0);
+            (0..libc::CPU_SETSIZE as usize).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) }).collect::<Vec<_>>()
+        };
+        let cpu = allowed()[0];
+        let options = BatchOptions {
+            threads: 1,
+            order: BatchOrder::Input,
+        };
+
+        let cpus = run_stealing(&[1, 1], &options, &[Some(vec![cpu])], || (), |_, _| allowed());
+
+        assert_eq!(cpus, vec![vec![cpu], vec![cpu]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "item 2")]
+    fn test_run_stealing_panic() {
+        let options = BatchOptions {
+            threads: 2,
+            order: BatchOrder::Input,
+        };
+        run_stealing(&[1, 1, 1, 1], &options, &[], || (), |_, i| if i == 2 { panic!("item {}", i) } else { i });
+    }
+}
@@ -0,0 +1,286 @@
+//! Scratch buffers reused by the consensus calls of each thread, so that batch workers don't allocate, and
+//! contend on the allocator for, the temporaries of every cluster they process.
+use libc::c_void;
+use std::cell::RefCell;
+
+use crate::msa::{Msa, GAP};
+use crate::params::{AlignmentParams, SoftMask};
+
+extern "C" {
+    fn poa_graph_new(
+        alignment_type: i32,
+        match_score: i32,
+        mismatch_score: i32,
+        gap_open: i32,
+        gap_extend: i32,
+        gap2_open: i32,
+        gap2_extend: i32,
+        deterministic: i32,
+    ) -> *mut c_void;
+    fn poa_graph_free(graph: *mut c_void);
+    fn poa_graph_clear(graph: *mut c_void);
+    fn poa_graph_add(graph: *mut c_void, seq: *const u8, qual: *const u8);
+    fn poa_graph_num_nodes(graph: *mut c_void) -> i32;
+    fn poa_graph_consensus_into(graph: *mut c_void, consensus: *mut u8) -> i32;
+}
+
+// the temporaries of a consensus call, each cleared before use so that only its allocation is kept; the
+// alignment rows and metrics are handed to the caller and so are not pooled
+#[derive(Default)]
+pub(crate) struct Scratch {
+    seq_ptrs: Vec<*const u8>,
+    qual_ptrs: Vec<*const u8>,
+    pub(crate) columns: Vec<usize>,
+    pub(crate) spans: Vec<Option<(usize, usize)>>,
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::default());
+}
+
+// runs `f` with the scratch buffers of the calling thread, or with fresh buffers if they are already in use
+// further up the stack
+pub(crate) fn with_scratch<T, F: FnOnce(&mut Scratch) -> T>(f: F) -> T {
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut scratch) => f(&mut scratch),
+        Err(_) => f(&mut Scratch::default()),
+    })
+}
+
+// checks that there are as many qualities as sequences, that both are null terminated, and that each sequence
+// is as long as its qualities up to their first null byte, where the C++ side ends them; SPOA rejects
+// sequences and qualities of different lengths by throwing across the FFI boundary
+pub(crate) fn check_inputs(seqs: &[Vec<u8>], quals: &[Vec<u8>]) {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    if seqs.iter().any(|seq| seq.last() != Some(&b'\0')) {
+        panic!("Input sequences must be null terminated");
+    }
+    if quals.iter().any(|qual| qual.last() != Some(&b'\0')) {
+        panic!("Input qualities must be null terminated");
+    }
+    let len = |bytes: &[u8]| bytes.iter().position(|&b| b == b'\0');
+    if let Some(i) = (0..seqs.len()).find(|&i| len(&seqs[i]) != len(&quals[i])) {
+        panic!("Input sequence {} and its qualities must be of same length", i);
+    }
+}
+
+impl Scratch {
+    // checks that the inputs are null terminated and collects the pointers handed to the C++ side
+    pub(crate) fn input_ptrs(&mut self, seqs: &[Vec<u8>], quals: &[Vec<u8>]) -> (&[*const u8], &[*const u8]) {
+        check_inputs(seqs, quals);
+        self.seq_ptrs.clear();
+        self.qual_ptrs.clear();
+        self.seq_ptrs.extend(seqs.iter().map(|seq| seq.as_ptr()));
+        self.qual_ptrs.extend(quals.iter().map(|qual| qual.as_ptr()));
+        (&self.seq_ptrs, &self.qual_ptrs)
+    }
+
+    // fills `columns` with the alignment columns of the consensus bases
+    pub(crate) fn consensus_columns(&mut self, msa: &Msa) {
+        self.columns.clear();
+        self.columns
+            .extend((0..msa.num_columns()).filter(|&col| msa.consensus[col] != GAP));
+    }
+
+    // fills `spans` with the first and last aligned column of each row, or None for empty rows
+    pub(crate) fn row_spans(&mut self, msa: &Msa) {
+        self.spans.clear();
+        self.spans.extend(msa.rows.iter().map(|row| {
+            Some((
+                row.iter().position(|&b| b != GAP)?,
+                row.iter().rposition(|&b| b != GAP)?,
+            ))
+        }));
+    }
+}
+
+/// The reusable temporaries of `poa_consensus_with_scratch`, created once and passed to every call so that,
+/// once its buffers have grown to the largest cluster, generating a consensus allocates nothing on the Rust
+/// side.
+///
+/// It holds the graph and alignment engine of the last call, cleared and reused as long as the alignment mode
+/// and scores are unchanged; the C++ side still allocates while aligning, like `PoaGraph`.
+pub struct ConsensusScratch {
+    graph: *mut c_void,
+    params: AlignmentParams,
+}
+
+// the graph and its engine are owned exclusively by the scratch
+unsafe impl Send for ConsensusScratch {}
+
+impl ConsensusScratch {
+    /// Creates an empty scratch, whose graph is created by its first use.
+    pub fn new() -> ConsensusScratch {
+        ConsensusScratch {
+            graph: std::ptr::null_mut(),
+            params: AlignmentParams::default(),
+        }
+    }
+
+    // the cleared graph of the scratch, created anew if it aligns with other parameters
+    fn graph(&mut self, params: &AlignmentParams) -> *mut c_void {
+        if !self.graph.is_null() && self.params == *params {
+            unsafe { poa_graph_clear(self.graph) };
+            return self.graph;
+        }
+        crate::cpu::assert_cpu();
+        params.check_engine_scores();
+        if !self.graph.is_null() {
+            unsafe { poa_graph_free(self.graph) };
+        }
+        self.graph = unsafe {
+            poa_graph_new(
+                params.alignment_type as i32,
+                params.match_score,
+                params.mismatch_score,
+                params.gap_open,
+                params.gap_extend,
+                params.gap2_open,
+                params.gap2_extend,
+                params.deterministic as i32,
+            )
+        };
+        self.params = *params;
+        self.graph
+    }
+}
+
+impl Default for ConsensusScratch {
+    fn default() -> ConsensusScratch {
+        ConsensusScratch::new()
+    }
+}
+
+impl Drop for ConsensusScratch {
+    fn drop(&mut self) {
+        if !self.graph.is_null() {
+            unsafe { poa_graph_free(self.graph) };
+        }
+    }
+}
+
+/// Generates the consensus sequence of a list of sequences into a caller-provided buffer like
+/// `poa_consensus_into`, reusing the graph, alignment engine and buffers of a scratch across calls.
+///
+/// With `params.collapse_duplicates`, `params.wildcard_score` or soft-masking, the consensus is taken from
+/// `poa`, which allocates its alignment.
+/// # Arguments
+///
+/// * `scratch` - the temporaries reused across calls
+/// * `consensus` - the buffer receiving the consensus
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_consensus_with_scratch, AlignmentParams, ConsensusScratch};
+///
+///     let mut scratch = ConsensusScratch::new();
+///     let mut consensus = Vec::with_capacity(1024);
+///     for _ in 0..3 {
+///         let seqs = vec![b"ACGTT\0".to_vec(), b"ACTT\0".to_vec()];
+///         let quals = vec![b"FFFFF\0".to_vec(), b"FFFF\0".to_vec()];
+///         poa_consensus_with_scratch(&mut scratch, &mut consensus, &seqs, &quals, &AlignmentParams::default());
+///     }
+/// ```
+pub fn poa_consensus_with_scratch(
+    scratch: &mut ConsensusScratch,
+    consensus: &mut Vec<u8>,
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+) {
+    consensus.clear();
+    if seqs.is_empty() {
+        return;
+    }
+    if params.collapse_duplicates || params.wildcard_score.is_some() || params.soft_mask != SoftMask::Preserve {
+        consensus.extend_from_slice(&crate::poa(seqs, quals, params).consensus);
+        return;
+    }
+
+    check_inputs(seqs, quals);
+    let graph = scratch.graph(params);
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        unsafe { poa_graph_add(graph, seq.as_ptr(), qual.as_ptr()) };
+    }
+    // the consensus spells at most one base per node
+    consensus.resize(unsafe { poa_graph_num_nodes(graph) } as usize, 0);
+    let len = unsafe { poa_graph_consensus_into(graph, consensus.as_mut_ptr()) } as usize;
+    consensus.truncate(len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_scratch() {
+        let seqs = vec![b"ACGT\0".to_vec(), b"AC\0".to_vec()];
+        let quals = vec![b"FFFF\0".to_vec(), b"FF\0".to_vec()];
+        let capacity = with_scratch(|scratch| {
+            let (seq_ptrs, qual_ptrs) = scratch.input_ptrs(&seqs, &quals);
+            assert_eq!(seq_ptrs, &[seqs[0].as_ptr(), seqs[1].as_ptr()]);
+            assert_eq!(qual_ptrs, &[quals[0].as_ptr(), quals[1].as_ptr()]);
+            // nested calls get buffers of their own
+            with_scratch(|nested| assert!(nested.seq_ptrs.is_empty()));
+            scratch.seq_ptrs.capacity()
+        });
+
+        // the buffers of the thread are reused by its next call
+        with_scratch(|scratch| {
+            scratch.input_ptrs(&seqs[..1], &quals[..1]);
+            assert_eq!(scratch.seq_ptrs.len(), 1);
+            assert_eq!(scratch.seq_ptrs.capacity(), capacity);
+        });
+
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string()],
+            rows: vec![b"-CG-".to_vec(), b"----".to_vec()],
+            consensus: b"AC-T".to_vec(),
+        };
+        with_scratch(|scratch| {
+            scratch.consensus_columns(&msa);
+            scratch.row_spans(&msa);
+            assert_eq!(scratch.columns, vec![0, 1, 3]);
+            assert_eq!(scratch.spans, vec![Some((1, 2)), None]);
+        });
+    }
+
+    #[test]
+    fn test_poa_consensus_with_scratch() {
+        let reads = [
+            "ATTGCCCGTT",
+            "AATGCCGTT",
+            "AATGCCCGAT",
+            "AACGCCCGTC",
+            "AGTGCTCGTT",
+            "AATGCTCGTT",
+        ];
+        let seqs: Vec<Vec<u8>> = reads.iter().map(|read| format!("{}\0", read).into_bytes()).collect();
+        let quals: Vec<Vec<u8>> = reads
+            .iter()
+            .map(|read| format!("{}\0", "F".repeat(read.len())).into_bytes())
+            .collect();
+        let mut scratch = ConsensusScratch::new();
+        let mut consensus = vec![];
+
+        // the graph is reused while the parameters are unchanged, and created anew when they change
+        let deterministic = AlignmentParams {
+            deterministic: true,
+            ..AlignmentParams::default()
+        };
+        for params in &[AlignmentParams::default(), AlignmentParams::default(), deterministic] {
+            poa_consensus_with_scratch(&mut scratch, &mut consensus, &seqs, &quals, params);
+            assert_eq!(consensus, b"AATGCCCGTT".to_vec());
+        }
+        assert_eq!(scratch.params, deterministic);
+
+        poa_consensus_with_scratch(&mut scratch, &mut consensus, &[], &[], &deterministic);
+        assert!(consensus.is_empty());
+    }
+}
@@ -0,0 +1,10 @@
+//! Serialization helpers, writing byte sequences as strings rather than arrays of numbers.
+use serde::Serializer;
+
+pub(crate) fn seq_as_str<S: Serializer>(seq: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(seq))
+}
+
+pub(crate) fn seqs_as_str<S: Serializer>(seqs: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(seqs.iter().map(|seq| String::from_utf8_lossy(seq)))
+}
@@ -0,0 +1,82 @@
+//! Deterministic partitioning of batches into shards, to spread a batch over several processes or nodes.
+use crate::batch::{Cluster, ClusterResult};
+use crate::cache::Fnv64;
+
+/// Returns the shard of a cluster among `num_shards` shards. The shard only depends on the cluster id, so
+/// that every process computes the same partition of a batch, whatever the order of its clusters.
+pub fn shard_of(id: &str, num_shards: usize) -> usize {
+    if num_shards == 0 {
+        panic!("The number of shards must be positive");
+    }
+    let mut hash = Fnv64::new();
+    hash.write(id.as_bytes());
+    (hash.0 % num_shards as u64) as usize
+}
+
+/// Returns the clusters of a batch belonging to one shard, see `shard_of`.
+/// # Arguments
+///
+/// * `clusters` - the clusters of the whole batch
+/// * `num_shards` - the number of shards the batch is split into
+/// * `shard` - the index of the shard to keep, below `num_shards`
+///
+/// # Returns
+/// * returns the clusters of the shard, in input order
+pub fn shard_clusters(clusters: &[Cluster], num_shards: usize, shard: usize) -> Vec<Cluster> {
+    if shard >= num_shards {
+        panic!("Shard index {} out of range for {} shards", shard, num_shards);
+    }
+    clusters.iter().filter(|cluster| shard_of(&cluster.id, num_shards) == shard).cloned().collect()
+}
+
+/// Merges the results of the shards of a batch, e.g. as read back with `read_checkpoint`, into the results
+/// of the whole batch ordered by cluster id, so that the merged output does not depend on the number of
+/// shards nor on the order they complete.
+pub fn merge_shards<I: IntoIterator<Item = Vec<ClusterResult>>>(shards: I) -> Vec<ClusterResult> {
+    let mut results: Vec<ClusterResult> = shards.into_iter().flatten().collect();
+    results.sort_by(|a, b| a.id.cmp(&b.id));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shards() {
+        let clusters: Vec<Cluster> = (0..20)
+            .map(|i| Cluster {
+                id: format!("cluster_{}", i),
+                ..Cluster::default()
+            })
+            .collect();
+
+        let shards: Vec<Vec<Cluster>> = (0..3).map(|shard| shard_clusters(&clusters, 3, shard)).collect();
+
+        assert_eq!(shards.iter().map(|shard| shard.len()).sum::<usize>(), 20);
+        assert!(shards.iter().all(|shard| !shard.is_empty()));
+        assert_eq!(shard_of("cluster_7", 3), shard_of("cluster_7", 3));
+        assert!(shards[shard_of("cluster_7", 3)].iter().any(|cluster| cluster.id == "cluster_7"));
+
+        let results = merge_shards(shards.iter().map(|shard| {
+            shard
+                .iter()
+                .map(|cluster| ClusterResult {
+                    id: cluster.id.clone(),
+                    ..ClusterResult::default()
+                })
+                .collect()
+        }));
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(results[0].id, "cluster_0");
+        assert_eq!(results[1].id, "cluster_1");
+        assert_eq!(results[2].id, "cluster_10");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_shard_out_of_range() {
+        shard_clusters(&[], 2, 2);
+    }
+}
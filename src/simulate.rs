@@ -0,0 +1,188 @@
+//! A small simulator of the reads of random templates, to benchmark and test on clusters resembling those of
+//! nanopore or PacBio HiFi sequencing.
+use std::collections::BTreeMap;
+
+use crate::batch::Cluster;
+use crate::pairwise::null_terminated;
+use crate::sample::SplitMix64;
+
+/// The sequencing errors of simulated reads, as rates per template base.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationProfile {
+    /// The rate of substitutions.
+    pub substitution: f64,
+    /// The rate of insertions of a random base after a template base.
+    pub insertion: f64,
+    /// The rate of deletions.
+    pub deletion: f64,
+    /// The additional rate of insertions and deletions, each half of it, of the bases repeating the previous
+    /// base of the template, as within homopolymer runs; the bases inserted there repeat the run.
+    pub homopolymer_indel: f64,
+    /// The Phred quality (without the +33 offset) of the correct bases; erroneous bases get a third of it.
+    pub qual: u8,
+}
+
+impl SimulationProfile {
+    /// The errors of nanopore reads, about 5% of the bases and more within homopolymers.
+    pub fn ont() -> SimulationProfile {
+        SimulationProfile {
+            substitution: 0.02,
+            insertion: 0.01,
+            deletion: 0.02,
+            homopolymer_indel: 0.05,
+            qual: 15,
+        }
+    }
+
+    /// The errors of PacBio HiFi reads, about 0.15% of the bases.
+    pub fn hifi() -> SimulationProfile {
+        SimulationProfile {
+            substitution: 0.0005,
+            insertion: 0.0005,
+            deletion: 0.0005,
+            homopolymer_indel: 0.005,
+            qual: 30,
+        }
+    }
+}
+
+impl Default for SimulationProfile {
+    fn default() -> SimulationProfile {
+        SimulationProfile::ont()
+    }
+}
+
+/// The reads simulated from a random template, see `simulate_cluster`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SimulatedCluster {
+    /// The template the reads were drawn from.
+    pub template: Vec<u8>,
+    /// The reads (each as a null-terminated vector of u8).
+    pub seqs: Vec<Vec<u8>>,
+    /// The Phred+33 qualities of the reads (each as a null-terminated vector of u8).
+    pub quals: Vec<Vec<u8>>,
+}
+
+// a read of `template` with the errors of `profile`, and its qualities
+fn simulate_read(rng: &mut SplitMix64, template: &[u8], profile: &SimulationProfile) -> (Vec<u8>, Vec<u8>) {
+    let (good, bad) = (profile.qual + 33, profile.qual / 3 + 33);
+    let (mut seq, mut qual) = (vec![], vec![]);
+    for (i, &base) in template.iter().enumerate() {
+        let in_run = i > 0 && template[i - 1] == base;
+        let extra = if in_run { profile.homopolymer_indel / 2.0 } else { 0.0 };
+        let deletion = profile.deletion + extra;
+        let insertion = deletion + profile.insertion + extra;
+        let x = rng.uniform();
+        if x < deletion {
+            continue;
+        }
+        if x < insertion {
+            let inserted = if in_run { base } else { b"ACGT"[rng.below(4)] };
+            seq.extend_from_slice(&[base, inserted]);
+            qual.extend_from_slice(&[good, bad]);
+        } else if x < insertion + profile.substitution {
+            // one of the three other bases
+            let others: Vec<u8> = b"ACGT".iter().cloned().filter(|&b| b != base).collect();
+            seq.push(others[rng.below(others.len())]);
+            qual.push(bad);
+        } else {
+            seq.push(base);
+            qual.push(good);
+        }
+    }
+    (seq, qual)
+}
+
+/// Simulates the reads of a random template, with the sequencing errors of an error profile.
+/// # Arguments
+///
+/// * `len` - the length of the template
+/// * `depth` - the number of reads
+/// * `profile` - the sequencing errors of the reads
+/// * `seed` - the seed of the random generator, so that clusters are reproducible
+///
+/// # Returns
+/// * returns the template and its reads, ready for `poa`
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa, simulate_cluster, AlignmentParams, SimulationProfile};
+///
+///     let cluster = simulate_cluster(1000, 20, &SimulationProfile::ont(), 42);
+///     let result = poa(&cluster.seqs, &cluster.quals, &AlignmentParams::default());
+///     assert_eq!((cluster.template.len(), cluster.seqs.len()), (1000, 20));
+///     assert_eq!(result.metrics.num_reads, 20);
+/// ```
+pub fn simulate_cluster(len: usize, depth: usize, profile: &SimulationProfile, seed: u64) -> SimulatedCluster {
+    let mut rng = SplitMix64::new(seed);
+    let template: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.below(4)]).collect();
+    let (mut seqs, mut quals) = (vec![], vec![]);
+    for _ in 0..depth {
+        let (seq, qual) = simulate_read(&mut rng, &template, profile);
+        seqs.push(null_terminated(&seq));
+        quals.push(null_terminated(&qual));
+    }
+    SimulatedCluster { template, seqs, quals }
+}
+
+/// Simulates a batch of clusters like `simulate_cluster`, named `cluster_<index>`, each of its own template.
+pub fn simulate_batch(
+    num_clusters: usize,
+    len: usize,
+    depth: usize,
+    profile: &SimulationProfile,
+    seed: u64,
+) -> Vec<Cluster> {
+    (0..num_clusters)
+        .map(|i| {
+            let cluster = simulate_cluster(len, depth, profile, seed.wrapping_add(i as u64));
+            Cluster {
+                id: format!("cluster_{}", i),
+                seqs: cluster.seqs,
+                quals: cluster.quals,
+                metadata: BTreeMap::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_cluster() {
+        let cluster = simulate_cluster(2000, 5, &SimulationProfile::ont(), 7);
+        assert_eq!(cluster.template.len(), 2000);
+        assert_eq!(cluster.seqs.len(), 5);
+        for (seq, qual) in cluster.seqs.iter().zip(cluster.quals.iter()) {
+            assert_eq!(seq.len(), qual.len());
+            assert_eq!(seq.last(), Some(&0));
+            // about 5% of errors, before those of homopolymers
+            let errors = qual.iter().filter(|&&q| q == b'&').count();
+            assert!(errors > 20 && errors < 200, "{} errors", errors);
+        }
+        assert_eq!(simulate_cluster(2000, 5, &SimulationProfile::ont(), 7), cluster);
+
+        let error_free = SimulationProfile {
+            substitution: 0.0,
+            insertion: 0.0,
+            deletion: 0.0,
+            homopolymer_indel: 0.0,
+            qual: 30,
+        };
+        let cluster = simulate_cluster(100, 2, &error_free, 1);
+        assert_eq!(cluster.seqs[0], null_terminated(&cluster.template));
+        assert_eq!(cluster.quals[1], null_terminated(&[b'?'; 100]));
+    }
+
+    #[test]
+    fn test_simulate_batch() {
+        let batch = simulate_batch(3, 50, 4, &SimulationProfile::hifi(), 1);
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch[2].id, "cluster_2");
+        assert_eq!(batch[0].seqs.len(), 4);
+        assert_ne!(batch[0].seqs[0], batch[1].seqs[0]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Per-column allele fractions and consensuses split by read strand.
+use crate::compare::{compare_consensus, ConsensusComparison};
+use crate::msa::{Msa, GAP};
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// The strand a read was sequenced from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Strand {
+    /// The read has the orientation of the consensus.
+    Forward,
+    /// The read was reverse complemented before alignment.
+    Reverse,
+}
+
+/// The reads of one strand covering an alignment column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StrandCounts {
+    /// The number of reads covering the column.
+    pub depth: usize,
+    /// The number of covering reads differing from the consensus at the column.
+    pub alt: usize,
+}
+
+impl StrandCounts {
+    /// Returns the fraction of the covering reads differing from the consensus (0 without coverage).
+    pub fn alt_fraction(&self) -> f64 {
+        if self.depth == 0 {
+            return 0.0;
+        }
+        self.alt as f64 / self.depth as f64
+    }
+}
+
+/// The allele counts of one alignment column, split by strand.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ColumnStrandStats {
+    /// The alignment column.
+    pub column: usize,
+    /// The consensus base of the column (`-` for columns inserted relative to the consensus).
+    pub consensus_base: u8,
+    /// The counts of the forward strand reads.
+    pub forward: StrandCounts,
+    /// The counts of the reverse strand reads.
+    pub reverse: StrandCounts,
+}
+
+impl ColumnStrandStats {
+    /// Returns the fraction of all covering reads differing from the consensus.
+    pub fn alt_fraction(&self) -> f64 {
+        StrandCounts {
+            depth: self.forward.depth + self.reverse.depth,
+            alt: self.forward.alt + self.reverse.alt,
+        }
+        .alt_fraction()
+    }
+
+    /// Returns the two-sided Fisher's exact test p-value of the alternate allele being independent of the
+    /// strand. Small values flag strand-biased (likely artifactual) differences.
+    pub fn strand_bias_pvalue(&self) -> f64 {
+        fisher_exact(
+            self.forward.alt,
+            self.forward.depth - self.forward.alt,
+            self.reverse.alt,
+            self.reverse.depth - self.reverse.alt,
+        )
+    }
+}
+
+/// Counts, for every alignment column, the reads of each strand covering it and differing from the consensus.
+/// A read covers the columns between its first and last aligned base.
+///
+/// # Arguments
+///
+/// * `msa` - the alignment
+/// * `strands` - the strand of each row of the alignment
+pub fn strand_column_stats(msa: &Msa, strands: &[Strand]) -> Vec<ColumnStrandStats> {
+    if strands.len() != msa.num_rows() {
+        panic!("Input strands and alignment rows must be of same length");
+    }
+
+    let mut stats: Vec<ColumnStrandStats> = msa
+        .consensus
+        .iter()
+        .enumerate()
+        .map(|(column, &consensus_base)| ColumnStrandStats {
+            column,
+            consensus_base,
+            forward: StrandCounts::default(),
+            reverse: StrandCounts::default(),
+        })
+        .collect();
+
+    for (row, strand) in msa.rows.iter().zip(strands.iter()) {
+        let (first, last) = match (row.iter().position(|&b| b != GAP), row.iter().rposition(|&b| b != GAP)) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        for col in first..=last {
+            let counts = match strand {
+                Strand::Forward => &mut stats[col].forward,
+                Strand::Reverse => &mut stats[col].reverse,
+            };
+            counts.depth += 1;
+            if row[col] != msa.consensus[col] {
+                counts.alt += 1;
+            }
+        }
+    }
+    stats
+}
+
+/// The separate consensuses of the forward and reverse strand reads of a cluster and their differences.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StrandConcordance {
+    /// The consensus of the forward strand reads.
+    pub forward: ConsensusResult,
+    /// The consensus of the reverse strand reads.
+    pub reverse: ConsensusResult,
+    /// The comparison of the forward consensus (`a`) with the reverse consensus (`b`), whose differences are
+    /// the discordant positions.
+    pub comparison: ConsensusComparison,
+}
+
+impl StrandConcordance {
+    /// Returns true if both strands have reads and their consensuses are identical.
+    pub fn is_concordant(&self) -> bool {
+        self.forward.metrics.num_reads > 0 && self.reverse.metrics.num_reads > 0 && self.comparison.is_identical()
+    }
+}
+
+/// Generates the consensus of the reads of each strand on its own and compares the two, so that differences
+/// from the reads of one strand only, typical of strand-specific artifacts such as oxidative damage, are told
+/// from true variants, which both strands carry.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8), reverse strand reads
+///   already reverse complemented to the orientation of the forward strand reads
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `strands` - the strand of each sequence
+/// * `params` - the alignment mode and scores of both consensuses and of their comparison
+///
+/// # Returns
+/// * returns both consensuses and their differences, ordered by position
+pub fn strand_concordance(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    strands: &[Strand],
+    params: &AlignmentParams,
+) -> StrandConcordance {
+    if seqs.len() != quals.len() || seqs.len() != strands.len() {
+        panic!("Input sequences, qualities and strands must be of same length");
+    }
+    let consensus = |strand: Strand| {
+        let reads: Vec<usize> = (0..seqs.len()).filter(|&i| strands[i] == strand).collect();
+        let strand_seqs: Vec<Vec<u8>> = reads.iter().map(|&i| seqs[i].clone()).collect();
+        let strand_quals: Vec<Vec<u8>> = reads.iter().map(|&i| quals[i].clone()).collect();
+        crate::poa(&strand_seqs, &strand_quals, params)
+    };
+    let forward = consensus(Strand::Forward);
+    let reverse = consensus(Strand::Reverse);
+    let comparison = compare_consensus(&forward.consensus, &reverse.consensus, params);
+    StrandConcordance {
+        forward,
+        reverse,
+        comparison,
+    }
+}
+
+// two-sided Fisher's exact test of the 2x2 table [[a, b], [c, d]]
+pub(crate) fn fisher_exact(a: usize, b: usize, c: usize, d: usize) -> f64 {
+    let n = a + b + c + d;
+    let log_fact: Vec<f64> = std::iter::once(0.0)
+        .chain((1..=n).scan(0.0, |acc, i| {
+            *acc += (i as f64).ln();
+            Some(*acc)
+        }))
+        .collect();
+
+    let (row1, col1) = (a + b, a + c);
+    let log_p = |x: usize| {
+        log_fact[row1] + log_fact[n - row1] + log_fact[col1] + log_fact[n - col1]
+            - log_fact[x]
+            - log_fact[row1 - x]
+            - log_fact[col1 - x]
+            - log_fact[n - row1 - col1 + x]
+            - log_fact[n]
+    };
+
+    let observed = log_p(a);
+    let min_x = (row1 + col1).saturating_sub(n);
+    let max_x = row1.min(col1);
+    let p: f64 = (min_x..=max_x)
+        .map(log_p)
+        .filter(|&lp| lp <= observed + 1e-7)
+        .map(f64::exp)
+        .sum();
+    p.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strand_column_stats() {
+        let msa = Msa {
+            names: (0..4).map(|i| i.to_string()).collect(),
+            rows: vec![b"ACT".to_vec(), b"AGT".to_vec(), b"ACT".to_vec(), b"-C-".to_vec()],
+            consensus: b"ACT".to_vec(),
+        };
+        let strands = vec![Strand::Forward, Strand::Forward, Strand::Reverse, Strand::Reverse];
+
+        let stats = strand_column_stats(&msa, &strands);
+
+        assert_eq!(stats[0].reverse, StrandCounts { depth: 1, alt: 0 });
+        assert_eq!(stats[1].forward, StrandCounts { depth: 2, alt: 1 });
+        assert_eq!(stats[1].reverse, StrandCounts { depth: 2, alt: 0 });
+        assert_eq!(stats[1].forward.alt_fraction(), 0.5);
+        assert_eq!(stats[1].alt_fraction(), 0.25);
+    }
+
+    #[test]
+    fn test_strand_concordance() {
+        let reads = ["ACGTACGTTA", "ACGTACGTTA", "ACGTACGTTA", "ACGAACGTTA", "ACGAACGTTA", "ACGAACGTTA"];
+        let seqs: Vec<Vec<u8>> = reads.iter().map(|s| format!("{}\0", s).into_bytes()).collect();
+        let quals: Vec<Vec<u8>> = reads.iter().map(|s| format!("{}\0", "F".repeat(s.len())).into_bytes()).collect();
+        let strands: Vec<Strand> = (0..6).map(|i| if i < 3 { Strand::Forward } else { Strand::Reverse }).collect();
+
+        let concordance = strand_concordance(&seqs, &quals, &strands, &AlignmentParams::default());
+
+        assert_eq!(concordance.forward.consensus, b"ACGTACGTTA".to_vec());
+        assert_eq!(concordance.reverse.consensus, b"ACGAACGTTA".to_vec());
+        assert_eq!(concordance.comparison.differences.len(), 1);
+        assert_eq!(concordance.comparison.differences[0].pos_a, 3);
+        assert!(!concordance.is_concordant());
+    }
+
+    #[test]
+    fn test_fisher_exact() {
+        // reference values from scipy.stats.fisher_exact
+        assert!((fisher_exact(10, 0, 0, 10) - 1.0825e-5).abs() < 1e-8);
+        assert!((fisher_exact(3, 1, 1, 3) - 0.4857).abs() < 1e-4);
+        assert_eq!(fisher_exact(0, 5, 0, 5), 1.0);
+    }
+}
@@ -0,0 +1,253 @@
+//! Lazy consensus of the groups of a stream of FASTQ records, holding one group in memory at a time.
+use std::io::{self, BufRead};
+
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// A FASTQ record.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FastqRecord {
+    /// The name of the read, the header up to its first whitespace.
+    pub name: String,
+    /// The sequence, not null-terminated.
+    pub seq: Vec<u8>,
+    /// The qualities (Phred+33), not null-terminated.
+    pub qual: Vec<u8>,
+}
+
+/// An iterator over the records of a FASTQ file, read one at a time. It ends after the first malformed record
+/// or read error, as the records after it cannot be told apart reliably.
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::FastqReader;
+///
+///     let fastq = &b"@read_1 umi=ACGT\nACGTT\n+\nFFFFF\n"[..];
+///     let records: Vec<_> = FastqReader::new(fastq).collect::<Result<_, _>>().unwrap();
+///     assert_eq!(records[0].name, "read_1");
+/// ```
+pub struct FastqReader<R> {
+    lines: io::Lines<R>,
+    failed: bool,
+}
+
+impl<R: BufRead> FastqReader<R> {
+    /// Creates a reader of the FASTQ records of `reader`.
+    pub fn new(reader: R) -> FastqReader<R> {
+        FastqReader {
+            lines: reader.lines(),
+            failed: false,
+        }
+    }
+
+    // the record of a header line
+    fn record(&mut self, header: &str) -> io::Result<FastqRecord> {
+        let name = match header.strip_prefix('@') {
+            Some(name) => name.split_whitespace().next().unwrap_or("").to_string(),
+            None => return Err(invalid("FASTQ header not starting with @")),
+        };
+        let seq = self.line()?.into_bytes();
+        if !self.line()?.starts_with('+') {
+            return Err(invalid("FASTQ separator not starting with +"));
+        }
+        let qual = self.line()?.into_bytes();
+        if seq.len() != qual.len() {
+            return Err(invalid("FASTQ sequence and qualities differ in length"));
+        }
+        Ok(FastqRecord { name, seq, qual })
+    }
+
+    // the next line of the current record
+    fn line(&mut self) -> io::Result<String> {
+        match self.lines.next() {
+            Some(line) => Ok(line?.trim_end().to_string()),
+            None => Err(invalid("truncated FASTQ record")),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: BufRead> Iterator for FastqReader<R> {
+    type Item = io::Result<FastqRecord>;
+
+    fn next(&mut self) -> Option<io::Result<FastqRecord>> {
+        if self.failed {
+            return None;
+        }
+        // blank lines between records are skipped
+        let record = loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => break self.record(&line),
+                Err(e) => break Err(e),
+            }
+        };
+        self.failed = record.is_err();
+        Some(record)
+    }
+}
+
+/// The consensus of each group of a stream of records, see `poa_grouped_stream`.
+pub struct ConsensusStream<I, F, K> {
+    records: I,
+    key: F,
+    params: AlignmentParams,
+    // the key and records of the group being read
+    group: Option<(K, Vec<FastqRecord>)>,
+}
+
+// the consensus of the records of a group, its rows named after the reads
+fn group_consensus(records: &[FastqRecord], params: &AlignmentParams) -> ConsensusResult {
+    let seqs: Vec<Vec<u8>> = records.iter().map(|record| null_terminated(&record.seq)).collect();
+    let quals: Vec<Vec<u8>> = records.iter().map(|record| null_terminated(&record.qual)).collect();
+    let names: Vec<String> = records.iter().map(|record| record.name.clone()).collect();
+    ConsensusResult::from_msa(crate::msa_with_params(&seqs, &quals, Some(&names), params))
+}
+
+impl<I, F, K, E> Iterator for ConsensusStream<I, F, K>
+where
+    I: Iterator<Item = Result<FastqRecord, E>>,
+    F: FnMut(&FastqRecord) -> K,
+    K: PartialEq,
+{
+    type Item = Result<(K, ConsensusResult), E>;
+
+    fn next(&mut self) -> Option<Result<(K, ConsensusResult), E>> {
+        loop {
+            let record = match self.records.next() {
+                Some(Ok(record)) => record,
+                // the group being read is kept, and completed by the records after the error
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let (key, records) = self.group.take()?;
+                    return Some(Ok((key, group_consensus(&records, &self.params))));
+                }
+            };
+            let key = (self.key)(&record);
+            match &mut self.group {
+                Some((current, records)) if *current == key => records.push(record),
+                _ => {
+                    if let Some((key, records)) = self.group.replace((key, vec![record])) {
+                        return Some(Ok((key, group_consensus(&records, &self.params))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Generates the consensus of each group of a stream of FASTQ records lazily, e.g. of the UMI families of a
+/// whole flowcell, holding only the records of the group being read in memory, unlike `poa_grouped`.
+///
+/// Groups are runs of consecutive records of the same key, as in a file sorted by key: the consensus of a
+/// group is generated once a record of another key, or the end of the stream, is read, and a key recurring
+/// after other keys starts a new group. Errors of the stream are passed through, the group being read then
+/// continuing with the records after them.
+/// # Arguments
+///
+/// * `records` - the records, e.g. of a `FastqReader`
+/// * `key` - the key of the group of a record, e.g. the UMI of its name
+/// * `params` - the alignment mode and scores, shared by all groups
+///
+/// # Returns
+/// * returns an iterator over the key and consensus of each group, in stream order; the rows of the alignment
+///   are named after the reads
+///
+/// # Examples
+///
+/// ```
+///     use rust_spoa::{poa_grouped_stream, AlignmentParams, FastqReader};
+///
+///     let fastq = &b"@r1:AAA\nACGTT\n+\nFFFFF\n@r2:AAA\nACTT\n+\nFFFF\n@r3:CCC\nTTGCA\n+\nFFFFF\n"[..];
+///     let umi = |record: &rust_spoa::FastqRecord| record.name.split(':').nth(1).unwrap_or("").to_string();
+///     let groups: Vec<(String, usize)> = poa_grouped_stream(FastqReader::new(fastq), umi, &AlignmentParams::default())
+///         .map(|group| {
+///             let (umi, result) = group.unwrap();
+///             (umi, result.metrics.num_reads)
+///         })
+///         .collect();
+///     assert_eq!(groups, vec![("AAA".to_string(), 2), ("CCC".to_string(), 1)]);
+/// ```
+pub fn poa_grouped_stream<I, F, K, E>(
+    records: I,
+    key: F,
+    params: &AlignmentParams,
+) -> ConsensusStream<I::IntoIter, F, K>
+where
+    I: IntoIterator<Item = Result<FastqRecord, E>>,
+    F: FnMut(&FastqRecord) -> K,
+    K: PartialEq,
+{
+    ConsensusStream {
+        records: records.into_iter(),
+        key,
+        params: *params,
+        group: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastq_reader() {
+        let fastq = &b"@r1 umi=AAA\nACGT\n+r1\nFFFF\n\n@r2\nAC\n+\nF#\n"[..];
+        let records: Vec<FastqRecord> = FastqReader::new(fastq).collect::<io::Result<_>>().unwrap();
+        let expected = vec![
+            FastqRecord {
+                name: "r1".to_string(),
+                seq: b"ACGT".to_vec(),
+                qual: b"FFFF".to_vec(),
+            },
+            FastqRecord {
+                name: "r2".to_string(),
+                seq: b"AC".to_vec(),
+                qual: b"F#".to_vec(),
+            },
+        ];
+        assert_eq!(records, expected);
+
+        let errors = |fastq: &[u8]| FastqReader::new(fastq).filter(|record| record.is_err()).count();
+        assert_eq!(errors(b"r1\nACGT\n+\nFFFF\n"), 1);
+        assert_eq!(errors(b"@r1\nACGT\n+\nFFF\n"), 1);
+        assert_eq!(errors(b"@r1\nACGT\n"), 1);
+    }
+
+    #[test]
+    fn test_poa_grouped_stream() {
+        let record = |name: &str, seq: &[u8]| FastqRecord {
+            name: name.to_string(),
+            seq: seq.to_vec(),
+            qual: vec![b'F'; seq.len()],
+        };
+        let records: Vec<Result<FastqRecord, &str>> = vec![
+            Ok(record("a:1", b"ACGTT")),
+            Ok(record("a:2", b"ACGTT")),
+            Err("unreadable record"),
+            Ok(record("a:3", b"ACTT")),
+            Ok(record("b:1", b"TTGCA")),
+            Ok(record("a:4", b"ACGTT")),
+        ];
+        let family = |record: &FastqRecord| record.name[..1].to_string();
+        let groups: Vec<Result<(String, ConsensusResult), &str>> =
+            poa_grouped_stream(records, family, &AlignmentParams::default()).collect();
+
+        assert_eq!(groups.len(), 4);
+        assert_eq!(groups[0], Err("unreadable record"));
+        let (key, result) = groups[1].as_ref().unwrap();
+        assert_eq!((key.as_str(), result.consensus.as_slice()), ("a", &b"ACGTT"[..]));
+        assert_eq!(result.msa.names, vec!["a:1", "a:2", "a:3"]);
+        // a key recurring after another starts a new group
+        let keys: Vec<&str> = groups[1..]
+            .iter()
+            .map(|group| group.as_ref().unwrap().0.as_str())
+            .collect();
+        assert_eq!(keys, vec!["a", "b", "a"]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Trimming of homopolymer tails, such as the poly-A tails of cDNA reads, before consensus.
+use crate::pairwise::null_terminated;
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// How homopolymer tails are detected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TailOptions {
+    /// The minimum length of a trimmed tail.
+    pub min_len: usize,
+    /// The maximum fraction of the bases of a tail differing from the homopolymer base.
+    pub max_error_rate: f64,
+    /// If true, poly-A tails are trimmed from the 3' end of reads.
+    pub poly_a: bool,
+    /// If true, poly-T heads, the poly-A tails of reverse strand reads, are trimmed from the 5' end of reads.
+    pub poly_t: bool,
+}
+
+impl Default for TailOptions {
+    fn default() -> TailOptions {
+        TailOptions {
+            min_len: 10,
+            max_error_rate: 0.1,
+            poly_a: true,
+            poly_t: true,
+        }
+    }
+}
+
+/// The number of bases trimmed from each end of a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TailTrim {
+    /// The length of the poly-T trimmed from the 5' end.
+    pub poly_t: usize,
+    /// The length of the poly-A trimmed from the 3' end.
+    pub poly_a: usize,
+}
+
+// the length of the homopolymer run of `base` at the start of the bases, as the best scoring prefix where bases
+// score 1 and errors lose the score of the bases a run needs per error to stay within the error rate
+fn homopolymer_len<'a, I: Iterator<Item = &'a u8>>(bases: I, base: u8, options: &TailOptions) -> usize {
+    let penalty = if options.max_error_rate > 0.0 { 1.0 / options.max_error_rate - 1.0 } else { f64::INFINITY };
+    let (mut score, mut best_score) = (0.0, 0.0);
+    let mut len = 0;
+    for (i, &b) in bases.enumerate() {
+        if b.to_ascii_uppercase() == base {
+            score += 1.0;
+            if score > best_score {
+                best_score = score;
+                len = i + 1;
+            }
+        } else {
+            score -= penalty;
+            if score < 0.0 {
+                break;
+            }
+        }
+    }
+    if len >= options.min_len {
+        len
+    } else {
+        0
+    }
+}
+
+/// Returns the number of bases of the poly-T head and poly-A tail of a read (not null-terminated).
+pub fn find_tails(seq: &[u8], options: &TailOptions) -> TailTrim {
+    let poly_t = if options.poly_t { homopolymer_len(seq.iter(), b'T', options) } else { 0 };
+    let poly_a = if options.poly_a { homopolymer_len(seq[poly_t..].iter().rev(), b'A', options) } else { 0 };
+    TailTrim { poly_t, poly_a }
+}
+
+/// Trims the homopolymer tails of reads.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8)
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `options` - how tails are detected
+///
+/// # Returns
+/// * returns the trimmed sequences and qualities (null-terminated), and the bases trimmed from each read,
+///   all in input order
+pub fn trim_tails(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    options: &TailOptions,
+) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<TailTrim>) {
+    if seqs.len() != quals.len() {
+        panic!("Input sequences and qualities must be of same length");
+    }
+    let mut trimmed_seqs = vec![];
+    let mut trimmed_quals = vec![];
+    let mut trims = vec![];
+    for (seq, qual) in seqs.iter().zip(quals.iter()) {
+        let seq = &seq[..seq.len().saturating_sub(1)];
+        let qual = &qual[..qual.len().saturating_sub(1)];
+        let trim = find_tails(seq, options);
+        let range = trim.poly_t..seq.len() - trim.poly_a;
+        trimmed_seqs.push(null_terminated(&seq[range.clone()]));
+        trimmed_quals.push(null_terminated(&qual[range]));
+        trims.push(trim);
+    }
+    (trimmed_seqs, trimmed_quals, trims)
+}
+
+/// Generates the consensus of reads after trimming their homopolymer tails, see `trim_tails`. Reads made only
+/// of a tail are left out of the consensus.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `params` - the alignment mode and scores
+/// * `options` - how tails are detected
+///
+/// # Returns
+/// * returns the consensus of the trimmed reads and the bases trimmed from each read, in input order
+pub fn poa_trim_tails(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    params: &AlignmentParams,
+    options: &TailOptions,
+) -> (ConsensusResult, Vec<TailTrim>) {
+    let (seqs, quals, trims) = trim_tails(seqs, quals, options);
+    let (seqs, quals): (Vec<Vec<u8>>, Vec<Vec<u8>>) =
+        seqs.into_iter().zip(quals).filter(|(seq, _)| seq.len() > 1).unzip();
+    (crate::poa(&seqs, &quals, params), trims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_tails() {
+        let options = TailOptions::default();
+
+        assert_eq!(find_tails(b"ACGTTGCATGAAAAAAAAAAAA", &options), TailTrim { poly_t: 0, poly_a: 12 });
+        // one error in 21 bases, the trailing C is not part of the tail
+        let read = b"TTTTTTTTTTGTTTTTTTTTTACGTTGCATGAAAAAAAAAAC";
+        assert_eq!(find_tails(read, &options), TailTrim { poly_t: 21, poly_a: 0 });
+        assert_eq!(find_tails(b"ACGTTGCATGAAAAAAAAAGAAC", &options).poly_a, 0);
+        assert_eq!(find_tails(b"ACGTAAAAAAAA", &options), TailTrim::default());
+        assert_eq!(find_tails(b"AAAAAAAAAAAA", &TailOptions { poly_t: false, ..options }).poly_a, 12);
+    }
+
+    #[test]
+    fn test_poa_trim_tails() {
+        let mut seqs = vec![];
+        for tail in [12, 20, 35].iter() {
+            seqs.push(null_terminated(format!("AATGCCCGTTAGCATCGGAT{}", "A".repeat(*tail)).as_bytes()));
+        }
+        seqs.push(null_terminated(&[b'A'; 15]));
+        let quals = crate::quals_for(&seqs);
+
+        let (result, trims) = poa_trim_tails(&seqs, &quals, &AlignmentParams::default(), &TailOptions::default());
+
+        assert_eq!(trims.iter().map(|trim| trim.poly_a).collect::<Vec<_>>(), vec![12, 20, 35, 15]);
+        assert_eq!(result.metrics.num_reads, 3);
+        assert_eq!(result.consensus, b"AATGCCCGTTAGCATCGGAT".to_vec());
+    }
+}
@@ -0,0 +1,90 @@
+//! Selection of alignment scores from the error profile observed in a first consensus pass.
+use crate::params::AlignmentParams;
+use crate::result::{ConsensusResult, ErrorProfile};
+
+/// The number of reads aligned by the first, estimating pass of `poa_auto`.
+pub const AUTO_TUNE_READS: usize = 20;
+
+/// Chooses mismatch and gap scores suited to an error profile.
+///
+/// Indel-dominated profiles (e.g. nanopore reads) get cheap gaps and costly mismatches, substitution-dominated
+/// profiles (e.g. short reads) get costly gaps; balanced profiles keep the scores of `base`. The alignment type
+/// and match score of `base` are always kept, and the derived scores are clamped to the range of the alignment
+/// engine.
+pub fn tune_params(profile: &ErrorProfile, base: &AlignmentParams) -> AlignmentParams {
+    let indels = (profile.insertions + profile.deletions) as f64;
+    let mismatches = profile.mismatches as f64;
+    let mut params = *base;
+
+    if indels >= 2.0 * mismatches.max(1.0) {
+        params.mismatch_score = -(base.match_score + 1).min(-(i8::MIN as i32));
+        params.gap_open = -2;
+        params.gap_extend = -1;
+    } else if mismatches >= 2.0 * indels.max(1.0) {
+        params.mismatch_score = -3;
+        params.gap_open = -(base.match_score + 3).min(-(i8::MIN as i32));
+        params.gap_extend = -2;
+    } else {
+        return params;
+    }
+    // keep the second affine function identical, the gap model stays affine
+    params.gap2_open = params.gap_open;
+    params.gap2_extend = params.gap_extend;
+    params
+}
+
+/// Generates a consensus with scores tuned to the reads: a quick first pass over the first
+/// `AUTO_TUNE_READS` reads estimates the error profile, from which `tune_params` selects the scores of the
+/// final pass over all reads.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8) to form a consensus from
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `base` - the scores of the first pass, and the alignment type and match score of the final pass
+///
+/// # Returns
+/// * returns the consensus result of the final pass together with the scores it used
+pub fn poa_auto(seqs: &[Vec<u8>], quals: &[Vec<u8>], base: &AlignmentParams) -> (ConsensusResult, AlignmentParams) {
+    let n = seqs.len().min(AUTO_TUNE_READS);
+    let first_pass = crate::poa(&seqs[..n], &quals[..n.min(quals.len())], base);
+    let params = tune_params(&first_pass.metrics.errors, base);
+    (crate::poa(seqs, quals, &params), params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(mismatches: usize, insertions: usize, deletions: usize) -> ErrorProfile {
+        ErrorProfile {
+            aligned_bases: 1000,
+            mismatches,
+            insertions,
+            deletions,
+        }
+    }
+
+    #[test]
+    fn test_tune_params() {
+        let base = AlignmentParams::default();
+
+        let ont = tune_params(&profile(10, 30, 40), &base);
+        assert_eq!((ont.mismatch_score, ont.gap_open, ont.gap_extend), (-6, -2, -1));
+        assert_eq!((ont.gap2_open, ont.gap2_extend), (-2, -1));
+
+        let illumina = tune_params(&profile(20, 1, 2), &base);
+        assert_eq!((illumina.mismatch_score, illumina.gap_open, illumina.gap_extend), (-3, -8, -2));
+
+        assert_eq!(tune_params(&profile(10, 5, 6), &base), base);
+        assert_eq!(tune_params(&ErrorProfile::default(), &base), base);
+
+        // derived scores stay within the range of the engine
+        let max_match = AlignmentParams {
+            match_score: 127,
+            ..base
+        };
+        assert_eq!(tune_params(&profile(10, 30, 40), &max_match).mismatch_score, -128);
+        assert_eq!(tune_params(&profile(20, 1, 2), &max_match).gap_open, -128);
+        assert!(tune_params(&profile(10, 30, 40), &max_match).validate().is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+//! Grouping of reads into molecule families by their UMI, correcting sequencing errors in the UMIs.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::batch::{poa_batch, Cluster, ClusterResult};
+use crate::params::AlignmentParams;
+
+/// The reads of one molecule: the reads carrying a true UMI or one of its erroneous copies.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct UmiFamily {
+    /// The most frequent UMI of the family, taken as the true UMI.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub umi: Vec<u8>,
+    /// The UMIs merged into the family, the true UMI first.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seqs_as_str"))]
+    pub umis: Vec<Vec<u8>>,
+    /// The input indices of the reads of the family, in input order.
+    pub reads: Vec<usize>,
+}
+
+// the UMIs differing from `umi` by one substitution
+fn neighbours(umi: &[u8]) -> Vec<Vec<u8>> {
+    let mut out = vec![];
+    for pos in 0..umi.len() {
+        for &base in b"ACGTN".iter() {
+            if base != umi[pos] {
+                let mut neighbour = umi.to_vec();
+                neighbour[pos] = base;
+                out.push(neighbour);
+            }
+        }
+    }
+    out
+}
+
+/// Groups reads into families with the directional method of UMI-tools.
+///
+/// UMIs one substitution apart are joined by an edge from UMI `a` to UMI `b` when
+/// `count(a) >= 2 * count(b) - 1`. Starting from the most frequent unassigned UMI, a family gathers all the
+/// UMIs reachable along these edges, so that an error UMI joins the family of the UMI it originates from
+/// without chaining two true UMIs together.
+/// # Arguments
+///
+/// * `umis` - the UMI of each read
+///
+/// # Returns
+/// * returns the families, largest first (ties by UMI)
+pub fn directional_families(umis: &[&[u8]]) -> Vec<UmiFamily> {
+    let mut reads: BTreeMap<&[u8], Vec<usize>> = BTreeMap::new();
+    for (i, umi) in umis.iter().enumerate() {
+        reads.entry(umi).or_default().push(i);
+    }
+    let counts: HashMap<&[u8], usize> = reads.iter().map(|(umi, r)| (*umi, r.len())).collect();
+
+    let mut order: Vec<&[u8]> = reads.keys().cloned().collect();
+    order.sort_by(|a, b| counts[b].cmp(&counts[a]).then(a.cmp(b)));
+
+    let mut assigned: HashSet<&[u8]> = HashSet::new();
+    let mut families = vec![];
+    for &seed in &order {
+        if !assigned.insert(seed) {
+            continue;
+        }
+        let mut members = vec![seed];
+        let mut next = 0;
+        while next < members.len() {
+            let umi = members[next];
+            next += 1;
+            for neighbour in neighbours(umi) {
+                if let Some((&other, &count)) = counts.get_key_value(neighbour.as_slice()) {
+                    if counts[umi] + 1 >= 2 * count && assigned.insert(other) {
+                        members.push(other);
+                    }
+                }
+            }
+        }
+
+        let mut family_reads: Vec<usize> = members.iter().flat_map(|umi| reads[umi].iter().cloned()).collect();
+        family_reads.sort_unstable();
+        families.push(UmiFamily {
+            umi: seed.to_vec(),
+            umis: members.iter().map(|umi| umi.to_vec()).collect(),
+            reads: family_reads,
+        });
+    }
+    families.sort_by(|a, b| b.reads.len().cmp(&a.reads.len()).then(a.umi.cmp(&b.umi)));
+    families
+}
+
+/// Generates one consensus per UMI family, after merging the UMIs of sequencing errors into their true UMI
+/// with `directional_families`.
+/// # Arguments
+///
+/// * `seqs` - a vector holding the sequences (each as a null-terminated vector of u8)
+/// * `quals` - a vector holding the qualities (each as a null-terminated vector of u8) of the sequences
+/// * `umis` - the UMI of each sequence
+/// * `params` - the alignment mode and scores, shared by all families
+///
+/// # Returns
+/// * returns the consensus of each family identified by its true UMI, largest family first
+pub fn poa_umi_families(
+    seqs: &[Vec<u8>],
+    quals: &[Vec<u8>],
+    umis: &[&[u8]],
+    params: &AlignmentParams,
+) -> Vec<ClusterResult> {
+    if seqs.len() != quals.len() || seqs.len() != umis.len() {
+        panic!("Input sequences, qualities and UMIs must be of same length");
+    }
+    let clusters: Vec<Cluster> = directional_families(umis)
+        .into_iter()
+        .map(|family| Cluster {
+            id: String::from_utf8_lossy(&family.umi).into_owned(),
+            seqs: family.reads.iter().map(|&i| seqs[i].clone()).collect(),
+            quals: family.reads.iter().map(|&i| quals[i].clone()).collect(),
+            metadata: Default::default(),
+        })
+        .collect();
+    poa_batch(&clusters, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directional_families() {
+        let mut umis: Vec<&[u8]> = vec![];
+        umis.extend(vec![&b"ACGTAC"[..]; 10]);
+        // errors of the first UMI, the second one chaining on the first error
+        umis.extend(vec![&b"ACGTAA"[..]; 2]);
+        umis.push(b"CCGTAA");
+        // a second true UMI one substitution away, too frequent to be an error
+        umis.extend(vec![&b"ACGTTC"[..]; 6]);
+        umis.extend(vec![&b"TTTTTT"[..]; 2]);
+
+        let families = directional_families(&umis);
+
+        assert_eq!(families.len(), 3);
+        assert_eq!(families[0].umi, b"ACGTAC".to_vec());
+        assert_eq!(families[0].umis, vec![b"ACGTAC".to_vec(), b"ACGTAA".to_vec(), b"CCGTAA".to_vec()]);
+        assert_eq!(families[0].reads, (0..13).collect::<Vec<_>>());
+        assert_eq!(families[1].umis, vec![b"ACGTTC".to_vec()]);
+        assert_eq!(families[2].reads, vec![19, 20]);
+    }
+
+    #[test]
+    fn test_poa_umi_families() {
+        let seqs: Vec<Vec<u8>> = ["AATGCCCGTT\0", "AATGCCGTT\0", "AATGCCCGTT\0", "GGCATTACCA\0"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        let quals = crate::quals_for(&seqs);
+        let umis: Vec<&[u8]> = vec![b"AAAA", b"AAAA", b"AAAT", b"CCCC"];
+
+        let results = poa_umi_families(&seqs, &quals, &umis, &AlignmentParams::default());
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, "AAAA");
+        assert_eq!(results[0].result.metrics.num_reads, 3);
+        assert_eq!(results[1].result.consensus, b"GGCATTACCA".to_vec());
+    }
+}
@@ -0,0 +1,191 @@
+//! Differences between a consensus and a reference sequence.
+use crate::msa::{Msa, GAP};
+use crate::pairwise::{align_pair, left_align_pair};
+use crate::params::AlignmentParams;
+use crate::result::ConsensusResult;
+
+/// The kind of a difference between the consensus and the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum VariantKind {
+    /// A single substituted base.
+    Snv,
+    /// Bases present in the consensus but not in the reference.
+    Insertion,
+    /// Bases present in the reference but not in the consensus.
+    Deletion,
+}
+
+/// A difference between the consensus and the reference.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Variant {
+    /// The kind of the variant.
+    pub kind: VariantKind,
+    /// The 0-based reference position of the first affected base. Insertions are placed before this base.
+    pub ref_pos: usize,
+    /// The 0-based consensus position of the first affected base. Deletions are placed before this base.
+    pub consensus_pos: usize,
+    /// The reference bases (empty for insertions).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub ref_allele: Vec<u8>,
+    /// The consensus bases (empty for deletions).
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde_util::seq_as_str"))]
+    pub alt_allele: Vec<u8>,
+    /// The number of reads spanning the site.
+    pub depth: usize,
+    /// The fraction of the reads spanning the site that agree with the consensus allele.
+    pub support: f64,
+}
+
+/// Aligns the consensus against a reference and reports their differences.
+/// # Arguments
+///
+/// * `result` - the consensus result, whose alignment provides the read support of each variant
+/// * `reference` - the reference sequence (not null-terminated)
+/// * `params` - the scores used to globally align the consensus against the reference
+///
+/// # Returns
+/// * returns the SNVs and indels of the consensus, ordered by reference position. Indels are left-aligned
+///   within repeats, as in normalized VCF
+pub fn call_variants(result: &ConsensusResult, reference: &[u8], params: &AlignmentParams) -> Vec<Variant> {
+    let (mut ref_row, mut cons_row) = align_pair(reference, &result.consensus, params);
+    left_align_pair(&mut ref_row, &mut cons_row);
+    variants_from_rows(&ref_row, &cons_row, &result.msa)
+}
+
+pub(crate) fn variants_from_rows(ref_row: &[u8], cons_row: &[u8], msa: &Msa) -> Vec<Variant> {
+    let cons_cols: Vec<usize> = (0..msa.num_columns()).filter(|&col| msa.consensus[col] != GAP).collect();
+
+    let mut variants = vec![];
+    let (mut ref_pos, mut cons_pos, mut col) = (0, 0, 0);
+    while col < ref_row.len() {
+        let (r, c) = (ref_row[col], cons_row[col]);
+        if r != GAP && c != GAP {
+            if r != c {
+                let msa_col = cons_cols[cons_pos];
+                let (depth, support) = allele_support(msa, msa_col..msa_col + 1, (msa_col, msa_col));
+                variants.push(Variant {
+                    kind: VariantKind::Snv,
+                    ref_pos,
+                    consensus_pos: cons_pos,
+                    ref_allele: vec![r],
+                    alt_allele: vec![c],
+                    depth,
+                    support,
+                });
+            }
+            ref_pos += 1;
+            cons_pos += 1;
+            col += 1;
+        } else if r == GAP && c != GAP {
+            let start = col;
+            while col < ref_row.len() && ref_row[col] == GAP && cons_row[col] != GAP {
+                col += 1;
+            }
+            let len = col - start;
+            let (first, last) = (cons_cols[cons_pos], cons_cols[cons_pos + len - 1]);
+            let (depth, support) = allele_support(msa, first..last + 1, (first, last));
+            variants.push(Variant {
+                kind: VariantKind::Insertion,
+                ref_pos,
+                consensus_pos: cons_pos,
+                ref_allele: vec![],
+                alt_allele: cons_row[start..col].to_vec(),
+                depth,
+                support,
+            });
+            cons_pos += len;
+        } else if r != GAP {
+            let start = col;
+            while col < ref_row.len() && cons_row[col] == GAP && ref_row[col] != GAP {
+                col += 1;
+            }
+            let len = col - start;
+            // the deletion lies between the flanking consensus bases
+            let left = if cons_pos > 0 { Some(cons_cols[cons_pos - 1]) } else { None };
+            let right = cons_cols.get(cons_pos).cloned();
+            let columns = left.map_or(0, |l| l + 1)..right.unwrap_or_else(|| msa.num_columns());
+            let span = (left.or(right).unwrap_or(0), right.or(left).unwrap_or(0));
+            let (depth, support) = allele_support(msa, columns, span);
+            variants.push(Variant {
+                kind: VariantKind::Deletion,
+                ref_pos,
+                consensus_pos: cons_pos,
+                ref_allele: ref_row[start..col].to_vec(),
+                alt_allele: vec![],
+                depth,
+                support,
+            });
+            ref_pos += len;
+        } else {
+            col += 1;
+        }
+    }
+    variants
+}
+
+// number of reads covering the columns `span.0..=span.1`, and the fraction of them whose row equals the
+// consensus over `columns`
+pub(crate) fn allele_support(msa: &Msa, columns: std::ops::Range<usize>, span: (usize, usize)) -> (usize, f64) {
+    let mut spanning = 0;
+    let mut agreeing = 0;
+    for row in &msa.rows {
+        let first = row.iter().position(|&b| b != GAP);
+        let last = row.iter().rposition(|&b| b != GAP);
+        match (first, last) {
+            (Some(first), Some(last)) if first <= span.0 && last >= span.1 => {
+                spanning += 1;
+                if columns.clone().all(|col| row[col] == msa.consensus[col]) {
+                    agreeing += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if spanning == 0 {
+        return (0, 0.0);
+    }
+    (spanning, agreeing as f64 / spanning as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_from_rows() {
+        let msa = Msa {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string()],
+            rows: vec![
+                b"ACTT-GAC".to_vec(),
+                b"ACTT-GAC".to_vec(),
+                b"ACGTTGAC".to_vec(),
+                b"ACTT-GA-".to_vec(),
+            ],
+            consensus: b"ACTT-GAC".to_vec(),
+        };
+        let ref_row = b"ACGTTGA-";
+        let cons_row = b"ACTT-GAC";
+
+        let variants = variants_from_rows(ref_row, cons_row, &msa);
+
+        assert_eq!(variants.len(), 3);
+        assert_eq!(variants[0].kind, VariantKind::Snv);
+        assert_eq!((variants[0].ref_pos, variants[0].consensus_pos), (2, 2));
+        assert_eq!((variants[0].ref_allele[0], variants[0].alt_allele[0]), (b'G', b'T'));
+        assert_eq!(variants[0].support, 0.75);
+
+        assert_eq!(variants[1].kind, VariantKind::Deletion);
+        assert_eq!((variants[1].ref_pos, variants[1].consensus_pos), (4, 4));
+        assert_eq!(variants[1].ref_allele, b"T".to_vec());
+        assert_eq!(variants[1].support, 0.75);
+
+        // the last read does not reach the inserted base
+        assert_eq!(variants[2].kind, VariantKind::Insertion);
+        assert_eq!((variants[2].ref_pos, variants[2].consensus_pos), (7, 6));
+        assert_eq!(variants[2].alt_allele, b"C".to_vec());
+        assert_eq!(variants[2].support, 1.0);
+        assert_eq!(variants[2].depth, 3);
+    }
+}
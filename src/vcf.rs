@@ -0,0 +1,147 @@
+//! Minimal VCF output of the variants of one or more clusters.
+use std::io::{self, Write};
+
+use crate::variant::{Variant, VariantKind};
+
+/// Writes variants as VCF 4.2 records, with the read depth (`DP`) and the fraction of reads supporting the
+/// consensus allele (`AF`) as INFO fields.
+pub struct VcfWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> VcfWriter<W> {
+    /// Creates a writer and writes the VCF header.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - the destination of the VCF
+    /// * `contigs` - the name and length of each reference that records will be written for, e.g. one per cluster
+    pub fn new(mut writer: W, contigs: &[(&str, usize)]) -> io::Result<VcfWriter<W>> {
+        writeln!(writer, "##fileformat=VCFv4.2")?;
+        writeln!(writer, "##source=rust-spoa")?;
+        for (name, len) in contigs {
+            writeln!(writer, "##contig=<ID={},length={}>", name, len)?;
+        }
+        writeln!(
+            writer,
+            "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Number of reads spanning the variant\">"
+        )?;
+        writeln!(
+            writer,
+            "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Fraction of spanning reads supporting the consensus allele\">"
+        )?;
+        writeln!(writer, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+        Ok(VcfWriter { writer })
+    }
+
+    /// Writes one record per variant.
+    ///
+    /// # Arguments
+    ///
+    /// * `chrom` - the name of the reference the variants were called against
+    /// * `reference` - the reference sequence, used to add the anchor base of indels
+    /// * `variants` - the variants, as returned by `call_variants`
+    pub fn write_variants(&mut self, chrom: &str, reference: &[u8], variants: &[Variant]) -> io::Result<()> {
+        for variant in variants {
+            let (pos, ref_allele, alt_allele) = vcf_alleles(variant, reference);
+            writeln!(
+                self.writer,
+                "{}\t{}\t.\t{}\t{}\t.\tPASS\tDP={};AF={:.4}",
+                chrom,
+                pos,
+                String::from_utf8_lossy(&ref_allele),
+                String::from_utf8_lossy(&alt_allele),
+                variant.depth,
+                variant.support
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Writes a complete VCF holding the variants of a single cluster.
+pub fn write_vcf<W: Write>(writer: W, chrom: &str, reference: &[u8], variants: &[Variant]) -> io::Result<()> {
+    let mut vcf = VcfWriter::new(writer, &[(chrom, reference.len())])?;
+    vcf.write_variants(chrom, reference, variants)
+}
+
+// 1-based position and alleles of a variant, indels being anchored on the preceding reference base
+// (or on the following one at the start of the reference, and on an `N` when the indel spans the whole
+// reference)
+fn vcf_alleles(variant: &Variant, reference: &[u8]) -> (usize, Vec<u8>, Vec<u8>) {
+    if variant.kind == VariantKind::Snv {
+        return (variant.ref_pos + 1, variant.ref_allele.clone(), variant.alt_allele.clone());
+    }
+    if variant.ref_pos > 0 {
+        let anchor = reference[variant.ref_pos - 1];
+        let mut ref_allele = vec![anchor];
+        ref_allele.extend_from_slice(&variant.ref_allele);
+        let mut alt_allele = vec![anchor];
+        alt_allele.extend_from_slice(&variant.alt_allele);
+        (variant.ref_pos, ref_allele, alt_allele)
+    } else {
+        let anchor = reference.get(variant.ref_allele.len()).copied().unwrap_or(b'N');
+        let mut ref_allele = variant.ref_allele.clone();
+        ref_allele.push(anchor);
+        let mut alt_allele = variant.alt_allele.clone();
+        alt_allele.push(anchor);
+        (1, ref_allele, alt_allele)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(kind: VariantKind, ref_pos: usize, ref_allele: &[u8], alt_allele: &[u8]) -> Variant {
+        Variant {
+            kind,
+            ref_pos,
+            consensus_pos: ref_pos,
+            ref_allele: ref_allele.to_vec(),
+            alt_allele: alt_allele.to_vec(),
+            depth: 8,
+            support: 0.75,
+        }
+    }
+
+    #[test]
+    fn test_write_vcf() {
+        let reference = b"ACGTTGA";
+        let variants = vec![
+            variant(VariantKind::Deletion, 0, b"A", b""),
+            variant(VariantKind::Snv, 2, b"G", b"T"),
+            variant(VariantKind::Deletion, 4, b"T", b""),
+            variant(VariantKind::Insertion, 7, b"", b"C"),
+        ];
+
+        let mut out = vec![];
+        write_vcf(&mut out, "amplicon", reference, &variants).unwrap();
+        let vcf = String::from_utf8(out).unwrap();
+        let records: Vec<&str> = vcf.lines().filter(|line| !line.starts_with('#')).collect();
+
+        assert!(vcf.contains("##contig=<ID=amplicon,length=7>"));
+        assert_eq!(records[0], "amplicon\t1\t.\tAC\tC\t.\tPASS\tDP=8;AF=0.7500");
+        assert_eq!(records[1], "amplicon\t3\t.\tG\tT\t.\tPASS\tDP=8;AF=0.7500");
+        assert_eq!(records[2], "amplicon\t4\t.\tTT\tT\t.\tPASS\tDP=8;AF=0.7500");
+        assert_eq!(records[3], "amplicon\t7\t.\tA\tAC\t.\tPASS\tDP=8;AF=0.7500");
+    }
+
+    #[test]
+    fn test_write_vcf_whole_reference() {
+        let mut out = vec![];
+        write_vcf(&mut out, "amplicon", b"ACG", &[variant(VariantKind::Deletion, 0, b"ACG", b"")]).unwrap();
+        let vcf = String::from_utf8(out).unwrap();
+        assert!(vcf.ends_with("amplicon\t1\t.\tACGN\tN\t.\tPASS\tDP=8;AF=0.7500\n"));
+
+        let mut out = vec![];
+        write_vcf(&mut out, "amplicon", b"", &[variant(VariantKind::Insertion, 0, b"", b"AC")]).unwrap();
+        let vcf = String::from_utf8(out).unwrap();
+        assert!(vcf.ends_with("amplicon\t1\t.\tN\tACN\t.\tPASS\tDP=8;AF=0.7500\n"));
+    }
+}